@@ -47,6 +47,56 @@ pub struct TransactionDataV1 {
     #[serde(with = "serde_bytes")]
     pub args: Vec<u8>,
 }
+/// Transaction expiration condition.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Expiry {
+    /// Last block height where the transaction can be included.
+    Height(u64),
+    /// Last block timestamp, in seconds since the unix epoch, where the
+    /// transaction can be included.
+    Timestamp(u64),
+}
+
+impl Expiry {
+    /// Check if the expiration condition is met for a block with the given
+    /// height and timestamp.
+    pub fn is_expired(&self, height: u64, timestamp: u64) -> bool {
+        match self {
+            Expiry::Height(expiry) => height > *expiry,
+            Expiry::Timestamp(expiry) => timestamp > *expiry,
+        }
+    }
+}
+
+/// Transaction payload with expiration and sequence number.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct TransactionDataV2 {
+    /// Target account identifier.
+    pub account: String,
+    /// Max allowed blockchain asset units for fee.
+    pub fuel_limit: u64,
+    /// Nonce to differentiate different transactions with same payload.
+    #[serde(with = "serde_bytes")]
+    pub nonce: Vec<u8>,
+    /// Network identifier.
+    pub network: String,
+    /// Expected smart contract application identifier.
+    pub contract: Option<Hash>,
+    /// Method name.
+    pub method: String,
+    /// Submitter public key.
+    pub caller: PublicKey,
+    /// Smart contract arguments.
+    #[serde(with = "serde_bytes")]
+    pub args: Vec<u8>,
+    /// Optional expiration, the transaction is refused once expired.
+    pub expiry: Option<Expiry>,
+    /// Optional caller sequence number, the transaction is executed only if
+    /// it matches the caller account next sequence number.
+    pub sequence: Option<u64>,
+}
+
 /// Empty Transaction payload.
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct EmptyTransactionDataV1 {
@@ -113,6 +163,8 @@ pub enum TransactionData {
     BulkV1(TransactionDataBulkV1),
     #[serde(rename = "f76bce109213ee2204e218f000b7c67770812e4b26f4dba90c532a10865968ff")]
     BulkEmpyRoot(EmptyTransactionDataV1),
+    #[serde(rename = "176f4c12e09bb69b156bb759c6654c8f01dcbef5aa39d5229529980832cc497b")]
+    V2(TransactionDataV2),
 }
 
 impl TransactionData {
@@ -120,6 +172,7 @@ impl TransactionData {
     pub fn sign(&self, keypair: &KeyPair) -> Result<Vec<u8>> {
         match &self {
             TransactionData::V1(tx_data) => tx_data.sign(keypair),
+            TransactionData::V2(tx_data) => tx_data.sign(keypair),
             TransactionData::BulkNodeV1(tx_data) => tx_data.sign(keypair),
             TransactionData::BulkV1(tx_data) => tx_data.sign(keypair),
             _ => Err(Error::new_ext(
@@ -132,6 +185,7 @@ impl TransactionData {
     pub fn verify(&self, public_key: &PublicKey, sig: &[u8]) -> Result<()> {
         match &self {
             TransactionData::V1(tx_data) => tx_data.verify(public_key, sig),
+            TransactionData::V2(tx_data) => tx_data.verify(public_key, sig),
             TransactionData::BulkNodeV1(tx_data) => tx_data.verify(public_key, sig),
             TransactionData::BulkV1(tx_data) => tx_data.verify(public_key, sig),
             _ => Err(Error::new_ext(
@@ -145,6 +199,7 @@ impl TransactionData {
         match &self {
             TransactionData::BulkV1(tx_data) => tx_data.check_integrity(),
             TransactionData::V1(tx_data) => tx_data.check_integrity(),
+            TransactionData::V2(tx_data) => tx_data.check_integrity(),
             _ => Err(Error::new_ext(
                 ErrorKind::NotImplemented,
                 "verify method not implemented for this tx data type",
@@ -155,6 +210,7 @@ impl TransactionData {
     pub fn get_caller(&self) -> &PublicKey {
         match &self {
             TransactionData::V1(tx_data) => &tx_data.caller,
+            TransactionData::V2(tx_data) => &tx_data.caller,
             TransactionData::BulkNodeV1(tx_data) => &tx_data.caller,
             TransactionData::BulkRootV1(tx_data) => &tx_data.caller,
             TransactionData::BulkV1(tx_data) => tx_data.txs.root.data.get_caller(),
@@ -164,6 +220,7 @@ impl TransactionData {
     pub fn get_network(&self) -> &str {
         match &self {
             TransactionData::V1(tx_data) => &tx_data.network,
+            TransactionData::V2(tx_data) => &tx_data.network,
             TransactionData::BulkNodeV1(tx_data) => &tx_data.network,
             TransactionData::BulkRootV1(tx_data) => &tx_data.network,
             TransactionData::BulkV1(tx_data) => tx_data.txs.root.data.get_network(),
@@ -173,6 +230,7 @@ impl TransactionData {
     pub fn get_account(&self) -> &str {
         match &self {
             TransactionData::V1(tx_data) => &tx_data.account,
+            TransactionData::V2(tx_data) => &tx_data.account,
             TransactionData::BulkNodeV1(tx_data) => &tx_data.account,
             TransactionData::BulkRootV1(tx_data) => &tx_data.account,
             TransactionData::BulkV1(tx_data) => tx_data.txs.root.data.get_account(),
@@ -182,6 +240,7 @@ impl TransactionData {
     pub fn get_method(&self) -> &str {
         match &self {
             TransactionData::V1(tx_data) => &tx_data.method,
+            TransactionData::V2(tx_data) => &tx_data.method,
             TransactionData::BulkNodeV1(tx_data) => &tx_data.method,
             TransactionData::BulkRootV1(tx_data) => &tx_data.method,
             TransactionData::BulkV1(tx_data) => tx_data.txs.root.data.get_method(),
@@ -191,6 +250,7 @@ impl TransactionData {
     pub fn get_args(&self) -> &[u8] {
         match &self {
             TransactionData::V1(tx_data) => &tx_data.args,
+            TransactionData::V2(tx_data) => &tx_data.args,
             TransactionData::BulkNodeV1(tx_data) => &tx_data.args,
             TransactionData::BulkRootV1(tx_data) => &tx_data.args,
             TransactionData::BulkV1(tx_data) => tx_data.txs.root.data.get_args(),
@@ -200,10 +260,12 @@ impl TransactionData {
     pub fn get_fuel_limit(&self) -> u64 {
         match &self {
             TransactionData::V1(tx_data) => tx_data.fuel_limit,
+            TransactionData::V2(tx_data) => tx_data.fuel_limit,
             TransactionData::BulkV1(tx_data) => match &tx_data.txs.root.data {
                 TransactionData::BulkRootV1(tx_data_v1) => tx_data_v1.fuel_limit,
                 TransactionData::BulkEmpyRoot(empty_tx_data) => empty_tx_data.fuel_limit,
                 TransactionData::V1(_)
+                | TransactionData::V2(_)
                 | TransactionData::BulkNodeV1(_)
                 | TransactionData::BulkV1(_) => 0,
             },
@@ -215,12 +277,32 @@ impl TransactionData {
     pub fn get_contract(&self) -> &Option<Hash> {
         match &self {
             TransactionData::V1(tx_data) => &tx_data.contract,
+            TransactionData::V2(tx_data) => &tx_data.contract,
             TransactionData::BulkNodeV1(tx_data) => &tx_data.contract,
             TransactionData::BulkRootV1(tx_data) => &tx_data.contract,
             TransactionData::BulkV1(tx_data) => tx_data.txs.root.data.get_contract(),
             TransactionData::BulkEmpyRoot(_) => &None, // This should not happen
         }
     }
+    pub fn get_expiry(&self) -> Option<Expiry> {
+        match &self {
+            TransactionData::V2(tx_data) => tx_data.expiry,
+            _ => None,
+        }
+    }
+    pub fn get_sequence(&self) -> Option<u64> {
+        match &self {
+            TransactionData::V2(tx_data) => tx_data.sequence,
+            _ => None,
+        }
+    }
+    /// Check if the transaction can't be included anymore in a block with the
+    /// given height and timestamp.
+    pub fn is_expired(&self, height: u64, timestamp: u64) -> bool {
+        self.get_expiry()
+            .map(|expiry| expiry.is_expired(height, timestamp))
+            .unwrap_or_default()
+    }
     pub fn get_dependency(&self) -> Result<Hash> {
         match &self {
             TransactionData::BulkNodeV1(tx_data) => Ok(tx_data.depends_on),
@@ -233,6 +315,7 @@ impl TransactionData {
     pub fn set_contract(&mut self, contract: Option<Hash>) {
         match self {
             TransactionData::V1(tx_data) => tx_data.contract = contract,
+            TransactionData::V2(tx_data) => tx_data.contract = contract,
             TransactionData::BulkNodeV1(tx_data) => tx_data.contract = contract,
             TransactionData::BulkRootV1(tx_data) => tx_data.contract = contract,
             TransactionData::BulkV1(tx_data) => tx_data.txs.root.data.set_contract(contract),
@@ -242,6 +325,7 @@ impl TransactionData {
     pub fn set_account(&mut self, account: String) {
         match self {
             TransactionData::V1(tx_data) => tx_data.account = account,
+            TransactionData::V2(tx_data) => tx_data.account = account,
             TransactionData::BulkNodeV1(tx_data) => tx_data.account = account,
             TransactionData::BulkRootV1(tx_data) => tx_data.account = account,
             TransactionData::BulkV1(tx_data) => tx_data.txs.root.data.set_account(account),
@@ -251,6 +335,7 @@ impl TransactionData {
     pub fn set_nonce(&mut self, nonce: Vec<u8>) {
         match self {
             TransactionData::V1(tx_data) => tx_data.nonce = nonce,
+            TransactionData::V2(tx_data) => tx_data.nonce = nonce,
             TransactionData::BulkNodeV1(tx_data) => tx_data.nonce = nonce,
             TransactionData::BulkRootV1(tx_data) => tx_data.nonce = nonce,
             TransactionData::BulkV1(tx_data) => tx_data.txs.root.data.set_nonce(nonce),
@@ -290,6 +375,37 @@ impl TransactionDataV1 {
     }
 }
 
+impl TransactionDataV2 {
+    /// Sign transaction data.
+    /// Serialization is performed using message pack format with named field.
+    pub fn sign(&self, keypair: &KeyPair) -> Result<Vec<u8>> {
+        let data = self.serialize();
+        keypair.sign(&data)
+    }
+
+    /// Transaction data signature verification.
+    pub fn verify(&self, public_key: &PublicKey, sig: &[u8]) -> Result<()> {
+        let data = self.serialize();
+        match public_key.verify(&data, sig) {
+            true => Ok(()),
+            false => Err(ErrorKind::InvalidSignature.into()),
+        }
+    }
+
+    /// Check if tx is intact and coherent
+    pub fn check_integrity(&self) -> Result<()> {
+        if !self.account.is_empty()
+            && !self.nonce.is_empty()
+            && !self.network.is_empty()
+            && !self.method.is_empty()
+        {
+            Ok(())
+        } else {
+            Err(ErrorKind::BrokenIntegrity.into())
+        }
+    }
+}
+
 impl TransactionDataBulkNodeV1 {
     /// Sign transaction data.
     /// Serialization is performed using message pack format with named field.
@@ -505,6 +621,24 @@ impl Transaction {
             Transaction::BulkTransaction(tx) => tx.data.get_contract(),
         }
     }
+    pub fn get_expiry(&self) -> Option<Expiry> {
+        match &self {
+            Transaction::UnitTransaction(tx) => tx.data.get_expiry(),
+            Transaction::BulkTransaction(tx) => tx.data.get_expiry(),
+        }
+    }
+    pub fn get_sequence(&self) -> Option<u64> {
+        match &self {
+            Transaction::UnitTransaction(tx) => tx.data.get_sequence(),
+            Transaction::BulkTransaction(tx) => tx.data.get_sequence(),
+        }
+    }
+    pub fn is_expired(&self, height: u64, timestamp: u64) -> bool {
+        match &self {
+            Transaction::UnitTransaction(tx) => tx.data.is_expired(height, timestamp),
+            Transaction::BulkTransaction(tx) => tx.data.is_expired(height, timestamp),
+        }
+    }
     pub fn get_dependency(&self) -> Result<Hash> {
         match &self {
             Transaction::UnitTransaction(tx) => tx.data.get_dependency(),
@@ -637,6 +771,10 @@ pub struct Account {
     pub contract: Option<Hash>,
     /// Merkle tree root of the data associated with the account.
     pub data_hash: Option<Hash>,
+    /// Next expected sequence number of the transactions submitted by the
+    /// account. Not present until the first sequenced transaction.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<u64>,
 }
 
 impl Account {
@@ -648,9 +786,15 @@ impl Account {
             assets: BTreeMap::new(),
             contract,
             data_hash: None,
+            sequence: None,
         }
     }
 
+    /// Next expected sequence number of the account transactions.
+    pub fn next_sequence(&self) -> u64 {
+        self.sequence.unwrap_or_default()
+    }
+
     /// Get account balance for the given asset.
     pub fn load_asset(&self, asset: &str) -> Vec<u8> {
         self.assets
@@ -689,6 +833,59 @@ pub struct BlockchainSettings {
     pub is_production: bool,
     /// Compatibility of the bootstrap.bin
     pub min_node_version: String,
+    /// Max size of the transactions in a block (bytes)
+    #[serde(default)]
+    pub block_max_size: Option<usize>,
+    /// Max sum of the transactions fuel limits in a block
+    #[serde(default)]
+    pub block_max_fuel: Option<u64>,
+    /// Fuel pricing schedule, when not set the legacy flat fee is charged
+    #[serde(default)]
+    pub fuel_schedule: Option<FuelSchedule>,
+}
+
+/// Fuel pricing schedule.
+/// Converts the wasm machine fuel consumed by the smart contracts execution
+/// into fuel units charged to the transaction caller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FuelSchedule {
+    /// Fuel units charged for each transaction, regardless of the execution.
+    pub base_fuel: u64,
+    /// Wasm machine fuel corresponding to one fuel unit.
+    pub wm_fuel_per_unit: u64,
+}
+
+impl FuelSchedule {
+    /// Fuel units corresponding to the given wasm machine fuel consumption,
+    /// rounded up. The base fuel is not included.
+    pub fn wm_fuel_cost(&self, wm_fuel: u64) -> u64 {
+        let per_unit = self.wm_fuel_per_unit.max(1);
+        wm_fuel / per_unit + u64::from(wm_fuel % per_unit != 0)
+    }
+
+    /// Wasm machine fuel available to a transaction with the given fuel
+    /// limit, once the base fuel is charged.
+    pub fn wm_fuel_limit(&self, fuel_limit: u64) -> u64 {
+        fuel_limit
+            .saturating_sub(self.base_fuel)
+            .saturating_mul(self.wm_fuel_per_unit.max(1))
+    }
+}
+
+/// Fuel estimation of a transaction, executed against the current state.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FuelEstimate {
+    /// Execution outcome.
+    pub success: bool,
+    /// Execution result data on success. Error string on failure.
+    #[serde(with = "serde_bytes")]
+    pub returns: Vec<u8>,
+    /// Wasm machine fuel consumed.
+    pub wm_fuel: u64,
+    /// Fuel burned by the transaction.
+    pub burned_fuel: u64,
+    /// Suggested transaction fuel limit.
+    pub fuel_limit: u64,
 }
 
 #[cfg(test)]
@@ -696,7 +893,7 @@ pub mod tests {
 
     use super::*;
     use crate::{
-        base::serialize::MessagePack,
+        base::serialize::{rmp_deserialize, rmp_serialize, MessagePack},
         crypto::{
             ecdsa::tests::{ecdsa_secp384_test_keypair, ecdsa_secp384_test_public_key},
             Hashable,
@@ -765,6 +962,35 @@ pub mod tests {
         })
     }
 
+    fn create_test_data_unit_v2(expiry: Option<Expiry>, sequence: Option<u64>) -> TransactionData {
+        let public_key = PublicKey::Ecdsa(ecdsa_secp384_test_public_key(0));
+        let account = public_key.to_account_id();
+        let contract =
+            Hash::from_hex("12202c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae")
+                .unwrap();
+
+        TransactionData::V2(TransactionDataV2 {
+            account,
+            fuel_limit: FUEL_LIMIT,
+            nonce: [0xab, 0x82, 0xb7, 0x41, 0xe0, 0x23, 0xa4, 0x12].to_vec(),
+            network: "skynet".to_string(),
+            contract: Some(contract),
+            method: "terminate".to_string(),
+            caller: public_key,
+            args: hex::decode("4f706171756544617461").unwrap(),
+            expiry,
+            sequence,
+        })
+    }
+
+    pub fn create_test_unit_tx_v2(expiry: Option<Expiry>, sequence: Option<u64>) -> Transaction {
+        let keypair = KeyPair::Ecdsa(ecdsa_secp384_test_keypair(0));
+        let data = create_test_data_unit_v2(expiry, sequence);
+        let signature = data.sign(&keypair).unwrap();
+
+        Transaction::UnitTransaction(SignedTransaction { data, signature })
+    }
+
     fn create_transactiondata_bulk_node_v1(
         pk: PublicKey,
         contract: Hash,
@@ -1294,6 +1520,60 @@ pub mod tests {
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn unit_transaction_data_v2_sign_verify() {
+        let data = create_test_data_unit_v2(Some(Expiry::Height(10)), Some(0));
+        let keypair = KeyPair::Ecdsa(ecdsa_secp384_test_keypair(0));
+
+        let signature = data.sign(&keypair).unwrap();
+        let result = data.verify(&keypair.public_key(), &signature);
+
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn unit_transaction_data_v2_expiry_is_signed() {
+        let tx = create_test_unit_tx_v2(Some(Expiry::Height(10)), None);
+        let mut tx = match tx {
+            Transaction::UnitTransaction(tx) => tx,
+            _ => unreachable!(),
+        };
+        if let TransactionData::V2(ref mut data) = tx.data {
+            data.expiry = Some(Expiry::Height(20));
+        }
+
+        let err = tx
+            .data
+            .verify(tx.data.get_caller(), &tx.signature)
+            .unwrap_err();
+
+        assert_eq!(err.kind, ErrorKind::InvalidSignature);
+    }
+
+    #[test]
+    fn unit_transaction_data_v2_serialize_deserialize() {
+        let data = create_test_data_unit_v2(Some(Expiry::Timestamp(1_650_000_000)), Some(7));
+
+        let buf = data.serialize();
+        let result = TransactionData::deserialize(&buf).unwrap();
+
+        assert_eq!(result, data);
+    }
+
+    #[test]
+    fn transaction_expiry() {
+        let by_height = create_test_unit_tx_v2(Some(Expiry::Height(10)), None);
+        let by_time = create_test_unit_tx_v2(Some(Expiry::Timestamp(1000)), None);
+        let no_expiry = create_test_unit_tx_v2(None, None);
+
+        assert!(!by_height.is_expired(10, u64::MAX));
+        assert!(by_height.is_expired(11, 0));
+        assert!(!by_time.is_expired(u64::MAX, 1000));
+        assert!(by_time.is_expired(0, 1001));
+        assert!(!no_expiry.is_expired(u64::MAX, u64::MAX));
+        assert!(!create_test_unit_tx(FUEL_LIMIT).is_expired(u64::MAX, u64::MAX));
+    }
+
     #[test]
     fn bulk_transaction_data_sign_verify() {
         let data = create_test_data_bulk(false, false);
@@ -1517,6 +1797,63 @@ pub mod tests {
         assert_eq!(account, expected);
     }
 
+    #[test]
+    fn account_serialize_deserialize_sequence() {
+        let mut expected = create_test_account();
+        expected.sequence = Some(5);
+
+        let buf = expected.serialize();
+        let account = Account::deserialize(&buf).unwrap();
+
+        assert_eq!(buf[0], 0x95);
+        assert_eq!(account, expected);
+        assert_eq!(account.next_sequence(), 5);
+    }
+
+    #[test]
+    fn blockchain_settings_deserialize_without_block_limits() {
+        let buf =
+            rmp_serialize(&(false, 42usize, 2u16, "burn", Some("skynet"), false, "0.2.9")).unwrap();
+
+        let settings: BlockchainSettings = rmp_deserialize(&buf).unwrap();
+
+        assert_eq!(settings.block_threshold, 42);
+        assert_eq!(settings.min_node_version, "0.2.9");
+        assert_eq!(settings.block_max_size, None);
+        assert_eq!(settings.block_max_fuel, None);
+        assert_eq!(settings.fuel_schedule, None);
+    }
+
+    #[test]
+    fn blockchain_settings_serialize_deserialize_block_limits() {
+        let buf =
+            rmp_serialize(&(false, 42usize, 2u16, "burn", Some("skynet"), false, "0.2.9")).unwrap();
+        let mut settings: BlockchainSettings = rmp_deserialize(&buf).unwrap();
+        settings.block_max_fuel = Some(1000);
+
+        let buf = rmp_serialize(&settings).unwrap();
+        let settings: BlockchainSettings = rmp_deserialize(&buf).unwrap();
+
+        assert_eq!(settings.block_max_size, None);
+        assert_eq!(settings.block_max_fuel, Some(1000));
+    }
+
+    #[test]
+    fn fuel_schedule_cost() {
+        let schedule = FuelSchedule {
+            base_fuel: 10,
+            wm_fuel_per_unit: 1000,
+        };
+
+        assert_eq!(schedule.wm_fuel_cost(0), 0);
+        assert_eq!(schedule.wm_fuel_cost(1), 1);
+        assert_eq!(schedule.wm_fuel_cost(1000), 1);
+        assert_eq!(schedule.wm_fuel_cost(1001), 2);
+        assert_eq!(schedule.wm_fuel_limit(5), 0);
+        assert_eq!(schedule.wm_fuel_limit(15), 5000);
+        assert_eq!(schedule.wm_fuel_limit(u64::MAX), u64::MAX);
+    }
+
     #[test]
     fn account_deserialize_fail() {
         let mut buf = hex::decode(ACCOUNT_CONTRACT_HEX).unwrap();
@@ -25,12 +25,57 @@
 //! right to produce new blocks via a consensus algorithm.
 
 use crate::{
-    base::RwLock,
+    base::{serialize::rmp_deserialize, BlockchainSettings, RwLock},
     blockchain::pool::{BlockInfo, Pool},
     db::Db,
 };
 use std::{sync::Arc, time::SystemTime};
 
+/// Block content limits, in addition to the transactions per block threshold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockLimits {
+    /// Max size of the block transactions (bytes).
+    pub max_bytes: usize,
+    /// Max sum of the block transactions fuel limits.
+    pub max_fuel: u64,
+}
+
+impl Default for BlockLimits {
+    fn default() -> Self {
+        BlockLimits {
+            max_bytes: usize::MAX,
+            max_fuel: u64::MAX,
+        }
+    }
+}
+
+impl From<&BlockchainSettings> for BlockLimits {
+    fn from(settings: &BlockchainSettings) -> Self {
+        let default = BlockLimits::default();
+        BlockLimits {
+            max_bytes: settings.block_max_size.unwrap_or(default.max_bytes),
+            max_fuel: settings.block_max_fuel.unwrap_or(default.max_fuel),
+        }
+    }
+}
+
+impl BlockLimits {
+    /// Loads the limits from the blockchain settings stored in the database.
+    /// Without settings the blocks are not limited.
+    pub fn load<D: Db>(db: &D) -> Self {
+        db.load_configuration("blockchain:settings")
+            .and_then(|buf| rmp_deserialize::<BlockchainSettings>(&buf).ok())
+            .map(|settings| BlockLimits::from(&settings))
+            .unwrap_or_default()
+    }
+
+    /// Checks if transactions with a total size of `bytes` and a total fuel
+    /// limit of `fuel` fit in a block.
+    pub fn fits(&self, bytes: usize, fuel: u64) -> bool {
+        bytes <= self.max_bytes && fuel <= self.max_fuel
+    }
+}
+
 /// Builder context data.
 pub(crate) struct Builder<D: Db> {
     /// Transactions per block upper limit.
@@ -80,8 +125,10 @@ impl<D: Db> Builder<D> {
 
     /// Adds a bunch of entries to the blockchain confirmed blocks queue.
     /// The added blocks are ready to be executed.
-    /// Each block will have at most `threshold` transactions.
+    /// Each block will have at most `threshold` transactions and will respect
+    /// the block size and fuel limits of the blockchain settings.
     pub fn run(&mut self) {
+        let limits = BlockLimits::load(&*self.db.read());
         let mut height = match self.pool.read().confirmed.iter().next_back() {
             Some((height, _)) => *height + 1,
             None => self
@@ -95,14 +142,6 @@ impl<D: Db> Builder<D> {
         loop {
             while count > 0 {
                 let mut pool = self.pool.write();
-                let mut txs_hashes = vec![];
-                for _ in 0..self.threshold {
-                    match pool.unconfirmed.pop() {
-                        Some(hash) => txs_hashes.push(hash),
-                        None => break,
-                    }
-                }
-                count = count.checked_sub(txs_hashes.len()).unwrap_or_default();
 
                 // If the node is the validator,
                 // the block is created,
@@ -117,6 +156,52 @@ impl<D: Db> Builder<D> {
                         .as_secs()
                 };
 
+                // Expired transactions are not included in the block.
+                let expired = pool.remove_expired(height, timestamp);
+                expired.iter().for_each(|hash| {
+                    debug!("Dropped expired transaction: {}", hex::encode(hash));
+                });
+                count = count.checked_sub(expired.len()).unwrap_or_default();
+                if count == 0 {
+                    break;
+                }
+
+                let mut txs_hashes = vec![];
+                let mut bytes = 0usize;
+                let mut fuel = 0u64;
+                while txs_hashes.len() < self.threshold {
+                    let hash = match pool.unconfirmed.peek() {
+                        Some(hash) => *hash,
+                        None => break,
+                    };
+                    let size = pool.unconfirmed.size(&hash).unwrap_or_default();
+                    let fuel_limit = match pool.txs.get(&hash) {
+                        Some(Some(tx)) => tx.get_fuel_limit(),
+                        _ => 0,
+                    };
+                    if !limits.fits(size, fuel_limit) {
+                        // The transaction doesn't fit even in an empty block.
+                        warn!(
+                            "Dropped transaction exceeding the block limits: {}",
+                            hex::encode(hash)
+                        );
+                        pool.remove_unconfirmed(&hash);
+                        count = count.checked_sub(1).unwrap_or_default();
+                        continue;
+                    }
+                    if !limits.fits(bytes.saturating_add(size), fuel.saturating_add(fuel_limit)) {
+                        break;
+                    }
+                    pool.unconfirmed.pop();
+                    txs_hashes.push(hash);
+                    bytes += size;
+                    fuel += fuel_limit;
+                }
+                if txs_hashes.is_empty() {
+                    break;
+                }
+                count = count.checked_sub(txs_hashes.len()).unwrap_or_default();
+
                 let blk_info = BlockInfo {
                     hash: None,
                     validator: None,
@@ -134,3 +219,120 @@ impl<D: Db> Builder<D> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        base::serialize::rmp_serialize, blockchain::ordering::tests::create_tx, crypto::Hash,
+        db::MockDb,
+    };
+
+    fn create_settings(
+        block_max_size: Option<usize>,
+        block_max_fuel: Option<u64>,
+    ) -> BlockchainSettings {
+        BlockchainSettings {
+            accept_broadcast: false,
+            block_threshold: 42,
+            block_timeout: 2,
+            burning_fuel_method: String::new(),
+            network_name: None,
+            is_production: false,
+            min_node_version: String::new(),
+            block_max_size,
+            block_max_fuel,
+            fuel_schedule: None,
+        }
+    }
+
+    fn create_builder(threshold: usize, limits: BlockLimits) -> Builder<MockDb> {
+        let settings = create_settings(Some(limits.max_bytes), Some(limits.max_fuel));
+        let buf = rmp_serialize(&settings).unwrap();
+        let mut db = MockDb::new();
+        db.expect_load_block().returning(|_| None);
+        db.expect_load_configuration()
+            .returning(move |_| Some(buf.clone()));
+        let pool = Arc::new(RwLock::new(Pool::default()));
+        Builder::new(threshold, pool, Arc::new(RwLock::new(db)))
+    }
+
+    fn block_txs(builder: &Builder<MockDb>, height: u64) -> Vec<Hash> {
+        builder.pool.read().confirmed[&height]
+            .txs_hashes
+            .clone()
+            .unwrap()
+    }
+
+    #[test]
+    fn block_limits_from_settings() {
+        let settings = create_settings(Some(1024), None);
+
+        let limits = BlockLimits::from(&settings);
+
+        assert_eq!(limits.max_bytes, 1024);
+        assert_eq!(limits.max_fuel, u64::MAX);
+    }
+
+    #[test]
+    fn block_limits_without_settings() {
+        let mut db = MockDb::new();
+        db.expect_load_configuration().returning(|_| None);
+
+        assert_eq!(BlockLimits::load(&db), BlockLimits::default());
+    }
+
+    #[test]
+    fn run_within_fuel_limit() {
+        let builder = create_builder(
+            10,
+            BlockLimits {
+                max_bytes: usize::MAX,
+                max_fuel: 25,
+            },
+        );
+        let (hash1, tx1) = create_tx(1, 10, 1);
+        let (big_hash, big_tx) = create_tx(2, 100, 2);
+        let (hash2, tx2) = create_tx(3, 10, 3);
+        let (hash3, tx3) = create_tx(4, 10, 4);
+        {
+            let mut pool = builder.pool.write();
+            pool.insert_unconfirmed(hash1, tx1).unwrap();
+            pool.insert_unconfirmed(big_hash, big_tx).unwrap();
+            pool.insert_unconfirmed(hash2, tx2).unwrap();
+            pool.insert_unconfirmed(hash3, tx3).unwrap();
+        }
+
+        builder.clone().run();
+
+        assert_eq!(block_txs(&builder, 0), vec![hash1, hash2]);
+        assert_eq!(block_txs(&builder, 1), vec![hash3]);
+        let pool = builder.pool.read();
+        assert!(pool.unconfirmed.is_empty());
+        assert!(!pool.txs.contains_key(&big_hash));
+    }
+
+    #[test]
+    fn run_within_size_limit() {
+        let (hash1, tx1) = create_tx(1, 10, 1);
+        let (hash2, tx2) = create_tx(2, 10, 2);
+        let size = rmp_serialize(&tx1).unwrap().len();
+        let builder = create_builder(
+            10,
+            BlockLimits {
+                max_bytes: size,
+                max_fuel: u64::MAX,
+            },
+        );
+        {
+            let mut pool = builder.pool.write();
+            pool.insert_unconfirmed(hash1, tx1).unwrap();
+            pool.insert_unconfirmed(hash2, tx2).unwrap();
+        }
+
+        builder.clone().run();
+
+        assert_eq!(block_txs(&builder, 0), vec![hash1]);
+        assert_eq!(block_txs(&builder, 1), vec![hash2]);
+    }
+}
@@ -44,14 +44,19 @@ use crate::{
         pubsub::{Event, PubSub},
         BlockConfig,
     },
-    crypto::{drand::SeedSource, Hash, HashAlgorithm, Hashable},
-    db::Db,
+    crypto::{
+        drand::SeedSource,
+        merkle::{AccountProof, ListProof},
+        Hash, HashAlgorithm, Hashable,
+    },
+    db::{Db, EventFilter},
     wm::Wm,
     Error, ErrorKind, Result, Transaction,
 };
 use std::{
     sync::{Arc, Condvar, Mutex as StdMutex},
     thread,
+    time::SystemTime,
 };
 
 use super::aligner::NodeAligner;
@@ -72,6 +77,9 @@ pub(crate) struct AlignerInterface(
 /// WARNING THIS MUST BE AT MAX EQUAL TO THE p2p MAX_TRANSMIT_SIZE
 pub const MAX_TRANSACTION_SIZE: usize = 524288 * 2;
 
+/// Maximum number of entries returned by a paginated request.
+pub const MAX_PAGE_LIMIT: u64 = 100;
+
 /// Dispatcher context data.
 pub(crate) struct Dispatcher<D: Db, W: Wm> {
     /// Blockchain configuration.
@@ -145,7 +153,7 @@ impl<D: Db, W: Wm> Dispatcher<D, W> {
         self.config.clone().lock().timeout = block_timeout;
     }
 
-    fn put_transaction_internal(&self, tx: Transaction) -> Result<Hash> {
+    pub(crate) fn put_transaction_internal(&self, tx: Transaction) -> Result<Hash> {
         let buf = rmp_serialize(&tx)?;
         if buf.len() >= MAX_TRANSACTION_SIZE {
             return Err(ErrorKind::TooLargeTx.into());
@@ -164,23 +172,86 @@ impl<D: Db, W: Wm> Dispatcher<D, W> {
             return Err(ErrorKind::DuplicatedConfirmedTx.into());
         }
 
+        // Check if already expired for the next block.
+        let expired = match tx.get_expiry() {
+            Some(expiry) => {
+                let height = self
+                    .db
+                    .read()
+                    .load_block(u64::MAX)
+                    .map(|block| block.data.height + 1)
+                    .unwrap_or_default();
+                let timestamp = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs();
+                expiry.is_expired(height, timestamp)
+            }
+            None => false,
+        };
+
+        // Caller next sequence number, for sequenced transactions.
+        let next_sequence = tx.get_sequence().map(|_| {
+            self.db
+                .read()
+                .load_account(&tx.get_caller().to_account_id())
+                .map(|account| account.next_sequence())
+                .unwrap_or_default()
+        });
+
         let mut pool = self.pool.write();
+        let mut replaced = None;
+        let mut evicted = vec![];
         match pool.txs.get_mut(&hash) {
             None => {
-                pool.txs.insert(hash, Some(tx));
-                pool.unconfirmed.push(hash);
+                if expired {
+                    return Err(ErrorKind::ExpiredTx.into());
+                }
+                evicted = match next_sequence {
+                    Some(next) => match pool.sequence_holder(&tx, next) {
+                        Some(old) => {
+                            pool.replace_sequenced(&old, hash, tx)?;
+                            replaced = Some(old);
+                            vec![]
+                        }
+                        None => pool.insert_sequenced(hash, tx, next)?,
+                    },
+                    None => pool.insert_unconfirmed(hash, tx)?,
+                };
             }
             Some(tx_ref @ None) => {
                 *tx_ref = Some(tx);
             }
             Some(Some(_)) => {
-                return if pool.unconfirmed.contains(&hash) {
+                return if pool.unconfirmed.contains(&hash) || pool.future.contains_key(&hash) {
                     Err(ErrorKind::DuplicatedUnconfirmedTx.into())
                 } else {
                     Err(ErrorKind::DuplicatedConfirmedTx.into())
                 };
             }
         }
+        drop(pool);
+
+        if !evicted.is_empty() {
+            self.pubsub.lock().publish_evicted(evicted);
+        }
+        if let Some(old) = replaced {
+            debug!(
+                "Replaced transaction: {} by {}",
+                hex::encode(old),
+                hex::encode(hash)
+            );
+            let mut sub = self.pubsub.lock();
+            if sub.has_subscribers(Event::TRANSACTION_REPLACED) {
+                sub.publish(
+                    Event::TRANSACTION_REPLACED,
+                    Message::TransactionReplaced {
+                        hash: old,
+                        replacement: hash,
+                    },
+                );
+            }
+        }
         Ok(hash)
     }
 
@@ -263,6 +334,15 @@ impl<D: Db, W: Wm> Dispatcher<D, W> {
 
     fn get_block_handler(&self, height: u64, txs: bool) -> Message {
         let opt = self.db.read().load_block(height);
+        self.block_response(opt, txs)
+    }
+
+    fn get_block_by_hash_handler(&self, hash: Hash, txs: bool) -> Message {
+        let opt = self.db.read().load_block_by_hash(&hash);
+        self.block_response(opt, txs)
+    }
+
+    fn block_response(&self, opt: Option<Block>, txs: bool) -> Message {
         match opt {
             Some(block) => {
                 let blk_txs = if txs {
@@ -280,17 +360,29 @@ impl<D: Db, W: Wm> Dispatcher<D, W> {
         }
     }
 
-    fn get_account_handler(&self, id: String, data_names: Vec<String>) -> Message {
-        let opt = self.db.read().load_account(&id);
+    fn get_account_handler(
+        &self,
+        id: String,
+        data_names: Vec<String>,
+        height: Option<u64>,
+    ) -> Message {
+        let db = self.db.read();
+        let opt = match height {
+            Some(height) => db.load_account_at(&id, height),
+            None => db.load_account(&id),
+        };
         match opt {
             Some(acc) => {
                 let mut data = vec![];
                 for name in data_names.iter() {
-                    let val = if name == "*" {
-                        let keys = self.db.read().load_account_keys(&id);
-                        Some(rmp_serialize(&keys).unwrap())
-                    } else {
-                        self.db.read().load_account_data(&id, name)
+                    let val = match height {
+                        Some(_) if name == "*" => None,
+                        Some(height) => db.load_account_data_at(&id, name, height),
+                        None if name == "*" => {
+                            let keys = db.load_account_keys(&id);
+                            Some(rmp_serialize(&keys).unwrap())
+                        }
+                        None => db.load_account_data(&id, name),
                     };
                     data.push(val);
                 }
@@ -300,6 +392,85 @@ impl<D: Db, W: Wm> Dispatcher<D, W> {
         }
     }
 
+    fn get_account_transactions_handler(&self, id: String, from: u64, limit: u64) -> Message {
+        let limit = limit.min(MAX_PAGE_LIMIT);
+        let txs = self.db.read().load_account_transactions(&id, from, limit);
+        Message::GetAccountTransactionsResponse { txs }
+    }
+
+    fn get_events_handler(&self, filter: EventFilter, from: u64, limit: u64) -> Message {
+        let limit = limit.min(MAX_PAGE_LIMIT);
+        let events = self.db.read().load_events(&filter, from, limit);
+        Message::GetEventsResponse { events }
+    }
+
+    fn get_account_keys_handler(
+        &self,
+        id: String,
+        prefix: String,
+        after: Option<String>,
+        limit: u64,
+    ) -> Message {
+        let limit = limit.min(MAX_PAGE_LIMIT);
+        let keys = self
+            .db
+            .read()
+            .load_account_keys_range(&id, &prefix, after.as_deref(), limit);
+        Message::GetAccountKeysResponse { keys }
+    }
+
+    fn get_account_proof_handler(&self, id: String, data: Option<String>) -> Message {
+        let db = self.db.read();
+        let height = match db.load_block(u64::MAX) {
+            Some(block) => block.data.height,
+            None => return Message::Exception(Error::new(ErrorKind::ResourceNotFound)),
+        };
+        let data = match data {
+            Some(key) => match db.load_account_data_proof(&id, &key) {
+                Some(proof) => Some(proof),
+                None => return Message::Exception(Error::new(ErrorKind::ResourceNotFound)),
+            },
+            None => None,
+        };
+        match db.load_account_proof(&id) {
+            Some(account) => Message::GetAccountProofResponse {
+                proof: AccountProof {
+                    height,
+                    account,
+                    data,
+                },
+            },
+            None => Message::Exception(Error::new(ErrorKind::ResourceNotFound)),
+        }
+    }
+
+    /// Returns the block height and the proof of the transaction, or of its
+    /// receipt, within the block hashes lists.
+    fn block_entry_proof(&self, hash: Hash, receipt: bool) -> Option<(u64, ListProof)> {
+        let db = self.db.read();
+        let rx = db.load_receipt(&hash)?;
+        let hashes = match receipt {
+            true => db.load_receipts_hashes(rx.height)?,
+            false => db.load_transactions_hashes(rx.height)?,
+        };
+        let proof = ListProof::new(&hashes, rx.index as u64)?;
+        Some((rx.height, proof))
+    }
+
+    fn get_transaction_proof_handler(&self, hash: Hash) -> Message {
+        match self.block_entry_proof(hash, false) {
+            Some((height, proof)) => Message::GetTransactionProofResponse { height, proof },
+            None => Message::Exception(ErrorKind::ResourceNotFound.into()),
+        }
+    }
+
+    fn get_receipt_proof_handler(&self, hash: Hash) -> Message {
+        match self.block_entry_proof(hash, true) {
+            Some((height, proof)) => Message::GetReceiptProofResponse { height, proof },
+            None => Message::Exception(ErrorKind::ResourceNotFound.into()),
+        }
+    }
+
     #[allow(clippy::mutex_atomic)]
     fn get_transaction_res_handler(&self, transaction: Transaction, origin: Option<String>) {
         let res = self.put_transaction_internal(transaction.clone());
@@ -467,6 +638,11 @@ impl<D: Db, W: Wm> Dispatcher<D, W> {
         Message::GetCoreStatsResponse((hash_pool, len_pool, last_block))
     }
 
+    fn get_pool_stats_handler(&self) -> Message {
+        let stats = self.pool.read().stats();
+        Message::GetPoolStatsResponse { stats }
+    }
+
     fn get_network_id_handler(&self) -> Message {
         let buf = self
             .db
@@ -528,6 +704,51 @@ impl<D: Db, W: Wm> Dispatcher<D, W> {
         None
     }
 
+    #[allow(unused_variables)]
+    #[allow(clippy::too_many_arguments)]
+    fn simulate_read_only_transaction_handler(
+        &self,
+        target: String,
+        method: String,
+        args: Vec<u8>,
+        origin: String,
+        contract: Option<Hash>,
+        max_fuel: u64,
+        network: String,
+    ) -> Option<Message> {
+        #[cfg(feature = "ro-exec")]
+        {
+            let mut executor =
+                read_only_executor::Executor::new(self.db.clone(), self.seed.clone());
+
+            let fork = self.db.write().fork_create();
+            let (rx, diff) = executor.simulate(
+                fork, max_fuel, origin, target, contract, method, args, network,
+            );
+            Some(Message::SimulateTransactionResponse { rx, diff })
+        }
+
+        #[cfg(not(feature = "ro-exec"))]
+        None
+    }
+
+    #[allow(unused_variables)]
+    fn estimate_fuel_handler(&self, tx: Transaction) -> Option<Message> {
+        #[cfg(feature = "ro-exec")]
+        {
+            let mut executor =
+                read_only_executor::Executor::new(self.db.clone(), self.seed.clone());
+
+            let mut fork = self.db.write().fork_create();
+            Some(Message::EstimateFuelResponse {
+                estimate: executor.estimate(&mut fork, &tx),
+            })
+        }
+
+        #[cfg(not(feature = "ro-exec"))]
+        None
+    }
+
     fn packed_message_handler(
         &mut self,
         buf: Vec<u8>,
@@ -607,14 +828,55 @@ impl<D: Db, W: Wm> Dispatcher<D, W> {
                 let res = self.get_block_handler(height, txs);
                 Some(res)
             }
-            Message::GetAccountRequest { id, data } => {
-                let res = self.get_account_handler(id, data);
+            Message::GetBlockByHashRequest { hash, txs } => {
+                let res = self.get_block_by_hash_handler(hash, txs);
+                Some(res)
+            }
+            Message::GetAccountTransactionsRequest { id, from, limit } => {
+                let res = self.get_account_transactions_handler(id, from, limit);
+                Some(res)
+            }
+            Message::GetEventsRequest {
+                filter,
+                from,
+                limit,
+            } => {
+                let res = self.get_events_handler(filter, from, limit);
+                Some(res)
+            }
+            Message::GetAccountKeysRequest {
+                id,
+                prefix,
+                after,
+                limit,
+            } => {
+                let res = self.get_account_keys_handler(id, prefix, after, limit);
+                Some(res)
+            }
+            Message::GetAccountRequest { id, data, height } => {
+                let res = self.get_account_handler(id, data, height);
+                Some(res)
+            }
+            Message::GetAccountProofRequest { id, data } => {
+                let res = self.get_account_proof_handler(id, data);
+                Some(res)
+            }
+            Message::GetTransactionProofRequest { hash } => {
+                let res = self.get_transaction_proof_handler(hash);
+                Some(res)
+            }
+            Message::GetReceiptProofRequest { hash } => {
+                let res = self.get_receipt_proof_handler(hash);
                 Some(res)
             }
             Message::GetCoreStatsRequest => {
                 let res = self.get_stats_handler();
                 Some(res)
             }
+            Message::GetPoolStatsRequest => {
+                let res = self.get_pool_stats_handler();
+                Some(res)
+            }
             Message::GetNetworkIdRequest => {
                 let res = self.get_network_id_handler();
                 Some(res)
@@ -662,6 +924,18 @@ impl<D: Db, W: Wm> Dispatcher<D, W> {
             } => self.exec_read_only_transaction_handler(
                 target, method, args, origin, contract, max_fuel, network,
             ),
+            Message::EstimateFuelRequest { tx } => self.estimate_fuel_handler(tx),
+            Message::SimulateReadOnlyTransaction {
+                target,
+                method,
+                args,
+                origin,
+                contract,
+                max_fuel,
+                network,
+            } => self.simulate_read_only_transaction_handler(
+                target, method, args, origin, contract, max_fuel, network,
+            ),
             Message::Packed { buf } => self.packed_message_handler(buf, res_chan, pack_level + 1),
             _ => None,
         }
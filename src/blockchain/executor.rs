@@ -28,6 +28,7 @@
 use serde_value::value;
 
 use super::{
+    builder::BlockLimits,
     message::Message,
     pool::{BlockInfo, Pool},
     pubsub::{Event, PubSub},
@@ -39,19 +40,26 @@ use crate::blockchain::indexer::{Indexer, StoreAssetDb};
 use crate::{
     base::{
         schema::{
-            Block, BlockData, BulkTransaction, SignedTransaction, SmartContractEvent,
-            TransactionData, UnsignedTransaction, FUEL_LIMIT,
+            Block, BlockData, BlockchainSettings, BulkTransaction, FuelSchedule, SignedTransaction,
+            SmartContractEvent, TransactionData, UnsignedTransaction, FUEL_LIMIT,
         },
         serialize::{rmp_deserialize, rmp_serialize},
         Mutex, RwLock,
     },
     crypto::{drand::SeedSource, Hash, Hashable},
-    db::{Db, DbFork},
+    db::{
+        transaction_accounts, AccountTransaction, Db, DbFork, EventRecord, StateAccess,
+        TrackingFork,
+    },
     wm::{get_fuel_consumed_for_error, CtxArgs, Wm, MAX_FUEL},
-    Error, ErrorKind, KeyPair, PublicKey, Receipt, Result, Transaction, SERVICE_ACCOUNT_ID,
+    Account, Error, ErrorKind, KeyPair, PublicKey, Receipt, Result, Transaction,
+    SERVICE_ACCOUNT_ID,
 };
 
-use std::sync::Arc;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
 
 #[cfg(feature = "rt-monitor")]
 use crate::network_monitor::{
@@ -59,9 +67,6 @@ use crate::network_monitor::{
     types::{Action, Event as MonitorEvent},
 };
 
-#[cfg(feature = "rt-monitor")]
-use crate::base::schema::BlockchainSettings;
-
 /// Result struct for bulk transaction
 #[derive(Serialize, Deserialize)]
 pub struct BulkResult {
@@ -85,6 +90,17 @@ struct ConsumeFuelReturns {
     units: u64,
 }
 
+/// Outcome of a speculative transaction execution
+struct Speculation {
+    receipt: Receipt,
+    /// State read and written by the execution
+    access: StateAccess,
+    /// Drand previous seed before and after the execution, if used
+    seed: Option<(u64, u64)>,
+    /// Contract events to emit once committed
+    events: Vec<SmartContractEvent>,
+}
+
 struct BurnFuelArgs {
     account: String,
     fuel_to_burn: u64,
@@ -111,6 +127,16 @@ pub(crate) struct Executor<D: Db, W: Wm> {
     p2p_id: String,
     /// Validator flag
     is_validator: Arc<bool>,
+    /// Per-account transactions index flag
+    account_txs_index: bool,
+    /// Smart contract events index flag
+    events_index: bool,
+    /// Fuel pricing schedule, loaded from the blockchain settings
+    fuel_schedule: Option<FuelSchedule>,
+    /// Wasm machines of the parallel execution workers
+    parallel_wms: Vec<Arc<Mutex<W>>>,
+    /// Contract events held back during a speculative execution
+    held_events: Option<Vec<SmartContractEvent>>,
     #[cfg(feature = "indexer")]
     /// Indexer structure
     indexer: Indexer,
@@ -128,6 +154,11 @@ impl<D: Db, W: Wm> Clone for Executor<D, W> {
             seed: self.seed.clone(),
             p2p_id: self.p2p_id.clone(),
             is_validator: self.is_validator.clone(),
+            account_txs_index: self.account_txs_index,
+            events_index: self.events_index,
+            fuel_schedule: self.fuel_schedule,
+            parallel_wms: self.parallel_wms.clone(),
+            held_events: self.held_events.clone(),
             #[cfg(feature = "indexer")]
             indexer: self.indexer.clone(),
         }
@@ -207,6 +238,11 @@ impl<D: Db, W: Wm> Executor<D, W> {
             seed,
             p2p_id,
             is_validator: Arc::new(false),
+            account_txs_index: false,
+            events_index: false,
+            fuel_schedule: None,
+            parallel_wms: vec![],
+            held_events: None,
             #[cfg(feature = "indexer")]
             indexer,
         }
@@ -217,28 +253,110 @@ impl<D: Db, W: Wm> Executor<D, W> {
         self.burn_fuel_method = burn_fuel_method;
     }
 
-    // Calculates the fuel consumed by the transaction execution
+    // Allows to enable the per-account transactions index
+    pub fn set_account_txs_index(&mut self, enabled: bool) {
+        self.account_txs_index = enabled;
+    }
+
+    // Allows to enable the smart contract events index
+    pub fn set_events_index(&mut self, enabled: bool) {
+        self.events_index = enabled;
+    }
+
+    // Allows to enable the optimistic parallel execution of the block
+    // transactions, with a speculative worker for each wasm machine
+    pub fn set_parallel_execution(&mut self, wms: Vec<W>) {
+        self.parallel_wms = wms.into_iter().map(|wm| Arc::new(Mutex::new(wm))).collect();
+    }
+
+    // Checks that the block transactions are within the block limits stored
+    // in the blockchain settings.
+    // Fails if a transaction payload is missing, the block can't be checked.
+    fn check_block_limits(&self, txs_hashes: &[Hash]) -> Result<bool> {
+        let limits = BlockLimits::load(&*self.db.read());
+        let pool = self.pool.read();
+        let mut bytes = 0usize;
+        let mut fuel = 0u64;
+        for hash in txs_hashes {
+            let tx = match pool.txs.get(hash) {
+                Some(Some(tx)) => tx,
+                _ => {
+                    return Err(Error::new_ext(
+                        ErrorKind::ResourceNotFound,
+                        "missing block transaction payload",
+                    ))
+                }
+            };
+            bytes = bytes.saturating_add(rmp_serialize(tx)?.len());
+            fuel = fuel.saturating_add(tx.get_fuel_limit());
+        }
+        Ok(limits.fits(bytes, fuel))
+    }
+
+    // Loads the fuel pricing schedule from the blockchain settings
+    fn load_fuel_schedule(&self) -> Option<FuelSchedule> {
+        let buf = self.db.read().load_configuration("blockchain:settings")?;
+        rmp_deserialize::<BlockchainSettings>(&buf)
+            .ok()?
+            .fuel_schedule
+    }
+
+    // Calculates the fuel consumed by a smart contract execution
     fn calculate_burned_fuel(&self, wm_fuel: u64) -> u64 {
-        // TODO find a f(_wm_fuel) to calculate the fuel in TRINCI
-        warn!("calculate_burned_fuel::{}", wm_fuel);
-        // wm_fuel
-        if wm_fuel == 0 {
-            0
-        } else {
-            FUEL_LIMIT
+        match self.fuel_schedule {
+            Some(schedule) => schedule.wm_fuel_cost(wm_fuel),
+            // Legacy flat fee.
+            None if wm_fuel == 0 => 0,
+            None => FUEL_LIMIT,
+        }
+    }
+
+    // Wasm machine fuel charged for a smart contract execution.
+    // Without a schedule the wasm machine faults, e.g. traps, are not charged.
+    fn charged_wm_fuel<T>(&self, wm_fuel: u64, result: &Result<T>) -> u64 {
+        match result {
+            Err(err) if self.fuel_schedule.is_none() && err.kind == ErrorKind::WasmMachineFault => {
+                0
+            }
+            _ => wm_fuel,
+        }
+    }
+
+    // Fuel charged for each transaction, regardless of the execution
+    fn base_fuel(&self) -> u64 {
+        self.fuel_schedule
+            .map(|schedule| schedule.base_fuel)
+            .unwrap_or_default()
+    }
+
+    // Fuel charged for a transaction failing before the smart contract execution
+    fn error_fuel(&self) -> u64 {
+        match self.fuel_schedule {
+            Some(schedule) => schedule.base_fuel,
+            None => get_fuel_consumed_for_error(),
         }
     }
 
     // Calculated the max fuel allow to spend
     // from the tx fuel_limit field
-    fn calculate_internal_fuel_limit(&self, _fuel_limit: u64) -> u64 {
-        // TODO create a method the get the fuel_limit
-        MAX_FUEL
+    fn calculate_internal_fuel_limit(&self, fuel_limit: u64) -> u64 {
+        match self.fuel_schedule {
+            Some(schedule) => schedule.wm_fuel_limit(fuel_limit).min(MAX_FUEL),
+            None => MAX_FUEL,
+        }
+    }
+
+    // Calculates the max fuel allowed to spend by a bulk transaction call
+    // from its fuel_limit field and the fuel left to the bulk transaction
+    fn calculate_bulk_fuel_limit(&self, fuel_limit: u64, remaining_fuel: u64) -> u64 {
+        self.calculate_internal_fuel_limit(
+            fuel_limit.min(remaining_fuel.saturating_add(self.base_fuel())),
+        )
     }
 
     fn call_burn_fuel(
         &self,
-        fork: &mut <D as Db>::DbForkType,
+        fork: &mut dyn DbFork,
         burn_fuel_method: &str,
         origin: &str,
         fuel: u64,
@@ -297,7 +415,7 @@ impl<D: Db, W: Wm> Executor<D, W> {
     // Tries to burn fuel from the origin account
     fn try_burn_fuel(
         &self,
-        fork: &mut <D as Db>::DbForkType,
+        fork: &mut dyn DbFork,
         burn_fuel_method: &str,
         burn_fuel_args: BurnFuelArgs,
         block_timestamp: u64,
@@ -346,7 +464,7 @@ impl<D: Db, W: Wm> Executor<D, W> {
     fn handle_unit_transaction(
         &mut self,
         tx: &SignedTransaction,
-        fork: &mut <D as Db>::DbForkType,
+        fork: &mut dyn DbFork,
         height: u64,
         index: u32,
         mut events: Vec<SmartContractEvent>,
@@ -389,6 +507,7 @@ impl<D: Db, W: Wm> Executor<D, W> {
                     initial_fuel,
                     block_timestamp,
                 );
+                let fuel_consumed = self.charged_wm_fuel(fuel_consumed, &result);
 
                 let event_tx = tx.data.primary_hash();
                 events.iter_mut().for_each(|e| e.event_tx = event_tx);
@@ -426,7 +545,9 @@ impl<D: Db, W: Wm> Executor<D, W> {
                 log_wm_fuel_consumed_st(tx, fuel_consumed);
 
                 // Total fuel burned
-                let burned_fuel = self.calculate_burned_fuel(fuel_consumed);
+                let burned_fuel = self
+                    .base_fuel()
+                    .saturating_add(self.calculate_burned_fuel(fuel_consumed));
 
                 HandleTransactionReturns {
                     burn_fuel_args: BurnFuelArgs {
@@ -449,12 +570,12 @@ impl<D: Db, W: Wm> Executor<D, W> {
             Err(e) => HandleTransactionReturns {
                 burn_fuel_args: BurnFuelArgs {
                     account: tx.data.get_caller().to_account_id(),
-                    fuel_to_burn: get_fuel_consumed_for_error(), // FIXME * How much should the caller pay for this operation?
+                    fuel_to_burn: self.error_fuel(),
                     fuel_limit: tx.data.get_fuel_limit(),
                 },
                 receipt: Receipt {
                     height,
-                    burned_fuel: get_fuel_consumed_for_error(), // FIXME * How much should the caller pay for this operation?
+                    burned_fuel: self.error_fuel(),
                     index,
                     success: false,
                     returns: e.to_string_full().as_bytes().to_vec(),
@@ -470,7 +591,7 @@ impl<D: Db, W: Wm> Executor<D, W> {
     fn handle_bulk_transaction(
         &mut self,
         tx: &BulkTransaction,
-        fork: &mut <D as Db>::DbForkType,
+        fork: &mut dyn DbFork,
         height: u64,
         index: u32,
         mut input_events: Vec<SmartContractEvent>,
@@ -479,6 +600,8 @@ impl<D: Db, W: Wm> Executor<D, W> {
         let mut results = Vec::<(String, BulkResult)>::new();
         let mut execution_fail = false;
         let mut burned_fuel = 0;
+        // Fuel left to the calls, the base fuel is charged once.
+        let mut remaining_fuel = tx.data.get_fuel_limit().saturating_sub(self.base_fuel());
 
         #[cfg(feature = "indexer")]
         let bulk_hash_tx = tx.data.primary_hash();
@@ -500,7 +623,7 @@ impl<D: Db, W: Wm> Executor<D, W> {
                 let mut bulk_store_asset_db: Vec<StoreAssetDb> = vec![];
 
                 let initial_fuel =
-                    self.calculate_internal_fuel_limit(root_tx.data.get_fuel_limit());
+                    self.calculate_bulk_fuel_limit(root_tx.data.get_fuel_limit(), remaining_fuel);
 
                 let ctx_args = CtxArgs {
                     origin: &root_tx.data.get_caller().to_account_id(),
@@ -521,7 +644,7 @@ impl<D: Db, W: Wm> Executor<D, W> {
                             Err(e) => {
                                 let root_fuel = BurnFuelArgs {
                                     account: tx_data.caller.to_account_id(),
-                                    fuel_to_burn: get_fuel_consumed_for_error(), // FIXME * How much should the caller pay for this operation?
+                                    fuel_to_burn: self.error_fuel(),
                                     fuel_limit: tx_data.fuel_limit,
                                 };
 
@@ -530,7 +653,7 @@ impl<D: Db, W: Wm> Executor<D, W> {
                                     receipt: Receipt {
                                         height,
                                         index,
-                                        burned_fuel: get_fuel_consumed_for_error(), // FIXME * How much should the caller pay for this operation?
+                                        burned_fuel: self.error_fuel(),
                                         success: false,
                                         returns: e.to_string_full().as_bytes().to_vec(),
                                         events: None,
@@ -563,7 +686,7 @@ impl<D: Db, W: Wm> Executor<D, W> {
                     _ => {
                         let root_fuel = BurnFuelArgs {
                             account: root_tx.data.get_caller().to_account_id(),
-                            fuel_to_burn: get_fuel_consumed_for_error(), // FIXME * How much should the caller pay for this operation?
+                            fuel_to_burn: self.error_fuel(),
                             fuel_limit: root_tx.data.get_fuel_limit(),
                         };
 
@@ -572,7 +695,7 @@ impl<D: Db, W: Wm> Executor<D, W> {
                             receipt: Receipt {
                                 height,
                                 index,
-                                burned_fuel: get_fuel_consumed_for_error(), // FIXME * How much should the caller pay for this operation?
+                                burned_fuel: self.error_fuel(),
                                 success: false,
                                 returns: "wrong transaction schema".as_bytes().to_vec(),
                                 events: None,
@@ -583,12 +706,15 @@ impl<D: Db, W: Wm> Executor<D, W> {
                     }
                 };
 
+                let fuel_consumed = self.charged_wm_fuel(fuel_consumed, &result);
+
                 // FIXME * LOG REAL CONSUMPTION
                 log_wm_fuel_consumed_bt(root_tx, fuel_consumed);
 
                 // Convert wm fuel in TRINCI
                 let fuel_consumed = self.calculate_burned_fuel(fuel_consumed);
                 burn_fuel_args.fuel_to_burn += fuel_consumed;
+                remaining_fuel = remaining_fuel.saturating_sub(fuel_consumed);
 
                 burned_fuel += fuel_consumed;
 
@@ -633,8 +759,10 @@ impl<D: Db, W: Wm> Executor<D, W> {
                         for node in nodes {
                             let mut bulk_events: Vec<SmartContractEvent> = vec![];
 
-                            let initial_fuel =
-                                self.calculate_internal_fuel_limit(node.data.get_fuel_limit());
+                            let initial_fuel = self.calculate_bulk_fuel_limit(
+                                node.data.get_fuel_limit(),
+                                remaining_fuel,
+                            );
                             let ctx_args = CtxArgs {
                                 origin: &node.data.get_caller().to_account_id(),
                                 owner: node.data.get_account(),
@@ -668,6 +796,8 @@ impl<D: Db, W: Wm> Executor<D, W> {
                                         initial_fuel,
                                         block_timestamp,
                                     );
+                                    let fuel_consumed =
+                                        self.charged_wm_fuel(fuel_consumed, &result);
 
                                     // FIXME * LOG REAL CONSUMPTION
                                     log_wm_fuel_consumed_st(node, fuel_consumed);
@@ -675,6 +805,7 @@ impl<D: Db, W: Wm> Executor<D, W> {
                                     // Convert wm fuel in TRINCI
                                     let fuel_consumed = self.calculate_burned_fuel(fuel_consumed);
                                     burn_fuel_args.fuel_to_burn += fuel_consumed;
+                                    remaining_fuel = remaining_fuel.saturating_sub(fuel_consumed);
 
                                     burned_fuel += fuel_consumed;
 
@@ -727,7 +858,7 @@ impl<D: Db, W: Wm> Executor<D, W> {
                                         BulkResult {
                                             success: false,
                                             result: e.to_string_full().as_bytes().to_vec(),
-                                            fuel_consumed: get_fuel_consumed_for_error(), // FIXME * How much should the caller pay for this operation?
+                                            fuel_consumed: self.error_fuel(),
                                         },
                                     ));
                                     execution_fail = true;
@@ -756,7 +887,16 @@ impl<D: Db, W: Wm> Executor<D, W> {
                 (None, rmp_serialize(&results))
             } // Receipt should be empty?
         };
-        let burned_fuel = self.calculate_burned_fuel(burned_fuel);
+        let burned_fuel = match self.fuel_schedule {
+            // The calls fuel is already converted, the base fuel is charged once.
+            Some(schedule) => {
+                burn_fuel_args.fuel_to_burn = burn_fuel_args
+                    .fuel_to_burn
+                    .saturating_add(schedule.base_fuel);
+                schedule.base_fuel.saturating_add(burned_fuel)
+            }
+            None => self.calculate_burned_fuel(burned_fuel),
+        };
 
         HandleTransactionReturns {
             burn_fuel_args,
@@ -774,6 +914,11 @@ impl<D: Db, W: Wm> Executor<D, W> {
     }
 
     fn emit_events(&mut self, events: &[SmartContractEvent]) {
+        // Speculative execution events are emitted once committed.
+        if let Some(held_events) = self.held_events.as_mut() {
+            held_events.extend_from_slice(events);
+            return;
+        }
         if self.pubsub.lock().has_subscribers(Event::CONTRACT_EVENTS) {
             events.iter().for_each(|event| {
                 // Notify subscribers about contract events
@@ -789,7 +934,7 @@ impl<D: Db, W: Wm> Executor<D, W> {
     fn exec_transaction(
         &mut self,
         tx: &Transaction,
-        fork: &mut <D as Db>::DbForkType,
+        fork: &mut dyn DbFork,
         height: u64,
         index: u32,
         burn_fuel_method: &str,
@@ -797,6 +942,61 @@ impl<D: Db, W: Wm> Executor<D, W> {
     ) -> Receipt {
         fork.flush();
 
+        // A sequenced transaction is refused, without consuming the sequence
+        // number, if it is not the next one of the caller.
+        let caller = tx.get_caller().to_account_id();
+        let sequence = tx.get_sequence();
+        if let Some(sequence) = sequence {
+            let next = fork
+                .load_account(&caller)
+                .map(|account| account.next_sequence())
+                .unwrap_or_default();
+            if next != sequence {
+                return Receipt {
+                    height,
+                    index,
+                    burned_fuel: 0,
+                    success: false,
+                    returns: ErrorKind::InvalidSequence.to_string().as_bytes().to_vec(),
+                    events: None,
+                };
+            }
+        }
+
+        // An expired transaction is refused without touching the state.
+        let receipt = if tx.is_expired(height, block_timestamp) {
+            Receipt {
+                height,
+                index,
+                burned_fuel: 0,
+                success: false,
+                returns: ErrorKind::ExpiredTx.to_string().as_bytes().to_vec(),
+                events: None,
+            }
+        } else {
+            self.run_transaction(tx, fork, height, index, burn_fuel_method, block_timestamp)
+        };
+
+        // The sequence number is consumed even if the execution fails.
+        if let Some(sequence) = sequence {
+            let mut account = fork
+                .load_account(&caller)
+                .unwrap_or_else(|| Account::new(&caller, None));
+            account.sequence = Some(sequence + 1);
+            fork.store_account(account);
+        }
+        receipt
+    }
+
+    fn run_transaction(
+        &mut self,
+        tx: &Transaction,
+        fork: &mut dyn DbFork,
+        height: u64,
+        index: u32,
+        burn_fuel_method: &str,
+        block_timestamp: u64,
+    ) -> Receipt {
         let events: Vec<SmartContractEvent> = vec![];
 
         let res = match tx {
@@ -855,8 +1055,126 @@ impl<D: Db, W: Wm> Executor<D, W> {
         }
     }
 
-    /// Returns a vector of executed transactions
-    fn exec_transactions(
+    // Executes a transaction on a separate database fork, tracking the
+    // accessed state.
+    fn speculate_transaction(
+        &mut self,
+        tx: &Transaction,
+        height: u64,
+        index: u32,
+        block_timestamp: u64,
+    ) -> Speculation {
+        let seed = Arc::new(self.seed.snapshot());
+        let initial_seed = *seed.previous_seed.lock();
+        let block_seed = std::mem::replace(&mut self.seed, seed.clone());
+        self.held_events = Some(vec![]);
+
+        let mut fork = TrackingFork::new(self.db.write().fork_create());
+        let burn_fuel_method = self.burn_fuel_method.clone();
+        let receipt = self.exec_transaction(
+            tx,
+            &mut fork,
+            height,
+            index,
+            &burn_fuel_method,
+            block_timestamp,
+        );
+        self.seed = block_seed;
+
+        let used = *seed.used.lock();
+        Speculation {
+            receipt,
+            access: fork.into_access(),
+            seed: used.then(|| (initial_seed, *seed.previous_seed.lock())),
+            events: self.held_events.take().unwrap_or_default(),
+        }
+    }
+
+    // Executes the transactions speculatively, sharing them among the
+    // parallel execution workers.
+    fn speculate_transactions(
+        &self,
+        txs: &[Transaction],
+        height: u64,
+        block_timestamp: u64,
+    ) -> Vec<Option<Speculation>> {
+        // The indexer data are collected during the execution.
+        if self.parallel_wms.is_empty() || cfg!(feature = "indexer") {
+            return vec![];
+        }
+
+        let next = AtomicUsize::new(0);
+        let mut speculations: Vec<Option<Speculation>> = txs.iter().map(|_| None).collect();
+        std::thread::scope(|scope| {
+            let workers: Vec<_> = self
+                .parallel_wms
+                .iter()
+                .map(|wm| {
+                    let mut executor = self.clone();
+                    executor.wm = wm.clone();
+                    let next = &next;
+                    scope.spawn(move || {
+                        let mut results = vec![];
+                        loop {
+                            let index = next.fetch_add(1, Ordering::Relaxed);
+                            let tx = match txs.get(index) {
+                                Some(tx) => tx,
+                                None => break results,
+                            };
+                            let speculation = executor.speculate_transaction(
+                                tx,
+                                height,
+                                index as u32,
+                                block_timestamp,
+                            );
+                            results.push((index, speculation));
+                        }
+                    })
+                })
+                .collect();
+
+            for worker in workers {
+                match worker.join() {
+                    Ok(results) => results.into_iter().for_each(|(index, speculation)| {
+                        speculations[index] = Some(speculation);
+                    }),
+                    // The transactions left behind are executed sequentially.
+                    Err(_) => warn!("Parallel execution worker failure"),
+                }
+            }
+        });
+        speculations
+    }
+
+    // Checks that a speculative execution observed the same state of the
+    // block fork.
+    fn is_speculation_valid(&self, speculation: &Speculation, fork: &dyn DbFork) -> bool {
+        let seed_valid = match speculation.seed {
+            Some((seed, _)) => *self.seed.previous_seed.lock() == seed,
+            None => true,
+        };
+        seed_valid && speculation.access.is_valid(fork)
+    }
+
+    // Commits a speculative execution to the block fork.
+    fn commit_speculation(&mut self, speculation: Speculation, fork: &mut dyn DbFork) -> Receipt {
+        fork.flush();
+        speculation.access.apply(fork);
+        if let Some((_, seed)) = speculation.seed {
+            *self.seed.previous_seed.lock() = seed;
+        }
+        self.emit_events(&speculation.events);
+        speculation.receipt
+    }
+
+    /// Returns a vector of executed transactions.
+    /// With the parallel execution enabled the transactions are executed
+    /// speculatively, each one on a separate fork, then committed in block
+    /// order. A speculative execution is committed only if the state it read
+    /// has not been changed by the previous transactions, otherwise the
+    /// transaction is executed again on the block fork. Either way the outcome
+    /// is the one of the sequential execution.
+    pub(crate) fn exec_transactions(
         &mut self,
         fork: &mut <D as Db>::DbForkType,
         height: u64,
@@ -865,29 +1183,66 @@ impl<D: Db, W: Wm> Executor<D, W> {
     ) -> Vec<Hash> {
         let mut rxs_hashes = vec![];
 
-        for (index, hash) in txs_hashes.iter().enumerate() {
-            debug!("Executing transaction: {}", hex::encode(hash));
+        // The fuel schedule in force is the one of the previous block state.
+        self.fuel_schedule = self.load_fuel_schedule();
 
-            // Execute the transaction.
-            let tx = match self.pool.read().txs.get(hash) {
+        let txs: Vec<Transaction> = txs_hashes
+            .iter()
+            .map(|hash| match self.pool.read().txs.get(hash) {
                 Some(Some(tx)) => tx.to_owned(),
                 _ => panic!(
                     "Unexpected missing transaction during execution: {}",
                     hex::encode(hash)
                 ),
-            };
+            })
+            .collect();
 
-            let rx = self.exec_transaction(
-                &tx,
-                fork,
-                height,
-                index as u32,
-                &self.burn_fuel_method.clone(),
-                block_timestamp,
-            );
+        let mut speculations = self.speculate_transactions(&txs, height, block_timestamp);
+
+        for (index, (hash, tx)) in txs_hashes.iter().zip(txs).enumerate() {
+            debug!("Executing transaction: {}", hex::encode(hash));
+
+            // Commit the speculative execution or execute the transaction.
+            let speculation = speculations
+                .get_mut(index)
+                .and_then(Option::take)
+                .filter(|speculation| self.is_speculation_valid(speculation, &*fork));
+            let rx = match speculation {
+                Some(speculation) => self.commit_speculation(speculation, fork),
+                None => self.exec_transaction(
+                    &tx,
+                    fork,
+                    height,
+                    index as u32,
+                    &self.burn_fuel_method.clone(),
+                    block_timestamp,
+                ),
+            };
 
             rxs_hashes.push(rx.primary_hash());
 
+            if self.account_txs_index {
+                for id in transaction_accounts(&tx) {
+                    let entry = AccountTransaction {
+                        height,
+                        index: index as u32,
+                        hash: *hash,
+                    };
+                    fork.store_account_transaction(&id, entry);
+                }
+            }
+
+            if self.events_index {
+                for event in rx.events.iter().flatten() {
+                    let record = EventRecord {
+                        height,
+                        index: index as u32,
+                        event: event.clone(),
+                    };
+                    fork.store_event(record);
+                }
+            }
+
             fork.store_transaction(hash, tx);
             fork.store_receipt(hash, rx);
         }
@@ -908,6 +1263,7 @@ impl<D: Db, W: Wm> Executor<D, W> {
         is_validator_closure: Arc<dyn IsValidator>,
     ) -> Result<Hash> {
         debug!("Executing block: {}", height);
+
         // Write on a fork.
 
         #[cfg(feature = "indexer")]
@@ -1079,6 +1435,41 @@ impl<D: Db, W: Wm> Executor<D, W> {
         }
     }
 
+    // Update the seed infos with the last block in the database.
+    fn reset_seed(&self) {
+        let (prev_hash, txs_hash, rxs_hash) = match self.db.read().load_block(u64::MAX) {
+            Some(block) => (
+                block.data.primary_hash(),
+                block.data.txs_hash,
+                block.data.rxs_hash,
+            ),
+            None => (Hash::default(), Hash::default(), Hash::default()),
+        };
+
+        let mut seed_prev_hash = self.seed.prev_hash.lock();
+        let mut seed_rxs_hash = self.seed.rxs_hash.lock();
+        let mut seed_txs_hash = self.seed.txs_hash.lock();
+        let mut seed_prev_seed = self.seed.previous_seed.lock();
+
+        *seed_prev_hash = prev_hash;
+        *seed_txs_hash = txs_hash;
+        *seed_rxs_hash = rxs_hash;
+        *seed_prev_seed = 0;
+    }
+
+    /// Revert the blocks following `height`, see `Db::revert_to`.
+    /// The confirmed blocks following `height` are discarded and the seed
+    /// infos are reset to the block at `height`.
+    ///
+    /// Returns the transactions to put back in the pool: the reverted ones,
+    /// in execution order, followed by the ones of the discarded blocks.
+    pub fn revert_to(&mut self, height: u64) -> Result<Vec<Transaction>> {
+        let mut txs = self.db.write().revert_to(height)?;
+        txs.append(&mut self.pool.write().remove_confirmed_after(height));
+        self.reset_seed();
+        Ok(txs)
+    }
+
     pub fn run(&mut self, is_validator: bool, is_validator_closure: Arc<dyn IsValidator>) {
         let (mut prev_hash, mut height) = match self.db.read().load_block(u64::MAX) {
             Some(block) => (block.data.primary_hash(), block.data.height + 1),
@@ -1106,50 +1497,62 @@ impl<D: Db, W: Wm> Executor<D, W> {
                     _ => break,
                 };
 
-            match self.exec_block(
-                height,
-                &txs_hashes,
-                prev_hash,
-                BlockValues {
-                    exp_hash: block_hash,
-                    signature: block_signature.clone(),
-                    validator: block_validator.clone(),
-                    timestamp: block_timestamp,
-                },
-                is_validator,
-                is_validator_closure.clone(),
-            ) {
+            // Blocks built by other validators shall respect the block limits.
+            let checked = match height > 0 && block_validator.is_some() {
+                true => self.check_block_limits(&txs_hashes),
+                false => Ok(true),
+            };
+            let res = match checked {
+                Ok(true) => self.exec_block(
+                    height,
+                    &txs_hashes,
+                    prev_hash,
+                    BlockValues {
+                        exp_hash: block_hash,
+                        signature: block_signature.clone(),
+                        validator: block_validator.clone(),
+                        timestamp: block_timestamp,
+                    },
+                    is_validator,
+                    is_validator_closure.clone(),
+                ),
+                Ok(false) => {
+                    // Discarded, a valid block for the same height is awaited.
+                    error!("Discarded block {} exceeding the block limits", height);
+                    self.pool.write().confirmed.remove(&height);
+                    break;
+                }
+                Err(err) => Err(err),
+            };
+
+            match res {
                 Ok(hash) => {
                     let mut pool = self.pool.write();
                     pool.confirmed.remove(&height);
-                    txs_hashes.iter().for_each(|hash| {
-                        let _ = pool.txs.remove(hash);
-                    });
+                    pool.remove_executed(&txs_hashes);
+
+                    // The block may have consumed the sequence numbers the
+                    // held transactions were waiting for.
+                    let mut evicted = vec![];
+                    {
+                        let db = self.db.read();
+                        for caller in pool.held_callers() {
+                            let next = db
+                                .load_account(&caller)
+                                .map(|account| account.next_sequence())
+                                .unwrap_or_default();
+                            evicted.append(&mut pool.update_sequences(&caller, next));
+                        }
+                    }
+                    drop(pool);
+                    if !evicted.is_empty() {
+                        self.pubsub.lock().publish_evicted(evicted);
+                    }
                     prev_hash = hash;
                     height += 1;
 
                     // Update seed infos.
-                    let (prev_hash, txs_hash, rxs_hash) = match self.db.read().load_block(u64::MAX)
-                    {
-                        Some(block) => (
-                            block.data.primary_hash(),
-                            block.data.txs_hash,
-                            block.data.rxs_hash,
-                        ),
-                        None => (Hash::default(), Hash::default(), Hash::default()),
-                    };
-
-                    {
-                        let mut seed_prev_hash = self.seed.prev_hash.lock();
-                        let mut seed_rxs_hash = self.seed.rxs_hash.lock();
-                        let mut seed_txs_hash = self.seed.txs_hash.lock();
-                        let mut seed_prev_seed = self.seed.previous_seed.lock();
-
-                        *seed_prev_hash = prev_hash;
-                        *seed_txs_hash = txs_hash;
-                        *seed_rxs_hash = rxs_hash;
-                        *seed_prev_seed = 0;
-                    }
+                    self.reset_seed();
 
                     // Propagate block execution event
                     // Notify subscribers about block execution.
@@ -1191,9 +1594,10 @@ mod tests {
     use crate::{
         base::{
             schema::{
-                BulkTransaction, BulkTransactions, EmptyTransactionDataV1, SignedTransaction,
-                TransactionData, TransactionDataBulkNodeV1, TransactionDataBulkV1,
-                UnsignedTransaction,
+                tests::{create_test_unit_tx, create_test_unit_tx_v2},
+                BulkTransaction, BulkTransactions, EmptyTransactionDataV1, Expiry,
+                SignedTransaction, TransactionData, TransactionDataBulkNodeV1,
+                TransactionDataBulkV1, UnsignedTransaction,
             },
             serialize::{rmp_deserialize, rmp_serialize},
         },
@@ -1210,6 +1614,11 @@ mod tests {
 
     #[cfg(feature = "indexer")]
     use crate::blockchain::indexer::IndexerConfig;
+    #[cfg(feature = "with-rocksdb")]
+    use crate::{
+        blockchain::ordering::tests::create_tx,
+        crypto::{drand::Drand, ecdsa::tests::ecdsa_secp384_test_public_key},
+    };
 
     use serde_value::{value, Value};
 
@@ -1320,6 +1729,7 @@ mod tests {
             Some(rmp_deserialize(&buf).unwrap())
         });
         db.expect_fork_create().returning(create_fork_mock);
+        db.expect_load_configuration().returning(|_| None);
         db.expect_fork_merge().returning(move |_| match fail {
             false => Ok(()),
             true => Err(Error::new_ext(ErrorKind::DatabaseFault, "merge error")),
@@ -1559,6 +1969,177 @@ mod tests {
         assert!(rcpt.success);
     }
 
+    #[test]
+    fn exec_expired_transaction() {
+        let mut executor = create_executor(false, FUEL_LIMIT);
+        let mut fork = executor.db.write().fork_create();
+
+        let tx = create_test_unit_tx_v2(Some(Expiry::Timestamp(1000)), None);
+
+        let rcpt = executor.exec_transaction(&tx, &mut fork, 1, 0, &String::new(), 1001);
+
+        assert!(!rcpt.success);
+        assert_eq!(rcpt.burned_fuel, 0);
+        assert_eq!(rcpt.returns, ErrorKind::ExpiredTx.to_string().as_bytes());
+    }
+
+    #[test]
+    fn exec_sequenced_transaction() {
+        let mut executor = create_executor(false, FUEL_LIMIT);
+        let mut fork = create_fork_mock();
+        fork.expect_load_account().returning(|id| {
+            let mut account = Account::new(id, None);
+            account.sequence = Some(3);
+            Some(account)
+        });
+        fork.expect_store_account()
+            .withf(|account| account.sequence == Some(4))
+            .times(1)
+            .returning(|_| ());
+
+        // Not the next sequence number, refused without consuming it.
+        let tx = create_test_unit_tx_v2(None, Some(4));
+        let rcpt = executor.exec_transaction(&tx, &mut fork, 1, 0, &String::new(), 0);
+        assert!(!rcpt.success);
+        assert_eq!(
+            rcpt.returns,
+            ErrorKind::InvalidSequence.to_string().as_bytes()
+        );
+
+        // Expired, the sequence number is consumed anyway.
+        let tx = create_test_unit_tx_v2(Some(Expiry::Height(0)), Some(3));
+        let rcpt = executor.exec_transaction(&tx, &mut fork, 1, 1, &String::new(), 0);
+        assert!(!rcpt.success);
+        assert_eq!(rcpt.returns, ErrorKind::ExpiredTx.to_string().as_bytes());
+    }
+
+    #[test]
+    fn exec_unit_transaction_fuel_schedule() {
+        let mut executor = create_executor(false, FUEL_LIMIT);
+        executor.fuel_schedule = Some(FuelSchedule {
+            base_fuel: 10,
+            wm_fuel_per_unit: 1000,
+        });
+        let mut wm = MockWm::new();
+        wm.expect_app_hash_check()
+            .returning(|_, _, _, _, _| Ok(Hash::default()));
+        wm.expect_call().returning(
+            |_: &mut dyn DbFork,
+             _,
+             _,
+             _,
+             _,
+             _,
+             _,
+             _,
+             _,
+             _,
+             _,
+             #[cfg(feature = "indexer")] _,
+             initial_fuel,
+             _| {
+                // The execution is capped by the transaction fuel limit.
+                assert_eq!(initial_fuel, (FUEL_LIMIT - 10) * 1000);
+                (2500, Ok(vec![]))
+            },
+        );
+        executor.wm = Arc::new(Mutex::new(wm));
+        let mut fork = executor.db.write().fork_create();
+
+        let tx = match create_test_unit_tx(FUEL_LIMIT) {
+            Transaction::UnitTransaction(tx) => tx,
+            _ => unreachable!(),
+        };
+        let res = executor.handle_unit_transaction(&tx, &mut fork, 1, 0, vec![], 0);
+
+        assert!(res.receipt.success);
+        assert_eq!(res.receipt.burned_fuel, 13);
+        assert_eq!(res.burn_fuel_args.fuel_to_burn, 13);
+    }
+
+    #[test]
+    fn exec_unit_transaction_legacy_trap() {
+        let mut executor = create_executor(false, FUEL_LIMIT);
+        let mut wm = MockWm::new();
+        wm.expect_app_hash_check()
+            .returning(|_, _, _, _, _| Ok(Hash::default()));
+        wm.expect_call().returning(
+            |_: &mut dyn DbFork,
+             _,
+             _,
+             _,
+             _,
+             _,
+             _,
+             _,
+             _,
+             _,
+             _,
+             #[cfg(feature = "indexer")] _,
+             _,
+             _| {
+                (
+                    2500,
+                    Err(Error::new_ext(ErrorKind::WasmMachineFault, "trap")),
+                )
+            },
+        );
+        executor.wm = Arc::new(Mutex::new(wm));
+        let mut fork = executor.db.write().fork_create();
+
+        let tx = match create_test_unit_tx(FUEL_LIMIT) {
+            Transaction::UnitTransaction(tx) => tx,
+            _ => unreachable!(),
+        };
+        let res = executor.handle_unit_transaction(&tx, &mut fork, 1, 0, vec![], 0);
+
+        // Without a schedule the traps are not charged.
+        assert!(!res.receipt.success);
+        assert_eq!(res.receipt.burned_fuel, 0);
+        assert_eq!(res.burn_fuel_args.fuel_to_burn, 0);
+    }
+
+    #[test]
+    fn exec_bulk_transaction_fuel_budget() {
+        let mut executor = create_executor_bulk(false, FUEL_LIMIT);
+        executor.fuel_schedule = Some(FuelSchedule {
+            base_fuel: 10,
+            wm_fuel_per_unit: 1000,
+        });
+        let mut wm = MockWm::new();
+        wm.expect_app_hash_check()
+            .returning(|_, _, _, _, _| Ok(Hash::default()));
+        wm.expect_call().times(3).returning(
+            |_: &mut dyn DbFork,
+             _,
+             _,
+             _,
+             _,
+             _,
+             _,
+             _,
+             _,
+             _,
+             _,
+             #[cfg(feature = "indexer")] _,
+             initial_fuel,
+             _| (initial_fuel.min(400_000), Ok(vec![])),
+        );
+        executor.wm = Arc::new(Mutex::new(wm));
+        let mut fork = executor.db.write().fork_create();
+
+        let tx = match create_bulk_tx(false) {
+            Transaction::BulkTransaction(tx) => tx,
+            _ => unreachable!(),
+        };
+        let res = executor.handle_bulk_transaction(&tx, &mut fork, 1, 0, vec![], 0);
+
+        // The last node gets the fuel left by the root and the first node.
+        assert!(res.receipt.success);
+        assert_eq!(res.receipt.burned_fuel, FUEL_LIMIT);
+        assert_eq!(res.burn_fuel_args.fuel_to_burn, FUEL_LIMIT);
+    }
+
     #[test]
     fn can_run() {
         let executor = create_executor(false, FUEL_LIMIT);
@@ -1718,6 +2299,46 @@ mod tests {
         assert_eq!(err.to_string_full(), "database fault: merge error");
     }
 
+    #[test]
+    fn check_block_limits_from_settings() {
+        let mut executor = create_executor(false, FUEL_LIMIT);
+        let hashes = executor.pool.read().confirmed[&0]
+            .txs_hashes
+            .clone()
+            .unwrap();
+        assert!(executor.check_block_limits(&hashes).unwrap());
+
+        let settings = BlockchainSettings {
+            accept_broadcast: false,
+            block_threshold: 42,
+            block_timeout: 2,
+            burning_fuel_method: String::new(),
+            network_name: None,
+            is_production: false,
+            min_node_version: String::new(),
+            block_max_size: None,
+            block_max_fuel: Some(FUEL_LIMIT),
+            fuel_schedule: None,
+        };
+        let buf = rmp_serialize(&settings).unwrap();
+        let mut db = MockDb::new();
+        db.expect_load_configuration()
+            .returning(move |_| Some(buf.clone()));
+        executor.db = Arc::new(RwLock::new(db));
+        assert!(!executor.check_block_limits(&hashes).unwrap());
+
+        // Without the payload the block can't be checked.
+        let _ = executor
+            .pool
+            .write()
+            .txs
+            .get_mut(&hashes[0])
+            .unwrap()
+            .take();
+        let err = executor.check_block_limits(&hashes).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::ResourceNotFound);
+    }
+
     #[test]
     #[should_panic(expected = "Unexpected missing transaction")]
     fn exec_block_missing_tx() {
@@ -1753,6 +2374,186 @@ mod tests {
             .unwrap();
     }
 
+    #[cfg(feature = "with-rocksdb")]
+    fn create_counter_wm_mock(calls: Option<usize>) -> MockWm {
+        let sender = ecdsa_secp384_test_public_key(0).to_account_id();
+        let mut wm = MockWm::new();
+        wm.expect_app_hash_check()
+            .returning(|_, _, _, _, _| Ok(Hash::default()));
+        let expectation = wm.expect_call();
+        if let Some(calls) = calls {
+            expectation.times(calls);
+        }
+        expectation.returning(
+            move |fork: &mut dyn DbFork,
+                  _,
+                  _,
+                  origin: &str,
+                  _,
+                  _,
+                  _,
+                  _,
+                  _,
+                  seed: Arc<SeedSource>,
+                  _,
+                  #[cfg(feature = "indexer")] _,
+                  _,
+                  _| {
+                // Increments the caller counter, the other callers draw a
+                // random number too.
+                let count = fork
+                    .load_account_data(origin, "count")
+                    .map_or(0, |buf| buf[0])
+                    + 1;
+                fork.store_account_data(origin, "count", vec![count]);
+                let mut returns = vec![count];
+                if origin != sender {
+                    returns.push(Drand::new(seed).rand(255) as u8);
+                }
+                (0, Ok(returns))
+            },
+        );
+        wm
+    }
+
+    #[cfg(feature = "with-rocksdb")]
+    fn exec_counter_transactions(parallel: bool) -> (Vec<Hash>, Vec<Option<Vec<u8>>>) {
+        let dir = tempfile::TempDir::new().unwrap();
+        let db = Arc::new(RwLock::new(RocksDb::new(dir.path())));
+        let senders = [0, 1, 0, 2];
+        let mut pool = Pool::default();
+        let hashes: Vec<Hash> = senders
+            .iter()
+            .enumerate()
+            .map(|(nonce, sender)| {
+                let (hash, tx) = create_tx(*sender, FUEL_LIMIT, nonce as u8);
+                pool.txs.insert(hash, Some(tx));
+                hash
+            })
+            .collect();
+        let seed = SeedSource::new(
+            "skynet".to_owned(),
+            vec![0; 8],
+            Hash::default(),
+            Hash::default(),
+            Hash::default(),
+        );
+
+        // The third transaction reads the first one counter and the fourth one
+        // the seed drawn by the second one, thus both are executed again.
+        let calls = match parallel && !cfg!(feature = "indexer") {
+            true => Some(2),
+            false => None,
+        };
+        let mut executor = Executor::new(
+            Arc::new(RwLock::new(pool)),
+            db.clone(),
+            Arc::new(Mutex::new(create_counter_wm_mock(calls))),
+            Arc::new(Mutex::new(PubSub::new())),
+            Arc::new(create_test_keypair()),
+            Arc::new(seed),
+            "test_id".to_string(),
+            #[cfg(feature = "indexer")]
+            Indexer::new(IndexerConfig::default()),
+        );
+        if parallel {
+            executor.set_parallel_execution(vec![
+                create_counter_wm_mock(None),
+                create_counter_wm_mock(None),
+            ]);
+        }
+
+        let mut fork = db.write().fork_create();
+        let rxs_hashes = executor.exec_transactions(&mut fork, 1, &hashes, 0);
+        let counts = [0, 1, 2]
+            .iter()
+            .map(|sender| {
+                let id = ecdsa_secp384_test_public_key(*sender).to_account_id();
+                fork.load_account_data(&id, "count")
+            })
+            .collect();
+        (rxs_hashes, counts)
+    }
+
+    #[test]
+    #[cfg(feature = "with-rocksdb")]
+    fn exec_transactions_parallel() {
+        let sequential = exec_counter_transactions(false);
+
+        let parallel = exec_counter_transactions(true);
+
+        assert_eq!(parallel, sequential);
+        assert_eq!(
+            sequential.1,
+            vec![Some(vec![2]), Some(vec![1]), Some(vec![1])]
+        );
+    }
+
+    #[cfg(feature = "with-rocksdb")]
+    fn confirm_block(pool: &RwLock<Pool>, height: u64, txs: Vec<Transaction>) {
+        let mut pool = pool.write();
+        let hashes = txs
+            .into_iter()
+            .map(|tx| {
+                let hash = tx.primary_hash();
+                pool.txs.insert(hash, Some(tx));
+                hash
+            })
+            .collect();
+        let blk_info = BlockInfo {
+            hash: None,
+            validator: None,
+            signature: None,
+            txs_hashes: Some(hashes),
+            timestamp: 0,
+        };
+        pool.confirmed.insert(height, blk_info);
+    }
+
+    #[test]
+    #[cfg(feature = "with-rocksdb")]
+    fn revert_and_exec_again() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut db = RocksDb::new(dir.path());
+        db.set_history_retention(Some(10));
+        let db = Arc::new(RwLock::new(db));
+        let seed = SeedSource::new(
+            "skynet".to_owned(),
+            vec![0; 8],
+            Hash::default(),
+            Hash::default(),
+            Hash::default(),
+        );
+        let mut executor = Executor::new(
+            Arc::new(RwLock::new(Pool::default())),
+            db.clone(),
+            Arc::new(Mutex::new(create_counter_wm_mock(None))),
+            Arc::new(Mutex::new(PubSub::new())),
+            Arc::new(create_test_keypair()),
+            Arc::new(seed),
+            "test_id".to_string(),
+            #[cfg(feature = "indexer")]
+            Indexer::new(IndexerConfig::default()),
+        );
+        let is_validator_closure: Arc<dyn IsValidator> = Arc::new(is_validator_function());
+        confirm_block(&executor.pool, 0, vec![create_tx(0, FUEL_LIMIT, 0).1]);
+        // The transactions of the other callers draw a random number.
+        let txs = vec![create_tx(1, FUEL_LIMIT, 1).1, create_tx(2, FUEL_LIMIT, 2).1];
+        confirm_block(&executor.pool, 1, txs.clone());
+        executor.run(false, is_validator_closure.clone());
+        let block = db.read().load_block(1).unwrap();
+
+        let reverted = executor.revert_to(0).unwrap();
+        assert_eq!(reverted, txs);
+        assert!(db.read().load_block(1).is_none());
+        confirm_block(&executor.pool, 1, reverted);
+        executor.run(false, is_validator_closure);
+
+        let again = db.read().load_block(1).unwrap();
+        assert_eq!(again.data.state_hash, block.data.state_hash);
+        assert_eq!(again.data.primary_hash(), block.data.primary_hash());
+    }
+
     #[test]
     fn test_drad_seed() {
         let nw_name = String::from("skynet");
@@ -18,14 +18,18 @@
 //! Message used to send notification to the blockchain service.
 //! Message elements defined as "packed` are structures serialized in
 //! "MessagePack" format.
-use super::Event;
+use super::{Event, PoolStats};
 use crate::{
     base::{
         schema::{Block, SmartContractEvent},
-        Account, Receipt, Transaction,
+        Account, FuelEstimate, Receipt, Transaction,
     },
     channel,
-    crypto::Hash,
+    crypto::{
+        merkle::{AccountProof, ListProof},
+        Hash,
+    },
+    db::{AccountTransaction, EventFilter, EventRecord, StateDiff},
     Error,
 };
 
@@ -131,6 +135,11 @@ pub enum Message {
         id: String,
         /// Account data fields.
         data: Vec<String>,
+        /// Block height of the requested state, `None` for the last one.
+        /// Historical state is available only within the node retention window
+        /// and doesn't support the data keys list (`*`).
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        height: Option<u64>,
     },
     /// Get account response.
     #[serde(rename = "12")]
@@ -191,6 +200,174 @@ pub enum Message {
         /// Network
         network: String,
     },
+    /// Get account state Merkle proof request.
+    #[serde(rename = "24")]
+    GetAccountProofRequest {
+        /// Account identifier.
+        id: String,
+        /// Account data field to prove. `None` to prove the account only.
+        data: Option<String>,
+    },
+    /// Get account state Merkle proof response.
+    #[serde(rename = "25")]
+    GetAccountProofResponse {
+        /// `AccountProof` structure.
+        proof: AccountProof,
+    },
+    /// Get transaction inclusion proof request.
+    #[serde(rename = "26")]
+    GetTransactionProofRequest {
+        /// `Transaction::data` hash.
+        hash: Hash,
+    },
+    /// Get transaction inclusion proof response.
+    /// The proof leaf is the transaction hash, and the proof is verifiable
+    /// against the block `txs_hash`.
+    #[serde(rename = "27")]
+    GetTransactionProofResponse {
+        /// Block height.
+        height: u64,
+        /// Transaction Merkle path, the index is the `Receipt::index`.
+        proof: ListProof,
+    },
+    /// Get receipt inclusion proof request.
+    #[serde(rename = "28")]
+    GetReceiptProofRequest {
+        /// `Transaction::data` hash.
+        hash: Hash,
+    },
+    /// Get receipt inclusion proof response.
+    /// The proof leaf is the receipt hash, and the proof is verifiable
+    /// against the block `rxs_hash`.
+    #[serde(rename = "29")]
+    GetReceiptProofResponse {
+        /// Block height.
+        height: u64,
+        /// Receipt Merkle path, the index is the `Receipt::index`.
+        proof: ListProof,
+    },
+    /// Get block by hash request.
+    /// The response is a `GetBlockResponse`.
+    #[serde(rename = "30")]
+    GetBlockByHashRequest {
+        /// Block header hash.
+        hash: Hash,
+        /// Request for block transactions hashes.
+        txs: bool,
+    },
+    /// Get the transactions that touched an account, as target or caller.
+    #[serde(rename = "31")]
+    GetAccountTransactionsRequest {
+        /// Account identifier.
+        id: String,
+        /// Position of the first entry.
+        from: u64,
+        /// Maximum number of entries.
+        limit: u64,
+    },
+    /// Account transactions response, in execution order.
+    #[serde(rename = "32")]
+    GetAccountTransactionsResponse {
+        /// Transactions index entries.
+        txs: Vec<AccountTransaction>,
+    },
+    /// Get the smart contract events selected by a filter.
+    #[serde(rename = "33")]
+    GetEventsRequest {
+        /// Events filter.
+        filter: EventFilter,
+        /// Position of the first entry, within the selected events.
+        from: u64,
+        /// Maximum number of entries.
+        limit: u64,
+    },
+    /// Smart contract events response, in emission order.
+    #[serde(rename = "34")]
+    GetEventsResponse {
+        /// Events index entries.
+        events: Vec<EventRecord>,
+    },
+    /// Get a page of the account data keys starting with a prefix.
+    #[serde(rename = "35")]
+    GetAccountKeysRequest {
+        /// Account identifier.
+        id: String,
+        /// Keys prefix.
+        prefix: String,
+        /// Last key of the previous page.
+        after: Option<String>,
+        /// Maximum number of keys.
+        limit: u64,
+    },
+    /// Account data keys response, in lexicographic order.
+    #[serde(rename = "36")]
+    GetAccountKeysResponse {
+        /// Account data keys.
+        keys: Vec<String>,
+    },
+    /// Notification of a pending transaction replaced by a new one from the
+    /// same caller with the same sequence number.
+    #[serde(rename = "37")]
+    TransactionReplaced {
+        /// Replaced transaction hash.
+        hash: Hash,
+        /// Replacement transaction hash.
+        replacement: Hash,
+    },
+    /// Estimate the fuel burned by a transaction, without submitting it.
+    #[serde(rename = "38")]
+    EstimateFuelRequest {
+        /// Transaction to estimate.
+        tx: Transaction,
+    },
+    /// Estimate the fuel burned by a transaction response.
+    #[serde(rename = "39")]
+    EstimateFuelResponse {
+        /// Fuel estimation.
+        estimate: FuelEstimate,
+    },
+    /// Simulate a read-only transaction, recording the state changes.
+    #[serde(rename = "40")]
+    SimulateReadOnlyTransaction {
+        /// Target account.
+        target: String,
+        /// Method.
+        method: String,
+        /// Method's args.
+        args: Vec<u8>,
+        /// Origin. (PublicKey)
+        origin: String,
+        /// Contract.
+        contract: Option<Hash>,
+        /// Max consumable fuel.
+        max_fuel: u64,
+        /// Network
+        network: String,
+    },
+    /// Simulate a read-only transaction response.
+    #[serde(rename = "41")]
+    SimulateTransactionResponse {
+        /// Execution receipt, including the emitted events.
+        rx: Receipt,
+        /// Accounts state changes.
+        diff: StateDiff,
+    },
+    /// Get unconfirmed transactions pool occupancy request.
+    #[serde(rename = "42")]
+    GetPoolStatsRequest,
+    /// Get unconfirmed transactions pool occupancy response.
+    #[serde(rename = "43")]
+    GetPoolStatsResponse {
+        /// `PoolStats` structure.
+        stats: PoolStats,
+    },
+    /// Notification of a pending transaction evicted by the pool limits, to
+    /// make room for higher priority transactions.
+    #[serde(rename = "44")]
+    TransactionEvicted {
+        /// Evicted transaction hash.
+        hash: Hash,
+    },
     /// Acknowledgment message for reqRes,
     /// it means that a req message
     /// has been received.
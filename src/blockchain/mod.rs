@@ -25,6 +25,8 @@
 //! - dispatcher: handle incoming blockchain messages.
 //! - builder: constructs new blocks. This is used by validator nodes.
 //! - executor: runs the transactions composing a block.
+//! - ordering: sorts the unconfirmed transactions picked by the builder.
+//! - replay: re-executes the stored blocks to check the execution determinism.
 //! - synchronizer: keeps our state up-to-date with the other nodes.
 //!
 //! The blockchain service is the main user of the wm, db and consensus modules.
@@ -41,13 +43,17 @@ pub(crate) mod read_only_executor;
 
 pub mod aligner;
 pub mod message;
+pub mod ordering;
 pub mod pubsub;
+pub mod replay;
 pub mod service;
 pub mod worker;
 
+pub use builder::BlockLimits;
 pub use message::{
     BlockRequestReceiver, BlockRequestSender, BlockResponseReceiver, BlockResponseSender, Message,
 };
+pub use pool::{PoolLimits, PoolStats};
 pub use pubsub::Event;
 pub use service::{BlockConfig, BlockService};
 
@@ -0,0 +1,505 @@
+// This file is part of TRINCI.
+//
+// Copyright (C) 2021 Affidaty Spa.
+//
+// TRINCI is free software: you can redistribute it and/or modify it under
+// the terms of the GNU Affero General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or (at your
+// option) any later version.
+//
+// TRINCI is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License
+// for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with TRINCI. If not, see <https://www.gnu.org/licenses/>.
+
+//! Unconfirmed transactions ordering.
+//!
+//! The unconfirmed transactions are picked by the block builder from the
+//! highest priority to the lowest one, as assigned by the configured
+//! `OrderingPolicy`. Transactions with the same priority are picked in
+//! arrival order.
+//!
+//! The transactions of a sender are always picked in arrival order: a sender
+//! transaction competes with the other senders only once all the previous
+//! transactions of the same sender have been picked.
+
+use crate::{
+    base::{schema::Transaction, serialize::rmp_serialize},
+    crypto::Hash,
+    wm::MAX_FUEL,
+};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap, VecDeque},
+};
+
+/// Unconfirmed transactions ordering policy.
+pub trait OrderingPolicy: Send + Sync {
+    /// Transaction priority, higher values are picked first.
+    fn priority(&self, tx: &Transaction) -> u64;
+}
+
+/// First In First Out ordering.
+pub struct FifoOrdering;
+
+impl OrderingPolicy for FifoOrdering {
+    fn priority(&self, _tx: &Transaction) -> u64 {
+        0
+    }
+}
+
+/// Ordering by the fuel the submitter is willing to pay (`fuel_limit`).
+/// The limit is capped at `MAX_FUEL`, so that declaring an unreachable
+/// `fuel_limit` doesn't buy the eviction of the other transactions.
+pub struct FuelLimitOrdering;
+
+impl OrderingPolicy for FuelLimitOrdering {
+    fn priority(&self, tx: &Transaction) -> u64 {
+        tx.get_fuel_limit().min(MAX_FUEL)
+    }
+}
+
+/// Transaction queue entry.
+struct QueueEntry {
+    /// Sender account identifier.
+    sender: String,
+    /// Priority assigned by the policy.
+    priority: u64,
+    /// Arrival sequence number.
+    seq: u64,
+    /// Serialized transaction size.
+    size: usize,
+}
+
+impl QueueEntry {
+    fn head_key(&self) -> (Reverse<u64>, u64) {
+        (Reverse(self.priority), self.seq)
+    }
+
+    fn tail_key(&self) -> (u64, Reverse<u64>) {
+        (self.priority, Reverse(self.seq))
+    }
+}
+
+/// Unconfirmed transactions queue that prevents duplicates insertion.
+pub struct TxQueue {
+    /// Ordering policy.
+    policy: Box<dyn OrderingPolicy>,
+    /// Next arrival sequence number.
+    next_seq: u64,
+    /// Queued transactions.
+    entries: HashMap<Hash, QueueEntry>,
+    /// Queued transactions sorted by arrival time.
+    arrival: BTreeMap<u64, Hash>,
+    /// Per sender transactions sorted by arrival time.
+    senders: HashMap<String, VecDeque<Hash>>,
+    /// First transaction of each sender, sorted by priority and arrival time.
+    heads: BTreeMap<(Reverse<u64>, u64), Hash>,
+    /// Last transaction of each sender, from the lowest priority and the most
+    /// recent arrival time. These are the eviction candidates.
+    tails: BTreeMap<(u64, Reverse<u64>), Hash>,
+    /// Queued transactions total size.
+    bytes: usize,
+    /// Number of changes of the queue content.
+    changes: u64,
+}
+
+impl Default for TxQueue {
+    fn default() -> Self {
+        TxQueue::new(Box::new(FifoOrdering))
+    }
+}
+
+impl TxQueue {
+    /// Instance a new empty queue using the given ordering policy.
+    pub fn new(policy: Box<dyn OrderingPolicy>) -> Self {
+        TxQueue {
+            policy,
+            next_seq: 0,
+            entries: HashMap::new(),
+            arrival: BTreeMap::new(),
+            senders: HashMap::new(),
+            heads: BTreeMap::new(),
+            tails: BTreeMap::new(),
+            bytes: 0,
+            changes: 0,
+        }
+    }
+
+    /// Get the number of entries in the queue.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Checks if the queue is empty.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Checks if the queue contains the given transaction hash.
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.entries.contains_key(hash)
+    }
+
+    /// Get the total size of the queued transactions.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Get the number of changes of the queue content, e.g. to detect a
+    /// change without comparing the queued transactions.
+    pub fn changes(&self) -> u64 {
+        self.changes
+    }
+
+    /// Get the number of queued transactions of a sender.
+    pub fn sender_len(&self, sender: &str) -> usize {
+        self.senders
+            .get(sender)
+            .map(VecDeque::len)
+            .unwrap_or_default()
+    }
+
+    /// Priority assigned to a transaction by the ordering policy.
+    pub fn priority(&self, tx: &Transaction) -> u64 {
+        self.policy.priority(tx)
+    }
+
+    /// Adds a transaction to the queue.
+    /// If the transaction is already present returns false.
+    pub fn push(&mut self, hash: Hash, tx: &Transaction) -> bool {
+        if self.entries.contains_key(&hash) {
+            return false;
+        }
+        let entry = QueueEntry {
+            sender: tx.get_caller().to_account_id(),
+            priority: self.policy.priority(tx),
+            seq: self.next_seq,
+            size: rmp_serialize(tx).map(|buf| buf.len()).unwrap_or_default(),
+        };
+        self.next_seq += 1;
+        self.append(hash, entry);
+        true
+    }
+
+    /// Append an entry to its sender lane.
+    fn append(&mut self, hash: Hash, entry: QueueEntry) {
+        let lane = self.senders.entry(entry.sender.clone()).or_default();
+        match lane.back().and_then(|tail| self.entries.get(tail)) {
+            Some(tail) => {
+                self.tails.remove(&tail.tail_key());
+            }
+            None => {
+                self.heads.insert(entry.head_key(), hash);
+            }
+        }
+        lane.push_back(hash);
+        self.tails.insert(entry.tail_key(), hash);
+        self.arrival.insert(entry.seq, hash);
+        self.bytes += entry.size;
+        self.changes += 1;
+        self.entries.insert(hash, entry);
+    }
+
+    /// Replace a queued transaction with a new one of the same sender.
+    /// The new transaction takes the old one position within the sender
+    /// transactions and keeps its arrival time.
+    /// If the old transaction is not queued, or the new one is already present,
+    /// returns false.
+    pub fn replace(&mut self, old: &Hash, hash: Hash, tx: &Transaction) -> bool {
+        if self.entries.contains_key(&hash) {
+            return false;
+        }
+        let entry = match self.entries.remove(old) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        let new_entry = QueueEntry {
+            sender: entry.sender.clone(),
+            priority: self.policy.priority(tx),
+            seq: entry.seq,
+            size: rmp_serialize(tx).map(|buf| buf.len()).unwrap_or_default(),
+        };
+        let (is_head, is_tail) = match self.senders.get_mut(&entry.sender) {
+            Some(lane) => match lane.iter().position(|queued| queued == old) {
+                Some(pos) => {
+                    lane[pos] = hash;
+                    (pos == 0, pos == lane.len() - 1)
+                }
+                None => (false, false),
+            },
+            None => (false, false),
+        };
+        if is_head {
+            self.heads.remove(&entry.head_key());
+            self.heads.insert(new_entry.head_key(), hash);
+        }
+        if is_tail {
+            self.tails.remove(&entry.tail_key());
+            self.tails.insert(new_entry.tail_key(), hash);
+        }
+        self.arrival.insert(entry.seq, hash);
+        self.bytes = self.bytes - entry.size + new_entry.size;
+        self.changes += 1;
+        self.entries.insert(hash, new_entry);
+        true
+    }
+
+    /// Get the transaction with the highest priority, without removing it.
+    pub fn peek(&self) -> Option<&Hash> {
+        self.heads.values().next()
+    }
+
+    /// Get the size of a queued transaction.
+    pub fn size(&self, hash: &Hash) -> Option<usize> {
+        self.entries.get(hash).map(|entry| entry.size)
+    }
+
+    /// Removes and returns the transaction with the highest priority.
+    pub fn pop(&mut self) -> Option<Hash> {
+        let hash = *self.heads.values().next()?;
+        self.remove(&hash);
+        Some(hash)
+    }
+
+    /// Remove a transaction by hash.
+    pub fn remove(&mut self, hash: &Hash) -> bool {
+        self.take(hash).is_some()
+    }
+
+    /// Remove a transaction by hash, returning its entry.
+    fn take(&mut self, hash: &Hash) -> Option<QueueEntry> {
+        let entry = self.entries.remove(hash)?;
+        self.arrival.remove(&entry.seq);
+        self.bytes -= entry.size;
+        self.changes += 1;
+        let lane = self.senders.get_mut(&entry.sender)?;
+        let pos = lane.iter().position(|queued| queued == hash)?;
+        lane.remove(pos);
+        if pos == 0 {
+            self.heads.remove(&entry.head_key());
+            if let Some(next) = lane.front().and_then(|next| self.entries.get(next)) {
+                self.heads.insert(next.head_key(), lane[0]);
+            }
+        }
+        if pos == lane.len() {
+            self.tails.remove(&entry.tail_key());
+            if let Some(prev) = lane.back().and_then(|prev| self.entries.get(prev)) {
+                self.tails.insert(prev.tail_key(), lane[pos - 1]);
+            }
+        }
+        if lane.is_empty() {
+            self.senders.remove(&entry.sender);
+        }
+        Some(entry)
+    }
+
+    /// Evict the lowest priority transactions, with a priority lower than
+    /// `priority`, until a new transaction of `size` bytes fits within the
+    /// given limits.
+    /// Only the last transaction of a sender can be evicted, thus the sender
+    /// transactions order is preserved.
+    /// Returns the evicted transactions, or `None` if the room cannot be made;
+    /// in this case no transaction is evicted.
+    pub fn make_room(
+        &mut self,
+        size: usize,
+        priority: u64,
+        max_txs: usize,
+        max_bytes: usize,
+    ) -> Option<Vec<Hash>> {
+        let mut evicted = vec![];
+        while self.len() >= max_txs || self.bytes.saturating_add(size) > max_bytes {
+            let lowest = match self.tails.iter().next() {
+                Some(((lowest_priority, _), hash)) if *lowest_priority < priority => *hash,
+                _ => {
+                    // Restore the evicted entries, from the last one.
+                    for (hash, entry) in evicted.into_iter().rev() {
+                        self.append(hash, entry);
+                    }
+                    return None;
+                }
+            };
+            if let Some(entry) = self.take(&lowest) {
+                evicted.push((lowest, entry));
+            }
+        }
+        Some(evicted.into_iter().map(|(hash, _)| hash).collect())
+    }
+
+    /// Get an iterator over the queued transactions hashes, in arrival order.
+    pub fn iter(&self) -> impl Iterator<Item = &Hash> {
+        self.arrival.values()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{
+        base::schema::{tests::create_test_unit_tx, TransactionData},
+        crypto::{ecdsa::tests::ecdsa_secp384_test_public_key, Hashable},
+    };
+
+    pub fn create_tx(sender: u8, fuel_limit: u64, nonce: u8) -> (Hash, Transaction) {
+        let mut tx = create_test_unit_tx(fuel_limit);
+        if let Transaction::UnitTransaction(ref mut tx) = tx {
+            tx.data.set_nonce(vec![nonce; 8]);
+            if let TransactionData::V1(ref mut data) = tx.data {
+                data.caller = ecdsa_secp384_test_public_key(sender);
+            }
+        }
+        (tx.primary_hash(), tx)
+    }
+
+    fn pop_all(queue: &mut TxQueue) -> Vec<Hash> {
+        std::iter::from_fn(|| queue.pop()).collect()
+    }
+
+    #[test]
+    fn fifo_ordering() {
+        let mut queue = TxQueue::default();
+        let txs: Vec<_> = (0..3).map(|i| create_tx(i, 10 * i as u64, i)).collect();
+        txs.iter()
+            .for_each(|(hash, tx)| assert!(queue.push(*hash, tx)));
+
+        let hashes: Vec<Hash> = txs.iter().map(|(hash, _)| *hash).collect();
+        assert_eq!(queue.iter().cloned().collect::<Vec<_>>(), hashes);
+        assert_eq!(pop_all(&mut queue), hashes);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn push_duplicate() {
+        let mut queue = TxQueue::default();
+        let (hash, tx) = create_tx(0, 10, 0);
+        queue.push(hash, &tx);
+
+        assert!(!queue.push(hash, &tx));
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn fuel_limit_ordering() {
+        let mut queue = TxQueue::new(Box::new(FuelLimitOrdering));
+        let (hash0, tx0) = create_tx(0, 10, 0);
+        let (hash1, tx1) = create_tx(1, 30, 1);
+        let (hash2, tx2) = create_tx(2, 20, 2);
+        let (hash3, tx3) = create_tx(2, 30, 3);
+        queue.push(hash0, &tx0);
+        queue.push(hash1, &tx1);
+        queue.push(hash2, &tx2);
+        queue.push(hash3, &tx3);
+
+        // The sender 2 transactions are picked in arrival order.
+        assert_eq!(pop_all(&mut queue), vec![hash1, hash2, hash3, hash0]);
+    }
+
+    #[test]
+    fn fuel_limit_priority_capped() {
+        let mut queue = TxQueue::new(Box::new(FuelLimitOrdering));
+        let (hash0, tx0) = create_tx(0, MAX_FUEL, 0);
+        let (_, tx1) = create_tx(1, u64::MAX, 1);
+        queue.push(hash0, &tx0);
+
+        assert_eq!(FuelLimitOrdering.priority(&tx1), MAX_FUEL);
+        // Not enough to evict a transaction with the same capped priority.
+        assert_eq!(
+            queue.make_room(0, FuelLimitOrdering.priority(&tx1), 1, usize::MAX),
+            None
+        );
+    }
+
+    #[test]
+    fn same_priority_arrival_order() {
+        let mut queue = TxQueue::new(Box::new(FuelLimitOrdering));
+        let (hash0, tx0) = create_tx(0, 10, 0);
+        let (hash1, tx1) = create_tx(1, 10, 1);
+        queue.push(hash0, &tx0);
+        queue.push(hash1, &tx1);
+
+        assert_eq!(pop_all(&mut queue), vec![hash0, hash1]);
+    }
+
+    #[test]
+    fn remove_sender_head() {
+        let mut queue = TxQueue::new(Box::new(FuelLimitOrdering));
+        let (hash0, tx0) = create_tx(0, 10, 0);
+        let (hash1, tx1) = create_tx(0, 50, 1);
+        let (hash2, tx2) = create_tx(1, 20, 2);
+        queue.push(hash0, &tx0);
+        queue.push(hash1, &tx1);
+        queue.push(hash2, &tx2);
+
+        assert!(queue.remove(&hash0));
+        assert!(!queue.remove(&hash0));
+
+        assert!(!queue.contains(&hash0));
+        assert_eq!(pop_all(&mut queue), vec![hash1, hash2]);
+    }
+
+    #[test]
+    fn replace_keeps_sender_position() {
+        let mut queue = TxQueue::new(Box::new(FuelLimitOrdering));
+        let (hash0, tx0) = create_tx(0, 10, 0);
+        let (hash1, tx1) = create_tx(0, 50, 1);
+        let (hash2, tx2) = create_tx(1, 20, 2);
+        let (hash3, tx3) = create_tx(0, 40, 3);
+        queue.push(hash0, &tx0);
+        queue.push(hash1, &tx1);
+        queue.push(hash2, &tx2);
+
+        assert!(queue.replace(&hash0, hash3, &tx3));
+        assert!(!queue.replace(&hash0, hash3, &tx3));
+
+        assert!(!queue.contains(&hash0));
+        assert_eq!(queue.len(), 3);
+        assert_eq!(
+            queue.iter().cloned().collect::<Vec<_>>(),
+            vec![hash3, hash1, hash2]
+        );
+        assert_eq!(pop_all(&mut queue), vec![hash3, hash1, hash2]);
+    }
+
+    #[test]
+    fn make_room_evicts_lowest_tail() {
+        let mut queue = TxQueue::new(Box::new(FuelLimitOrdering));
+        let (hash0, tx0) = create_tx(0, 10, 0);
+        let (hash1, tx1) = create_tx(0, 50, 1);
+        let (hash2, tx2) = create_tx(1, 20, 2);
+        queue.push(hash0, &tx0);
+        queue.push(hash1, &tx1);
+        queue.push(hash2, &tx2);
+
+        // The sender 0 lowest transaction is not the lane tail.
+        let evicted = queue.make_room(0, 30, 3, usize::MAX);
+
+        assert_eq!(evicted, Some(vec![hash2]));
+        assert_eq!(queue.len(), 2);
+        assert_eq!(pop_all(&mut queue), vec![hash0, hash1]);
+    }
+
+    #[test]
+    fn make_room_without_lower_priority() {
+        let mut queue = TxQueue::new(Box::new(FuelLimitOrdering));
+        let (hash0, tx0) = create_tx(0, 10, 0);
+        let (hash1, tx1) = create_tx(1, 20, 1);
+        queue.push(hash0, &tx0);
+        queue.push(hash1, &tx1);
+        let bytes = queue.bytes();
+
+        // Room for the new transaction requires evicting both.
+        let evicted = queue.make_room(0, 15, 1, usize::MAX);
+
+        assert_eq!(evicted, None);
+        assert_eq!(queue.bytes(), bytes);
+        assert_eq!(
+            queue.iter().cloned().collect::<Vec<_>>(),
+            vec![hash0, hash1]
+        );
+        assert_eq!(pop_all(&mut queue), vec![hash1, hash0]);
+    }
+}
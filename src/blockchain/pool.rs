@@ -17,12 +17,24 @@
 
 //! Blockchain outstanding transaction and blocks pool.
 
+use super::ordering::{OrderingPolicy, TxQueue};
 use crate::{
-    base::{queue_set::QueueSet, schema::Transaction},
+    base::{
+        schema::Transaction,
+        serialize::{rmp_deserialize, rmp_serialize},
+    },
     crypto::hash::Hash,
-    PublicKey,
+    Error, ErrorKind, PublicKey, Result,
 };
-use std::collections::{BTreeMap, HashMap};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs, io,
+    path::Path,
+};
+
+/// Max distance of a sequenced transaction from the caller next sequence
+/// number. Farther transactions are refused instead of being held.
+pub const MAX_SEQUENCE_GAP: u64 = 64;
 
 /// Confirmed block information.
 ///
@@ -54,6 +66,39 @@ pub struct BlockInfo {
     pub timestamp: u64,
 }
 
+/// Unconfirmed transactions pool limits.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct PoolLimits {
+    /// Max number of unconfirmed transactions.
+    pub max_txs: usize,
+    /// Max size of the unconfirmed transactions (bytes).
+    pub max_bytes: usize,
+    /// Max number of unconfirmed transactions submitted by the same caller.
+    pub max_txs_per_caller: usize,
+}
+
+impl Default for PoolLimits {
+    fn default() -> Self {
+        PoolLimits {
+            max_txs: usize::MAX,
+            max_bytes: usize::MAX,
+            max_txs_per_caller: usize::MAX,
+        }
+    }
+}
+
+/// Unconfirmed transactions pool occupancy.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PoolStats {
+    /// Number of unconfirmed transactions, including the held sequenced ones.
+    pub txs: usize,
+    /// Size of the unconfirmed transactions, including the held sequenced
+    /// ones (bytes).
+    pub bytes: usize,
+    /// Configured limits.
+    pub limits: PoolLimits,
+}
+
 /// Pool of outstanding transactions and blocks.
 /// The structure contains both confirmed and unconfirmed transactions.
 #[derive(Default)]
@@ -64,15 +109,444 @@ pub struct Pool {
     pub txs: HashMap<Hash, Option<Transaction>>,
     /// Unconfirmed transactions queue. This contains the transactions waiting
     /// to be inserted in a new confirmed block.
-    pub unconfirmed: QueueSet<Hash>,
+    pub unconfirmed: TxQueue,
     /// Confirmed blocks information.
     pub confirmed: BTreeMap<u64, BlockInfo>,
+    /// Unconfirmed transactions limits.
+    pub limits: PoolLimits,
+    /// Sequenced transactions, by caller and sequence number, until executed.
+    pub sequences: BTreeMap<(String, u64), Hash>,
+    /// Caller and sequence number of the transactions in `sequences`.
+    pub sequenced: HashMap<Hash, (String, u64)>,
+    /// Sequenced transactions held until the previous sequence numbers are
+    /// received, with their serialized size. These are not yet in the
+    /// unconfirmed queue, but count against the pool limits.
+    pub future: HashMap<Hash, usize>,
+    /// Number of held sequenced transactions, by caller.
+    pub held: BTreeMap<String, usize>,
+    /// Number of changes of the pending transactions not tracked by the
+    /// unconfirmed queue.
+    changes: u64,
+}
+
+impl Pool {
+    /// Set the unconfirmed transactions ordering policy.
+    /// The already queued transactions are sorted again, keeping their
+    /// relative arrival order.
+    pub fn set_ordering(&mut self, policy: Box<dyn OrderingPolicy>) {
+        let mut unconfirmed = TxQueue::new(policy);
+        for hash in self.unconfirmed.iter() {
+            if let Some(Some(tx)) = self.txs.get(hash) {
+                unconfirmed.push(*hash, tx);
+            }
+        }
+        self.unconfirmed = unconfirmed;
+        self.changes += 1;
+    }
+
+    /// Revision of the pending transactions, see `pending_txs`.
+    /// The revision changes on any change of the unconfirmed queue or of the
+    /// held transactions.
+    pub fn pending_revision(&self) -> (u64, u64) {
+        (self.changes, self.unconfirmed.changes())
+    }
+
+    /// Insert a new unconfirmed transaction, enforcing the pool limits.
+    ///
+    /// When the pool is full, the lowest priority transactions are evicted to
+    /// make room for the new one, only if their priority is lower than the new
+    /// transaction one. Otherwise the new transaction is rejected.
+    /// With the default FIFO ordering the new transactions are rejected.
+    ///
+    /// The room taken by the held sequenced transactions is reserved, these
+    /// are never evicted.
+    ///
+    /// Returns the evicted transactions.
+    pub fn insert_unconfirmed(&mut self, hash: Hash, tx: Transaction) -> Result<Vec<Hash>> {
+        let caller = tx.get_caller().to_account_id();
+        self.check_caller_quota(&caller)?;
+        let size = rmp_serialize(&tx)?.len();
+        let priority = self.unconfirmed.priority(&tx);
+        let max_txs = self.limits.max_txs.saturating_sub(self.future.len());
+        let max_bytes = self.limits.max_bytes.saturating_sub(self.held_bytes());
+        let evicted = self
+            .unconfirmed
+            .make_room(size, priority, max_txs, max_bytes)
+            .ok_or_else(pool_full_error)?;
+        for hash in evicted.iter() {
+            self.txs.remove(hash);
+        }
+        self.forget_sequences(&evicted);
+        self.unconfirmed.push(hash, &tx);
+        self.txs.insert(hash, Some(tx));
+        Ok(evicted)
+    }
+
+    /// Insert a new transaction carrying a sequence number.
+    ///
+    /// The transaction is queued as unconfirmed only once all the caller
+    /// previous sequence numbers, starting from `next` (the caller next
+    /// sequence number within the state), are in the pool.
+    /// Until then the transaction is held, and it is moved in the queue as soon
+    /// as the gap is filled (see also `update_sequences`).
+    /// Held transactions don't evict the queued ones: if the pool is full the
+    /// transaction is rejected.
+    ///
+    /// Returns the evicted transactions, see `insert_unconfirmed`.
+    pub fn insert_sequenced(
+        &mut self,
+        hash: Hash,
+        tx: Transaction,
+        next: u64,
+    ) -> Result<Vec<Hash>> {
+        let sequence = match tx.get_sequence() {
+            Some(sequence) => sequence,
+            None => return self.insert_unconfirmed(hash, tx),
+        };
+        let caller = tx.get_caller().to_account_id();
+        self.drop_executed_sequences(&caller, next);
+
+        if sequence < next || self.sequences.contains_key(&(caller.clone(), sequence)) {
+            return Err(Error::new_ext(
+                ErrorKind::InvalidSequence,
+                "sequence number already used",
+            ));
+        }
+        if sequence - next > MAX_SEQUENCE_GAP {
+            return Err(Error::new_ext(
+                ErrorKind::InvalidSequence,
+                "sequence number too far ahead",
+            ));
+        }
+
+        // Ready if the previous transaction is queued or already in a block.
+        let ready = sequence == next
+            || matches!(
+                self.sequences.get(&(caller.clone(), sequence - 1)),
+                Some(prev) if !self.future.contains_key(prev)
+            );
+        if !ready {
+            self.check_caller_quota(&caller)?;
+            let size = rmp_serialize(&tx)?.len();
+            if self.unconfirmed.len() + self.future.len() >= self.limits.max_txs
+                || self.unconfirmed.bytes() + self.held_bytes() + size > self.limits.max_bytes
+            {
+                return Err(pool_full_error());
+            }
+            self.future.insert(hash, size);
+            *self.held.entry(caller.clone()).or_default() += 1;
+            self.changes += 1;
+            self.txs.insert(hash, Some(tx));
+            self.reserve_sequence(caller, sequence, hash);
+            return Ok(vec![]);
+        }
+
+        let mut evicted = self.insert_unconfirmed(hash, tx)?;
+        self.reserve_sequence(caller.clone(), sequence, hash);
+        evicted.append(&mut self.promote_held(&caller, sequence + 1));
+        Ok(evicted)
+    }
+
+    /// Re-evaluate the caller held transactions given `next`, the caller next
+    /// sequence number within the state, e.g. after a block execution.
+    /// The stale transactions are dropped, while the ones no longer waiting
+    /// for a previous sequence number are moved in the queue.
+    ///
+    /// Returns the evicted transactions, see `insert_unconfirmed`.
+    pub fn update_sequences(&mut self, caller: &str, next: u64) -> Vec<Hash> {
+        self.drop_executed_sequences(caller, next);
+        self.promote_held(caller, next)
+    }
+
+    /// Get the callers with held sequenced transactions.
+    pub fn held_callers(&self) -> BTreeSet<String> {
+        self.held.keys().cloned().collect()
+    }
+
+    /// Move in the queue the caller held transactions, starting from the
+    /// `sequence` number up to the first missing one.
+    fn promote_held(&mut self, caller: &str, mut sequence: u64) -> Vec<Hash> {
+        let mut evicted = vec![];
+        while let Some(hash) = self.sequences.get(&(caller.to_owned(), sequence)).cloned() {
+            sequence += 1;
+            if !self.release_held(&hash) {
+                // Already queued.
+                continue;
+            }
+            let tx = match self.txs.remove(&hash) {
+                Some(Some(tx)) => tx,
+                _ => break,
+            };
+            match self.insert_unconfirmed(hash, tx) {
+                Ok(mut dropped) => evicted.append(&mut dropped),
+                Err(_) => {
+                    self.forget_sequences(&[hash]);
+                    evicted.push(hash);
+                    break;
+                }
+            }
+        }
+        evicted
+    }
+
+    /// Check the caller quota, for both queued and held transactions.
+    fn check_caller_quota(&self, caller: &str) -> Result<()> {
+        let held = self.held.get(caller).copied().unwrap_or_default();
+        if self.unconfirmed.sender_len(caller) + held >= self.limits.max_txs_per_caller {
+            return Err(Error::new_ext(
+                ErrorKind::PoolLimitExceeded,
+                "too many unconfirmed transactions for the caller",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Stop holding a sequenced transaction.
+    /// Returns false if the transaction is not held.
+    fn release_held(&mut self, hash: &Hash) -> bool {
+        if self.future.remove(hash).is_none() {
+            return false;
+        }
+        self.changes += 1;
+        if let Some((caller, _)) = self.sequenced.get(hash) {
+            if let Some(count) = self.held.get_mut(caller) {
+                *count -= 1;
+                if *count == 0 {
+                    self.held.remove(caller);
+                }
+            }
+        }
+        true
+    }
+
+    /// Size of the held sequenced transactions (bytes).
+    fn held_bytes(&self) -> usize {
+        self.future.values().sum()
+    }
+
+    /// Get the pending transaction, not yet in a block, using the same caller
+    /// sequence number of `tx`.
+    /// Sequence numbers lower than `next` (the caller next sequence number
+    /// within the state) are not considered.
+    pub fn sequence_holder(&self, tx: &Transaction, next: u64) -> Option<Hash> {
+        let sequence = tx.get_sequence().filter(|sequence| *sequence >= next)?;
+        let caller = tx.get_caller().to_account_id();
+        self.sequences
+            .get(&(caller, sequence))
+            .filter(|hash| self.unconfirmed.contains(hash) || self.future.contains_key(hash))
+            .cloned()
+    }
+
+    /// Replace a pending sequenced transaction with a new one from the same
+    /// caller with the same sequence number.
+    ///
+    /// The new transaction shall offer a strictly higher fuel limit, and takes
+    /// the old one place within the pool, thus the caller quota is unchanged.
+    /// The pool size limit is enforced against the size difference, without
+    /// evicting other transactions.
+    pub fn replace_sequenced(&mut self, old: &Hash, hash: Hash, tx: Transaction) -> Result<()> {
+        let old_tx = match self.txs.get(old) {
+            Some(Some(old_tx)) => old_tx,
+            _ => return Err(ErrorKind::ResourceNotFound.into()),
+        };
+        let caller = tx.get_caller().to_account_id();
+        let sequence = match tx.get_sequence() {
+            Some(sequence)
+                if old_tx.get_sequence() == Some(sequence)
+                    && old_tx.get_caller().to_account_id() == caller =>
+            {
+                sequence
+            }
+            _ => {
+                return Err(Error::new_ext(
+                    ErrorKind::InvalidSequence,
+                    "replacement with a different caller or sequence number",
+                ))
+            }
+        };
+        if tx.get_fuel_limit() <= old_tx.get_fuel_limit() {
+            return Err(Error::new_ext(
+                ErrorKind::InvalidSequence,
+                "sequence number already used, replacement requires a higher fuel limit",
+            ));
+        }
+        let old_size = match self.future.get(old) {
+            Some(size) => *size,
+            None => rmp_serialize(old_tx)?.len(),
+        };
+        let size = rmp_serialize(&tx)?.len();
+        if (self.unconfirmed.bytes() + self.held_bytes() + size).saturating_sub(old_size)
+            > self.limits.max_bytes
+        {
+            return Err(pool_full_error());
+        }
+
+        if self.future.remove(old).is_some() {
+            self.future.insert(hash, size);
+            self.changes += 1;
+        } else if !self.unconfirmed.replace(old, hash, &tx) {
+            return Err(ErrorKind::DuplicatedUnconfirmedTx.into());
+        }
+        self.txs.remove(old);
+        self.txs.insert(hash, Some(tx));
+        self.reserve_sequence(caller, sequence, hash);
+        Ok(())
+    }
+
+    /// Drop the caller sequenced transactions with a sequence number lower
+    /// than `next`, they are already executed or can't be executed anymore.
+    fn drop_executed_sequences(&mut self, caller: &str, next: u64) {
+        let stale: Vec<Hash> = self
+            .sequences
+            .range((caller.to_owned(), 0)..(caller.to_owned(), next))
+            .map(|(_, hash)| *hash)
+            .collect();
+        for hash in stale.iter() {
+            if self.release_held(hash) || self.unconfirmed.remove(hash) {
+                self.txs.remove(hash);
+            }
+        }
+        self.forget_sequences(&stale);
+    }
+
+    /// Reserve the caller sequence number for the transaction `hash`,
+    /// replacing the previous reservation if any.
+    fn reserve_sequence(&mut self, caller: String, sequence: u64, hash: Hash) {
+        if let Some(old) = self.sequences.insert((caller.clone(), sequence), hash) {
+            self.sequenced.remove(&old);
+        }
+        self.sequenced.insert(hash, (caller, sequence));
+    }
+
+    /// Drop the sequence numbers reservations of removed transactions.
+    fn forget_sequences(&mut self, hashes: &[Hash]) {
+        for hash in hashes.iter() {
+            self.release_held(hash);
+            if let Some(key) = self.sequenced.remove(hash) {
+                self.sequences.remove(&key);
+            }
+        }
+    }
+
+    /// Drop the transactions of an executed block, together with their
+    /// sequence numbers reservations.
+    pub fn remove_executed(&mut self, hashes: &[Hash]) {
+        for hash in hashes.iter() {
+            self.txs.remove(hash);
+        }
+        self.forget_sequences(hashes);
+    }
+
+    /// Drop an unconfirmed transaction.
+    /// Returns false if the transaction is not in the unconfirmed queue.
+    pub fn remove_unconfirmed(&mut self, hash: &Hash) -> bool {
+        if !self.unconfirmed.remove(hash) {
+            return false;
+        }
+        self.txs.remove(hash);
+        self.forget_sequences(&[*hash]);
+        true
+    }
+
+    /// Drop the confirmed blocks following `height`, e.g. after a revert.
+    /// Their transactions are removed from the pool too.
+    ///
+    /// Returns the removed transactions whose payload was in the pool.
+    pub fn remove_confirmed_after(&mut self, height: u64) -> Vec<Transaction> {
+        let hashes: Vec<Hash> = self
+            .confirmed
+            .split_off(&height.saturating_add(1))
+            .into_values()
+            .flat_map(|info| info.txs_hashes.unwrap_or_default())
+            .collect();
+        let txs = hashes
+            .iter()
+            .filter_map(|hash| self.txs.remove(hash).flatten())
+            .collect();
+        self.forget_sequences(&hashes);
+        txs
+    }
+
+    /// Drop the unconfirmed transactions that can't be included anymore in a
+    /// block with the given height and timestamp.
+    ///
+    /// Returns the dropped transactions.
+    pub fn remove_expired(&mut self, height: u64, timestamp: u64) -> Vec<Hash> {
+        let expired: Vec<Hash> = self
+            .unconfirmed
+            .iter()
+            .chain(self.future.keys())
+            .filter(|hash| match self.txs.get(hash) {
+                Some(Some(tx)) => tx.is_expired(height, timestamp),
+                _ => false,
+            })
+            .cloned()
+            .collect();
+        for hash in expired.iter() {
+            self.unconfirmed.remove(hash);
+            self.txs.remove(hash);
+        }
+        self.forget_sequences(&expired);
+        expired
+    }
+
+    /// Get the pending transactions: the unconfirmed queue, in arrival order,
+    /// followed by the held sequenced transactions.
+    pub fn pending_txs(&self) -> Vec<Transaction> {
+        let held = self
+            .sequences
+            .values()
+            .filter(|hash| self.future.contains_key(hash));
+        self.unconfirmed
+            .iter()
+            .chain(held)
+            .filter_map(|hash| self.txs.get(hash).cloned().flatten())
+            .collect()
+    }
+
+    /// Get the unconfirmed transactions pool occupancy.
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            txs: self.unconfirmed.len() + self.future.len(),
+            bytes: self.unconfirmed.bytes() + self.held_bytes(),
+            limits: self.limits,
+        }
+    }
+}
+
+fn pool_full_error() -> Error {
+    Error::new_ext(ErrorKind::PoolLimitExceeded, "transactions pool full")
+}
+
+/// Write the pending transactions snapshot file.
+/// The snapshot is written in a temporary file first, thus an interrupted
+/// write never leaves a broken snapshot.
+pub fn store_snapshot(path: &Path, txs: &[Transaction]) -> Result<()> {
+    let buf = rmp_serialize(&txs)?;
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, buf)
+        .and_then(|_| fs::rename(&tmp, path))
+        .map_err(|err| Error::new_ext(ErrorKind::Other, err))
+}
+
+/// Read the pending transactions snapshot file.
+/// A missing snapshot is an empty one.
+pub fn load_snapshot(path: &Path) -> Result<Vec<Transaction>> {
+    match fs::read(path) {
+        Ok(buf) => rmp_deserialize(&buf),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+        Err(err) => Err(Error::new_ext(ErrorKind::Other, err)),
+    }
 }
 
 #[cfg(test)]
 pub mod tests {
     use super::*;
-    use crate::base::schema::tests::create_test_unit_tx;
+    use crate::base::schema::{
+        tests::{create_test_unit_tx, create_test_unit_tx_v2},
+        Expiry, TransactionData,
+    };
+    use crate::blockchain::ordering::{tests::create_tx, FuelLimitOrdering};
     use crate::crypto::Hashable;
 
     pub fn create_pool(fuel_limit: u64) -> Pool {
@@ -100,4 +574,363 @@ pub mod tests {
         pool.confirmed.insert(0, blk_info);
         pool
     }
+
+    fn create_limited_pool(max_txs: usize, max_txs_per_caller: usize) -> Pool {
+        let mut pool = Pool::default();
+        pool.set_ordering(Box::new(FuelLimitOrdering));
+        pool.limits = PoolLimits {
+            max_txs,
+            max_txs_per_caller,
+            ..Default::default()
+        };
+        pool
+    }
+
+    #[test]
+    fn caller_quota_exceeded() {
+        let mut pool = create_limited_pool(10, 1);
+        let (hash0, tx0) = create_tx(0, 10, 0);
+        let (hash1, tx1) = create_tx(0, 20, 1);
+        let (hash2, tx2) = create_tx(1, 20, 2);
+        pool.insert_unconfirmed(hash0, tx0).unwrap();
+
+        let err = pool.insert_unconfirmed(hash1, tx1).unwrap_err();
+
+        assert_eq!(err.kind, ErrorKind::PoolLimitExceeded);
+        assert!(!pool.txs.contains_key(&hash1));
+        assert!(pool.insert_unconfirmed(hash2, tx2).is_ok());
+    }
+
+    #[test]
+    fn full_pool_rejection() {
+        let mut pool = create_limited_pool(1, 10);
+        let (hash0, tx0) = create_tx(0, 20, 0);
+        let (hash1, tx1) = create_tx(1, 20, 1);
+        pool.insert_unconfirmed(hash0, tx0).unwrap();
+
+        let err = pool.insert_unconfirmed(hash1, tx1).unwrap_err();
+
+        assert_eq!(err.kind, ErrorKind::PoolLimitExceeded);
+        assert!(pool.unconfirmed.contains(&hash0));
+        assert!(!pool.txs.contains_key(&hash1));
+    }
+
+    #[test]
+    fn full_pool_eviction() {
+        let mut pool = create_limited_pool(2, 10);
+        let (hash0, tx0) = create_tx(0, 20, 0);
+        let (hash1, tx1) = create_tx(1, 10, 1);
+        let (hash2, tx2) = create_tx(2, 30, 2);
+        pool.insert_unconfirmed(hash0, tx0).unwrap();
+        pool.insert_unconfirmed(hash1, tx1).unwrap();
+
+        let evicted = pool.insert_unconfirmed(hash2, tx2).unwrap();
+
+        assert_eq!(evicted, vec![hash1]);
+        assert!(!pool.txs.contains_key(&hash1));
+        assert!(!pool.unconfirmed.contains(&hash1));
+        assert!(pool.unconfirmed.contains(&hash2));
+    }
+
+    #[test]
+    fn pool_bytes_limit() {
+        let mut pool = create_limited_pool(10, 10);
+        let (hash0, tx0) = create_tx(0, 20, 0);
+        let (hash1, tx1) = create_tx(1, 20, 1);
+        pool.insert_unconfirmed(hash0, tx0).unwrap();
+        pool.limits.max_bytes = pool.unconfirmed.bytes();
+
+        let err = pool.insert_unconfirmed(hash1, tx1).unwrap_err();
+
+        assert_eq!(err.kind, ErrorKind::PoolLimitExceeded);
+    }
+
+    #[test]
+    fn remove_expired_txs() {
+        let mut pool = Pool::default();
+        let expiring = create_test_unit_tx_v2(Some(Expiry::Height(5)), None);
+        let expiring_hash = expiring.get_primary_hash();
+        let (hash, tx) = create_tx(0, 20, 0);
+        pool.insert_unconfirmed(expiring_hash, expiring).unwrap();
+        pool.insert_unconfirmed(hash, tx).unwrap();
+
+        assert!(pool.remove_expired(5, 0).is_empty());
+        let expired = pool.remove_expired(6, 0);
+
+        assert_eq!(expired, vec![expiring_hash]);
+        assert!(!pool.unconfirmed.contains(&expiring_hash));
+        assert!(!pool.txs.contains_key(&expiring_hash));
+        assert!(pool.unconfirmed.contains(&hash));
+    }
+
+    #[test]
+    fn remove_confirmed_after_height() {
+        let mut pool = create_pool(10);
+        let (hash, tx) = create_tx(0, 20, 0);
+        pool.txs.insert(hash, Some(tx.clone()));
+        pool.confirmed.insert(
+            1,
+            BlockInfo {
+                hash: None,
+                validator: None,
+                signature: None,
+                txs_hashes: Some(vec![hash]),
+                timestamp: 0,
+            },
+        );
+
+        let txs = pool.remove_confirmed_after(0);
+
+        assert_eq!(txs, vec![tx]);
+        assert!(pool.confirmed.contains_key(&0));
+        assert!(!pool.confirmed.contains_key(&1));
+        assert!(!pool.txs.contains_key(&hash));
+        assert_eq!(pool.txs.len(), 3);
+    }
+
+    fn create_sequenced_tx(sequence: u64) -> (Hash, Transaction) {
+        let tx = create_test_unit_tx_v2(None, Some(sequence));
+        (tx.get_primary_hash(), tx)
+    }
+
+    #[test]
+    fn sequenced_held_until_gap_filled() {
+        let mut pool = Pool::default();
+        let (hash0, tx0) = create_sequenced_tx(0);
+        let (hash1, tx1) = create_sequenced_tx(1);
+        let (hash2, tx2) = create_sequenced_tx(2);
+
+        pool.insert_sequenced(hash2, tx2, 0).unwrap();
+        pool.insert_sequenced(hash1, tx1, 0).unwrap();
+
+        assert!(pool.unconfirmed.is_empty());
+        assert!(pool.future.contains_key(&hash1));
+        assert!(pool.txs.contains_key(&hash2));
+
+        pool.insert_sequenced(hash0, tx0, 0).unwrap();
+
+        assert!(pool.future.is_empty());
+        assert_eq!(pool.unconfirmed.pop(), Some(hash0));
+        assert_eq!(pool.unconfirmed.pop(), Some(hash1));
+        assert_eq!(pool.unconfirmed.pop(), Some(hash2));
+    }
+
+    #[test]
+    fn sequenced_reuse_and_gap_rejection() {
+        let mut pool = Pool::default();
+        let (hash, tx) = create_sequenced_tx(3);
+        pool.insert_sequenced(hash, tx, 3).unwrap();
+
+        let reused = create_test_unit_tx_v2(Some(Expiry::Height(100)), Some(3));
+        let err = pool
+            .insert_sequenced(reused.get_primary_hash(), reused, 3)
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::InvalidSequence);
+
+        let (hash, tx) = create_sequenced_tx(2);
+        let err = pool.insert_sequenced(hash, tx, 3).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::InvalidSequence);
+
+        let (hash, tx) = create_sequenced_tx(4 + MAX_SEQUENCE_GAP);
+        let err = pool.insert_sequenced(hash, tx, 3).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::InvalidSequence);
+        assert!(!pool.txs.contains_key(&hash));
+    }
+
+    #[test]
+    fn sequenced_stale_dropped() {
+        let mut pool = Pool::default();
+        let (hash1, tx1) = create_sequenced_tx(1);
+        let (hash2, tx2) = create_sequenced_tx(2);
+        pool.insert_sequenced(hash1, tx1, 0).unwrap();
+
+        // Another transaction with sequence number 1 has been executed.
+        pool.insert_sequenced(hash2, tx2, 2).unwrap();
+
+        assert!(!pool.txs.contains_key(&hash1));
+        assert!(pool.future.is_empty());
+        assert_eq!(pool.unconfirmed.pop(), Some(hash2));
+    }
+
+    #[test]
+    fn sequenced_held_promoted_after_block() {
+        let mut pool = Pool::default();
+        let (hash1, tx1) = create_sequenced_tx(1);
+        let (hash2, tx2) = create_sequenced_tx(2);
+        let caller = tx1.get_caller().to_account_id();
+        pool.insert_sequenced(hash1, tx1, 0).unwrap();
+        pool.insert_sequenced(hash2, tx2, 0).unwrap();
+        assert_eq!(
+            pool.held_callers().into_iter().collect::<Vec<_>>(),
+            vec![caller.clone()]
+        );
+
+        // The sequence number 0 has been executed within a block.
+        let evicted = pool.update_sequences(&caller, 1);
+
+        assert!(evicted.is_empty());
+        assert!(pool.future.is_empty());
+        assert!(pool.held.is_empty());
+        assert!(pool.held_callers().is_empty());
+        assert_eq!(pool.unconfirmed.pop(), Some(hash1));
+        assert_eq!(pool.unconfirmed.pop(), Some(hash2));
+    }
+
+    #[test]
+    fn sequenced_executed_forgotten() {
+        let mut pool = Pool::default();
+        let (hash0, tx0) = create_sequenced_tx(0);
+        let (hash1, tx1) = create_sequenced_tx(1);
+        pool.insert_sequenced(hash0, tx0, 0).unwrap();
+        pool.insert_sequenced(hash1, tx1, 0).unwrap();
+        assert_eq!(pool.unconfirmed.pop(), Some(hash0));
+        assert_eq!(pool.unconfirmed.pop(), Some(hash1));
+
+        pool.remove_executed(&[hash0, hash1]);
+
+        assert!(pool.txs.is_empty());
+        assert!(pool.sequences.is_empty());
+        assert!(pool.sequenced.is_empty());
+    }
+
+    #[test]
+    fn sequenced_held_count_against_limits() {
+        let mut pool = create_limited_pool(2, 10);
+        let (hash1, tx1) = create_sequenced_tx(1);
+        let (hash2, tx2) = create_sequenced_tx(2);
+        let (hash0, tx0) = create_tx(0, 20, 0);
+        pool.insert_sequenced(hash2, tx2, 0).unwrap();
+        pool.insert_unconfirmed(hash0, tx0).unwrap();
+
+        let err = pool.insert_sequenced(hash1, tx1.clone(), 0).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::PoolLimitExceeded);
+        assert_eq!(pool.stats().txs, 2);
+
+        // Only the queued transactions are evicted.
+        let (hash, tx) = create_tx(1, 30, 1);
+        assert_eq!(pool.insert_unconfirmed(hash, tx).unwrap(), vec![hash0]);
+        assert!(pool.future.contains_key(&hash2));
+
+        pool.limits.max_txs = 10;
+        pool.limits.max_txs_per_caller = 1;
+        let err = pool.insert_sequenced(hash1, tx1, 0).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::PoolLimitExceeded);
+    }
+
+    #[test]
+    fn sequenced_replacement() {
+        let mut pool = Pool::default();
+        let (hash0, tx0) = create_sequenced_tx(0);
+        let (hash1, tx1) = create_sequenced_tx(1);
+        pool.insert_sequenced(hash0, tx0, 0).unwrap();
+        pool.insert_sequenced(hash1, tx1, 0).unwrap();
+
+        let mut tx = create_test_unit_tx_v2(None, Some(0));
+        if let Transaction::UnitTransaction(ref mut signed) = tx {
+            if let TransactionData::V2(ref mut data) = signed.data {
+                data.fuel_limit += 1;
+            }
+        }
+        let hash = tx.get_primary_hash();
+        let caller = tx.get_caller().to_account_id();
+
+        assert_eq!(pool.sequence_holder(&tx, 0), Some(hash0));
+        assert_eq!(pool.sequence_holder(&tx, 1), None);
+        pool.replace_sequenced(&hash0, hash, tx).unwrap();
+
+        assert!(!pool.txs.contains_key(&hash0));
+        assert_eq!(pool.sequences.get(&(caller.clone(), 0)), Some(&hash));
+        assert!(!pool.sequenced.contains_key(&hash0));
+        assert!(pool.remove_unconfirmed(&hash1));
+        assert!(!pool.sequences.contains_key(&(caller, 1)));
+        assert!(!pool.sequenced.contains_key(&hash1));
+        assert_eq!(pool.unconfirmed.pop(), Some(hash));
+        assert_eq!(pool.unconfirmed.pop(), None);
+    }
+
+    #[test]
+    fn sequenced_replacement_lower_fuel() {
+        let mut pool = Pool::default();
+        let (hash, tx) = create_sequenced_tx(0);
+        pool.insert_sequenced(hash, tx, 0).unwrap();
+        let replacement = create_test_unit_tx_v2(Some(Expiry::Height(100)), Some(0));
+
+        let err = pool
+            .replace_sequenced(&hash, replacement.get_primary_hash(), replacement)
+            .unwrap_err();
+
+        assert_eq!(err.kind, ErrorKind::InvalidSequence);
+        assert!(pool.unconfirmed.contains(&hash));
+    }
+
+    #[test]
+    fn sequenced_replacement_pool_full() {
+        let mut pool = Pool::default();
+        let (hash, tx) = create_sequenced_tx(0);
+        pool.insert_sequenced(hash, tx, 0).unwrap();
+        pool.limits.max_bytes = pool.stats().bytes;
+        let mut replacement = create_test_unit_tx_v2(Some(Expiry::Height(100)), Some(0));
+        if let Transaction::UnitTransaction(ref mut signed) = replacement {
+            if let TransactionData::V2(ref mut data) = signed.data {
+                data.fuel_limit += 1;
+            }
+        }
+
+        let err = pool
+            .replace_sequenced(&hash, replacement.get_primary_hash(), replacement)
+            .unwrap_err();
+
+        assert_eq!(err.kind, ErrorKind::PoolLimitExceeded);
+        assert!(pool.unconfirmed.contains(&hash));
+    }
+
+    #[test]
+    fn pending_txs_snapshot() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("pool");
+        let mut pool = Pool::default();
+        let (hash0, tx0) = create_tx(0, 20, 0);
+        let (hash1, tx1) = create_sequenced_tx(1);
+        let (hash2, tx2) = create_tx(1, 20, 2);
+        pool.insert_unconfirmed(hash0, tx0.clone()).unwrap();
+        pool.insert_sequenced(hash1, tx1.clone(), 0).unwrap();
+        pool.insert_unconfirmed(hash2, tx2.clone()).unwrap();
+
+        assert!(load_snapshot(&path).unwrap().is_empty());
+        store_snapshot(&path, &pool.pending_txs()).unwrap();
+
+        assert_eq!(load_snapshot(&path).unwrap(), vec![tx0, tx2, tx1]);
+    }
+
+    #[test]
+    fn pending_revision_changes() {
+        let mut pool = Pool::default();
+        let (hash0, tx0) = create_tx(0, 20, 0);
+        let (hash1, tx1) = create_sequenced_tx(1);
+        let revision = pool.pending_revision();
+
+        pool.insert_unconfirmed(hash0, tx0).unwrap();
+        let queued = pool.pending_revision();
+        assert_ne!(queued, revision);
+        pool.insert_sequenced(hash1, tx1, 0).unwrap();
+        let held = pool.pending_revision();
+        assert_ne!(held, queued);
+        assert_eq!(pool.pending_revision(), held);
+        pool.unconfirmed.pop();
+        assert_ne!(pool.pending_revision(), held);
+    }
+
+    #[test]
+    fn pool_stats() {
+        let mut pool = create_limited_pool(10, 10);
+        let (hash, tx) = create_tx(0, 20, 0);
+        let size = rmp_serialize(&tx).unwrap().len();
+        pool.insert_unconfirmed(hash, tx).unwrap();
+
+        let stats = pool.stats();
+
+        assert_eq!(stats.txs, 1);
+        assert_eq!(stats.bytes, size);
+        assert_eq!(stats.limits, pool.limits);
+    }
 }
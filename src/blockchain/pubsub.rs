@@ -24,7 +24,7 @@
 //! interpreted as an implicit unsubscribe.
 
 use super::{BlockResponseSender, Message};
-use crate::base::serialize::rmp_serialize;
+use crate::{base::serialize::rmp_serialize, crypto::Hash};
 use serde::de::Error as SerdeError;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -44,10 +44,14 @@ bitflags::bitflags! {
         const UNICAST_REQUEST = 1 << 4;
         /// New block has been executed.
         const BLOCK_EXEC = 1 << 5;
+        /// Unconfirmed transaction replaced by a new one.
+        const TRANSACTION_REPLACED = 1 << 6;
+        /// Unconfirmed transaction evicted by the pool limits.
+        const TRANSACTION_EVICTED = 1 << 7;
     }
 }
 
-const EVENTS_NUM: usize = 6;
+const EVENTS_NUM: usize = 8;
 
 impl Serialize for Event {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -216,6 +220,19 @@ impl PubSub {
             }
         }
     }
+
+    /// Publish the transactions evicted from the pool to subscribers.
+    pub fn publish_evicted(&mut self, hashes: Vec<Hash>) {
+        for hash in hashes {
+            debug!("Evicted transaction: {}", hex::encode(hash));
+            if self.has_subscribers(Event::TRANSACTION_EVICTED) {
+                self.publish(
+                    Event::TRANSACTION_EVICTED,
+                    Message::TransactionEvicted { hash },
+                );
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -260,6 +277,24 @@ mod tests {
         assert!(pubsub.has_subscribers(Event::BLOCK_EXEC));
     }
 
+    #[test]
+    fn events_subscribe_transaction_replaced() {
+        let mut pubsub = PubSub::default();
+        let (sender, _) = channel::simple_channel();
+
+        pubsub.subscribe(
+            "foo".to_string(),
+            Event::TRANSACTION | Event::TRANSACTION_REPLACED | Event::TRANSACTION_EVICTED,
+            0,
+            sender,
+        );
+
+        assert!(pubsub.has_subscribers(Event::TRANSACTION));
+        assert!(pubsub.has_subscribers(Event::TRANSACTION_REPLACED));
+        assert!(pubsub.has_subscribers(Event::TRANSACTION_EVICTED));
+        assert!(!pubsub.has_subscribers(Event::BLOCK_EXEC));
+    }
+
     #[test]
     fn events_unsubscribe() {
         let mut pubsub = PubSub::default();
@@ -26,17 +26,24 @@
 
 use crate::{
     base::{
-        schema::{SmartContractEvent, FUEL_LIMIT},
+        schema::{
+            BlockchainSettings, FuelEstimate, FuelSchedule, SmartContractEvent, TransactionData,
+            FUEL_LIMIT,
+        },
+        serialize::rmp_deserialize,
         RwLock,
     },
     crypto::{drand::SeedSource, Hash},
-    db::{Db, DbFork},
+    db::{Db, DbFork, RecordingFork, StateDiff},
     wm::{get_fuel_consumed_for_error, CtxArgs, Wm, WmLocal, MAX_FUEL},
-    ErrorKind, Receipt,
+    ErrorKind, Receipt, Result, Transaction,
 };
 
 use std::{sync::Arc, time::SystemTime};
 
+/// Margin added to the estimated burned fuel to suggest a fuel limit (percent).
+const FUEL_LIMIT_MARGIN: u64 = 10;
+
 /// Result struct for bulk transaction
 #[derive(Serialize, Deserialize)]
 pub struct BulkResult {
@@ -65,6 +72,8 @@ pub(crate) struct Executor<D: Db> {
     seed: Arc<SeedSource>,
     /// Validator flag
     is_validator: Arc<bool>,
+    /// Fuel pricing schedule, loaded from the blockchain settings
+    fuel_schedule: Option<FuelSchedule>,
 }
 
 // DELETE
@@ -111,34 +120,92 @@ impl<D: Db> Executor<D> {
     pub fn new(db: Arc<RwLock<D>>, seed: Arc<SeedSource>) -> Self {
         let wm = WmLocal::new(10);
 
+        let fuel_schedule = db
+            .read()
+            .load_configuration("blockchain:settings")
+            .and_then(|buf| rmp_deserialize::<BlockchainSettings>(&buf).ok())
+            .and_then(|settings| settings.fuel_schedule);
+
         Executor {
             db,
             wm,
             burn_fuel_method: String::new(),
             seed,
             is_validator: Arc::new(false),
+            fuel_schedule,
         }
     }
 
-    // Calculates the fuel consumed by the transaction execution
+    // Calculates the fuel consumed by a smart contract execution
     fn calculate_burned_fuel(&self, wm_fuel: u64) -> u64 {
-        // TODO find a f(_wm_fuel) to calculate the fuel in TRINCI
-        warn!("calculate_burned_fuel::{}", wm_fuel);
-        // wm_fuel
-        if wm_fuel == 0 {
-            0
-        } else {
-            FUEL_LIMIT
+        match self.fuel_schedule {
+            Some(schedule) => schedule.wm_fuel_cost(wm_fuel),
+            // Legacy flat fee.
+            None if wm_fuel == 0 => 0,
+            None => FUEL_LIMIT,
+        }
+    }
+
+    // Wasm machine fuel charged for a smart contract execution.
+    // Without a schedule the wasm machine faults, e.g. traps, are not charged.
+    fn charged_wm_fuel<T>(&self, wm_fuel: u64, result: &Result<T>) -> u64 {
+        match result {
+            Err(err) if self.fuel_schedule.is_none() && err.kind == ErrorKind::WasmMachineFault => {
+                0
+            }
+            _ => wm_fuel,
+        }
+    }
+
+    // Calculates the fuel burned by a transaction from the fuel consumed by
+    // its smart contract executions
+    fn calculate_transaction_fuel(&self, fuel: u64) -> u64 {
+        match self.fuel_schedule {
+            Some(schedule) => schedule.base_fuel.saturating_add(fuel),
+            None => self.calculate_burned_fuel(fuel),
+        }
+    }
+
+    // Fuel charged for a transaction failing before the smart contract execution
+    fn error_fuel(&self) -> u64 {
+        match self.fuel_schedule {
+            Some(schedule) => schedule.base_fuel,
+            None => get_fuel_consumed_for_error(),
         }
     }
 
     // Calculated the max fuel allow to spend
     // from the tx fuel_limit field
-    fn calculate_internal_fuel_limit(&self, _fuel_limit: u64) -> u64 {
-        // TODO create a method the get the fuel_limit
-        MAX_FUEL
+    fn calculate_internal_fuel_limit(&self, fuel_limit: u64) -> u64 {
+        match self.fuel_schedule {
+            Some(schedule) => schedule.wm_fuel_limit(fuel_limit).min(MAX_FUEL),
+            None => MAX_FUEL,
+        }
     }
 
+    // Seed and timestamp of the read-only executions
+    fn exec_context(network: &str) -> (Arc<SeedSource>, u64) {
+        let hash_hex: &str =
+            "c4221220879ecb0adedfa6a8aa19d972d225c3ce74d95619fda302ab4090fcff2ab45e6f";
+        let hash = Hash::from_hex(&hash_hex[4..]).unwrap();
+
+        let seed = Arc::new(SeedSource::new(
+            network.to_owned(),
+            hash.to_bytes(),
+            hash,
+            hash,
+            hash,
+        ));
+
+        let block_timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        (seed, block_timestamp)
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn exec(
         &mut self,
         fork: &mut <D as Db>::DbForkType,
@@ -149,6 +216,48 @@ impl<D: Db> Executor<D> {
         method: String,
         args: Vec<u8>,
         network: String,
+    ) -> Receipt {
+        let receipt = self.run(
+            fork, max_fuel, origin, target, contract, method, args, network,
+        );
+        fork.rollback();
+        receipt
+    }
+
+    /// Simulates a read-only execution, returning the receipt along with the
+    /// accounts state changes the execution would apply.
+    /// The execution changes are discarded.
+    #[allow(clippy::too_many_arguments)]
+    pub fn simulate(
+        &mut self,
+        fork: <D as Db>::DbForkType,
+        max_fuel: u64,
+        origin: String,
+        target: String,
+        contract: Option<Hash>,
+        method: String,
+        args: Vec<u8>,
+        network: String,
+    ) -> (Receipt, StateDiff) {
+        let mut fork = RecordingFork::new(fork);
+        let receipt = self.run(
+            &mut fork, max_fuel, origin, target, contract, method, args, network,
+        );
+        (receipt, fork.diff())
+    }
+
+    // Executes a smart contract method, the changes are left in the fork
+    #[allow(clippy::too_many_arguments)]
+    fn run(
+        &mut self,
+        fork: &mut dyn DbFork,
+        max_fuel: u64,
+        origin: String,
+        target: String,
+        contract: Option<Hash>,
+        method: String,
+        args: Vec<u8>,
+        network: String,
     ) -> Receipt {
         fork.flush();
 
@@ -160,22 +269,7 @@ impl<D: Db> Executor<D> {
             caller: &origin,
         };
 
-        let hash_hex: &str =
-            "c4221220879ecb0adedfa6a8aa19d972d225c3ce74d95619fda302ab4090fcff2ab45e6f";
-        let hash = Hash::from_hex(&hash_hex[4..]).unwrap();
-
-        let seed = Arc::new(SeedSource::new(
-            network.clone(),
-            hash.to_bytes(),
-            hash,
-            hash,
-            hash,
-        ));
-
-        let block_timestamp = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
+        let (seed, block_timestamp) = Self::exec_context(&network);
 
         let app_hash =
             self.wm
@@ -200,6 +294,7 @@ impl<D: Db> Executor<D> {
                     initial_fuel,
                     block_timestamp,
                 );
+                let fuel_consumed = self.charged_wm_fuel(fuel_consumed, &result);
 
                 if result.is_err() {
                     fork.rollback();
@@ -228,12 +323,11 @@ impl<D: Db> Executor<D> {
                 };
 
                 // FIXME LOG REAL CONSUMPTION
-                log_wm_fuel_consumed_st(hash, origin, method, &args, fuel_consumed);
+                log_wm_fuel_consumed_st(app_hash, origin, method, &args, fuel_consumed);
 
-                // Total fuel burned
-                let burned_fuel = self.calculate_burned_fuel(fuel_consumed);
-
-                fork.rollback();
+                // Total fuel burned, as estimated for a transaction
+                let burned_fuel =
+                    self.calculate_transaction_fuel(self.calculate_burned_fuel(fuel_consumed));
 
                 debug!("seed: {:?}", seed);
 
@@ -246,18 +340,124 @@ impl<D: Db> Executor<D> {
                     events,
                 };
             }
-            Err(e) => {
-                fork.rollback();
+            Err(e) => Receipt {
+                height: 0,
+                burned_fuel: self.error_fuel(),
+                index: 0 as u32,
+                success: false,
+                returns: e.to_string_full().as_bytes().to_vec(),
+                events: None,
+            },
+        }
+    }
 
-                Receipt {
-                    height: 0,
-                    burned_fuel: get_fuel_consumed_for_error(), // FIXME * How much should the caller pay for this operation?
-                    index: 0 as u32,
-                    success: false,
-                    returns: e.to_string_full().as_bytes().to_vec(),
-                    events: None,
+    /// Estimates the fuel burned by a unit or bulk transaction, executing it
+    /// against the current state. The execution changes are discarded and
+    /// the transaction fuel limit is not enforced.
+    pub fn estimate(&mut self, fork: &mut <D as Db>::DbForkType, tx: &Transaction) -> FuelEstimate {
+        fork.flush();
+
+        let calls: Vec<&TransactionData> = match tx {
+            Transaction::UnitTransaction(tx) => vec![&tx.data],
+            Transaction::BulkTransaction(tx) => match &tx.data {
+                TransactionData::BulkV1(bulk_tx) => std::iter::once(&bulk_tx.txs.root.data)
+                    .chain(bulk_tx.txs.nodes.iter().flatten().map(|node| &node.data))
+                    .collect(),
+                _ => vec![],
+            },
+        };
+
+        let (seed, block_timestamp) = Self::exec_context(tx.get_network());
+
+        let mut wm_fuel = 0u64;
+        let mut fuel = 0u64;
+        let mut result = Ok(vec![]);
+        for (index, data) in calls.into_iter().enumerate() {
+            if let TransactionData::BulkEmpyRoot(_) = data {
+                continue;
+            }
+            let origin = data.get_caller().to_account_id();
+            let ctx_args = CtxArgs {
+                origin: &origin,
+                owner: data.get_account(),
+                caller: &origin,
+            };
+            let app_hash = match self.wm.app_hash_check(
+                fork,
+                *data.get_contract(),
+                ctx_args,
+                seed.clone(),
+                block_timestamp,
+            ) {
+                Ok(app_hash) => app_hash,
+                Err(err) if index == 0 => {
+                    // The transaction fails before any execution.
+                    fork.rollback();
+                    return self.fuel_estimate(Err(err), 0, self.error_fuel());
                 }
+                Err(err) => {
+                    result = Err(err);
+                    break;
+                }
+            };
+
+            let (consumed, res) = self.wm.call(
+                fork,
+                0,
+                data.get_network(),
+                &origin,
+                data.get_account(),
+                &origin,
+                app_hash,
+                data.get_method(),
+                data.get_args(),
+                seed.clone(),
+                &mut vec![],
+                MAX_FUEL,
+                block_timestamp,
+            );
+            let consumed = self.charged_wm_fuel(consumed, &res);
+            wm_fuel = wm_fuel.saturating_add(consumed);
+            fuel = fuel.saturating_add(self.calculate_burned_fuel(consumed));
+            result = res;
+            if result.is_err() {
+                break;
             }
         }
+
+        fork.rollback();
+
+        let burned_fuel = self.calculate_transaction_fuel(fuel);
+        self.fuel_estimate(result, wm_fuel, burned_fuel)
+    }
+
+    // Builds the fuel estimate of an execution, suggesting a fuel limit
+    // with a safety margin over the burned fuel
+    fn fuel_estimate(
+        &self,
+        result: Result<Vec<u8>>,
+        wm_fuel: u64,
+        burned_fuel: u64,
+    ) -> FuelEstimate {
+        let (success, returns) = match result {
+            Ok(value) => (true, value),
+            Err(err) => {
+                let msg = match err.kind {
+                    ErrorKind::SmartContractFault | ErrorKind::ResourceNotFound => {
+                        err.to_string_full()
+                    }
+                    _ => err.to_string(),
+                };
+                (false, msg.as_bytes().to_vec())
+            }
+        };
+        let margin = burned_fuel.saturating_mul(FUEL_LIMIT_MARGIN) / 100;
+        FuelEstimate {
+            success,
+            returns,
+            wm_fuel,
+            burned_fuel,
+            fuel_limit: burned_fuel.saturating_add(margin),
+        }
     }
 }
@@ -0,0 +1,366 @@
+// This file is part of TRINCI.
+//
+// Copyright (C) 2021 Affidaty Spa.
+//
+// TRINCI is free software: you can redistribute it and/or modify it under
+// the terms of the GNU Affero General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or (at your
+// option) any later version.
+//
+// TRINCI is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License
+// for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with TRINCI. If not, see <https://www.gnu.org/licenses/>.
+
+//! Deterministic blocks re-execution.
+//!
+//! The replayer re-executes the transactions of the blocks stored in a source
+//! database on top of a scratch database, then compares the recomputed
+//! `state_hash`, `rxs_hash` and receipts with the stored ones.
+//! This allows to check that a node version is consensus-compatible with the
+//! existing history.
+//!
+//! The scratch database shall be empty or contain a prefix of the source chain
+//! (e.g. a database bootstrapped from a snapshot). The blocks between the
+//! scratch last block and the replay range are executed without checks.
+//! Each replayed block is committed to the scratch database as stored in the
+//! source database, thus the following blocks are chained to the original ones.
+
+use crate::{
+    base::{Mutex, RwLock},
+    blockchain::{executor::Executor, pool::Pool, pubsub::PubSub},
+    crypto::{drand::SeedSource, ed25519, Hash, Hashable},
+    db::{Db, DbFork},
+    wm::Wm,
+    Error, ErrorKind, KeyPair, Receipt, Result,
+};
+use std::sync::Arc;
+
+#[cfg(feature = "indexer")]
+use crate::blockchain::indexer::{Indexer, IndexerConfig};
+
+/// Transaction receipt divergence.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReceiptDiff {
+    /// Transaction position within the block.
+    pub index: u32,
+    /// Transaction hash.
+    pub hash: Hash,
+    /// Stored receipt.
+    pub stored: Option<Receipt>,
+    /// Receipt produced by the replay.
+    pub replayed: Option<Receipt>,
+}
+
+/// Block divergence.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockDiff {
+    /// Block height.
+    pub height: u64,
+    /// Stored and replayed state hash.
+    pub state_hash: (Hash, Hash),
+    /// Stored and replayed receipts trie root.
+    pub rxs_hash: (Hash, Hash),
+    /// Diverging receipts.
+    pub receipts: Vec<ReceiptDiff>,
+}
+
+/// Blocks replayer.
+pub struct Replayer<S: Db, D: Db, W: Wm> {
+    /// Database holding the blocks to replay.
+    source: Arc<RwLock<S>>,
+    /// Database where the blocks are re-executed.
+    scratch: Arc<RwLock<D>>,
+    /// Pool used to feed the executor with the source transactions.
+    pool: Arc<RwLock<Pool>>,
+    /// Drand seed, updated after each block as done by the executor.
+    seed: Arc<SeedSource>,
+    /// Transactions executor.
+    executor: Executor<D, W>,
+}
+
+impl<S: Db, D: Db, W: Wm> Replayer<S, D, W> {
+    /// Create a new replayer.
+    /// The `wm` (typically a `WmLocal`) and the `seed` shall be configured as
+    /// the ones of the node that executed the source blocks.
+    pub fn new(source: Arc<RwLock<S>>, scratch: D, wm: W, seed: SeedSource) -> Self {
+        let pool = Arc::new(RwLock::new(Pool::default()));
+        let scratch = Arc::new(RwLock::new(scratch));
+        let seed = Arc::new(seed);
+        // Blocks are not built, thus the keypair is never used.
+        let keypair = Arc::new(KeyPair::Ed25519(ed25519::KeyPair::from_random()));
+        let executor = Executor::new(
+            pool.clone(),
+            scratch.clone(),
+            Arc::new(Mutex::new(wm)),
+            Arc::new(Mutex::new(PubSub::new())),
+            keypair,
+            seed.clone(),
+            String::new(),
+            #[cfg(feature = "indexer")]
+            Indexer::new(IndexerConfig::default()),
+        );
+        Replayer {
+            source,
+            scratch,
+            pool,
+            seed,
+            executor,
+        }
+    }
+
+    /// Set the burn fuel method used by the source node.
+    pub fn set_burn_fuel_method(&mut self, burn_fuel_method: String) {
+        self.executor.set_burn_fuel_method(burn_fuel_method);
+    }
+
+    /// Scratch database.
+    pub fn scratch(&self) -> Arc<RwLock<D>> {
+        self.scratch.clone()
+    }
+
+    /// Replay the blocks with height within `start..=end`.
+    /// Returns the first block diverging from the stored one, if any.
+    pub fn replay(&mut self, start: u64, end: u64) -> Result<Option<BlockDiff>> {
+        if start > end {
+            return Err(Error::new_ext(ErrorKind::Other, "bad replay range"));
+        }
+        let next = match self.scratch.read().load_block(u64::MAX) {
+            Some(block) => {
+                let stored = self.source.read().load_block(block.data.height);
+                if stored.map(|stored| stored.data.primary_hash())
+                    != Some(block.data.primary_hash())
+                {
+                    return Err(Error::new_ext(
+                        ErrorKind::BrokenIntegrity,
+                        "scratch chain differs from the source one",
+                    ));
+                }
+                block.data.height + 1
+            }
+            None => 0,
+        };
+        if next > start {
+            return Err(Error::new_ext(
+                ErrorKind::Other,
+                "scratch database is beyond the replay range start",
+            ));
+        }
+        for height in next..=end {
+            let diff = self.replay_block(height, height >= start)?;
+            if diff.is_some() {
+                return Ok(diff);
+            }
+        }
+        Ok(None)
+    }
+
+    fn replay_block(&mut self, height: u64, check: bool) -> Result<Option<BlockDiff>> {
+        let source = self.source.read();
+        let block = source
+            .load_block(height)
+            .ok_or_else(|| Error::new(ErrorKind::ResourceNotFound))?;
+        let txs_hashes = source.load_transactions_hashes(height).unwrap_or_default();
+        {
+            let mut pool = self.pool.write();
+            for hash in txs_hashes.iter() {
+                let tx = source
+                    .load_transaction(hash)
+                    .ok_or_else(|| Error::new(ErrorKind::ResourceNotFound))?;
+                pool.txs.insert(*hash, Some(tx));
+            }
+        }
+
+        // The seed is the one set by the executor after the previous block.
+        if let Some(prev) = height
+            .checked_sub(1)
+            .and_then(|height| source.load_block(height))
+        {
+            *self.seed.prev_hash.lock() = prev.data.primary_hash();
+            *self.seed.txs_hash.lock() = prev.data.txs_hash;
+            *self.seed.rxs_hash.lock() = prev.data.rxs_hash;
+            *self.seed.previous_seed.lock() = 0;
+        }
+
+        let mut fork = self.scratch.write().fork_create();
+        let rxs_hashes =
+            self.executor
+                .exec_transactions(&mut fork, height, &txs_hashes, block.data.timestamp);
+        self.pool.write().txs.clear();
+        fork.store_transactions_hashes(height, txs_hashes.clone());
+        let rxs_hash = fork.store_receipts_hashes(height, rxs_hashes);
+        let state_hash = fork.state_hash("");
+        fork.store_block(block.clone());
+        self.scratch.write().fork_merge(fork)?;
+
+        if !check {
+            return Ok(None);
+        }
+        let scratch = self.scratch.read();
+        let receipts: Vec<ReceiptDiff> = txs_hashes
+            .iter()
+            .enumerate()
+            .filter_map(|(index, hash)| {
+                let stored = source.load_receipt(hash);
+                let replayed = scratch.load_receipt(hash);
+                (stored != replayed).then(|| ReceiptDiff {
+                    index: index as u32,
+                    hash: *hash,
+                    stored,
+                    replayed,
+                })
+            })
+            .collect();
+        if state_hash == block.data.state_hash
+            && rxs_hash == block.data.rxs_hash
+            && receipts.is_empty()
+        {
+            return Ok(None);
+        }
+        Ok(Some(BlockDiff {
+            height,
+            state_hash: (block.data.state_hash, state_hash),
+            rxs_hash: (block.data.rxs_hash, rxs_hash),
+            receipts,
+        }))
+    }
+}
+
+#[cfg(all(test, feature = "with-rocksdb"))]
+mod tests {
+    use super::*;
+    use crate::{
+        base::schema::{tests::create_test_unit_tx, FUEL_LIMIT},
+        blockchain::pool::BlockInfo,
+        crypto::sign::tests::create_test_keypair,
+        db::RocksDb,
+        wm::MockWm,
+        Transaction,
+    };
+    use tempfile::TempDir;
+
+    fn create_test_seed() -> SeedSource {
+        let nonce = vec![0x12, 0x34, 0x56, 0x78, 0x90, 0x12, 0x34, 0x56];
+        let hash = Hash::default();
+        SeedSource::new("skynet".to_owned(), nonce, hash, hash, hash)
+    }
+
+    // Each call stores and returns the given value.
+    fn create_wm_mock(value: u8) -> MockWm {
+        let mut wm = MockWm::new();
+        wm.expect_app_hash_check()
+            .returning(|_, _, _, _, _| Ok(Hash::default()));
+        wm.expect_call().returning(
+            move |fork: &mut dyn DbFork,
+                  _,
+                  _,
+                  _,
+                  owner: &str,
+                  _,
+                  _,
+                  _,
+                  _,
+                  _,
+                  _,
+                  #[cfg(feature = "indexer")] _,
+                  _,
+                  _| {
+                fork.store_account_data(owner, "k", vec![value]);
+                (0, Ok(vec![value]))
+            },
+        );
+        wm
+    }
+
+    fn create_source_chain(dir: &TempDir) -> Arc<RwLock<RocksDb>> {
+        let db = Arc::new(RwLock::new(RocksDb::new(dir.path())));
+        let pool = Arc::new(RwLock::new(Pool::default()));
+        for height in 0..3 {
+            let mut tx = create_test_unit_tx(FUEL_LIMIT);
+            if let Transaction::UnitTransaction(ref mut tx) = tx {
+                tx.data.set_nonce(vec![height as u8; 8]);
+            }
+            let hash = tx.primary_hash();
+            let mut pool = pool.write();
+            pool.txs.insert(hash, Some(tx));
+            pool.confirmed.insert(
+                height,
+                BlockInfo {
+                    hash: None,
+                    validator: None,
+                    signature: None,
+                    txs_hashes: Some(vec![hash]),
+                    timestamp: height,
+                },
+            );
+        }
+        let mut executor = Executor::new(
+            pool,
+            db.clone(),
+            Arc::new(Mutex::new(create_wm_mock(0))),
+            Arc::new(Mutex::new(PubSub::new())),
+            Arc::new(create_test_keypair()),
+            Arc::new(create_test_seed()),
+            String::new(),
+            #[cfg(feature = "indexer")]
+            Indexer::new(IndexerConfig::default()),
+        );
+        executor.run(false, Arc::new(|_: String| Result::Ok(true)));
+        db
+    }
+
+    fn create_replayer(
+        source: Arc<RwLock<RocksDb>>,
+        dir: &TempDir,
+        value: u8,
+    ) -> Replayer<RocksDb, RocksDb, MockWm> {
+        let scratch = RocksDb::new(dir.path());
+        Replayer::new(source, scratch, create_wm_mock(value), create_test_seed())
+    }
+
+    #[test]
+    fn replay_consistent_history() {
+        let (source_dir, scratch_dir) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        let source = create_source_chain(&source_dir);
+        let mut replayer = create_replayer(source.clone(), &scratch_dir, 0);
+
+        let diff = replayer.replay(0, 2).unwrap();
+
+        assert_eq!(diff, None);
+        assert_eq!(
+            replayer.scratch().read().load_block(u64::MAX),
+            source.read().load_block(2)
+        );
+    }
+
+    #[test]
+    fn replay_divergent_history() {
+        let (source_dir, scratch_dir) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        let source = create_source_chain(&source_dir);
+        let mut replayer = create_replayer(source, &scratch_dir, 1);
+
+        let diff = replayer.replay(1, 2).unwrap().unwrap();
+
+        assert_eq!(diff.height, 1);
+        assert_ne!(diff.state_hash.0, diff.state_hash.1);
+        assert_ne!(diff.rxs_hash.0, diff.rxs_hash.1);
+        assert_eq!(diff.receipts.len(), 1);
+        assert_eq!(diff.receipts[0].index, 0);
+        assert_eq!(diff.receipts[0].stored.as_ref().unwrap().returns, vec![0]);
+        assert_eq!(diff.receipts[0].replayed.as_ref().unwrap().returns, vec![1]);
+    }
+
+    #[test]
+    fn replay_range_before_scratch_tail() {
+        let (source_dir, scratch_dir) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        let source = create_source_chain(&source_dir);
+        let mut replayer = create_replayer(source, &scratch_dir, 0);
+        replayer.replay(0, 1).unwrap();
+
+        assert!(replayer.replay(1, 2).is_err());
+        assert_eq!(replayer.replay(2, 2).unwrap(), None);
+    }
+}
@@ -20,6 +20,8 @@ use super::indexer::IndexerConfig;
 
 use super::{
     message::{BlockRequestSender, Message},
+    ordering::OrderingPolicy,
+    pool::PoolLimits,
     worker::{BlockWorker, IsValidator},
 };
 
@@ -29,8 +31,9 @@ use crate::{
     crypto::drand::SeedSource,
     db::{Db, DbFork},
     wm::Wm,
-    KeyPair, Transaction,
+    Error, ErrorKind, KeyPair, Result, Transaction,
 };
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::thread::{self, JoinHandle};
 
@@ -115,6 +118,7 @@ impl<D: Db, W: Wm> BlockService<D, W> {
                 return;
             }
         };
+        worker.load_pool_snapshot();
 
         let mut canary = Arc::clone(&self.canary);
         let account_id = self.account_id.clone();
@@ -190,11 +194,31 @@ impl<D: Db, W: Wm> BlockService<D, W> {
     }
 
     /// Put transactions directly in the pool
-    /// If this panics, it panics early at node boot. Not a big deal.
+    /// The transactions that can't be inserted are dropped with a warning.
     pub fn put_txs(&mut self, txs: Vec<Transaction>) {
         self.worker.as_mut().unwrap().put_txs(txs)
     }
 
+    /// Revert the blocks following `height` and put back the reverted
+    /// transactions in the pool as unconfirmed.
+    /// The service shall not be running.
+    ///
+    /// The state is rebuilt from the database history: the history retention
+    /// shall be enabled (see `RocksDb::set_history_retention`) since before
+    /// the execution of the block following `height`, and `height` shall not
+    /// be older than the last block height minus the retention.
+    /// Otherwise a `ResourceNotFound` error is returned and the database is
+    /// left untouched.
+    pub fn revert_to(&mut self, height: u64) -> Result<()> {
+        match self.worker.as_mut() {
+            Some(worker) => worker.revert_to(height),
+            None => Err(Error::new_ext(
+                ErrorKind::Other,
+                "cannot revert while the service is running",
+            )),
+        }
+    }
+
     /// Set the burn fuel method
     /// If this panics, it panics early at node boot. Not a big deal.
     pub fn set_burn_fuel_method(&mut self, burn_fuel_method: String) {
@@ -203,6 +227,52 @@ impl<D: Db, W: Wm> BlockService<D, W> {
             .unwrap()
             .set_burn_fuel_method(burn_fuel_method);
     }
+
+    /// Enable the per-account transactions index.
+    /// Only the blocks executed after the call are indexed.
+    pub fn set_account_txs_index(&mut self, enabled: bool) {
+        self.worker.as_mut().unwrap().set_account_txs_index(enabled);
+    }
+
+    /// Enable the smart contract events index.
+    /// Only the blocks executed after the call are indexed.
+    pub fn set_events_index(&mut self, enabled: bool) {
+        self.worker.as_mut().unwrap().set_events_index(enabled);
+    }
+
+    /// Set the unconfirmed transactions ordering policy.
+    /// Defaults to `FifoOrdering`, use `FuelLimitOrdering` to let the
+    /// submitters pay for priority.
+    pub fn set_pool_ordering(&mut self, policy: Box<dyn OrderingPolicy>) {
+        self.worker.as_mut().unwrap().set_pool_ordering(policy);
+    }
+
+    /// Set the unconfirmed transactions pool limits.
+    /// Submitted transactions exceeding the limits are rejected with
+    /// `PoolLimitExceeded` error, unless lower priority transactions
+    /// can be evicted (see `Pool::insert_unconfirmed`).
+    pub fn set_pool_limits(&mut self, limits: PoolLimits) {
+        self.worker.as_mut().unwrap().set_pool_limits(limits);
+    }
+
+    /// Enable the optimistic parallel execution of the block transactions,
+    /// with a worker for each of the given wasm machines.
+    /// Transactions are executed concurrently on separate forks and
+    /// re-executed in block order when conflicting, thus the resulting state
+    /// and receipts are the ones of the sequential execution.
+    /// Not available with the `indexer` feature.
+    pub fn set_parallel_execution(&mut self, wms: Vec<W>) {
+        self.worker.as_mut().unwrap().set_parallel_execution(wms);
+    }
+
+    /// Set the pending transactions snapshot file.
+    /// The snapshot is refreshed in background while running, at most every
+    /// 30 seconds and only if the pool has changed, and when the service is
+    /// stopped, then reloaded at the next start.
+    /// If this panics, it panics early at node boot. Not a big deal.
+    pub fn set_pool_snapshot(&mut self, path: Option<PathBuf>) {
+        self.worker.as_mut().unwrap().set_pool_snapshot(path);
+    }
 }
 
 #[cfg(test)]
@@ -268,6 +338,17 @@ mod tests {
         assert!(!svc.is_running());
     }
 
+    #[test]
+    fn revert_while_running() {
+        let mut svc = create_block_service();
+        svc.start();
+
+        let res = svc.revert_to(0);
+
+        assert!(res.is_err());
+        svc.stop();
+    }
+
     #[test]
     fn stopped_subthread() {
         let mut svc = create_block_service();
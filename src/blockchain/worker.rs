@@ -21,8 +21,14 @@ use crate::crypto::hash::Hashable;
 use crate::{
     base::{Mutex, RwLock},
     blockchain::{
-        builder::Builder, dispatcher::Dispatcher, executor::Executor, message::*, pool::*,
-        pubsub::PubSub, BlockConfig,
+        builder::Builder,
+        dispatcher::Dispatcher,
+        executor::Executor,
+        message::*,
+        ordering::OrderingPolicy,
+        pool::{self, *},
+        pubsub::PubSub,
+        BlockConfig,
     },
     db::Db,
     wm::Wm,
@@ -34,8 +40,10 @@ use futures::future::FutureExt;
 use futures::{future, prelude::*};
 use std::sync::{Arc, Condvar, Mutex as StdMutex};
 use std::{
+    path::{Path, PathBuf},
     sync::atomic::{AtomicBool, Ordering},
-    time::Duration,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime},
 };
 
 use super::aligner::{AlignerWorker, NodeAligner};
@@ -44,6 +52,9 @@ use super::dispatcher::AlignerInterface;
 #[cfg(feature = "indexer")]
 use super::indexer::{Indexer, IndexerConfig};
 
+/// Min interval between two pending transactions snapshots writes.
+const POOL_SNAPSHOT_INTERVAL: Duration = Duration::from_secs(30);
+
 /// Closure trait to load a wasm binary.
 pub trait IsValidator: Fn(String) -> Result<bool> + Send + Sync + 'static {}
 
@@ -74,6 +85,14 @@ pub struct BlockWorker<D: Db, W: Wm> {
     is_validator: Arc<bool>,
     /// Check the status of the aligner. If true cannot build blocks
     aligner_status: Arc<(StdMutex<bool>, Condvar)>,
+    /// Pending transactions snapshot file.
+    pool_snapshot: Option<PathBuf>,
+    /// Pool revision of the last snapshot written, see `Pool::pending_revision`.
+    pool_snapshot_revision: Arc<Mutex<Option<(u64, u64)>>>,
+    /// Last snapshot write start time.
+    pool_snapshot_time: Instant,
+    /// Background snapshot write, with its completion flag.
+    pool_snapshot_worker: Option<(JoinHandle<()>, Arc<AtomicBool>)>,
 }
 
 impl<D: Db, W: Wm> BlockWorker<D, W> {
@@ -153,6 +172,10 @@ impl<D: Db, W: Wm> BlockWorker<D, W> {
             is_validator_closure: Arc::new(is_validator_closure),
             is_validator: Arc::new(false),
             aligner_status,
+            pool_snapshot: None,
+            pool_snapshot_revision: Arc::new(Mutex::new(None)),
+            pool_snapshot_time: Instant::now(),
+            pool_snapshot_worker: None,
         }
     }
 
@@ -166,6 +189,116 @@ impl<D: Db, W: Wm> BlockWorker<D, W> {
         self.executor.set_burn_fuel_method(burn_fuel_method);
     }
 
+    /// Enable the per-account transactions index
+    pub fn set_account_txs_index(&mut self, enabled: bool) {
+        self.executor.set_account_txs_index(enabled);
+    }
+
+    /// Enable the smart contract events index
+    pub fn set_events_index(&mut self, enabled: bool) {
+        self.executor.set_events_index(enabled);
+    }
+
+    /// Set the unconfirmed transactions ordering policy
+    pub fn set_pool_ordering(&mut self, policy: Box<dyn OrderingPolicy>) {
+        self.executor.pool.write().set_ordering(policy);
+    }
+
+    /// Set the unconfirmed transactions pool limits
+    pub fn set_pool_limits(&mut self, limits: PoolLimits) {
+        self.executor.pool.write().limits = limits;
+    }
+
+    /// Set the wasm machines of the parallel transactions execution
+    pub fn set_parallel_execution(&mut self, wms: Vec<W>) {
+        self.executor.set_parallel_execution(wms);
+    }
+
+    /// Set the pending transactions snapshot file
+    pub fn set_pool_snapshot(&mut self, path: Option<PathBuf>) {
+        self.pool_snapshot = path;
+    }
+
+    /// Put back in the pool the transactions of the pending transactions
+    /// snapshot. Each transaction goes through the same checks of a newly
+    /// submitted one, thus confirmed or invalid transactions are dropped.
+    pub fn load_pool_snapshot(&self) {
+        let path = match &self.pool_snapshot {
+            Some(path) => path,
+            None => return,
+        };
+        let txs = match pool::load_snapshot(path) {
+            Ok(txs) => txs,
+            Err(err) => {
+                warn!("Error loading pool snapshot: {}", err.to_string_full());
+                return;
+            }
+        };
+        let count = txs.len();
+        let mut restored = 0;
+        for tx in txs {
+            let hash = tx.get_primary_hash();
+            match self.dispatcher.put_transaction_internal(tx) {
+                Ok(_) => restored += 1,
+                Err(err) => debug!(
+                    "Dropped snapshot transaction {}: {}",
+                    hex::encode(hash),
+                    err
+                ),
+            }
+        }
+        info!("Restored {} of {} pending transactions", restored, count);
+    }
+
+    // Write the pool snapshot in background if the pool has changed since
+    // the last one, at most once per `POOL_SNAPSHOT_INTERVAL`.
+    fn store_pool_snapshot(&mut self) {
+        let path = match &self.pool_snapshot {
+            Some(path) => path.clone(),
+            None => return,
+        };
+        if self.pool_snapshot_time.elapsed() < POOL_SNAPSHOT_INTERVAL {
+            return;
+        }
+        if let Some((_, done)) = &self.pool_snapshot_worker {
+            if !done.load(Ordering::Acquire) {
+                return;
+            }
+        }
+        let revision = self.executor.pool.read().pending_revision();
+        if *self.pool_snapshot_revision.lock() == Some(revision) {
+            return;
+        }
+        self.wait_pool_snapshot();
+        self.pool_snapshot_time = Instant::now();
+        let pool = self.executor.pool.clone();
+        let last = self.pool_snapshot_revision.clone();
+        let done = Arc::new(AtomicBool::new(false));
+        let worker_done = done.clone();
+        let handle = thread::spawn(move || {
+            write_pool_snapshot(&path, &pool, &last);
+            worker_done.store(true, Ordering::Release);
+        });
+        self.pool_snapshot_worker = Some((handle, done));
+    }
+
+    // Wait for the background snapshot write, if any, then write the last
+    // pool changes.
+    fn flush_pool_snapshot(&mut self) {
+        self.wait_pool_snapshot();
+        if let Some(path) = &self.pool_snapshot {
+            write_pool_snapshot(path, &self.executor.pool, &self.pool_snapshot_revision);
+        }
+    }
+
+    fn wait_pool_snapshot(&mut self) {
+        if let Some((handle, _)) = self.pool_snapshot_worker.take() {
+            if handle.join().is_err() {
+                warn!("Pool snapshot writing thread panicked");
+            }
+        }
+    }
+
     /// Set the block configuration
     pub fn set_config(&mut self, network: String, threshold: usize, timeout: u16) {
         self.config.clone().lock().network = network;
@@ -176,7 +309,18 @@ impl<D: Db, W: Wm> BlockWorker<D, W> {
         self.dispatcher.set_block_timeout(timeout);
     }
 
-    /// Insert transactions directly in the pool
+    /// Revert the blocks following `height` and put back the reverted
+    /// transactions in the pool as unconfirmed, see `Executor::revert_to`.
+    pub fn revert_to(&mut self, height: u64) -> Result<()> {
+        let txs = self.executor.revert_to(height)?;
+        self.put_txs(txs);
+        Ok(())
+    }
+
+    /// Insert transactions directly in the pool.
+    /// Sequenced transactions go through the sequence numbers checks, see
+    /// `Pool::insert_sequenced`.
+    /// The transactions that can't be inserted are dropped with a warning.
     pub fn put_txs(&mut self, txs: Vec<Transaction>) {
         txs.iter().for_each(|tx| {
             let hash = tx.primary_hash();
@@ -184,21 +328,47 @@ impl<D: Db, W: Wm> BlockWorker<D, W> {
 
             // Check the network.
             if self.config.lock().network != tx.get_network() {
-                panic!();
+                warn!("Dropped transaction {}: bad network", hex::encode(hash));
+                return;
             }
 
             // Check if already present in db.
             if self.db.read().contains_transaction(&hash) {
-                panic!();
+                warn!(
+                    "Dropped transaction {}: already confirmed",
+                    hex::encode(hash)
+                );
+                return;
             }
 
+            // Caller next sequence number, for sequenced transactions.
+            let next_sequence = tx.get_sequence().map(|_| {
+                self.db
+                    .read()
+                    .load_account(&tx.get_caller().to_account_id())
+                    .map(|account| account.next_sequence())
+                    .unwrap_or_default()
+            });
+
             let mut pool = self.executor.pool.write();
-            match pool.txs.get_mut(&hash) {
-                None => {
+            match (pool.txs.get_mut(&hash), next_sequence) {
+                (None, Some(next)) => {
+                    if let Err(err) = pool.insert_sequenced(hash, tx.to_owned(), next) {
+                        warn!(
+                            "Dropped transaction {}: {}",
+                            hex::encode(hash),
+                            err.to_string_full()
+                        );
+                    }
+                }
+                (None, None) => {
+                    pool.unconfirmed.push(hash, tx);
                     pool.txs.insert(hash, Some(tx.to_owned()));
-                    pool.unconfirmed.push(hash);
                 }
-                _ => panic!(),
+                _ => warn!(
+                    "Dropped transaction {}: already in the pool",
+                    hex::encode(hash)
+                ),
             }
         });
     }
@@ -219,6 +389,26 @@ impl<D: Db, W: Wm> BlockWorker<D, W> {
         });
     }
 
+    fn prune_expired_txs(&self) {
+        let height = self
+            .db
+            .read()
+            .load_block(u64::MAX)
+            .map(|block| block.data.height + 1)
+            .unwrap_or_default();
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        // We use try_write because we don't want to wait.
+        // If the pool is locked, expired transactions are dropped the next time.
+        if let Some(mut pool) = self.executor.pool.try_write() {
+            pool.remove_expired(height, timestamp)
+                .iter()
+                .for_each(|hash| debug!("Dropped expired transaction: {}", hex::encode(hash)));
+        }
+    }
+
     fn try_exec_block(&self, is_validator: bool, is_validator_closure: Arc<dyn IsValidator>) {
         if !self.executor.can_run(u64::MAX) {
             return;
@@ -262,6 +452,8 @@ impl<D: Db, W: Wm> BlockWorker<D, W> {
 
         let future = future::poll_fn(move |cx: &mut Context<'_>| -> Poll<()> {
             while exec_sleep.poll_unpin(cx).is_ready() {
+                self.prune_expired_txs();
+                self.store_pool_snapshot();
                 if *self.is_validator && *self.aligner_status.0.lock().unwrap() {
                     self.try_build_block(1);
                 }
@@ -271,7 +463,10 @@ impl<D: Db, W: Wm> BlockWorker<D, W> {
 
             loop {
                 match self.rx_chan.poll_next_unpin(cx) {
-                    Poll::Ready(Some((Message::Stop, _))) => return Poll::Ready(()),
+                    Poll::Ready(Some((Message::Stop, _))) => {
+                        self.flush_pool_snapshot();
+                        return Poll::Ready(());
+                    }
                     Poll::Ready(Some((req, res_chan))) => {
                         self.handle_message(req, res_chan.clone())
                     }
@@ -309,3 +504,21 @@ impl<D: Db, W: Wm> BlockWorker<D, W> {
         self.wm.clone()
     }
 }
+
+// Write the pending transactions snapshot, if the pool has changed since the
+// `last` snapshot revision.
+fn write_pool_snapshot(path: &Path, pool: &RwLock<Pool>, last: &Mutex<Option<(u64, u64)>>) {
+    let mut last = last.lock();
+    let (revision, txs) = {
+        let pool = pool.read();
+        let revision = pool.pending_revision();
+        if *last == Some(revision) {
+            return;
+        }
+        (revision, pool.pending_txs())
+    };
+    match pool::store_snapshot(path, &txs) {
+        Ok(()) => *last = Some(revision),
+        Err(err) => warn!("Error storing pool snapshot: {}", err.to_string_full()),
+    }
+}
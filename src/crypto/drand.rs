@@ -36,6 +36,8 @@ pub struct SeedSource {
     pub rxs_hash: Mutex<Hash>,
     /// Previous seed
     pub previous_seed: Mutex<u64>,
+    /// Set when the seed is used to draw random numbers
+    pub used: Mutex<bool>,
 }
 
 impl SeedSource {
@@ -54,6 +56,21 @@ impl SeedSource {
             txs_hash: Mutex::new(txs_hash),
             rxs_hash: Mutex::new(rxs_hash),
             previous_seed: Mutex::new(0),
+            used: Mutex::new(false),
+        }
+    }
+
+    /// It returns a copy of the current seed sources, with the usage flag
+    /// cleared
+    pub fn snapshot(&self) -> Self {
+        SeedSource {
+            nw_name: self.nw_name.clone(),
+            nonce: Mutex::new(self.nonce.lock().clone()),
+            prev_hash: Mutex::new(*self.prev_hash.lock()),
+            txs_hash: Mutex::new(*self.txs_hash.lock()),
+            rxs_hash: Mutex::new(*self.rxs_hash.lock()),
+            previous_seed: Mutex::new(*self.previous_seed.lock()),
+            used: Mutex::new(false),
         }
     }
 
@@ -144,6 +161,7 @@ pub struct Drand {
 
 impl Drand {
     pub fn new(seed: Arc<SeedSource>) -> Self {
+        *seed.used.lock() = true;
         Drand {
             drng: Pcg32::seed_from_u64(seed.get_seed()),
             seed,
@@ -208,4 +226,26 @@ mod test {
 
         println!("{:?}", vec);
     }
+
+    #[test]
+    fn test_seed_snapshot() {
+        let nw_name = String::from("nw_name_test");
+        let nonce: Vec<u8> = vec![0x12, 0x34, 0x56, 0x78, 0x90, 0x12, 0x34, 0x56];
+        let hash =
+            Hash::from_hex("1220a4cea0f0f6eddc6865fd6092a319ccc6d2387cd8bb65e64bdc486f1a9a998569")
+                .unwrap();
+        let seed = SeedSource::new(nw_name, nonce, hash, hash, hash);
+        *seed.previous_seed.lock() = 5;
+
+        let snapshot = Arc::new(seed.snapshot());
+        assert!(!*snapshot.used.lock());
+        assert_eq!(snapshot.get_seed(), seed.get_seed());
+
+        Drand::new(snapshot.clone()).rand(9);
+
+        assert!(*snapshot.used.lock());
+        assert_ne!(*snapshot.previous_seed.lock(), 5);
+        assert_eq!(*seed.previous_seed.lock(), 5);
+        assert!(!*seed.used.lock());
+    }
 }
@@ -0,0 +1,639 @@
+// This file is part of TRINCI.
+//
+// Copyright (C) 2021 Affidaty Spa.
+//
+// TRINCI is free software: you can redistribute it and/or modify it under
+// the terms of the GNU Affero General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or (at your
+// option) any later version.
+//
+// TRINCI is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License
+// for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with TRINCI. If not, see <https://www.gnu.org/licenses/>.
+
+//! Merkle trees hashing and inclusion proofs.
+//!
+//! The hashing scheme is the one adopted by the merkledb `ProofListIndex` and
+//! `ProofMapIndex` (used by the `RocksDb` backend), so the hashes computed here
+//! match the ones found in the blocks (e.g. `BlockData::state_hash`).
+//! This module doesn't depend on merkledb and can be used by light clients to
+//! verify the proofs returned by a node.
+
+use crate::{
+    base::{schema::Account, serialize::rmp_deserialize},
+    crypto::{Hash, HashAlgorithm},
+};
+use ring::digest::{Context, SHA256};
+
+/// Hash prefix of a leaf value.
+const BLOB_PREFIX: u8 = 0x00;
+/// Hash prefix of a list branch node.
+const LIST_BRANCH_PREFIX: u8 = 0x01;
+/// Hash prefix of a list object.
+const LIST_PREFIX: u8 = 0x02;
+/// Hash prefix of a map object.
+const MAP_PREFIX: u8 = 0x03;
+/// Hash prefix of a map branch node.
+const MAP_BRANCH_PREFIX: u8 = 0x04;
+
+/// Map keys are hashed, thus each path is 256 bits long.
+const PATH_BITS: usize = 256;
+
+type Digest = [u8; 32];
+
+fn sha256(chunks: &[&[u8]]) -> Digest {
+    let mut ctx = Context::new(&SHA256);
+    chunks.iter().for_each(|chunk| ctx.update(chunk));
+    let mut digest = Digest::default();
+    digest.copy_from_slice(ctx.finish().as_ref());
+    digest
+}
+
+fn to_hash(digest: Digest) -> Hash {
+    // This is safe as far as the digest is a SHA256.
+    Hash::new(HashAlgorithm::Sha256, &digest).unwrap()
+}
+
+fn leaf_hash(value: &[u8]) -> Digest {
+    sha256(&[&[BLOB_PREFIX], value])
+}
+
+fn map_object_hash(root: &Digest) -> Hash {
+    to_hash(sha256(&[&[MAP_PREFIX], root]))
+}
+
+/// Digest wrapped by a SHA256 hash.
+fn from_hash(hash: &Hash) -> Option<Digest> {
+    let mut digest = Digest::default();
+    if hash.hash_algorithm() != HashAlgorithm::Sha256 || hash.hash_size() != digest.len() {
+        return None;
+    }
+    digest.copy_from_slice(&hash.hash_value()[..digest.len()]);
+    Some(digest)
+}
+
+/// Hash of a list branch node, the right child is missing for the last odd node.
+fn list_branch_hash(left: &Digest, right: Option<&Digest>) -> Digest {
+    match right {
+        Some(right) => sha256(&[&[LIST_BRANCH_PREFIX], left, right]),
+        None => sha256(&[&[LIST_BRANCH_PREFIX], left]),
+    }
+}
+
+/// Nodes of the list tree level above the given one.
+fn list_parent_level(level: &[Digest]) -> Vec<Digest> {
+    level
+        .chunks(2)
+        .map(|pair| list_branch_hash(&pair[0], pair.get(1)))
+        .collect()
+}
+
+fn list_object_hash(len: u64, root: &Digest) -> Hash {
+    to_hash(sha256(&[&[LIST_PREFIX], &len.to_le_bytes(), root]))
+}
+
+/// Object hash of a list with the given values.
+pub fn list_hash<V: AsRef<[u8]>>(values: impl IntoIterator<Item = V>) -> Hash {
+    let mut level: Vec<Digest> = values
+        .into_iter()
+        .map(|value| leaf_hash(value.as_ref()))
+        .collect();
+    let len = level.len() as u64;
+    while level.len() > 1 {
+        level = list_parent_level(&level);
+    }
+    list_object_hash(len, &level.pop().unwrap_or_default())
+}
+
+/// Object hash of a map with the given entries.
+/// Keys are required to be unique.
+pub fn map_hash<K, V>(entries: impl IntoIterator<Item = (K, V)>) -> Hash
+where
+    K: AsRef<[u8]>,
+    V: AsRef<[u8]>,
+{
+    let entries = map_entries(entries);
+    let root = match entries.len() {
+        0 => Digest::default(),
+        1 => single_entry_hash(&entries[0]),
+        _ => subtree_hash(&entries.iter().collect::<Vec<_>>()).1,
+    };
+    map_object_hash(&root)
+}
+
+/// Map entries as (key path, value hash) pairs.
+fn map_entries<K, V>(entries: impl IntoIterator<Item = (K, V)>) -> Vec<(Digest, Digest)>
+where
+    K: AsRef<[u8]>,
+    V: AsRef<[u8]>,
+{
+    entries
+        .into_iter()
+        .map(|(key, value)| (sha256(&[key.as_ref()]), leaf_hash(value.as_ref())))
+        .collect()
+}
+
+/// Root of a map with a single entry.
+fn single_entry_hash((path, hash): &(Digest, Digest)) -> Digest {
+    sha256(&[&[MAP_BRANCH_PREFIX], &compress_path(path, PATH_BITS), hash])
+}
+
+/// Hash of a map branch node.
+fn branch_hash(left: (&Digest, usize, &Digest), right: (&Digest, usize, &Digest)) -> Digest {
+    sha256(&[
+        &[MAP_BRANCH_PREFIX],
+        left.2,
+        right.2,
+        &compress_path(left.0, left.1),
+        &compress_path(right.0, right.1),
+    ])
+}
+
+/// Bit at position `pos`, least significant bit first.
+fn bit(path: &Digest, pos: usize) -> u8 {
+    (path[pos / 8] >> (pos % 8)) & 1
+}
+
+/// Length in bits of the common prefix of two paths.
+fn common_prefix_len(a: &Digest, b: &Digest) -> usize {
+    a.iter()
+        .zip(b.iter())
+        .enumerate()
+        .find(|(_, (a, b))| a != b)
+        .map(|(i, (a, b))| i * 8 + (a ^ b).trailing_zeros() as usize)
+        .unwrap_or(PATH_BITS)
+}
+
+/// Significant bytes of a path prefix, with the exceeding bits set to zero.
+fn path_prefix(path: &Digest, bits: usize) -> Vec<u8> {
+    let mut buf = path[..(bits + 7) / 8].to_vec();
+    if bits % 8 != 0 {
+        if let Some(last) = buf.last_mut() {
+            *last &= (1 << (bits % 8)) - 1;
+        }
+    }
+    buf
+}
+
+/// Path serialization: LEB128 bits length followed by the significant bytes.
+fn compress_path(path: &Digest, bits: usize) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(3 + bits / 8);
+    let mut len = bits;
+    loop {
+        let byte = (len & 0x7f) as u8;
+        len >>= 7;
+        if len == 0 {
+            buf.push(byte);
+            break;
+        }
+        buf.push(byte | 0x80);
+    }
+    buf.extend(path_prefix(path, bits));
+    buf
+}
+
+/// Length in bits of the common prefix of a set of (at least two) entries.
+fn entries_prefix_len(entries: &[&(Digest, Digest)]) -> usize {
+    let first = &entries[0].0;
+    entries[1..]
+        .iter()
+        .map(|(path, _)| common_prefix_len(first, path))
+        .min()
+        .unwrap_or(PATH_BITS)
+}
+
+/// Returns the bits length of the subtree path and the subtree node hash.
+/// A subtree with a single entry is the leaf itself.
+fn subtree_hash(entries: &[&(Digest, Digest)]) -> (usize, Digest) {
+    if let [(_, hash)] = entries {
+        return (PATH_BITS, *hash);
+    }
+    let prefix_len = entries_prefix_len(entries);
+    let (left, right): (Vec<_>, Vec<_>) = entries
+        .iter()
+        .copied()
+        .partition(|(path, _)| bit(path, prefix_len) == 0);
+    let (left_bits, left_hash) = subtree_hash(&left);
+    let (right_bits, right_hash) = subtree_hash(&right);
+    let hash = branch_hash(
+        (&left[0].0, left_bits, &left_hash),
+        (&right[0].0, right_bits, &right_hash),
+    );
+    (prefix_len, hash)
+}
+
+/// Map subtree sibling of a node along the path of the proven entry.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct MapProofNode {
+    /// Subtree path length in bits.
+    pub bits: u16,
+    /// Subtree path significant bytes.
+    #[serde(with = "serde_bytes")]
+    pub prefix: Vec<u8>,
+    /// Subtree node hash.
+    pub hash: Hash,
+}
+
+impl MapProofNode {
+    /// Build a node from the subtree path bytes, least significant bit first,
+    /// as found in the merkledb map proofs.
+    /// Returns `None` if the path is shorter than `bits`.
+    pub fn from_path(path: &[u8], bits: usize, hash: Hash) -> Option<Self> {
+        let mut digest = Digest::default();
+        if bits > PATH_BITS || path.len() < (bits + 7) / 8 {
+            return None;
+        }
+        let len = path.len().min(digest.len());
+        digest[..len].copy_from_slice(&path[..len]);
+        Some(MapProofNode {
+            bits: bits as u16,
+            prefix: path_prefix(&digest, bits),
+            hash,
+        })
+    }
+
+    /// Length in bits of the common prefix with a path.
+    fn split_len(&self, path: &Digest) -> usize {
+        let mut node_path = Digest::default();
+        let len = self.prefix.len().min(node_path.len());
+        node_path[..len].copy_from_slice(&self.prefix[..len]);
+        common_prefix_len(path, &node_path).min(self.bits as usize)
+    }
+}
+
+/// Proof of existence of an entry within a Merkle map.
+/// Only proofs of existence are supported.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct MapProof {
+    /// Entry key.
+    pub key: String,
+    /// Entry value.
+    #[serde(with = "serde_bytes")]
+    pub value: Vec<u8>,
+    /// Siblings of the nodes along the entry path, ordered from the root.
+    pub siblings: Vec<MapProofNode>,
+}
+
+impl MapProof {
+    /// Build the proof of the entry with the given `key`.
+    /// The proof is computed traversing the whole set of map entries.
+    /// Returns `None` if the key is not found.
+    pub fn new<K, V>(entries: impl IntoIterator<Item = (K, V)>, key: &str) -> Option<Self>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        let mut value = None;
+        let entries: Vec<_> = entries
+            .into_iter()
+            .inspect(|(k, v)| {
+                if k.as_ref() == key.as_bytes() {
+                    value = Some(v.as_ref().to_vec());
+                }
+            })
+            .collect();
+        let value = value?;
+        let entries = map_entries(entries);
+        let target = sha256(&[key.as_bytes()]);
+
+        let mut siblings = vec![];
+        let mut subtree: Vec<_> = entries.iter().collect();
+        while subtree.len() > 1 {
+            let prefix_len = entries_prefix_len(&subtree);
+            let (path_side, other_side): (Vec<_>, Vec<_>) = subtree
+                .iter()
+                .copied()
+                .partition(|(path, _)| bit(path, prefix_len) == bit(&target, prefix_len));
+            let (bits, hash) = subtree_hash(&other_side);
+            siblings.push(MapProofNode {
+                bits: bits as u16,
+                prefix: path_prefix(&other_side[0].0, bits),
+                hash: to_hash(hash),
+            });
+            subtree = path_side;
+        }
+
+        Some(MapProof {
+            key: key.to_owned(),
+            value,
+            siblings,
+        })
+    }
+
+    /// Build the proof of an entry from the siblings of the nodes along its
+    /// path, given in any order.
+    pub fn from_siblings(key: &str, value: Vec<u8>, mut siblings: Vec<MapProofNode>) -> Self {
+        let path = sha256(&[key.as_bytes()]);
+        siblings.sort_by_key(|node| node.split_len(&path));
+        MapProof {
+            key: key.to_owned(),
+            value,
+            siblings,
+        }
+    }
+
+    /// Map object hash computed from the proof.
+    /// Returns `None` if the proof is malformed.
+    pub fn root_hash(&self) -> Option<Hash> {
+        let path = sha256(&[self.key.as_bytes()]);
+        let leaf = (path, leaf_hash(&self.value));
+        if self.siblings.is_empty() {
+            return Some(map_object_hash(&single_entry_hash(&leaf)));
+        }
+
+        let mut bits = PATH_BITS;
+        let mut hash = leaf.1;
+        for node in self.siblings.iter().rev() {
+            let node_bits = node.bits as usize;
+            if node_bits > PATH_BITS || node.prefix.len() != (node_bits + 7) / 8 {
+                return None;
+            }
+            let mut node_path = Digest::default();
+            node_path[..node.prefix.len()].copy_from_slice(&node.prefix);
+            if path_prefix(&node_path, node_bits) != node.prefix {
+                return None;
+            }
+            let node_hash = from_hash(&node.hash)?;
+
+            // Nodes shall be ordered by depth, the branch splits the two paths.
+            let prefix_len = common_prefix_len(&path, &node_path);
+            if prefix_len >= bits || prefix_len >= node_bits {
+                return None;
+            }
+            let current = (&path, bits, &hash);
+            let sibling = (&node_path, node_bits, &node_hash);
+            hash = match bit(&path, prefix_len) {
+                0 => branch_hash(current, sibling),
+                _ => branch_hash(sibling, current),
+            };
+            bits = prefix_len;
+        }
+        Some(map_object_hash(&hash))
+    }
+
+    /// Check the proof against the expected map object hash.
+    pub fn verify(&self, root: &Hash) -> bool {
+        self.root_hash().as_ref() == Some(root)
+    }
+}
+
+/// Proof of inclusion of a value within a Merkle list.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ListProof {
+    /// Value position within the list.
+    pub index: u64,
+    /// List length.
+    pub len: u64,
+    /// Siblings of the nodes along the value path, ordered from the leaf.
+    /// The last node of a level with odd length has no sibling.
+    pub siblings: Vec<Hash>,
+}
+
+impl ListProof {
+    /// Build the proof of the value at the given `index`.
+    /// Returns `None` if the index is out of bounds.
+    pub fn new<V: AsRef<[u8]>>(values: impl IntoIterator<Item = V>, index: u64) -> Option<Self> {
+        let mut level: Vec<Digest> = values
+            .into_iter()
+            .map(|value| leaf_hash(value.as_ref()))
+            .collect();
+        let len = level.len() as u64;
+        if index >= len {
+            return None;
+        }
+
+        let mut siblings = vec![];
+        let mut pos = index as usize;
+        while level.len() > 1 {
+            if let Some(sibling) = level.get(pos ^ 1) {
+                siblings.push(to_hash(*sibling));
+            }
+            level = list_parent_level(&level);
+            pos /= 2;
+        }
+
+        Some(ListProof {
+            index,
+            len,
+            siblings,
+        })
+    }
+
+    /// List object hash computed from the proof and the proven `value`.
+    /// Returns `None` if the proof is malformed.
+    pub fn root_hash(&self, value: &[u8]) -> Option<Hash> {
+        if self.index >= self.len {
+            return None;
+        }
+        let mut siblings = self.siblings.iter();
+        let mut hash = leaf_hash(value);
+        let mut pos = self.index;
+        let mut level_len = self.len;
+        while level_len > 1 {
+            hash = if pos ^ 1 < level_len {
+                let sibling = from_hash(siblings.next()?)?;
+                match pos % 2 {
+                    0 => list_branch_hash(&hash, Some(&sibling)),
+                    _ => list_branch_hash(&sibling, Some(&hash)),
+                }
+            } else {
+                list_branch_hash(&hash, None)
+            };
+            pos /= 2;
+            level_len = (level_len + 1) / 2;
+        }
+        match siblings.next() {
+            Some(_) => None,
+            None => Some(list_object_hash(self.len, &hash)),
+        }
+    }
+
+    /// Check the proof of `value` against the expected list object hash.
+    pub fn verify(&self, value: &[u8], root: &Hash) -> bool {
+        self.root_hash(value).as_ref() == Some(root)
+    }
+}
+
+/// Proof of an account state, and optionally of one of its data entries,
+/// at a given block height.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct AccountProof {
+    /// Height of the block whose state hash the proof refers to.
+    pub height: u64,
+    /// Proof of the packed `Account` within the accounts map.
+    pub account: MapProof,
+    /// Proof of the data entry within the account data map.
+    pub data: Option<MapProof>,
+}
+
+impl AccountProof {
+    /// Get the proven account.
+    pub fn account(&self) -> Option<Account> {
+        rmp_deserialize(&self.account.value).ok()
+    }
+
+    /// Check the proof against the block `state_hash`.
+    /// The data entry proof, if any, is checked against the account `data_hash`.
+    pub fn verify(&self, state_hash: &Hash) -> bool {
+        if !self.account.verify(state_hash) {
+            return false;
+        }
+        match &self.data {
+            None => true,
+            Some(data) => match self.account().and_then(|account| account.data_hash) {
+                Some(data_hash) => data.verify(&data_hash),
+                None => false,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base::{schema::tests::create_test_account, serialize::rmp_serialize};
+
+    fn test_entries(count: u8) -> Vec<(String, Vec<u8>)> {
+        (0..count)
+            .map(|i| (format!("key{}", i), vec![i; i as usize + 1]))
+            .collect()
+    }
+
+    #[test]
+    fn list_hash_test_vector() {
+        let hashes: Vec<Hash> = vec![
+            "1220b706053eb366e5a649ec7117dd896c63707d52b9a02f38bb01f13ab17a798f61",
+            "12200194fa02f34ddedb3f6d9bd09d774a865f26ec498e361e082240ac9ed1b82005",
+            "1220b09d7f52bba3792ce81d011aa213c96de4ce4203312aa8fe1c3be933b3725df5",
+            "1220816e1626269c0f8f7c1861101516f83cc6528cd59560f64cf13127f1fd0017b0",
+        ]
+        .into_iter()
+        .map(|h| Hash::from_hex(h).unwrap())
+        .collect();
+
+        let root = list_hash(hashes.iter().map(|hash| hash.to_bytes()));
+
+        assert_eq!(
+            "1220d76a63134cc183deca8e35eb005e249ea3308b6d339419b2d777e09c7637e548",
+            hex::encode(&root.to_bytes())
+        );
+    }
+
+    #[test]
+    fn list_proof_every_value() {
+        for len in 1..=9u8 {
+            let values: Vec<Vec<u8>> = (0..len).map(|i| vec![i]).collect();
+            let root = list_hash(&values);
+
+            for (index, value) in values.iter().enumerate() {
+                let proof = ListProof::new(&values, index as u64).unwrap();
+                assert!(proof.verify(value, &root));
+            }
+        }
+    }
+
+    #[test]
+    fn list_proof_out_of_bounds() {
+        let values = vec![vec![1], vec![2]];
+
+        assert!(ListProof::new(&values, 2).is_none());
+        assert!(ListProof::new(Vec::<Vec<u8>>::new(), 0).is_none());
+    }
+
+    #[test]
+    fn list_proof_tampered() {
+        let values: Vec<Vec<u8>> = (0..5).map(|i| vec![i]).collect();
+        let root = list_hash(&values);
+        let proof = ListProof::new(&values, 2).unwrap();
+
+        let mut bad_index = proof.clone();
+        bad_index.index = 3;
+        let mut bad_len = proof.clone();
+        bad_len.len = 6;
+        let mut bad_siblings = proof.clone();
+        bad_siblings.siblings.pop();
+
+        assert!(!proof.verify(&[3], &root));
+        assert!(!bad_index.verify(&[2], &root));
+        assert!(!bad_len.verify(&[2], &root));
+        assert_eq!(bad_siblings.root_hash(&[2]), None);
+    }
+
+    #[test]
+    fn map_proof_single_entry() {
+        let entries = test_entries(1);
+        let root = map_hash(entries.clone());
+
+        let proof = MapProof::new(entries, "key0").unwrap();
+
+        assert!(proof.siblings.is_empty());
+        assert!(proof.verify(&root));
+    }
+
+    #[test]
+    fn map_proof_every_entry() {
+        let entries = test_entries(33);
+        let root = map_hash(entries.clone());
+
+        for (key, value) in entries.iter() {
+            let proof = MapProof::new(entries.clone(), key).unwrap();
+            assert_eq!(&proof.value, value);
+            assert!(proof.verify(&root));
+        }
+    }
+
+    #[test]
+    fn map_proof_not_found() {
+        let proof = MapProof::new(test_entries(8), "unknown");
+
+        assert!(proof.is_none());
+    }
+
+    #[test]
+    fn map_proof_tampered() {
+        let entries = test_entries(8);
+        let root = map_hash(entries.clone());
+        let proof = MapProof::new(entries, "key3").unwrap();
+
+        let mut bad_value = proof.clone();
+        bad_value.value.push(0);
+        let mut bad_key = proof.clone();
+        bad_key.key = "key4".to_owned();
+        let mut bad_sibling = proof.clone();
+        bad_sibling.siblings.swap(0, 1);
+        let mut bad_prefix = proof;
+        bad_prefix.siblings[0].prefix.push(0);
+
+        assert!(!bad_value.verify(&root));
+        assert!(!bad_key.verify(&root));
+        assert!(!bad_sibling.verify(&root));
+        assert_eq!(bad_prefix.root_hash(), None);
+    }
+
+    #[test]
+    fn account_proof_verify() {
+        let mut account = create_test_account();
+        let data = test_entries(4);
+        account.data_hash = Some(map_hash(data.clone()));
+        let accounts = vec![
+            (account.id.clone(), rmp_serialize(&account).unwrap()),
+            ("other".to_owned(), vec![1, 2, 3]),
+        ];
+        let state_hash = map_hash(accounts.clone());
+
+        let mut proof = AccountProof {
+            height: 0,
+            account: MapProof::new(accounts, &account.id).unwrap(),
+            data: MapProof::new(data, "key2"),
+        };
+
+        assert_eq!(proof.account(), Some(account));
+        assert!(proof.verify(&state_hash));
+        proof.data.as_mut().unwrap().value = vec![3];
+        assert!(!proof.verify(&state_hash));
+    }
+}
@@ -19,6 +19,7 @@ pub mod drand;
 pub mod ecdsa;
 pub mod ed25519;
 pub mod hash;
+pub mod merkle;
 pub mod sign;
 #[cfg(feature = "tpm2")]
 pub mod tpm2;
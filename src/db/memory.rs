@@ -0,0 +1,1859 @@
+// This file is part of TRINCI.
+//
+// Copyright (C) 2021 Affidaty Spa.
+//
+// TRINCI is free software: you can redistribute it and/or modify it under
+// the terms of the GNU Affero General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or (at your
+// option) any later version.
+//
+// TRINCI is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License
+// for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with TRINCI. If not, see <https://www.gnu.org/licenses/>.
+
+//! Volatile database implementation.
+//!
+//! All the data is kept in memory and is lost when the database is dropped.
+//! The Merkle trees are computed using the same scheme adopted by merkledb
+//! (used by the `RocksDb` backend), thus for the same sequence of operations
+//! the two backends produce the same state, transactions and receipts hashes.
+//! See the `crypto::merkle` module.
+
+use crate::{
+    base::{
+        schema::{Account, Block, Receipt, Transaction},
+        serialize::{rmp_deserialize, rmp_serialize},
+    },
+    crypto::{
+        merkle::{self, MapProof},
+        Hash, Hashable,
+    },
+    db::{
+        event_index_keys, events_page,
+        snapshot::{self, rewind_entries, SnapshotWriter, StateRewind},
+        transaction_accounts, AccountTransaction, Db, DbFork, EventFilter, EventRecord,
+    },
+    Error, ErrorKind,
+};
+use std::{
+    cell::Cell,
+    collections::{BTreeMap, HashMap},
+    io::Write,
+    mem,
+    ops::Bound,
+    sync::Arc,
+};
+
+/// Per block hashes lists.
+#[derive(Clone, Copy)]
+enum HashesList {
+    Transactions,
+    Receipts,
+}
+
+/// Historical state entry.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum HistoryKey {
+    Account(String),
+    AccountData(String, String),
+    Config(String),
+    Internal(String),
+}
+
+/// Entries values before the modifications applied by a block.
+type HistoryUndo = BTreeMap<HistoryKey, Option<Vec<u8>>>;
+
+/// Database content.
+#[derive(Clone, Default)]
+struct Storage {
+    accounts: BTreeMap<String, Account>,
+    /// Accounts state hash, `None` until computed.
+    accounts_hash: Option<Hash>,
+    accounts_data: BTreeMap<String, BTreeMap<String, Vec<u8>>>,
+    config: BTreeMap<String, Vec<u8>>,
+    internal_db: BTreeMap<String, Vec<u8>>,
+    transactions: HashMap<Hash, Transaction>,
+    receipts: HashMap<Hash, Receipt>,
+    blocks: Vec<Block>,
+    blocks_by_hash: HashMap<Hash, u64>,
+    transactions_hashes: HashMap<u64, Vec<Hash>>,
+    receipts_hashes: HashMap<u64, Vec<Hash>>,
+    account_txs: HashMap<String, Vec<AccountTransaction>>,
+    events: Vec<EventRecord>,
+    /// Events positions within `events`, for each index list.
+    events_index: HashMap<String, Vec<u64>>,
+    /// Entries versions, each one holding the value preceding the block at that height.
+    history: BTreeMap<HistoryKey, BTreeMap<u64, Option<Vec<u8>>>>,
+    /// Entries modified by each recorded block.
+    history_journal: BTreeMap<u64, Vec<HistoryKey>>,
+}
+
+impl Storage {
+    fn hashes(&self, kind: HashesList) -> &HashMap<u64, Vec<Hash>> {
+        match kind {
+            HashesList::Transactions => &self.transactions_hashes,
+            HashesList::Receipts => &self.receipts_hashes,
+        }
+    }
+
+    /// Accounts state hash.
+    fn accounts_hash(&self) -> Hash {
+        self.accounts_hash
+            .unwrap_or_else(|| merkle::map_hash(packed_accounts(self.accounts.iter())))
+    }
+
+    /// Apply a set of modifications.
+    fn apply(&mut self, patch: Patch) {
+        if !patch.accounts.is_empty() {
+            self.accounts.extend(patch.accounts);
+            self.accounts_hash = Some(merkle::map_hash(packed_accounts(self.accounts.iter())));
+        }
+        for (id, changes) in patch.accounts_data {
+            let map = self.accounts_data.entry(id).or_default();
+            for (key, value) in changes {
+                match value {
+                    Some(value) => map.insert(key, value),
+                    None => map.remove(&key),
+                };
+            }
+        }
+        self.config.extend(patch.config);
+        self.internal_db.extend(patch.internal_db);
+        self.transactions.extend(patch.transactions);
+        self.receipts.extend(patch.receipts);
+        self.blocks_by_hash.extend(patch.blocks_by_hash);
+        for (height, block) in patch.blocks {
+            match self.blocks.get_mut(height as usize) {
+                Some(entry) => *entry = block,
+                None => self.blocks.push(block),
+            }
+        }
+        self.transactions_hashes.extend(patch.transactions_hashes);
+        self.receipts_hashes.extend(patch.receipts_hashes);
+        for (id, entries) in patch.account_txs {
+            self.account_txs.entry(id).or_default().extend(entries);
+        }
+        for record in patch.events {
+            let pos = self.events.len() as u64;
+            for key in event_index_keys(&record.event) {
+                self.events_index.entry(key).or_default().push(pos);
+            }
+            self.events.push(record);
+        }
+        if let Some((height, undo)) = patch.history {
+            let keys = undo
+                .into_iter()
+                .map(|(key, value)| {
+                    self.history
+                        .entry(key.clone())
+                        .or_default()
+                        .insert(height, value);
+                    key
+                })
+                .collect();
+            self.history_journal.insert(height, keys);
+        }
+    }
+
+    /// Drop the versions not required to rebuild the state of the last `retention` blocks.
+    fn prune_history(&mut self, retention: u64) {
+        let last = match (self.blocks.len() as u64).checked_sub(1) {
+            Some(last) => last,
+            None => return,
+        };
+        let stale: Vec<u64> = self
+            .history_journal
+            .keys()
+            .take_while(|height| height.saturating_add(retention) <= last)
+            .copied()
+            .collect();
+        for height in stale {
+            let keys = self.history_journal.remove(&height).unwrap_or_default();
+            for key in keys {
+                if let Some(versions) = self.history.get_mut(&key) {
+                    versions.remove(&height);
+                    if versions.is_empty() {
+                        self.history.remove(&key);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Check if the state at the given `height` can be rebuilt from the history.
+    fn history_covers(&self, height: u64, retention: u64) -> bool {
+        let last = match (self.blocks.len() as u64).checked_sub(1) {
+            Some(last) => last,
+            None => return false,
+        };
+        if height > last || height.saturating_add(retention) < last {
+            return false;
+        }
+        // All the blocks following `height` shall be recorded.
+        height == last
+            || matches!(self.history_journal.keys().next(), Some(first) if *first <= height + 1)
+    }
+
+    /// Revert the blocks following `height`.
+    /// Returns the reverted transactions, in execution order.
+    fn revert(&mut self, height: u64) -> Vec<Transaction> {
+        let last = self.blocks.len() as u64 - 1;
+        let mut txs = vec![];
+        for h in height + 1..=last {
+            for hash in self.transactions_hashes.remove(&h).unwrap_or_default() {
+                txs.extend(self.transactions.remove(&hash));
+                self.receipts.remove(&hash);
+            }
+            self.receipts_hashes.remove(&h);
+        }
+
+        // Drop the reverted entries from the accounts transactions index.
+        for id in txs.iter().flat_map(transaction_accounts) {
+            if let Some(entries) = self.account_txs.get_mut(&id) {
+                entries.retain(|entry| entry.height <= height);
+            }
+        }
+
+        // Drop the events emitted by the reverted blocks.
+        while let Some(record) = self.events.last().filter(|record| record.height > height) {
+            for key in event_index_keys(&record.event) {
+                if let Some(index) = self.events_index.get_mut(&key) {
+                    index.pop();
+                }
+            }
+            self.events.pop();
+        }
+
+        // Restore the entries modified by each block, from the last one.
+        for h in (height + 1..=last).rev() {
+            for key in self.history_journal.remove(&h).unwrap_or_default() {
+                let value = self
+                    .history
+                    .get_mut(&key)
+                    .and_then(|versions| versions.remove(&h))
+                    .flatten();
+                if self.history.get(&key).map_or(false, BTreeMap::is_empty) {
+                    self.history.remove(&key);
+                }
+                match (key, value) {
+                    (HistoryKey::AccountData(id, data_key), Some(value)) => {
+                        self.accounts_data
+                            .entry(id)
+                            .or_default()
+                            .insert(data_key, value);
+                    }
+                    (HistoryKey::AccountData(id, data_key), None) => {
+                        if let Some(map) = self.accounts_data.get_mut(&id) {
+                            map.remove(&data_key);
+                        }
+                    }
+                    (HistoryKey::Account(id), Some(value)) => {
+                        if let Ok(account) = rmp_deserialize(&value) {
+                            self.accounts.insert(id, account);
+                        }
+                    }
+                    (HistoryKey::Account(id), None) => {
+                        self.accounts.remove(&id);
+                    }
+                    (HistoryKey::Config(id), Some(value)) => {
+                        self.config.insert(id, value);
+                    }
+                    (HistoryKey::Config(id), None) => {
+                        self.config.remove(&id);
+                    }
+                    (HistoryKey::Internal(key), Some(value)) => {
+                        self.internal_db.insert(key, value);
+                    }
+                    (HistoryKey::Internal(key), None) => {
+                        self.internal_db.remove(&key);
+                    }
+                }
+            }
+        }
+
+        for block in self.blocks.drain(height as usize + 1..) {
+            self.blocks_by_hash.remove(&block.data.primary_hash());
+        }
+        self.accounts_hash = Some(merkle::map_hash(packed_accounts(self.accounts.iter())));
+        txs
+    }
+
+    /// Values, after the block at `height`, of the entries modified afterwards.
+    /// The history shall cover the given `height`.
+    fn history_rewind(&self, height: u64) -> StateRewind {
+        let mut rewind = StateRewind::default();
+        for (key, versions) in self.history.iter() {
+            // The first entry version after `height` holds the value it had at `height`.
+            let value = match versions.range(height + 1..).next() {
+                Some((_, value)) => value.clone(),
+                None => continue,
+            };
+            match key {
+                HistoryKey::Account(id) => {
+                    let account = value.and_then(|buf| rmp_deserialize(&buf).ok());
+                    rewind.accounts.insert(id.clone(), account);
+                }
+                HistoryKey::AccountData(id, key) => {
+                    rewind
+                        .accounts_data
+                        .entry(id.clone())
+                        .or_default()
+                        .insert(key.clone(), value);
+                }
+                HistoryKey::Config(id) => {
+                    rewind.config.insert(id.clone(), value);
+                }
+                HistoryKey::Internal(key) => {
+                    rewind.internal_db.insert(key.clone(), value);
+                }
+            }
+        }
+        rewind
+    }
+
+    /// Lookup an entry value as it was after the block at the given `height`.
+    /// The outer `None` is returned if the height is not covered by the history.
+    fn load_history(
+        &self,
+        key: &HistoryKey,
+        height: u64,
+        retention: u64,
+    ) -> Option<Option<Vec<u8>>> {
+        if !self.history_covers(height, retention) {
+            return None;
+        }
+        // The first entry version after `height` holds the value it had at `height`.
+        let value = match self
+            .history
+            .get(key)
+            .and_then(|versions| versions.range(height + 1..).next())
+        {
+            Some((_, value)) => value.clone(),
+            None => match key {
+                HistoryKey::AccountData(id, data_key) => self
+                    .accounts_data
+                    .get(id)
+                    .and_then(|map| map.get(data_key))
+                    .cloned(),
+                HistoryKey::Account(id) => self
+                    .accounts
+                    .get(id)
+                    .map(|account| rmp_serialize(account).unwrap()),
+                HistoryKey::Config(id) => self.config.get(id).cloned(),
+                HistoryKey::Internal(key) => self.internal_db.get(key).cloned(),
+            },
+        };
+        Some(value)
+    }
+}
+
+/// Set of uncommitted modifications.
+/// Removed account data entries are marked with `None`.
+#[derive(Default)]
+struct Patch {
+    accounts: BTreeMap<String, Account>,
+    accounts_data: BTreeMap<String, BTreeMap<String, Option<Vec<u8>>>>,
+    config: BTreeMap<String, Vec<u8>>,
+    internal_db: BTreeMap<String, Vec<u8>>,
+    transactions: HashMap<Hash, Transaction>,
+    receipts: HashMap<Hash, Receipt>,
+    blocks: BTreeMap<u64, Block>,
+    blocks_by_hash: HashMap<Hash, u64>,
+    transactions_hashes: HashMap<u64, Vec<Hash>>,
+    receipts_hashes: HashMap<u64, Vec<Hash>>,
+    account_txs: HashMap<String, Vec<AccountTransaction>>,
+    events: Vec<EventRecord>,
+    history: Option<(u64, HistoryUndo)>,
+}
+
+impl Patch {
+    fn hashes(&self, kind: HashesList) -> &HashMap<u64, Vec<Hash>> {
+        match kind {
+            HashesList::Transactions => &self.transactions_hashes,
+            HashesList::Receipts => &self.receipts_hashes,
+        }
+    }
+
+    fn hashes_mut(&mut self, kind: HashesList) -> &mut HashMap<u64, Vec<Hash>> {
+        match kind {
+            HashesList::Transactions => &mut self.transactions_hashes,
+            HashesList::Receipts => &mut self.receipts_hashes,
+        }
+    }
+
+    /// Move the modifications into an older patch, overwriting its entries.
+    fn merge_into(self, older: &mut Patch) {
+        older.accounts.extend(self.accounts);
+        for (id, changes) in self.accounts_data {
+            older.accounts_data.entry(id).or_default().extend(changes);
+        }
+        older.config.extend(self.config);
+        older.internal_db.extend(self.internal_db);
+        older.transactions.extend(self.transactions);
+        older.receipts.extend(self.receipts);
+        older.blocks.extend(self.blocks);
+        older.blocks_by_hash.extend(self.blocks_by_hash);
+        older.transactions_hashes.extend(self.transactions_hashes);
+        older.receipts_hashes.extend(self.receipts_hashes);
+        for (id, entries) in self.account_txs {
+            older.account_txs.entry(id).or_default().extend(entries);
+        }
+        older.events.extend(self.events);
+        if self.history.is_some() {
+            older.history = self.history;
+        }
+    }
+}
+
+/// Accounts map entries with the packed `Account` as value.
+fn packed_accounts<'a>(
+    accounts: impl Iterator<Item = (&'a String, &'a Account)>,
+) -> impl Iterator<Item = (&'a String, Vec<u8>)> {
+    accounts.map(|(id, account)| (id, rmp_serialize(account).unwrap()))
+}
+
+/// Select a page of account data keys from the sorted account data map.
+fn account_keys_page(
+    map: &BTreeMap<String, Vec<u8>>,
+    prefix: &str,
+    after: Option<&str>,
+    limit: u64,
+) -> Vec<String> {
+    let start = match after {
+        Some(after) if after > prefix => after,
+        _ => prefix,
+    };
+    map.range::<str, _>((Bound::Included(start), Bound::Unbounded))
+        .map(|(key, _)| key)
+        .skip_while(|key| Some(key.as_str()) == after)
+        .take_while(|key| key.starts_with(prefix))
+        .take(limit as usize)
+        .cloned()
+        .collect()
+}
+
+/// Database implementation keeping everything in memory.
+#[derive(Default)]
+pub struct MemoryDb {
+    /// Last committed state.
+    storage: Arc<Storage>,
+    /// Historical state retention window (number of blocks).
+    history: Option<u64>,
+}
+
+/// Database writeable snapshot.
+/// This structure is obtained via the `fork` method and allows to atomically
+/// apply a set of changes to the database.
+/// In the end, the changes shall be merged into the database using the database
+/// `merge` method.
+pub struct MemoryDbFork {
+    /// Database state at fork creation.
+    base: Arc<Storage>,
+    /// Modifications preceding the last checkpoint.
+    flushed: Patch,
+    /// Modifications following the last checkpoint.
+    patch: Patch,
+    /// Historical state retention window (number of blocks).
+    history: Option<u64>,
+    /// Values of the entries modified by the fork, before the first modification.
+    undo: HistoryUndo,
+    /// Accounts state hash as seen by the fork, `None` until computed.
+    accounts_hash: Cell<Option<Hash>>,
+}
+
+impl MemoryDb {
+    /// Create an empty database.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Enable the historical state for the last `retention` blocks.
+    /// Only the state changes committed after the call are recorded.
+    /// Pass `None` to disable it.
+    pub fn set_history_retention(&mut self, retention: Option<u64>) {
+        self.history = retention;
+    }
+
+    /// Export a snapshot of the state at the block `height`.
+    /// The heights preceding the last block are rebuilt from the historical state.
+    /// Returns the snapshot digest.
+    pub fn export_snapshot<W: Write>(&self, height: u64, writer: W) -> crate::Result<Hash> {
+        let storage = &self.storage;
+        let block = storage
+            .blocks
+            .get(height as usize)
+            .ok_or_else(|| Error::new(ErrorKind::ResourceNotFound))?;
+        let rewind = match self.history {
+            _ if height + 1 == storage.blocks.len() as u64 => StateRewind::default(),
+            Some(retention) if storage.history_covers(height, retention) => {
+                storage.history_rewind(height)
+            }
+            _ => {
+                return Err(Error::new_ext(
+                    ErrorKind::ResourceNotFound,
+                    "state history not available for the target height",
+                ))
+            }
+        };
+        let mut snapshot = SnapshotWriter::new(writer, height, block.data.state_hash)?;
+        snapshot.write_blocks(
+            storage.blocks[..=height as usize]
+                .iter()
+                .map(|block| snapshot::snapshot_block(self, block.clone())),
+        )?;
+        let accounts = storage
+            .accounts
+            .iter()
+            .map(|(id, account)| (id.clone(), account.clone()));
+        snapshot.write_accounts(
+            rewind_entries(accounts.clone(), &rewind.accounts).map(|(_, account)| account),
+        )?;
+        // Account data is exported for the accounts existing at `height`.
+        let empty = BTreeMap::new();
+        for (id, _) in rewind_entries(accounts, &rewind.accounts) {
+            let map = storage.accounts_data.get(&id).cloned().unwrap_or_default();
+            let previous = rewind.accounts_data.get(&id).unwrap_or(&empty);
+            snapshot.write_account_data(&id, rewind_entries(map.into_iter(), previous))?;
+        }
+        snapshot.write_config(rewind_entries(
+            storage.config.clone().into_iter(),
+            &rewind.config,
+        ))?;
+        snapshot.write_internal_db(rewind_entries(
+            storage.internal_db.clone().into_iter(),
+            &rewind.internal_db,
+        ))?;
+        snapshot.finish()
+    }
+}
+
+impl Db for MemoryDb {
+    /// Fork type.
+    type DbForkType = MemoryDbFork;
+
+    /// Fetch account.
+    fn load_account(&self, id: &str) -> Option<Account> {
+        self.storage.accounts.get(id).cloned()
+    }
+
+    /// Load full keys list associated to the account data.
+    fn load_account_keys(&self, id: &str) -> Vec<String> {
+        self.storage
+            .accounts_data
+            .get(id)
+            .map(|map| map.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    fn load_account_keys_range(
+        &self,
+        id: &str,
+        prefix: &str,
+        after: Option<&str>,
+        limit: u64,
+    ) -> Vec<String> {
+        self.storage
+            .accounts_data
+            .get(id)
+            .map(|map| account_keys_page(map, prefix, after, limit))
+            .unwrap_or_default()
+    }
+
+    /// Load data associated to the given account `id`.
+    fn load_account_data(&self, id: &str, key: &str) -> Option<Vec<u8>> {
+        self.storage
+            .accounts_data
+            .get(id)
+            .and_then(|map| map.get(key))
+            .cloned()
+    }
+
+    /// Fetch DB generic data (this shuld be used by core only, this map sould be used only internally).
+    fn load_data(&self, key: &str) -> Option<Vec<u8>> {
+        self.storage.internal_db.get(key).cloned()
+    }
+
+    /// Check if transaction is present.
+    fn contains_transaction(&self, hash: &Hash) -> bool {
+        self.storage.transactions.contains_key(hash)
+    }
+
+    /// Fetch transaction.
+    fn load_transaction(&self, hash: &Hash) -> Option<Transaction> {
+        self.storage.transactions.get(hash).cloned()
+    }
+
+    /// Load transaction receipt using transaction hash.
+    fn load_receipt(&self, hash: &Hash) -> Option<Receipt> {
+        self.storage.receipts.get(hash).cloned()
+    }
+
+    /// Get block at a given `height` (position in the blockchain).
+    /// This can also be used to fetch the last block by passing u64::max_value as the height.
+    fn load_block(&self, height: u64) -> Option<Block> {
+        match height {
+            u64::MAX => self.storage.blocks.last(),
+            _ => self.storage.blocks.get(height as usize),
+        }
+        .cloned()
+    }
+
+    /// Get block by its header hash.
+    fn load_block_by_hash(&self, hash: &Hash) -> Option<Block> {
+        let height = self.storage.blocks_by_hash.get(hash)?;
+        self.storage.blocks.get(*height as usize).cloned()
+    }
+
+    /// Get a page of the transactions index of the account `id`.
+    fn load_account_transactions(
+        &self,
+        id: &str,
+        from: u64,
+        limit: u64,
+    ) -> Vec<AccountTransaction> {
+        self.storage
+            .account_txs
+            .get(id)
+            .map(|entries| {
+                entries
+                    .iter()
+                    .skip(from as usize)
+                    .take(limit as usize)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Get a page of the smart contract events selected by `filter`.
+    fn load_events(&self, filter: &EventFilter, from: u64, limit: u64) -> Vec<EventRecord> {
+        let events = &self.storage.events;
+        match filter.index_key() {
+            Some(key) => {
+                let index = match self.storage.events_index.get(&key) {
+                    Some(index) => index,
+                    None => return vec![],
+                };
+                let record = |i: u64| {
+                    index
+                        .get(i as usize)
+                        .and_then(|pos| events.get(*pos as usize))
+                        .cloned()
+                };
+                events_page(index.len() as u64, record, filter, from, limit)
+            }
+            None => {
+                let record = |i: u64| events.get(i as usize).cloned();
+                events_page(events.len() as u64, record, filter, from, limit)
+            }
+        }
+    }
+
+    /// Get transactions hashes associated to a given block identified by `height`.
+    /// The `height` refers to the block position within the blockchain.
+    fn load_transactions_hashes(&self, height: u64) -> Option<Vec<Hash>> {
+        self.storage
+            .transactions_hashes
+            .get(&height)
+            .filter(|hashes| !hashes.is_empty())
+            .cloned()
+    }
+
+    /// Get receipts hashes associated to a given block identified by `height`.
+    /// The `height` refers to the block position within the blockchain.
+    fn load_receipts_hashes(&self, height: u64) -> Option<Vec<Hash>> {
+        self.storage
+            .receipts_hashes
+            .get(&height)
+            .filter(|hashes| !hashes.is_empty())
+            .cloned()
+    }
+
+    /// Create a fork.
+    /// A fork is a set of uncommitted modifications to the database.
+    fn fork_create(&mut self) -> MemoryDbFork {
+        MemoryDbFork {
+            base: self.storage.clone(),
+            flushed: Patch::default(),
+            patch: Patch::default(),
+            history: self.history,
+            undo: HistoryUndo::new(),
+            accounts_hash: Cell::new(None),
+        }
+    }
+
+    /// Commit a fork.
+    /// Apply the modifications to the database.
+    /// As for the `RocksDb`, merging two conflicting forks can lead to an
+    /// inconsistent state: the entries written by the last merged fork win.
+    fn fork_merge(&mut self, mut fork: MemoryDbFork) -> crate::Result<()> {
+        fork.flush();
+        // Release the fork reference to the storage first, otherwise the
+        // whole storage would be cloned.
+        let MemoryDbFork { base, flushed, .. } = fork;
+        drop(base);
+        let storage = Arc::make_mut(&mut self.storage);
+        storage.apply(flushed);
+        if let Some(retention) = self.history {
+            storage.prune_history(retention);
+        }
+        Ok(())
+    }
+
+    fn load_configuration(&self, id: &str) -> Option<Vec<u8>> {
+        self.storage.config.get(id).cloned()
+    }
+
+    /// Load account as it was after the block at the given `height`.
+    fn load_account_at(&self, id: &str, height: u64) -> Option<Account> {
+        let key = HistoryKey::Account(id.to_owned());
+        let buf = self.storage.load_history(&key, height, self.history?)??;
+        rmp_deserialize(&buf).ok()
+    }
+
+    /// Load account data as it was after the block at the given `height`.
+    fn load_account_data_at(&self, id: &str, key: &str, height: u64) -> Option<Vec<u8>> {
+        let key = HistoryKey::AccountData(id.to_owned(), key.to_owned());
+        self.storage.load_history(&key, height, self.history?)?
+    }
+
+    /// Revert the blocks following `height` using the historical state.
+    /// Returns the reverted transactions, in execution order.
+    fn revert_to(&mut self, height: u64) -> crate::Result<Vec<Transaction>> {
+        let last = match self.storage.blocks.last() {
+            Some(block) if height <= block.data.height => block.data.height,
+            _ => return Err(Error::new(ErrorKind::ResourceNotFound)),
+        };
+        if height == last {
+            return Ok(vec![]);
+        }
+        match self.history {
+            Some(retention) if self.storage.history_covers(height, retention) => {
+                Ok(Arc::make_mut(&mut self.storage).revert(height))
+            }
+            _ => Err(Error::new_ext(
+                ErrorKind::ResourceNotFound,
+                "state history not available for the target height",
+            )),
+        }
+    }
+
+    /// Load the account proof against the accounts state hash.
+    fn load_account_proof(&self, id: &str) -> Option<MapProof> {
+        MapProof::new(packed_accounts(self.storage.accounts.iter()), id)
+    }
+
+    /// Load the account data proof against the account data hash.
+    fn load_account_data_proof(&self, id: &str, key: &str) -> Option<MapProof> {
+        MapProof::new(self.storage.accounts_data.get(id)?, key)
+    }
+}
+
+impl MemoryDbFork {
+    /// Patches ordered from the most recent one.
+    fn patches(&self) -> [&Patch; 2] {
+        [&self.patch, &self.flushed]
+    }
+
+    /// Full account data map, as seen by the fork.
+    fn account_data_map(&self, id: &str) -> BTreeMap<String, Vec<u8>> {
+        let mut map = self.base.accounts_data.get(id).cloned().unwrap_or_default();
+        for patch in self.patches().iter().rev() {
+            for (key, value) in patch.accounts_data.get(id).into_iter().flatten() {
+                match value {
+                    Some(value) => map.insert(key.clone(), value.clone()),
+                    None => map.remove(key),
+                };
+            }
+        }
+        map
+    }
+
+    /// Accounts state hash, as seen by the fork.
+    /// The hash is cached until the next accounts modification.
+    fn accounts_hash(&self) -> Hash {
+        if self.patches().iter().all(|patch| patch.accounts.is_empty()) {
+            return self.base.accounts_hash();
+        }
+        if let Some(hash) = self.accounts_hash.get() {
+            return hash;
+        }
+        let mut map: BTreeMap<&String, &Account> = self.base.accounts.iter().collect();
+        for patch in self.patches().iter().rev() {
+            map.extend(patch.accounts.iter());
+        }
+        let hash = merkle::map_hash(packed_accounts(map.into_iter()));
+        self.accounts_hash.set(Some(hash));
+        hash
+    }
+
+    /// Record the value of a state entry before its first modification.
+    fn record_undo(&mut self, key: HistoryKey) {
+        if self.history.is_none() || self.undo.contains_key(&key) {
+            return;
+        }
+        let value = match &key {
+            HistoryKey::AccountData(id, data_key) => self.load_account_data(id, data_key),
+            HistoryKey::Account(id) => self
+                .load_account(id)
+                .map(|account| rmp_serialize(&account).unwrap()),
+            HistoryKey::Config(id) => self
+                .patches()
+                .iter()
+                .find_map(|patch| patch.config.get(id))
+                .or_else(|| self.base.config.get(id))
+                .cloned(),
+            HistoryKey::Internal(key) => self.load_data(key),
+        };
+        self.undo.insert(key, value);
+    }
+
+    /// Blocks list length, as seen by the fork.
+    fn blocks_len(&self) -> u64 {
+        self.patches()
+            .iter()
+            .filter_map(|patch| patch.blocks.keys().next_back())
+            .max()
+            .map(|height| height + 1)
+            .unwrap_or(self.base.blocks.len() as u64)
+    }
+
+    /// Append hashes to the list identified by `height` and return the list hash.
+    fn push_hashes(&mut self, kind: HashesList, height: u64, hashes: Vec<Hash>) -> Hash {
+        let mut list = self
+            .patches()
+            .iter()
+            .find_map(|patch| patch.hashes(kind).get(&height))
+            .or_else(|| self.base.hashes(kind).get(&height))
+            .cloned()
+            .unwrap_or_default();
+        list.extend(hashes);
+        let root = merkle::list_hash(&list);
+        self.patch.hashes_mut(kind).insert(height, list);
+        root
+    }
+}
+
+impl DbFork for MemoryDbFork {
+    /// Get state hash.
+    fn state_hash(&self, id: &str) -> Hash {
+        match id.is_empty() {
+            false => merkle::map_hash(&self.account_data_map(id)),
+            true => self.accounts_hash(),
+        }
+    }
+
+    /// Fetch account.
+    fn load_account(&self, id: &str) -> Option<Account> {
+        self.patches()
+            .iter()
+            .find_map(|patch| patch.accounts.get(id))
+            .or_else(|| self.base.accounts.get(id))
+            .cloned()
+    }
+
+    /// Insert/Update account.
+    fn store_account(&mut self, account: Account) {
+        self.record_undo(HistoryKey::Account(account.id.clone()));
+        self.accounts_hash.set(None);
+        self.patch.accounts.insert(account.id.clone(), account);
+    }
+
+    /// Load data associated to the given account `id`.
+    fn load_account_data(&self, id: &str, key: &str) -> Option<Vec<u8>> {
+        match self
+            .patches()
+            .iter()
+            .find_map(|patch| patch.accounts_data.get(id).and_then(|map| map.get(key)))
+        {
+            Some(value) => value.clone(),
+            None => self
+                .base
+                .accounts_data
+                .get(id)
+                .and_then(|map| map.get(key))
+                .cloned(),
+        }
+    }
+
+    /// Store data associated to the given account `id`.
+    fn store_account_data(&mut self, id: &str, key: &str, data: Vec<u8>) {
+        self.record_undo(HistoryKey::AccountData(id.to_owned(), key.to_owned()));
+        self.patch
+            .accounts_data
+            .entry(id.to_owned())
+            .or_default()
+            .insert(key.to_owned(), Some(data));
+    }
+
+    /// Fetch DB generic data (this shuld be used by core only, this map sould be used only internally).
+    fn load_data(&self, key: &str) -> Option<Vec<u8>> {
+        self.patches()
+            .iter()
+            .find_map(|patch| patch.internal_db.get(key))
+            .or_else(|| self.base.internal_db.get(key))
+            .cloned()
+    }
+
+    /// Insert/Update generic data.
+    fn store_data(&mut self, key: &str, data: Vec<u8>) {
+        self.record_undo(HistoryKey::Internal(key.to_owned()));
+        self.patch.internal_db.insert(key.to_owned(), data);
+    }
+
+    /// Remove data associated to the given account `id`.
+    fn remove_account_data(&mut self, id: &str, key: &str) {
+        self.record_undo(HistoryKey::AccountData(id.to_owned(), key.to_owned()));
+        self.patch
+            .accounts_data
+            .entry(id.to_owned())
+            .or_default()
+            .insert(key.to_owned(), None);
+    }
+
+    /// Insert transaction.
+    fn store_transaction(&mut self, hash: &Hash, transaction: Transaction) {
+        self.patch.transactions.insert(*hash, transaction);
+    }
+
+    /// Insert transaction result.
+    fn store_receipt(&mut self, hash: &Hash, receipt: Receipt) {
+        self.patch.receipts.insert(*hash, receipt);
+    }
+
+    /// Append an entry to the account transactions index.
+    fn store_account_transaction(&mut self, id: &str, entry: AccountTransaction) {
+        self.patch
+            .account_txs
+            .entry(id.to_owned())
+            .or_default()
+            .push(entry);
+    }
+
+    /// Append a smart contract event to the events index.
+    fn store_event(&mut self, record: EventRecord) {
+        self.patch.events.push(record);
+    }
+
+    /// Insert new block.
+    /// If the historical state is enabled, the state changes are recorded at the block height.
+    fn store_block(&mut self, block: Block) {
+        if self.history.is_some() {
+            let undo = mem::take(&mut self.undo);
+            self.patch.history = Some((block.data.height, undo));
+        }
+        let height = self.blocks_len();
+        self.patch
+            .blocks_by_hash
+            .insert(block.data.primary_hash(), height);
+        self.patch.blocks.insert(height, block);
+    }
+
+    /// Insert transactions hashes associated to a given block identified by `height`.
+    /// The `height` refers to the block position within the blockchain.
+    /// Returns the transactions trie root.
+    fn store_transactions_hashes(&mut self, height: u64, hashes: Vec<Hash>) -> Hash {
+        self.push_hashes(HashesList::Transactions, height, hashes)
+    }
+
+    /// Insert the transactions results (receipts) associated with a given block.
+    /// The `height` refers to the associated block height within the blockchain.
+    /// Returns the receipts trie root.
+    fn store_receipts_hashes(&mut self, height: u64, hashes: Vec<Hash>) -> Hash {
+        self.push_hashes(HashesList::Receipts, height, hashes)
+    }
+
+    /// Creates a fork checkpoint.
+    fn flush(&mut self) {
+        mem::take(&mut self.patch).merge_into(&mut self.flushed);
+    }
+
+    /// Rollback to the last checkpoint (`flush` point).
+    fn rollback(&mut self) {
+        self.patch = Patch::default();
+        self.accounts_hash.set(None);
+    }
+
+    fn load_account_keys(&self, id: &str) -> Vec<String> {
+        self.account_data_map(id).into_keys().collect()
+    }
+
+    fn load_account_keys_range(
+        &self,
+        id: &str,
+        prefix: &str,
+        after: Option<&str>,
+        limit: u64,
+    ) -> Vec<String> {
+        account_keys_page(&self.account_data_map(id), prefix, after, limit)
+    }
+
+    fn store_configuration(&mut self, id: &str, config: Vec<u8>) {
+        self.record_undo(HistoryKey::Config(id.to_owned()));
+        self.patch.config.insert(id.to_owned(), config);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        base::schema::{
+            tests::{
+                create_test_account, create_test_block, create_test_receipt, create_test_unit_tx,
+            },
+            SmartContractEvent, FUEL_LIMIT,
+        },
+        db::snapshot,
+    };
+
+    const ACCOUNT_ID1: &str = "QmNLei78zWmzUdbeRB3CiUfAizWUrbeeZh5K1rhAQKCh51";
+    const ACCOUNT_ID2: &str = "QmYHnEQLdf5h7KYbjFPuHSRk2SPgdXrJWFh5W696HPfq7i";
+
+    fn test_hashes() -> Vec<Hash> {
+        vec![
+            "1220b706053eb366e5a649ec7117dd896c63707d52b9a02f38bb01f13ab17a798f61",
+            "12200194fa02f34ddedb3f6d9bd09d774a865f26ec498e361e082240ac9ed1b82005",
+            "1220b09d7f52bba3792ce81d011aa213c96de4ce4203312aa8fe1c3be933b3725df5",
+            "1220816e1626269c0f8f7c1861101516f83cc6528cd59560f64cf13127f1fd0017b0",
+        ]
+        .into_iter()
+        .map(|h| Hash::from_hex(h).unwrap())
+        .collect()
+    }
+
+    #[test]
+    fn store_account_no_merge() {
+        let mut db = MemoryDb::new();
+        let mut fork = db.fork_create();
+        let account = create_test_account();
+        let id = account.id.clone();
+
+        fork.store_account(account.clone());
+
+        assert_eq!(fork.load_account(&id), Some(account));
+        assert_eq!(db.load_account(&id), None);
+    }
+
+    #[test]
+    fn store_account_merge() {
+        let mut db = MemoryDb::new();
+        let mut fork = db.fork_create();
+        let account = create_test_account();
+        let id = account.id.clone();
+        fork.store_account(account.clone());
+
+        let result = db.fork_merge(fork);
+
+        assert!(result.is_ok());
+        assert_eq!(db.load_account(&id), Some(account));
+    }
+
+    #[test]
+    fn store_account_data_merge() {
+        let mut db = MemoryDb::new();
+        let mut fork = db.fork_create();
+        fork.store_account_data(ACCOUNT_ID1, "data", vec![1, 2, 3]);
+
+        assert_eq!(db.load_account_data(ACCOUNT_ID1, "data"), None);
+
+        db.fork_merge(fork).unwrap();
+
+        assert_eq!(
+            db.load_account_data(ACCOUNT_ID1, "data"),
+            Some(vec![1, 2, 3])
+        );
+        assert_eq!(db.load_account_data(ACCOUNT_ID2, "data"), None);
+    }
+
+    #[test]
+    fn delete_account_data_merge() {
+        let mut db = MemoryDb::new();
+        let mut fork = db.fork_create();
+        fork.store_account_data(ACCOUNT_ID1, "data1", vec![1, 2, 3]);
+        fork.store_account_data(ACCOUNT_ID1, "data2", vec![4, 5, 6]);
+        db.fork_merge(fork).unwrap();
+        let mut fork = db.fork_create();
+        fork.remove_account_data(ACCOUNT_ID1, "data1");
+
+        assert_eq!(fork.load_account_data(ACCOUNT_ID1, "data1"), None);
+        assert_eq!(fork.load_account_keys(ACCOUNT_ID1), vec!["data2"]);
+
+        db.fork_merge(fork).unwrap();
+
+        assert_eq!(db.load_account_data(ACCOUNT_ID1, "data1"), None);
+        assert_eq!(
+            db.load_account_data(ACCOUNT_ID1, "data2"),
+            Some(vec![4, 5, 6])
+        );
+    }
+
+    #[test]
+    fn get_account_keys() {
+        let mut db = MemoryDb::new();
+        let mut fork = db.fork_create();
+
+        fork.store_account_data(ACCOUNT_ID1, "data3", vec![1, 2, 3]);
+        fork.store_account_data(ACCOUNT_ID1, "data1", vec![1, 2, 3]);
+        fork.store_account_data(ACCOUNT_ID1, "data2", vec![1, 2, 3]);
+
+        let res = fork.load_account_keys(ACCOUNT_ID1);
+
+        assert_eq!(res, vec!["data1", "data2", "data3"]);
+    }
+
+    #[test]
+    fn store_transaction_merge() {
+        let mut db = MemoryDb::new();
+        let mut fork = db.fork_create();
+        let tx = create_test_unit_tx(FUEL_LIMIT);
+        let hash = tx.primary_hash();
+        fork.store_transaction(&hash, tx.clone());
+
+        assert!(!db.contains_transaction(&hash));
+
+        db.fork_merge(fork).unwrap();
+
+        assert!(db.contains_transaction(&hash));
+        assert_eq!(db.load_transaction(&hash), Some(tx));
+    }
+
+    #[test]
+    fn store_block_merge() {
+        let mut db = MemoryDb::new();
+        let mut fork = db.fork_create();
+        let block = create_test_block();
+        fork.store_block(block.clone());
+
+        assert_eq!(db.load_block(0), None);
+
+        db.fork_merge(fork).unwrap();
+        let mut fork = db.fork_create();
+        fork.store_block(block.clone());
+        db.fork_merge(fork).unwrap();
+
+        assert_eq!(db.load_block(1), Some(block));
+        assert_eq!(db.load_block(2), None);
+        assert!(db.load_block(u64::MAX).is_some());
+    }
+
+    #[test]
+    fn store_transactions_hashes() {
+        let mut db = MemoryDb::new();
+        let mut fork = db.fork_create();
+
+        let root_hash = fork.store_transactions_hashes(0, test_hashes());
+
+        // Same root computed by the `RocksDb` backend.
+        assert_eq!(
+            "1220d76a63134cc183deca8e35eb005e249ea3308b6d339419b2d777e09c7637e548",
+            hex::encode(&root_hash.to_bytes())
+        );
+        db.fork_merge(fork).unwrap();
+        assert_eq!(db.load_transactions_hashes(0), Some(test_hashes()));
+        assert_eq!(db.load_transactions_hashes(1), None);
+    }
+
+    #[test]
+    fn store_hashes_in_chunks() {
+        let mut db = MemoryDb::new();
+        let mut fork = db.fork_create();
+        let hashes = test_hashes();
+
+        let root_hash = fork.store_receipts_hashes(0, hashes.clone());
+        fork.store_receipts_hashes(1, hashes[..1].to_vec());
+        fork.flush();
+        let chunked_root_hash = fork.store_receipts_hashes(1, hashes[1..].to_vec());
+        db.fork_merge(fork).unwrap();
+
+        assert_eq!(root_hash, chunked_root_hash);
+        assert_eq!(db.load_receipts_hashes(1), Some(hashes));
+    }
+
+    #[test]
+    fn state_hash_is_order_independent() {
+        let mut db = MemoryDb::new();
+        let mut fork1 = db.fork_create();
+        let mut fork2 = db.fork_create();
+
+        for i in 0..16u8 {
+            fork1.store_account_data(ACCOUNT_ID1, &format!("key{}", i), vec![i]);
+            fork2.store_account_data(ACCOUNT_ID1, &format!("key{}", 15 - i), vec![15 - i]);
+        }
+        fork1.store_account(Account::new(ACCOUNT_ID1, None));
+        fork1.store_account(Account::new(ACCOUNT_ID2, None));
+        fork2.store_account(Account::new(ACCOUNT_ID2, None));
+        fork2.store_account(Account::new(ACCOUNT_ID1, None));
+
+        assert_eq!(fork1.state_hash(ACCOUNT_ID1), fork2.state_hash(ACCOUNT_ID1));
+        assert_eq!(fork1.state_hash(""), fork2.state_hash(""));
+        assert_ne!(fork1.state_hash(""), fork1.state_hash(ACCOUNT_ID1));
+    }
+
+    #[test]
+    fn state_hash_after_remove() {
+        let mut db = MemoryDb::new();
+        let mut fork = db.fork_create();
+        let empty_hash = fork.state_hash(ACCOUNT_ID1);
+        fork.store_account_data(ACCOUNT_ID1, "data1", vec![1, 2, 3]);
+        let hash1 = fork.state_hash(ACCOUNT_ID1);
+        fork.store_account_data(ACCOUNT_ID1, "data2", vec![4, 5, 6]);
+        let hash2 = fork.state_hash(ACCOUNT_ID1);
+        db.fork_merge(fork).unwrap();
+
+        let mut fork = db.fork_create();
+        assert_eq!(fork.state_hash(ACCOUNT_ID1), hash2);
+        fork.remove_account_data(ACCOUNT_ID1, "data2");
+        assert_eq!(fork.state_hash(ACCOUNT_ID1), hash1);
+        fork.remove_account_data(ACCOUNT_ID1, "data1");
+        assert_eq!(fork.state_hash(ACCOUNT_ID1), empty_hash);
+        assert_ne!(hash1, hash2);
+    }
+
+    #[test]
+    fn accounts_state_hash_cached() {
+        let mut db = MemoryDb::new();
+        let mut fork = db.fork_create();
+        let empty_hash = fork.state_hash("");
+        fork.store_account(Account::new(ACCOUNT_ID1, None));
+        fork.flush();
+        let hash1 = fork.state_hash("");
+        fork.store_account(Account::new(ACCOUNT_ID2, None));
+        let hash2 = fork.state_hash("");
+        assert_eq!(fork.state_hash(""), hash2);
+        fork.rollback();
+        assert_eq!(fork.state_hash(""), hash1);
+        fork.store_account(Account::new(ACCOUNT_ID2, None));
+        db.fork_merge(fork).unwrap();
+
+        let mut fork = db.fork_create();
+        assert_eq!(fork.state_hash(""), hash2);
+        fork.store_account(Account::new(ACCOUNT_ID1, None));
+        assert_eq!(fork.state_hash(""), hash2);
+        assert_ne!(empty_hash, hash1);
+        assert_ne!(hash1, hash2);
+    }
+
+    #[test]
+    fn account_proof_against_state_hash() {
+        let mut db = MemoryDb::new();
+        let mut fork = db.fork_create();
+        fork.store_account(create_test_account());
+        fork.store_account(Account::new(ACCOUNT_ID1, None));
+        fork.store_account(Account::new(ACCOUNT_ID2, None));
+        fork.store_account_data(ACCOUNT_ID1, "data1", vec![1, 2, 3]);
+        fork.store_account_data(ACCOUNT_ID1, "data2", vec![4, 5, 6]);
+        let state_hash = fork.state_hash("");
+        let data_hash = fork.state_hash(ACCOUNT_ID1);
+        db.fork_merge(fork).unwrap();
+
+        let proof = db.load_account_proof(ACCOUNT_ID1).unwrap();
+        let data_proof = db.load_account_data_proof(ACCOUNT_ID1, "data2").unwrap();
+
+        assert!(proof.verify(&state_hash));
+        assert!(data_proof.verify(&data_hash));
+        assert_eq!(data_proof.value, vec![4, 5, 6]);
+        assert!(db.load_account_proof("unknown").is_none());
+        assert!(db.load_account_data_proof(ACCOUNT_ID2, "data1").is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "with-rocksdb")]
+    fn state_hash_same_as_rocksdb() {
+        use crate::db::RocksDb;
+        use tempfile::TempDir;
+
+        fn apply_ops<D: Db>(db: &mut D) -> Vec<Hash> {
+            let mut fork = db.fork_create();
+            fork.store_account(create_test_account());
+            fork.store_account(Account::new(ACCOUNT_ID1, None));
+            for i in 0..16u8 {
+                fork.store_account_data(ACCOUNT_ID1, &format!("key{}", i), vec![i; i as usize]);
+            }
+            fork.store_account_data(ACCOUNT_ID2, "data", vec![1, 2, 3]);
+            db.fork_merge(fork).unwrap();
+
+            let mut fork = db.fork_create();
+            let mut account = Account::new(ACCOUNT_ID2, None);
+            account.store_asset("XYZ", &[9]);
+            fork.store_account(account);
+            fork.remove_account_data(ACCOUNT_ID1, "key3");
+            fork.store_account_data(ACCOUNT_ID1, "key5", vec![5, 5]);
+            let hashes = vec![
+                fork.state_hash(""),
+                fork.state_hash(ACCOUNT_ID1),
+                fork.state_hash(ACCOUNT_ID2),
+                fork.state_hash("unknown"),
+            ];
+            db.fork_merge(fork).unwrap();
+            hashes
+        }
+
+        let dir = TempDir::new().unwrap();
+        let rocks_hashes = apply_ops(&mut RocksDb::new(dir.path()));
+
+        let memory_hashes = apply_ops(&mut MemoryDb::new());
+
+        assert_eq!(memory_hashes, rocks_hashes);
+    }
+
+    #[test]
+    fn merge_conflict() {
+        let mut db = MemoryDb::new();
+        let mut fork1 = db.fork_create();
+        let mut fork2 = db.fork_create();
+
+        let mut account = Account::new("123", None);
+        account.store_asset("abc", &[1]);
+        fork1.store_account(account);
+        fork1.store_account(Account::new("456", None));
+
+        let mut account = Account::new("123", None);
+        account.store_asset("abc", &[3]);
+        fork2.store_account(account);
+
+        // Merge conflicting forks
+        db.fork_merge(fork1).unwrap();
+        db.fork_merge(fork2).unwrap();
+
+        let account = db.load_account("123").unwrap();
+        let asset = account.load_asset("abc");
+        assert_eq!(asset, &[3]);
+        assert!(db.load_account("456").is_some());
+    }
+
+    #[test]
+    fn fork_rollback() {
+        let mut db = MemoryDb::new();
+        let mut fork = db.fork_create();
+
+        // Modifications to hold.
+        let a1 = Account::new("123", None);
+        fork.store_account(a1.clone());
+        fork.store_account_data(&a1.id, "data", vec![1]);
+        let t1 = create_test_unit_tx(FUEL_LIMIT);
+        fork.store_transaction(&t1.primary_hash(), t1.clone());
+
+        // Checkpoint.
+        fork.flush();
+        let state_hash = fork.state_hash("");
+        let data_hash = fork.state_hash(&a1.id);
+
+        // Modifications to discard.
+        let a2 = Account::new("456", None);
+        fork.store_account(a2.clone());
+        fork.store_account_data(&a1.id, "data", vec![2]);
+        fork.remove_account_data(&a1.id, "data");
+        fork.store_block(create_test_block());
+
+        // Rollback
+        fork.rollback();
+
+        assert_eq!(fork.state_hash(""), state_hash);
+        assert_eq!(fork.state_hash(&a1.id), data_hash);
+        assert_eq!(fork.load_account_data(&a1.id, "data"), Some(vec![1]));
+
+        // Merge
+        db.fork_merge(fork).unwrap();
+
+        // Check that modifications between checkpoint and rollback are lost.
+        assert_eq!(db.load_account(&a1.id), Some(a1));
+        assert_eq!(db.load_account(&a2.id), None);
+        assert_eq!(db.load_transaction(&t1.primary_hash()), Some(t1));
+        assert_eq!(db.load_block(0), None);
+    }
+
+    fn commit_history_block(db: &mut MemoryDb, height: u64, op: impl FnOnce(&mut MemoryDbFork)) {
+        let mut fork = db.fork_create();
+        op(&mut fork);
+        let mut block = create_test_block();
+        block.data.height = height;
+        fork.store_block(block);
+        db.fork_merge(fork).unwrap();
+    }
+
+    #[test]
+    fn load_account_at_height() {
+        let mut db = MemoryDb::new();
+        db.set_history_retention(Some(3));
+        let account = create_test_account();
+        let id = account.id.clone();
+
+        commit_history_block(&mut db, 0, |fork| {
+            fork.store_account_data(&id, "k", vec![0])
+        });
+        commit_history_block(&mut db, 1, |fork| {
+            fork.store_account_data(&id, "k", vec![1])
+        });
+        commit_history_block(&mut db, 2, |fork| {
+            fork.store_account(account.clone());
+            fork.remove_account_data(&id, "k");
+        });
+        commit_history_block(&mut db, 3, |fork| {
+            fork.store_account_data(&id, "k", vec![2]);
+            fork.rollback();
+            fork.store_account_data(&id, "k", vec![3]);
+        });
+        commit_history_block(&mut db, 4, |_| ());
+
+        assert_eq!(db.load_account_at(&id, 1), None);
+        assert_eq!(db.load_account_at(&id, 2), Some(account.clone()));
+        assert_eq!(db.load_account_at(&id, 4), Some(account));
+        assert_eq!(db.load_account_data_at(&id, "k", 1), Some(vec![1]));
+        assert_eq!(db.load_account_data_at(&id, "k", 2), None);
+        assert_eq!(db.load_account_data_at(&id, "k", 3), Some(vec![3]));
+        assert_eq!(db.load_account_data_at(&id, "k", 4), Some(vec![3]));
+    }
+
+    #[test]
+    fn load_account_at_height_out_of_window() {
+        let mut db = MemoryDb::new();
+        db.set_history_retention(Some(1));
+        let id = ACCOUNT_ID1;
+
+        commit_history_block(&mut db, 0, |fork| fork.store_account_data(id, "k", vec![0]));
+        commit_history_block(&mut db, 1, |fork| fork.store_account_data(id, "k", vec![1]));
+        commit_history_block(&mut db, 2, |fork| fork.store_account_data(id, "k", vec![2]));
+
+        assert_eq!(db.load_account_data_at(id, "k", 0), None);
+        assert_eq!(db.load_account_data_at(id, "k", 1), Some(vec![1]));
+        assert_eq!(db.load_account_data_at(id, "k", 3), None);
+    }
+
+    #[test]
+    fn load_account_data_at_height_similar_keys() {
+        let mut db = MemoryDb::new();
+        db.set_history_retention(Some(10));
+        let id = ACCOUNT_ID1;
+        let other = "k0000000000000001";
+
+        commit_history_block(&mut db, 0, |fork| {
+            fork.store_account_data(id, "k", vec![0]);
+            fork.store_account_data(id, other, vec![10]);
+        });
+        commit_history_block(&mut db, 1, |fork| {
+            fork.store_account_data(id, other, vec![11])
+        });
+        commit_history_block(&mut db, 2, |fork| fork.store_account_data(id, "k", vec![2]));
+
+        assert_eq!(db.load_account_data_at(id, "k", 0), Some(vec![0]));
+        assert_eq!(db.load_account_data_at(id, "k", 1), Some(vec![0]));
+        assert_eq!(db.load_account_data_at(id, "k", 2), Some(vec![2]));
+        assert_eq!(db.load_account_data_at(id, other, 0), Some(vec![10]));
+        assert_eq!(db.load_account_data_at(id, other, 2), Some(vec![11]));
+    }
+
+    #[test]
+    fn load_account_at_height_history_disabled() {
+        let mut db = MemoryDb::new();
+        let account = create_test_account();
+        let id = account.id.clone();
+
+        commit_history_block(&mut db, 0, |fork| fork.store_account(account));
+
+        assert_eq!(db.load_account_at(&id, 0), None);
+    }
+
+    fn create_test_chain(db: &mut MemoryDb, len: u64) {
+        let mut prev_hash = Hash::default();
+        for height in 0..len {
+            let mut fork = db.fork_create();
+            fork.store_account_data(ACCOUNT_ID1, "k", height.to_be_bytes().to_vec());
+            let mut account = Account::new(ACCOUNT_ID1, None);
+            account.data_hash = Some(fork.state_hash(ACCOUNT_ID1));
+            fork.store_account(account);
+            fork.store_account(Account::new(ACCOUNT_ID2, None));
+            fork.store_configuration("blockchain:settings", vec![1]);
+            fork.store_data("seed", vec![height as u8]);
+            let mut block = create_test_block();
+            block.data.height = height;
+            block.data.prev_hash = prev_hash;
+            block.data.txs_hash = fork.store_transactions_hashes(height, vec![Hash::default()]);
+            block.data.rxs_hash = fork.store_receipts_hashes(height, vec![Hash::default()]);
+            block.data.state_hash = fork.state_hash("");
+            prev_hash = block.data.primary_hash();
+            fork.store_block(block);
+            db.fork_merge(fork).unwrap();
+        }
+    }
+
+    #[test]
+    fn snapshot_export_import() {
+        let mut db = MemoryDb::new();
+        create_test_chain(&mut db, 3);
+        let mut buf = vec![];
+        db.export_snapshot(2, &mut buf).unwrap();
+
+        let mut imported = MemoryDb::new();
+        let trusted_hash = db.load_block(2).unwrap().data.primary_hash();
+        let last = snapshot::import_snapshot(&mut imported, buf.as_slice(), &trusted_hash).unwrap();
+
+        assert_eq!(Some(last.clone()), db.load_block(u64::MAX));
+        assert_eq!(imported.load_block(1), db.load_block(1));
+        assert_eq!(
+            imported.load_transactions_hashes(1),
+            db.load_transactions_hashes(1)
+        );
+        assert_eq!(imported.load_receipts_hashes(1), db.load_receipts_hashes(1));
+        assert_eq!(
+            imported.load_account(ACCOUNT_ID1),
+            db.load_account(ACCOUNT_ID1)
+        );
+        assert_eq!(
+            imported.load_account(ACCOUNT_ID2),
+            db.load_account(ACCOUNT_ID2)
+        );
+        assert_eq!(
+            imported.load_account_data(ACCOUNT_ID1, "k"),
+            Some(2u64.to_be_bytes().to_vec())
+        );
+        assert_eq!(
+            imported.load_configuration("blockchain:settings"),
+            Some(vec![1])
+        );
+        assert_eq!(imported.load_data("seed"), Some(vec![2]));
+        assert_eq!(imported.fork_create().state_hash(""), last.data.state_hash);
+    }
+
+    #[test]
+    fn snapshot_export_at_height() {
+        let mut db = MemoryDb::new();
+        db.set_history_retention(Some(10));
+        create_test_chain(&mut db, 3);
+        let mut buf = vec![];
+        db.export_snapshot(1, &mut buf).unwrap();
+
+        let mut imported = MemoryDb::new();
+        let trusted_hash = db.load_block(1).unwrap().data.primary_hash();
+        let last = snapshot::import_snapshot(&mut imported, buf.as_slice(), &trusted_hash).unwrap();
+
+        assert_eq!(Some(last), db.load_block(1));
+        assert_eq!(imported.load_block(2), None);
+        assert_eq!(
+            imported.load_account_data(ACCOUNT_ID1, "k"),
+            Some(1u64.to_be_bytes().to_vec())
+        );
+        assert_eq!(imported.load_data("seed"), Some(vec![1]));
+    }
+
+    #[test]
+    fn snapshot_export_out_of_history() {
+        let mut db = MemoryDb::new();
+        create_test_chain(&mut db, 2);
+        let mut buf = vec![];
+
+        assert!(db.export_snapshot(0, &mut buf).is_err());
+        assert!(db.export_snapshot(2, &mut buf).is_err());
+        assert!(db.export_snapshot(1, &mut buf).is_ok());
+    }
+
+    #[test]
+    fn snapshot_import_state_mismatch() {
+        let mut db = MemoryDb::new();
+        create_test_chain(&mut db, 2);
+        let mut fork = db.fork_create();
+        fork.store_account(create_test_account());
+        db.fork_merge(fork).unwrap();
+        let mut buf = vec![];
+        db.export_snapshot(1, &mut buf).unwrap();
+
+        let mut imported = MemoryDb::new();
+        let trusted_hash = db.load_block(1).unwrap().data.primary_hash();
+        let err =
+            snapshot::import_snapshot(&mut imported, buf.as_slice(), &trusted_hash).unwrap_err();
+
+        assert_eq!(err.kind, ErrorKind::BrokenIntegrity);
+    }
+
+    #[test]
+    fn snapshot_import_untrusted() {
+        let mut db = MemoryDb::new();
+        create_test_chain(&mut db, 2);
+        let mut buf = vec![];
+        db.export_snapshot(1, &mut buf).unwrap();
+
+        let mut imported = MemoryDb::new();
+        let trusted_hash = db.load_block(0).unwrap().data.primary_hash();
+        let err =
+            snapshot::import_snapshot(&mut imported, buf.as_slice(), &trusted_hash).unwrap_err();
+
+        assert_eq!(err.kind, ErrorKind::BrokenIntegrity);
+    }
+
+    #[test]
+    fn snapshot_import_into_not_empty_db() {
+        let mut db = MemoryDb::new();
+        create_test_chain(&mut db, 1);
+        let mut buf = vec![];
+        db.export_snapshot(0, &mut buf).unwrap();
+
+        let trusted_hash = db.load_block(0).unwrap().data.primary_hash();
+        let err = snapshot::import_snapshot(&mut db, buf.as_slice(), &trusted_hash).unwrap_err();
+
+        assert_eq!(err.kind, ErrorKind::DatabaseFault);
+    }
+
+    fn create_test_event_record(height: u64, emitter: &str, name: &str) -> EventRecord {
+        EventRecord {
+            height,
+            index: 0,
+            event: SmartContractEvent {
+                event_tx: Hash::default(),
+                emitter_account: emitter.to_owned(),
+                emitter_smart_contract: Hash::default(),
+                event_name: name.to_owned(),
+                event_data: vec![height as u8],
+            },
+        }
+    }
+
+    fn commit_revert_test_block(db: &mut MemoryDb, value: u8) -> Block {
+        let (height, prev_hash) = match db.load_block(u64::MAX) {
+            Some(block) => (block.data.height + 1, block.data.primary_hash()),
+            None => (0, Hash::default()),
+        };
+        let mut fork = db.fork_create();
+        let mut tx = create_test_unit_tx(FUEL_LIMIT);
+        if let Transaction::UnitTransaction(ref mut tx) = tx {
+            tx.data.set_nonce(vec![value; 8]);
+        }
+        let hash = tx.primary_hash();
+        let rx = create_test_receipt();
+        let rx_hash = rx.primary_hash();
+        for id in transaction_accounts(&tx) {
+            let entry = AccountTransaction {
+                height,
+                index: 0,
+                hash,
+            };
+            fork.store_account_transaction(&id, entry);
+        }
+        fork.store_transaction(&hash, tx);
+        fork.store_receipt(&hash, rx);
+        fork.store_event(create_test_event_record(height, ACCOUNT_ID1, "k"));
+        fork.store_account_data(ACCOUNT_ID1, "k", vec![value]);
+        let mut account = Account::new(ACCOUNT_ID1, None);
+        account.data_hash = Some(fork.state_hash(ACCOUNT_ID1));
+        fork.store_account(account);
+        if height > 0 {
+            fork.store_account(Account::new(ACCOUNT_ID2, None));
+        }
+        let mut block = create_test_block();
+        block.data.height = height;
+        block.data.prev_hash = prev_hash;
+        block.data.txs_hash = fork.store_transactions_hashes(height, vec![hash]);
+        block.data.rxs_hash = fork.store_receipts_hashes(height, vec![rx_hash]);
+        block.data.state_hash = fork.state_hash("");
+        fork.store_block(block.clone());
+        db.fork_merge(fork).unwrap();
+        block
+    }
+
+    #[test]
+    fn revert_to_height() {
+        let mut db = MemoryDb::new();
+        db.set_history_retention(Some(10));
+        let target = commit_revert_test_block(&mut db, 0);
+        commit_revert_test_block(&mut db, 1);
+        commit_revert_test_block(&mut db, 2);
+        let reverted = db.load_transactions_hashes(1).unwrap()[0];
+
+        let reverted_block = db.load_block(1).unwrap().data.primary_hash();
+
+        let txs = db.revert_to(0).unwrap();
+
+        assert_eq!(txs.len(), 2);
+        assert_eq!(txs[0].primary_hash(), reverted);
+        assert_eq!(db.load_block(u64::MAX), Some(target.clone()));
+        assert_eq!(db.fork_create().state_hash(""), target.data.state_hash);
+        assert_eq!(db.load_account_data(ACCOUNT_ID1, "k"), Some(vec![0]));
+        assert_eq!(db.load_account(ACCOUNT_ID2), None);
+        assert!(!db.contains_transaction(&reverted));
+        assert_eq!(db.load_receipt(&reverted), None);
+        assert_eq!(db.load_transactions_hashes(1), None);
+        assert_eq!(db.load_receipts_hashes(1), None);
+        assert_eq!(db.load_block_by_hash(&reverted_block), None);
+        assert_eq!(
+            db.load_events(&EventFilter::default(), 0, 10),
+            vec![create_test_event_record(0, ACCOUNT_ID1, "k")]
+        );
+
+        let block = commit_revert_test_block(&mut db, 3);
+        assert_eq!(block.data.height, 1);
+        assert_eq!(db.load_block(1), Some(block));
+    }
+
+    #[test]
+    fn revert_to_height_restores_config_and_data() {
+        let mut db = MemoryDb::new();
+        db.set_history_retention(Some(10));
+        commit_history_block(&mut db, 0, |fork| {
+            fork.store_configuration("blockchain:settings", vec![0]);
+            fork.store_data("k", vec![0]);
+        });
+        commit_history_block(&mut db, 1, |fork| {
+            fork.store_configuration("blockchain:settings", vec![1]);
+            fork.store_configuration("other", vec![1]);
+            fork.store_data("k", vec![1]);
+            fork.store_data("other", vec![1]);
+        });
+
+        db.revert_to(0).unwrap();
+
+        assert_eq!(db.load_configuration("blockchain:settings"), Some(vec![0]));
+        assert_eq!(db.load_configuration("other"), None);
+        assert_eq!(db.load_data("k"), Some(vec![0]));
+        assert_eq!(db.load_data("other"), None);
+    }
+
+    #[test]
+    fn revert_to_height_without_history() {
+        let mut db = MemoryDb::new();
+        commit_revert_test_block(&mut db, 0);
+        commit_revert_test_block(&mut db, 1);
+
+        assert!(db.revert_to(0).is_err());
+        assert!(db.revert_to(2).is_err());
+        assert!(db.revert_to(1).unwrap().is_empty());
+        assert_eq!(db.load_block(u64::MAX).unwrap().data.height, 1);
+    }
+
+    #[test]
+    fn load_block_by_hash() {
+        let mut db = MemoryDb::new();
+        let block0 = commit_revert_test_block(&mut db, 0);
+        let block1 = commit_revert_test_block(&mut db, 1);
+
+        assert_eq!(
+            db.load_block_by_hash(&block0.data.primary_hash()),
+            Some(block0)
+        );
+        assert_eq!(
+            db.load_block_by_hash(&block1.data.primary_hash()),
+            Some(block1.clone())
+        );
+        assert_eq!(db.load_block_by_hash(&block1.primary_hash()), None);
+    }
+
+    #[test]
+    fn load_account_transactions() {
+        let mut db = MemoryDb::new();
+        db.set_history_retention(Some(10));
+        commit_revert_test_block(&mut db, 0);
+        commit_revert_test_block(&mut db, 1);
+        commit_revert_test_block(&mut db, 2);
+        let tx = db.load_transaction(&db.load_transactions_hashes(1).unwrap()[0]);
+        let account = tx.unwrap().get_account().to_owned();
+
+        let page = db.load_account_transactions(&account, 1, 10);
+        assert_eq!(page.len(), 2);
+        assert_eq!(page[0].height, 1);
+        assert_eq!(page[0].hash, db.load_transactions_hashes(1).unwrap()[0]);
+        assert_eq!(page[1].height, 2);
+        assert_eq!(db.load_account_transactions(&account, 0, 1).len(), 1);
+        assert!(db.load_account_transactions(&account, 3, 10).is_empty());
+        assert!(db.load_account_transactions(ACCOUNT_ID2, 0, 10).is_empty());
+
+        db.revert_to(0).unwrap();
+        let page = db.load_account_transactions(&account, 0, 10);
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].height, 0);
+    }
+
+    #[test]
+    fn load_events() {
+        let mut db = MemoryDb::new();
+        let records = vec![
+            create_test_event_record(0, ACCOUNT_ID1, "a"),
+            create_test_event_record(1, ACCOUNT_ID2, "a"),
+            create_test_event_record(1, ACCOUNT_ID1, "b"),
+            create_test_event_record(2, ACCOUNT_ID1, "a"),
+        ];
+        for record in records.iter() {
+            let mut fork = db.fork_create();
+            fork.store_event(record.clone());
+            db.fork_merge(fork).unwrap();
+        }
+        let filter = |emitter: Option<&str>, name: Option<&str>| EventFilter {
+            emitter: emitter.map(str::to_owned),
+            name: name.map(str::to_owned),
+            ..Default::default()
+        };
+
+        assert_eq!(db.load_events(&filter(None, None), 0, 10), records);
+        assert_eq!(
+            db.load_events(&filter(Some(ACCOUNT_ID1), None), 0, 10),
+            vec![records[0].clone(), records[2].clone(), records[3].clone()]
+        );
+        assert_eq!(
+            db.load_events(&filter(Some(ACCOUNT_ID1), None), 1, 1),
+            vec![records[2].clone()]
+        );
+        assert_eq!(
+            db.load_events(&filter(None, Some("a")), 0, 10),
+            vec![records[0].clone(), records[1].clone(), records[3].clone()]
+        );
+        let by_height = EventFilter {
+            min_height: Some(1),
+            ..filter(Some(ACCOUNT_ID1), Some("a"))
+        };
+        assert_eq!(db.load_events(&by_height, 0, 10), vec![records[3].clone()]);
+        let by_height = EventFilter {
+            min_height: Some(1),
+            max_height: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            db.load_events(&by_height, 0, 10),
+            vec![records[1].clone(), records[2].clone()]
+        );
+        assert!(db
+            .load_events(&filter(Some(ACCOUNT_ID2), Some("b")), 0, 10)
+            .is_empty());
+    }
+
+    #[test]
+    fn load_account_keys_range() {
+        let mut db = MemoryDb::new();
+        let mut fork = db.fork_create();
+        for key in ["a", "b:1", "b:2", "b:3", "c"] {
+            fork.store_account_data(ACCOUNT_ID1, key, vec![0]);
+        }
+        db.fork_merge(fork).unwrap();
+
+        assert_eq!(
+            db.load_account_keys_range(ACCOUNT_ID1, "", None, 10),
+            vec!["a", "b:1", "b:2", "b:3", "c"]
+        );
+        assert_eq!(
+            db.load_account_keys_range(ACCOUNT_ID1, "b:", None, 2),
+            vec!["b:1", "b:2"]
+        );
+        assert_eq!(
+            db.load_account_keys_range(ACCOUNT_ID1, "b:", Some("b:2"), 2),
+            vec!["b:3"]
+        );
+        assert_eq!(
+            db.load_account_keys_range(ACCOUNT_ID1, "b:", Some("a"), 1),
+            vec!["b:1"]
+        );
+        assert!(db
+            .load_account_keys_range(ACCOUNT_ID1, "d", None, 10)
+            .is_empty());
+        assert!(db
+            .load_account_keys_range(ACCOUNT_ID2, "", None, 10)
+            .is_empty());
+
+        let mut fork = db.fork_create();
+        fork.remove_account_data(ACCOUNT_ID1, "b:2");
+        fork.store_account_data(ACCOUNT_ID1, "b:4", vec![0]);
+        assert_eq!(
+            fork.load_account_keys_range(ACCOUNT_ID1, "b:", Some("b:1"), 10),
+            vec!["b:3", "b:4"]
+        );
+    }
+}
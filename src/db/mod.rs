@@ -15,14 +15,141 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with TRINCI. If not, see <https://www.gnu.org/licenses/>.
 
-use crate::{base::schema::Block, crypto::Hash, error::*, Account, Receipt, Transaction};
+use crate::{
+    base::schema::{Block, SmartContractEvent},
+    crypto::{merkle::MapProof, Hash},
+    error::*,
+    Account, Receipt, Transaction,
+};
 #[cfg(test)]
 use mockall::automock;
+use serde::{Deserialize, Serialize};
 
 #[cfg(feature = "with-rocksdb")]
 pub mod rocks;
 #[cfg(feature = "with-rocksdb")]
 pub use rocks::{RocksDb, RocksDbFork};
+#[cfg(feature = "with-rocksdb")]
+pub mod secondary;
+#[cfg(feature = "with-rocksdb")]
+pub use secondary::SecondaryDb;
+
+#[cfg(any(test, feature = "with-memorydb"))]
+pub mod memory;
+#[cfg(any(test, feature = "with-memorydb"))]
+pub use memory::{MemoryDb, MemoryDbFork};
+
+pub mod recording;
+pub mod snapshot;
+pub mod tracking;
+pub mod verify;
+
+pub use recording::{AccountDiff, AssetDiff, DataDiff, RecordingFork, StateDiff};
+pub use tracking::{StateAccess, TrackingFork};
+
+/// Entry of the per-account transactions index.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AccountTransaction {
+    /// Block height.
+    pub height: u64,
+    /// Transaction position within the block.
+    pub index: u32,
+    /// Transaction hash.
+    pub hash: Hash,
+}
+
+/// Accounts indexed for a transaction: the target and the caller.
+pub(crate) fn transaction_accounts(tx: &Transaction) -> Vec<String> {
+    let target = tx.get_account().to_owned();
+    let caller = tx.get_caller().to_account_id();
+    match target == caller {
+        true => vec![target],
+        false => vec![target, caller],
+    }
+}
+
+/// Entry of the smart contract events index.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EventRecord {
+    /// Block height.
+    pub height: u64,
+    /// Position within the block of the transaction that emitted the event.
+    pub index: u32,
+    /// Smart contract event.
+    pub event: SmartContractEvent,
+}
+
+/// Smart contract events query filter.
+/// Fields set to `None` are not used to filter the events.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct EventFilter {
+    /// Account that emitted the event.
+    pub emitter: Option<String>,
+    /// Event name.
+    pub name: Option<String>,
+    /// Lowest block height (inclusive).
+    pub min_height: Option<u64>,
+    /// Highest block height (inclusive).
+    pub max_height: Option<u64>,
+}
+
+impl EventFilter {
+    /// Key of the index list holding the events selected by the filter.
+    /// Returns `None` if all the events are selected.
+    pub(crate) fn index_key(&self) -> Option<String> {
+        event_index_key(self.emitter.as_deref(), self.name.as_deref())
+    }
+}
+
+fn event_index_key(emitter: Option<&str>, name: Option<&str>) -> Option<String> {
+    match (emitter, name) {
+        (None, None) => None,
+        (Some(emitter), None) => Some(format!("e:{}", emitter)),
+        (None, Some(name)) => Some(format!("n:{}", name)),
+        // Emitter length prefix keeps the key unambiguous.
+        (Some(emitter), Some(name)) => Some(format!("en:{}:{}{}", emitter.len(), emitter, name)),
+    }
+}
+
+/// Keys of the index lists an event is appended to.
+pub(crate) fn event_index_keys(event: &SmartContractEvent) -> Vec<String> {
+    let emitter = Some(event.emitter_account.as_str());
+    let name = Some(event.event_name.as_str());
+    [(emitter, None), (None, name), (emitter, name)]
+        .iter()
+        .filter_map(|(emitter, name)| event_index_key(*emitter, *name))
+        .collect()
+}
+
+/// Select a page of events from an index list of `len` entries.
+/// The `record` closure fetches the event referenced by the list entry at a given position.
+/// The list entries are sorted by height, thus the height range start is binary searched.
+pub(crate) fn events_page<F>(
+    len: u64,
+    record: F,
+    filter: &EventFilter,
+    from: u64,
+    limit: u64,
+) -> Vec<EventRecord>
+where
+    F: Fn(u64) -> Option<EventRecord>,
+{
+    let min_height = filter.min_height.unwrap_or_default();
+    let max_height = filter.max_height.unwrap_or(u64::MAX);
+    let (mut lo, mut hi) = (0, len);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        match record(mid) {
+            Some(entry) if entry.height < min_height => lo = mid + 1,
+            _ => hi = mid,
+        }
+    }
+    (lo.saturating_add(from)..len)
+        .filter_map(record)
+        .take_while(|entry| entry.height <= max_height)
+        .take(limit as usize)
+        .collect()
+}
 
 /// Trait providing access to the database.
 #[cfg_attr(test, automock(type DbForkType = MockDbFork;))]
@@ -36,9 +163,32 @@ pub trait Db: Send + Sync + 'static {
     /// Load full keys list associated to the account data.
     fn load_account_keys(&self, id: &str) -> Vec<String>;
 
+    /// Load a page of the account data keys starting with `prefix`, in
+    /// lexicographic order.
+    /// The page starts after the `after` key (the last key of the previous
+    /// page) and contains up to `limit` keys.
+    fn load_account_keys_range(
+        &self,
+        id: &str,
+        prefix: &str,
+        after: Option<&str>,
+        limit: u64,
+    ) -> Vec<String>;
+
     /// Load data associated to the given account `id`.
     fn load_account_data(&self, id: &str, key: &str) -> Option<Vec<u8>>;
 
+    /// Load account by id as it was after the block at the given `height`.
+    /// Requires the historical state to be enabled, returns `None` if the
+    /// height is out of the retention window.
+    fn load_account_at(&self, id: &str, height: u64) -> Option<Account>;
+
+    /// Load data associated to the given account `id` as it was after the
+    /// block at the given `height`.
+    /// Requires the historical state to be enabled, returns `None` if the
+    /// height is out of the retention window.
+    fn load_account_data_at(&self, id: &str, key: &str, height: u64) -> Option<Vec<u8>>;
+
     /// Fetch DB generic data (this shuld be used by core only, this map sould be used only internally).
     fn load_data(&self, key: &str) -> Option<Vec<u8>>;
 
@@ -55,10 +205,29 @@ pub trait Db: Send + Sync + 'static {
     /// This can be used to fetch the last block by passing u64::MAX as the height.
     fn load_block(&self, height: u64) -> Option<Block>;
 
+    /// Load block by its header (`BlockData`) hash.
+    fn load_block_by_hash(&self, hash: &Hash) -> Option<Block>;
+
+    /// Load the transactions that touched the account `id`, in execution order.
+    /// The page starts at position `from` and contains up to `limit` entries.
+    /// The index is populated only if enabled in the executor.
+    fn load_account_transactions(&self, id: &str, from: u64, limit: u64)
+        -> Vec<AccountTransaction>;
+
+    /// Load the smart contract events selected by `filter`, in emission order.
+    /// The page starts at position `from`, within the selected events, and
+    /// contains up to `limit` entries.
+    /// The index is populated only if enabled in the executor.
+    fn load_events(&self, filter: &EventFilter, from: u64, limit: u64) -> Vec<EventRecord>;
+
     /// Get transactions hashes associated to a given block identified by `height`.
     /// The `height` refers to the block position within the blockchain.
     fn load_transactions_hashes(&self, height: u64) -> Option<Vec<Hash>>;
 
+    /// Get receipts hashes associated to a given block identified by `height`.
+    /// The `height` refers to the block position within the blockchain.
+    fn load_receipts_hashes(&self, height: u64) -> Option<Vec<Hash>>;
+
     /// Create database fork.
     /// A fork is a set of uncommitted modifications to the database.
     fn fork_create(&mut self) -> Self::DbForkType;
@@ -68,6 +237,21 @@ pub trait Db: Send + Sync + 'static {
 
     /// Read configuration from the DB
     fn load_configuration(&self, id: &str) -> Option<Vec<u8>>;
+
+    /// Revert the blocks following `height`, restoring the state, the blocks,
+    /// the transactions, the receipts and the hashes lists as they were after
+    /// the block at `height`.
+    /// Requires the historical state to cover the target height.
+    /// Returns the reverted transactions, in execution order.
+    fn revert_to(&mut self, height: u64) -> Result<Vec<Transaction>>;
+
+    /// Load the Merkle proof of the account within the accounts map.
+    /// The proof is verifiable against the last block `state_hash`.
+    fn load_account_proof(&self, id: &str) -> Option<MapProof>;
+
+    /// Load the Merkle proof of a data entry within the account data map.
+    /// The proof is verifiable against the account `data_hash`.
+    fn load_account_data_proof(&self, id: &str, key: &str) -> Option<MapProof>;
 }
 
 /// Database fork trait.
@@ -103,12 +287,30 @@ pub trait DbFork: 'static {
     /// Load full keys list associated to the account data.
     fn load_account_keys(&self, id: &str) -> Vec<String>;
 
+    /// Load a page of the account data keys starting with `prefix`, in
+    /// lexicographic order.
+    /// The page starts after the `after` key (the last key of the previous
+    /// page) and contains up to `limit` keys.
+    fn load_account_keys_range(
+        &self,
+        id: &str,
+        prefix: &str,
+        after: Option<&str>,
+        limit: u64,
+    ) -> Vec<String>;
+
     /// Store transaction using transaction hash as the key.
     fn store_transaction(&mut self, hash: &Hash, tx: Transaction);
 
     /// Store transaction execution receipt using transaction hash as the key.
     fn store_receipt(&mut self, hash: &Hash, receipt: Receipt);
 
+    /// Append an entry to the transactions index of the account `id`.
+    fn store_account_transaction(&mut self, id: &str, entry: AccountTransaction);
+
+    /// Append a smart contract event to the events index.
+    fn store_event(&mut self, record: EventRecord);
+
     /// Insert block in the blockchain tail.
     fn store_block(&mut self, block: Block);
 
@@ -0,0 +1,349 @@
+// This file is part of TRINCI.
+//
+// Copyright (C) 2021 Affidaty Spa.
+//
+// TRINCI is free software: you can redistribute it and/or modify it under
+// the terms of the GNU Affero General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or (at your
+// option) any later version.
+//
+// TRINCI is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License
+// for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with TRINCI. If not, see <https://www.gnu.org/licenses/>.
+
+//! Database fork recording the accounts state changes.
+//!
+//! A `RecordingFork` wraps a fork and keeps track of the accounts and the
+//! accounts data written through it, including the writes performed by nested
+//! smart contract calls, together with their value before the first write.
+//! The state diff is computed against the current fork state, thus rolled
+//! back writes are not reported.
+
+use super::{AccountTransaction, DbFork, EventRecord};
+use crate::{base::schema::Block, crypto::Hash, Account, Receipt, Transaction};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Account data entry change.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DataDiff {
+    /// Data key.
+    pub key: String,
+    /// Value before the execution, `None` if missing.
+    #[serde(with = "serde_bytes")]
+    pub before: Option<Vec<u8>>,
+    /// Value after the execution, `None` if removed.
+    #[serde(with = "serde_bytes")]
+    pub after: Option<Vec<u8>>,
+}
+
+/// Account asset change.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AssetDiff {
+    /// Asset identifier.
+    pub asset: String,
+    /// Value before the execution, `None` if missing.
+    #[serde(with = "serde_bytes")]
+    pub before: Option<Vec<u8>>,
+    /// Value after the execution, `None` if removed.
+    #[serde(with = "serde_bytes")]
+    pub after: Option<Vec<u8>>,
+}
+
+/// Account state change.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AccountDiff {
+    /// Account identifier.
+    pub id: String,
+    /// Account before the execution, `None` if missing.
+    pub before: Option<Account>,
+    /// Account after the execution.
+    pub after: Option<Account>,
+    /// Changed assets, by asset identifier.
+    pub assets: Vec<AssetDiff>,
+    /// Changed data entries, by key.
+    pub data: Vec<DataDiff>,
+}
+
+/// Accounts state changes, by account identifier.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct StateDiff {
+    /// Changed accounts.
+    pub accounts: Vec<AccountDiff>,
+}
+
+/// Fork wrapper recording the accounts writes.
+pub struct RecordingFork<F: DbFork> {
+    /// Wrapped fork.
+    fork: F,
+    /// Written accounts, with their state before the first write.
+    accounts: BTreeMap<String, Option<Account>>,
+    /// Written accounts data, with their value before the first write.
+    data: BTreeMap<(String, String), Option<Vec<u8>>>,
+}
+
+impl<F: DbFork> RecordingFork<F> {
+    /// Wraps a fork.
+    pub fn new(fork: F) -> Self {
+        RecordingFork {
+            fork,
+            accounts: BTreeMap::new(),
+            data: BTreeMap::new(),
+        }
+    }
+
+    /// Get the wrapped fork.
+    pub fn into_inner(self) -> F {
+        self.fork
+    }
+
+    /// Get the changes of the recorded accounts, compared to the state before
+    /// the first write. Accounts written back to their original state are
+    /// not reported.
+    pub fn diff(&self) -> StateDiff {
+        let ids: BTreeSet<&String> = self
+            .accounts
+            .keys()
+            .chain(self.data.keys().map(|(id, _)| id))
+            .collect();
+
+        let accounts = ids
+            .into_iter()
+            .filter_map(|id| {
+                let after = self.fork.load_account(id);
+                let before = match self.accounts.get(id) {
+                    Some(before) => before.clone(),
+                    None => after.clone(),
+                };
+                let data: Vec<DataDiff> = self
+                    .data
+                    .range((id.clone(), String::new())..)
+                    .take_while(|((data_id, _), _)| data_id == id)
+                    .map(|((_, key), before)| DataDiff {
+                        key: key.clone(),
+                        before: before.clone(),
+                        after: self.fork.load_account_data(id, key),
+                    })
+                    .filter(|diff| diff.before != diff.after)
+                    .collect();
+                let assets = assets_diff(before.as_ref(), after.as_ref());
+                if before == after && data.is_empty() {
+                    return None;
+                }
+                Some(AccountDiff {
+                    id: id.clone(),
+                    before,
+                    after,
+                    assets,
+                    data,
+                })
+            })
+            .collect();
+
+        StateDiff { accounts }
+    }
+
+    /// Records the value of an account data entry before the first write.
+    fn record_data(&mut self, id: &str, key: &str) {
+        let entry = (id.to_owned(), key.to_owned());
+        if !self.data.contains_key(&entry) {
+            let before = self.fork.load_account_data(id, key);
+            self.data.insert(entry, before);
+        }
+    }
+}
+
+/// Changed assets between two account states.
+fn assets_diff(before: Option<&Account>, after: Option<&Account>) -> Vec<AssetDiff> {
+    let empty = BTreeMap::new();
+    let before = before.map_or(&empty, |account| &account.assets);
+    let after = after.map_or(&empty, |account| &account.assets);
+    before
+        .keys()
+        .chain(after.keys())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .map(|asset| AssetDiff {
+            asset: asset.clone(),
+            before: before.get(asset).map(|value| value.to_vec()),
+            after: after.get(asset).map(|value| value.to_vec()),
+        })
+        .filter(|diff| diff.before != diff.after)
+        .collect()
+}
+
+impl<F: DbFork> DbFork for RecordingFork<F> {
+    fn state_hash(&self, id: &str) -> Hash {
+        self.fork.state_hash(id)
+    }
+
+    fn load_account(&self, id: &str) -> Option<Account> {
+        self.fork.load_account(id)
+    }
+
+    fn store_account(&mut self, account: Account) {
+        if !self.accounts.contains_key(&account.id) {
+            let before = self.fork.load_account(&account.id);
+            self.accounts.insert(account.id.clone(), before);
+        }
+        self.fork.store_account(account)
+    }
+
+    fn load_account_data(&self, id: &str, key: &str) -> Option<Vec<u8>> {
+        self.fork.load_account_data(id, key)
+    }
+
+    fn store_account_data(&mut self, id: &str, key: &str, data: Vec<u8>) {
+        self.record_data(id, key);
+        self.fork.store_account_data(id, key, data)
+    }
+
+    fn load_data(&self, key: &str) -> Option<Vec<u8>> {
+        self.fork.load_data(key)
+    }
+
+    fn store_data(&mut self, key: &str, data: Vec<u8>) {
+        self.fork.store_data(key, data)
+    }
+
+    fn remove_account_data(&mut self, id: &str, key: &str) {
+        self.record_data(id, key);
+        self.fork.remove_account_data(id, key)
+    }
+
+    fn load_account_keys(&self, id: &str) -> Vec<String> {
+        self.fork.load_account_keys(id)
+    }
+
+    fn load_account_keys_range(
+        &self,
+        id: &str,
+        prefix: &str,
+        after: Option<&str>,
+        limit: u64,
+    ) -> Vec<String> {
+        self.fork.load_account_keys_range(id, prefix, after, limit)
+    }
+
+    fn store_transaction(&mut self, hash: &Hash, tx: Transaction) {
+        self.fork.store_transaction(hash, tx)
+    }
+
+    fn store_receipt(&mut self, hash: &Hash, receipt: Receipt) {
+        self.fork.store_receipt(hash, receipt)
+    }
+
+    fn store_account_transaction(&mut self, id: &str, entry: AccountTransaction) {
+        self.fork.store_account_transaction(id, entry)
+    }
+
+    fn store_event(&mut self, record: EventRecord) {
+        self.fork.store_event(record)
+    }
+
+    fn store_block(&mut self, block: Block) {
+        self.fork.store_block(block)
+    }
+
+    fn store_transactions_hashes(&mut self, height: u64, hashes: Vec<Hash>) -> Hash {
+        self.fork.store_transactions_hashes(height, hashes)
+    }
+
+    fn store_receipts_hashes(&mut self, height: u64, hashes: Vec<Hash>) -> Hash {
+        self.fork.store_receipts_hashes(height, hashes)
+    }
+
+    fn flush(&mut self) {
+        self.fork.flush()
+    }
+
+    fn rollback(&mut self) {
+        self.fork.rollback()
+    }
+
+    fn store_configuration(&mut self, id: &str, config: Vec<u8>) {
+        self.fork.store_configuration(id, config)
+    }
+}
+
+#[cfg(all(test, feature = "with-rocksdb"))]
+mod tests {
+    use super::*;
+    use crate::{
+        base::schema::tests::create_test_account,
+        db::{Db, RocksDb},
+    };
+    use tempfile::TempDir;
+
+    const ACCOUNT_ID: &str = "QmNLei78zWmzUdbeRB3CiUfAizWUrbeeZh5K1rhAQKCh51";
+
+    fn create_recording_fork(dir: &TempDir) -> RecordingFork<<RocksDb as Db>::DbForkType> {
+        let mut db = RocksDb::new(dir.path());
+        let mut fork = db.fork_create();
+        fork.store_account(create_test_account());
+        fork.store_account_data(ACCOUNT_ID, "kept", vec![1]);
+        fork.store_account_data(ACCOUNT_ID, "removed", vec![2]);
+        db.fork_merge(fork).unwrap();
+        RecordingFork::new(db.fork_create())
+    }
+
+    #[test]
+    fn diff_records_writes() {
+        let dir = TempDir::new().unwrap();
+        let mut fork = create_recording_fork(&dir);
+
+        let mut account = fork.load_account(ACCOUNT_ID).unwrap();
+        account.store_asset("XYZ", &[9]);
+        fork.store_account(account.clone());
+        fork.store_account_data(ACCOUNT_ID, "added", vec![3]);
+        fork.store_account_data(ACCOUNT_ID, "kept", vec![1]);
+        fork.remove_account_data(ACCOUNT_ID, "removed");
+
+        let diff = fork.diff();
+
+        assert_eq!(diff.accounts.len(), 1);
+        let account_diff = &diff.accounts[0];
+        assert_eq!(account_diff.id, ACCOUNT_ID);
+        assert_eq!(account_diff.before, Some(create_test_account()));
+        assert_eq!(account_diff.after, Some(account));
+        assert_eq!(
+            account_diff.assets,
+            vec![AssetDiff {
+                asset: "XYZ".to_owned(),
+                before: None,
+                after: Some(vec![9]),
+            }]
+        );
+        assert_eq!(
+            account_diff.data,
+            vec![
+                DataDiff {
+                    key: "added".to_owned(),
+                    before: None,
+                    after: Some(vec![3]),
+                },
+                DataDiff {
+                    key: "removed".to_owned(),
+                    before: Some(vec![2]),
+                    after: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_ignores_rolled_back_writes() {
+        let dir = TempDir::new().unwrap();
+        let mut fork = create_recording_fork(&dir);
+
+        fork.flush();
+        fork.store_account_data(ACCOUNT_ID, "kept", vec![5]);
+        fork.store_account(Account::new("other", None));
+        fork.rollback();
+
+        assert_eq!(fork.diff(), StateDiff::default());
+    }
+}
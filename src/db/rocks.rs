@@ -15,23 +15,43 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with TRINCI. If not, see <https://www.gnu.org/licenses/>.
 
-use std::path::Path;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::{Read, Write},
+    ops::Bound,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
 
 use crate::{
     base::{
         schema::{Account, Block, Receipt, Transaction},
         serialize::{rmp_deserialize, rmp_serialize},
     },
-    crypto::{Hash, HashAlgorithm},
-    db::{Db, DbFork},
+    crypto::{
+        merkle::{MapProof, MapProofNode},
+        Hash, HashAlgorithm, Hashable,
+    },
+    db::{
+        event_index_keys, events_page, secondary,
+        snapshot::{self, rewind_entries, SnapshotWriter, StateRewind},
+        transaction_accounts, AccountTransaction, Db, DbFork, EventFilter, EventRecord,
+    },
     Error, ErrorKind,
 };
 use merkledb::{
-    access::CopyAccessExt,
-    BinaryKey, BinaryValue, Database, DbOptions, Fork, ListIndex, MapIndex, ObjectHash,
-    ProofListIndex, ProofMapIndex, RocksDB, Snapshot,
+    access::{CopyAccessExt, RawAccess},
+    proof_map::BitsRange,
+    BinaryKey, BinaryValue, Database, DbOptions, Entry, Fork, KeySetIndex, ListIndex, MapIndex,
+    ObjectHash, ProofListIndex, ProofMapIndex, RocksDB, Snapshot,
     _reexports::{Error as MisteryError, Hash as MerkleDbHash},
 };
+use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 
 impl From<MerkleDbHash> for Hash {
@@ -107,6 +127,26 @@ impl BinaryValue for Block {
     }
 }
 
+impl BinaryValue for AccountTransaction {
+    fn to_bytes(&self) -> Vec<u8> {
+        rmp_serialize(self).unwrap()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> std::result::Result<Self, MisteryError> {
+        rmp_deserialize(bytes.as_ref()).map_err(|err| err.into())
+    }
+}
+
+impl BinaryValue for EventRecord {
+    fn to_bytes(&self) -> Vec<u8> {
+        rmp_serialize(self).unwrap()
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> std::result::Result<Self, MisteryError> {
+        rmp_deserialize(bytes.as_ref()).map_err(|err| err.into())
+    }
+}
+
 const ACCOUNTS: &str = "accounts";
 const CONFIG: &str = "config";
 const TRANSACTIONS: &str = "transactions";
@@ -114,14 +154,259 @@ const RECEIPTS: &str = "receipts";
 const TRANSACTIONS_HASH: &str = "transactions_hash";
 const RECEIPTS_HASH: &str = "receipts_hash";
 const BLOCKS: &str = "blocks";
+const BLOCKS_BY_HASH: &str = "blocks_by_hash";
+const ACCOUNT_TXS: &str = "account_txs";
+const EVENTS: &str = "events";
+const EVENTS_INDEX: &str = "events_index";
+const ACCOUNT_KEYS: &str = "account_keys";
+const SCHEMA_VERSION_KEY: &str = "schema_version";
 const INTERNAL_DB: &str = "internal_db";
+const HISTORY: &str = "history";
+const HISTORY_JOURNAL: &str = "history_journal";
+
+/// Entry tracked by the history map.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum HistoryKey {
+    Account(String),
+    AccountData(String, String),
+    Config(String),
+    Internal(String),
+}
+
+/// Fixed length identifier of an entry within the history map.
+/// Entry keys are chosen by the smart contracts, thus are hashed to prevent
+/// the versions of an entry from interleaving with the ones of another entry.
+fn history_entry_id(key: &HistoryKey) -> Vec<u8> {
+    let buf = rmp_serialize(key).unwrap_or_default();
+    Hash::from_data(HashAlgorithm::Sha256, &buf).to_bytes()
+}
+
+/// History map entries are the entry identifier followed by the block height
+/// (big endian), thus versions of the same entry are contiguous and height ordered.
+fn history_entry_key(key: &HistoryKey, height: u64) -> Vec<u8> {
+    let mut buf = history_entry_id(key);
+    buf.extend_from_slice(&height.to_be_bytes());
+    buf
+}
+
+/// History values keep track of entries that were not existing.
+fn history_value_encode(value: Option<Vec<u8>>) -> Vec<u8> {
+    match value {
+        Some(mut buf) => {
+            buf.insert(0, 1);
+            buf
+        }
+        None => vec![0],
+    }
+}
+
+/// Inverse of `history_value_encode`.
+fn history_value_decode(mut buf: Vec<u8>) -> Option<Vec<u8>> {
+    match buf.first() {
+        Some(1) => {
+            buf.remove(0);
+            Some(buf)
+        }
+        _ => None,
+    }
+}
+
+/// Revert within the `fork` the blocks in the range `(height, last]`.
+/// Returns the reverted transactions, in execution order.
+fn revert_fork(fork: &Fork, height: u64, last: u64) -> Vec<Transaction> {
+    let mut txs = vec![];
+    for h in height + 1..=last {
+        let mut hashes: ProofListIndex<_, Hash> = fork.get_proof_list((TRANSACTIONS_HASH, &h));
+        let mut transactions: MapIndex<_, Hash, Transaction> = fork.get_map(TRANSACTIONS);
+        let mut receipts: MapIndex<_, Hash, Receipt> = fork.get_map(RECEIPTS);
+        for hash in hashes.iter() {
+            if let Some(tx) = transactions.get(&hash) {
+                txs.push(tx);
+            }
+            transactions.remove(&hash);
+            receipts.remove(&hash);
+        }
+        hashes.clear();
+        let mut hashes: ProofListIndex<_, Hash> = fork.get_proof_list((RECEIPTS_HASH, &h));
+        hashes.clear();
+    }
+
+    // Drop the reverted entries from the accounts transactions index.
+    for id in txs.iter().flat_map(transaction_accounts) {
+        let mut list: ListIndex<_, AccountTransaction> = fork.get_list((ACCOUNT_TXS, id.as_str()));
+        while matches!(list.last(), Some(entry) if entry.height > height) {
+            list.pop();
+        }
+    }
+
+    // Drop the events emitted by the reverted blocks.
+    let mut records: ListIndex<_, EventRecord> = fork.get_list(EVENTS);
+    while let Some(record) = records.last().filter(|record| record.height > height) {
+        for key in event_index_keys(&record.event) {
+            let mut index: ListIndex<_, u64> = fork.get_list((EVENTS_INDEX, key.as_str()));
+            index.pop();
+        }
+        records.pop();
+    }
+
+    // Restore the entries modified by each block, from the last one.
+    let mut history: MapIndex<_, [u8], Vec<u8>> = fork.get_map(HISTORY);
+    let mut journal: MapIndex<_, u64, Vec<u8>> = fork.get_map(HISTORY_JOURNAL);
+    for h in (height + 1..=last).rev() {
+        let keys: Vec<HistoryKey> = journal
+            .get(&h)
+            .and_then(|buf| rmp_deserialize(&buf).ok())
+            .unwrap_or_default();
+        for key in keys.iter() {
+            let entry_key = history_entry_key(key, h);
+            let value = history
+                .get(entry_key.as_slice())
+                .and_then(history_value_decode);
+            history.remove(entry_key.as_slice());
+            let (mut map, key): (ProofMapIndex<_, str, Vec<u8>>, &str) = match key {
+                HistoryKey::Account(id) => (fork.get_proof_map(ACCOUNTS), id),
+                HistoryKey::AccountData(id, data_key) => {
+                    let mut keys: KeySetIndex<_, str> =
+                        fork.get_key_set((ACCOUNT_KEYS, id.as_str()));
+                    match value {
+                        Some(_) => keys.insert(data_key),
+                        None => keys.remove(data_key.as_str()),
+                    }
+                    (fork.get_proof_map((ACCOUNTS, id.as_str())), data_key)
+                }
+                HistoryKey::Config(id) => (fork.get_proof_map(CONFIG), id),
+                HistoryKey::Internal(key) => (fork.get_proof_map(INTERNAL_DB), key),
+            };
+            match value {
+                Some(value) => map.put(key, value),
+                None => map.remove(key),
+            }
+        }
+        journal.remove(&h);
+    }
+
+    let mut blocks: ListIndex<_, Block> = fork.get_list(BLOCKS);
+    let mut blocks_by_hash: MapIndex<_, Hash, u64> = fork.get_map(BLOCKS_BY_HASH);
+    for h in height + 1..=last {
+        if let Some(block) = blocks.get(h) {
+            blocks_by_hash.remove(&block.data.primary_hash());
+        }
+    }
+    blocks.truncate(height + 1);
+    txs
+}
+
+/// Select a page of account data keys from the sorted keys index.
+fn account_keys_page<I: Iterator<Item = String>>(
+    keys_from: impl FnOnce(&str) -> I,
+    prefix: &str,
+    after: Option<&str>,
+    limit: u64,
+) -> Vec<String> {
+    let start = match after {
+        Some(after) if after > prefix => after,
+        _ => prefix,
+    };
+    keys_from(start)
+        .skip_while(|key| Some(key.as_str()) == after)
+        .take_while(|key| key.starts_with(prefix))
+        .take(limit as usize)
+        .collect()
+}
+
+/// Current database schema version.
+/// Shall be increased, together with a new `MIGRATIONS` step, on any change
+/// of the stored data layout.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// Schema version introducing the blocks hash index.
+const BLOCKS_BY_HASH_VERSION: u32 = 1;
+/// Schema version introducing the account data keys index.
+const ACCOUNT_KEYS_VERSION: u32 = 2;
+
+/// Database schema migration step.
+struct Migration {
+    /// Schema version produced by the step.
+    version: u32,
+    /// Short description, logged while migrating.
+    description: &'static str,
+    /// Rewrite the database content in place.
+    /// Steps shall be idempotent.
+    apply: fn(&Fork),
+}
+
+/// Ordered schema migration steps.
+/// Each step upgrades the database from `version - 1` to `version`.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: BLOCKS_BY_HASH_VERSION,
+        description: "blocks hash index",
+        apply: migrate_blocks_by_hash,
+    },
+    Migration {
+        version: ACCOUNT_KEYS_VERSION,
+        description: "account data keys index",
+        apply: migrate_account_keys,
+    },
+];
+
+/// Build the blocks hash index.
+fn migrate_blocks_by_hash(fork: &Fork) {
+    let blocks: ListIndex<_, Block> = fork.get_list(BLOCKS);
+    let mut index: MapIndex<_, Hash, u64> = fork.get_map(BLOCKS_BY_HASH);
+    blocks
+        .iter()
+        .for_each(|block| index.put(&block.data.primary_hash(), block.data.height));
+}
+
+/// Build the sorted account data keys index.
+fn migrate_account_keys(fork: &Fork) {
+    let accounts: ProofMapIndex<_, str, Account> = fork.get_proof_map(ACCOUNTS);
+    for id in accounts.keys() {
+        let map: ProofMapIndex<_, str, Vec<u8>> = fork.get_proof_map((ACCOUNTS, id.as_str()));
+        let mut keys: KeySetIndex<_, str> = fork.get_key_set((ACCOUNT_KEYS, id.as_str()));
+        map.keys().for_each(|key| keys.insert(&key));
+    }
+}
+
+/// Create the checkpoint of the database with the block at `height` as the
+/// last one (at least) into `dir`.
+/// Failures are logged, the node keeps running.
+fn create_checkpoint(backend: &RocksDB, dir: &Path, height: u64) {
+    // The checkpoint is published once complete.
+    let path = secondary::checkpoint_path(dir, height);
+    let tmp = dir.join(format!(".tmp-{}", height));
+    let _ = fs::remove_dir_all(&tmp);
+    let res = fs::create_dir_all(dir)
+        .map_err(|err| err.to_string())
+        .and_then(|_| {
+            backend
+                .create_checkpoint(&tmp)
+                .map_err(|err| err.to_string())
+        })
+        .and_then(|_| fs::rename(&tmp, &path).map_err(|err| err.to_string()));
+    match res {
+        Ok(_) => secondary::prune_checkpoints(dir),
+        Err(err) => {
+            warn!("[db] error publishing checkpoint {}: {}", height, err);
+            let _ = fs::remove_dir_all(&tmp);
+        }
+    }
+}
 
 /// Database implementation using rocks db.
 pub struct RocksDb {
     /// Backend implementing the `Database` trait (defined by merkledb crate).
-    backend: RocksDB,
+    /// Shared with the checkpoints publishing thread.
+    backend: Arc<RocksDB>,
     /// Last state read-only snapshot.
     snap: Box<dyn Snapshot>,
+    /// Historical state retention window (number of blocks).
+    history: Option<u64>,
+    /// Checkpoints directory and interval (number of blocks).
+    checkpoints: Option<(PathBuf, u64)>,
+    /// Checkpoint publishing thread and its completion flag.
+    checkpoint_worker: Option<(JoinHandle<()>, Arc<AtomicBool>)>,
 }
 
 /// Database writeable snapshot.
@@ -129,17 +414,389 @@ pub struct RocksDb {
 /// apply a set of changes to the database.
 /// In the end, the changes shall be merged into the database using the database
 /// `merge` method.
-pub struct RocksDbFork(Fork);
+pub struct RocksDbFork {
+    /// Backend fork.
+    fork: Fork,
+    /// Historical state retention window (number of blocks).
+    history: Option<u64>,
+    /// Values of the entries modified by the fork, before the first modification.
+    /// Used to build the historical state when the block is stored.
+    undo: BTreeMap<HistoryKey, Option<Vec<u8>>>,
+}
 
 impl RocksDb {
     /// Create/Open a database from the filesystem.
+    /// Panics if the database cannot be opened, see `open`.
     pub fn new<P: AsRef<Path>>(path: P) -> Self {
-        let options = DbOptions::default();
-        let backend = RocksDB::open(path, &options).unwrap_or_else(|err| {
+        RocksDb::open(path).unwrap_or_else(|err| {
             panic!("Error opening rocks-db backend: {}", err);
-        });
+        })
+    }
+
+    /// Create/Open a database from the filesystem.
+    /// Databases with an older schema version are migrated in place, while
+    /// databases with an unknown (newer) schema version are refused.
+    pub fn open<P: AsRef<Path>>(path: P) -> crate::Result<Self> {
+        let mut db = RocksDb::open_backend(path)?;
+        db.migrate()?;
+        Ok(db)
+    }
+
+    /// Open a checkpoint published by a node, without migrating it.
+    /// Checkpoints with a different schema version are refused.
+    pub(crate) fn open_checkpoint<P: AsRef<Path>>(path: P) -> crate::Result<Self> {
+        let db = RocksDb::open_backend(path)?;
+        let version = db.schema_version();
+        if version != SCHEMA_VERSION {
+            return Err(Error::new_ext(
+                ErrorKind::DatabaseFault,
+                format!(
+                    "checkpoint schema version {} (expected {})",
+                    version, SCHEMA_VERSION
+                ),
+            ));
+        }
+        Ok(db)
+    }
+
+    fn open_backend<P: AsRef<Path>>(path: P) -> crate::Result<Self> {
+        let options = DbOptions::default();
+        let backend = RocksDB::open(path, &options)
+            .map_err(|err| Error::new_ext(ErrorKind::DatabaseFault, err))?;
         let snap = backend.snapshot();
-        RocksDb { backend, snap }
+        Ok(RocksDb {
+            backend: Arc::new(backend),
+            snap,
+            history: None,
+            checkpoints: None,
+            checkpoint_worker: None,
+        })
+    }
+
+    /// Schema version of the database content.
+    /// Databases created before the introduction of the version marker have
+    /// version 0, unless empty.
+    pub fn schema_version(&self) -> u32 {
+        let version: Entry<_, u32> = self.snap.get_entry(SCHEMA_VERSION_KEY);
+        version.get().unwrap_or_else(|| {
+            let blocks: ListIndex<_, Block> = self.snap.get_list(BLOCKS);
+            let accounts: ProofMapIndex<_, str, Account> = self.snap.get_proof_map(ACCOUNTS);
+            match blocks.is_empty() && accounts.keys().next().is_none() {
+                true => SCHEMA_VERSION,
+                false => 0,
+            }
+        })
+    }
+
+    /// Apply the migration steps following the stored schema version.
+    /// The steps and the new version marker are committed atomically.
+    fn migrate(&mut self) -> crate::Result<()> {
+        let version = self.schema_version();
+        if version > SCHEMA_VERSION {
+            return Err(Error::new_ext(
+                ErrorKind::DatabaseFault,
+                format!(
+                    "unknown database schema version {} (supported up to {})",
+                    version, SCHEMA_VERSION
+                ),
+            ));
+        }
+        let fork = self.backend.fork();
+        {
+            let mut marker: Entry<_, u32> = fork.get_entry(SCHEMA_VERSION_KEY);
+            if marker.get() == Some(SCHEMA_VERSION) {
+                return Ok(());
+            }
+            for migration in MIGRATIONS.iter().filter(|m| m.version > version) {
+                info!(
+                    "[db] migrating schema to version {}: {}",
+                    migration.version, migration.description
+                );
+                (migration.apply)(&fork);
+            }
+            marker.set(SCHEMA_VERSION);
+        }
+        self.backend
+            .merge(fork.into_patch())
+            .map_err(|err| Error::new_ext(ErrorKind::DatabaseFault, err))?;
+        self.snap = self.backend.snapshot();
+        Ok(())
+    }
+
+    /// Enable the historical state for the last `retention` blocks.
+    /// Only the state changes committed after the call are recorded.
+    /// Pass `None` to disable it.
+    pub fn set_history_retention(&mut self, retention: Option<u64>) {
+        self.history = retention;
+    }
+
+    /// Publish a checkpoint of the database into the given directory every
+    /// `interval` blocks, to be followed by `SecondaryDb` readers.
+    /// Only the last `secondary::CHECKPOINTS_KEPT` checkpoints are kept.
+    /// Pass `None` to disable it.
+    ///
+    /// Checkpoints are created by a background thread, out of the blocks
+    /// commit path. Thus the checkpoint named after a height may contain some
+    /// of the following blocks as well, and a checkpoint is skipped if the
+    /// previous one is still in progress.
+    pub fn set_checkpoints(&mut self, checkpoints: Option<(PathBuf, u64)>) {
+        self.checkpoints = checkpoints;
+    }
+
+    /// Start publishing a checkpoint if the last block height is a multiple
+    /// of the checkpoints interval.
+    fn publish_checkpoint(&mut self) {
+        let (dir, interval) = match &self.checkpoints {
+            Some((dir, interval)) if *interval > 0 => (dir.clone(), *interval),
+            _ => return,
+        };
+        let height = match self.load_block(u64::MAX) {
+            Some(block) if block.data.height % interval == 0 => block.data.height,
+            _ => return,
+        };
+        if secondary::checkpoint_path(&dir, height).exists() {
+            return;
+        }
+        if let Some((_, done)) = &self.checkpoint_worker {
+            if !done.load(Ordering::Acquire) {
+                warn!(
+                    "[db] checkpoint {} skipped, the previous one is in progress",
+                    height
+                );
+                return;
+            }
+        }
+        self.wait_checkpoint();
+        let backend = Arc::clone(&self.backend);
+        let done = Arc::new(AtomicBool::new(false));
+        let worker_done = Arc::clone(&done);
+        let handle = thread::spawn(move || {
+            create_checkpoint(&backend, &dir, height);
+            worker_done.store(true, Ordering::Release);
+        });
+        self.checkpoint_worker = Some((handle, done));
+    }
+
+    /// Wait for the completion of the checkpoint being published, if any.
+    pub fn wait_checkpoint(&mut self) {
+        if let Some((handle, _)) = self.checkpoint_worker.take() {
+            if handle.join().is_err() {
+                warn!("[db] checkpoint publishing thread panicked");
+            }
+        }
+    }
+
+    /// Create/Open a database from the filesystem and initialize it with
+    /// the content of a snapshot, whose last block hash shall match the
+    /// `trusted_hash`.
+    /// The database shall be empty, the blocks execution resumes from the
+    /// snapshot height + 1.
+    /// If the import fails the partially written database is removed.
+    pub fn import_snapshot<P: AsRef<Path>, R: Read>(
+        path: P,
+        reader: R,
+        trusted_hash: &Hash,
+    ) -> crate::Result<Self> {
+        let mut db = RocksDb::open(path.as_ref())?;
+        if db.load_block(u64::MAX).is_some() {
+            return Err(Error::new_ext(
+                ErrorKind::DatabaseFault,
+                "snapshot import requires an empty database",
+            ));
+        }
+        if let Err(err) = snapshot::import_snapshot(&mut db, reader, trusted_hash) {
+            drop(db);
+            if let Err(err) = fs::remove_dir_all(path.as_ref()) {
+                warn!("[db] partially imported database not removed: {}", err);
+            }
+            return Err(err);
+        }
+        Ok(db)
+    }
+
+    /// Export a snapshot of the state at the block `height`.
+    /// The heights preceding the last block are rebuilt from the historical state.
+    /// Returns the snapshot digest.
+    pub fn export_snapshot<W: Write>(&self, height: u64, writer: W) -> crate::Result<Hash> {
+        let blocks: ListIndex<_, Block> = self.snap.get_list(BLOCKS);
+        let block = blocks
+            .get(height)
+            .ok_or_else(|| Error::new(ErrorKind::ResourceNotFound))?;
+        let rewind = if height + 1 == blocks.len() {
+            StateRewind::default()
+        } else if self.history_covers(height) {
+            self.history_rewind(height)
+        } else {
+            return Err(Error::new_ext(
+                ErrorKind::ResourceNotFound,
+                "state history not available for the target height",
+            ));
+        };
+        let mut snapshot = SnapshotWriter::new(writer, height, block.data.state_hash)?;
+        snapshot.write_blocks(
+            blocks
+                .iter()
+                .take(height as usize + 1)
+                .map(|block| snapshot::snapshot_block(self, block)),
+        )?;
+        let accounts: ProofMapIndex<_, str, Account> = self.snap.get_proof_map(ACCOUNTS);
+        snapshot.write_accounts(
+            rewind_entries(accounts.iter(), &rewind.accounts).map(|(_, account)| account),
+        )?;
+        // Account data is exported for the accounts existing at `height`.
+        let empty = BTreeMap::new();
+        for (id, _) in rewind_entries(accounts.iter(), &rewind.accounts) {
+            let map: ProofMapIndex<_, str, Vec<u8>> =
+                self.snap.get_proof_map((ACCOUNTS, id.as_str()));
+            let previous = rewind.accounts_data.get(&id).unwrap_or(&empty);
+            snapshot.write_account_data(&id, rewind_entries(map.iter(), previous))?;
+        }
+        let config: ProofMapIndex<_, str, Vec<u8>> = self.snap.get_proof_map(CONFIG);
+        snapshot.write_config(rewind_entries(config.iter(), &rewind.config))?;
+        let internal: ProofMapIndex<_, str, Vec<u8>> = self.snap.get_proof_map(INTERNAL_DB);
+        snapshot.write_internal_db(rewind_entries(internal.iter(), &rewind.internal_db))?;
+        snapshot.finish()
+    }
+
+    /// Values, after the block at `height`, of the entries modified afterwards.
+    /// The history shall cover the given `height`.
+    fn history_rewind(&self, height: u64) -> StateRewind {
+        // The first entry version after `height` holds the value it had at `height`.
+        let map: MapIndex<_, [u8], Vec<u8>> = self.snap.get_map(HISTORY);
+        let journal: MapIndex<_, u64, Vec<u8>> = self.snap.get_map(HISTORY_JOURNAL);
+        let mut previous = BTreeMap::new();
+        for (h, buf) in journal.iter_from(&(height + 1)) {
+            let keys: Vec<HistoryKey> = rmp_deserialize(&buf).unwrap_or_default();
+            for key in keys {
+                if !previous.contains_key(&key) {
+                    let value = map
+                        .get(history_entry_key(&key, h).as_slice())
+                        .and_then(history_value_decode);
+                    previous.insert(key, value);
+                }
+            }
+        }
+
+        let mut rewind = StateRewind::default();
+        for (key, value) in previous {
+            match key {
+                HistoryKey::Account(id) => {
+                    let account = value.and_then(|buf| rmp_deserialize(&buf).ok());
+                    rewind.accounts.insert(id, account);
+                }
+                HistoryKey::AccountData(id, key) => {
+                    rewind
+                        .accounts_data
+                        .entry(id)
+                        .or_default()
+                        .insert(key, value);
+                }
+                HistoryKey::Config(id) => {
+                    rewind.config.insert(id, value);
+                }
+                HistoryKey::Internal(key) => {
+                    rewind.internal_db.insert(key, value);
+                }
+            }
+        }
+        rewind
+    }
+
+    /// Check if the state at the given `height` can be rebuilt from the history.
+    fn history_covers(&self, height: u64) -> bool {
+        let retention = match self.history {
+            Some(retention) => retention,
+            None => return false,
+        };
+        let blocks: ListIndex<_, Block> = self.snap.get_list(BLOCKS);
+        let last = match blocks.len().checked_sub(1) {
+            Some(last) => last,
+            None => return false,
+        };
+        if height > last || height.saturating_add(retention) < last {
+            return false;
+        }
+        // All the blocks following `height` shall be recorded.
+        let journal: MapIndex<_, u64, Vec<u8>> = self.snap.get_map(HISTORY_JOURNAL);
+        height == last || matches!(journal.keys().next(), Some(first) if first <= height + 1)
+    }
+
+    /// Lookup an entry value as it was after the block at the given `height`.
+    /// The `current` value is used if the entry was not modified afterwards.
+    /// The outer `None` is returned if the height is not covered by the history.
+    fn load_history(
+        &self,
+        key: &HistoryKey,
+        height: u64,
+        current: impl FnOnce() -> Option<Vec<u8>>,
+    ) -> Option<Option<Vec<u8>>> {
+        if !self.history_covers(height) {
+            return None;
+        }
+        // The first entry version after `height` holds the value it had at `height`.
+        let map: MapIndex<_, [u8], Vec<u8>> = self.snap.get_map(HISTORY);
+        let id = history_entry_id(key);
+        let value = map
+            .iter_from(&history_entry_key(key, height + 1))
+            .next()
+            .filter(|(entry_key, _)| entry_key.starts_with(&id))
+            .map(|(_, value)| history_value_decode(value));
+        Some(value.unwrap_or_else(current))
+    }
+}
+
+impl RocksDbFork {
+    /// Record the value of a state entry before its first modification.
+    fn record_undo(&mut self, key: HistoryKey, value: impl FnOnce(&Self) -> Option<Vec<u8>>) {
+        if self.history.is_some() && !self.undo.contains_key(&key) {
+            let value = value(self);
+            self.undo.insert(key, value);
+        }
+    }
+
+    /// Write the recorded entries previous values into the history and
+    /// prune the versions no longer needed by the retention window.
+    fn store_history(&mut self, height: u64) {
+        let retention = match self.history {
+            Some(retention) => retention,
+            None => return,
+        };
+        let undo = std::mem::take(&mut self.undo);
+        let mut map: MapIndex<_, [u8], Vec<u8>> = self.fork.get_map(HISTORY);
+        let keys: Vec<HistoryKey> = undo
+            .into_iter()
+            .map(|(key, value)| {
+                map.put(
+                    &history_entry_key(&key, height),
+                    history_value_encode(value),
+                );
+                key
+            })
+            .collect();
+        let mut journal: MapIndex<_, u64, Vec<u8>> = self.fork.get_map(HISTORY_JOURNAL);
+        journal.put(&height, rmp_serialize(&keys).unwrap_or_default());
+
+        // Versions at heights up to `height - retention` are not required to
+        // rebuild the state of any block within the window.
+        let stale: Vec<u64> = journal
+            .keys()
+            .take_while(|h| h.saturating_add(retention) <= height)
+            .collect();
+        for h in stale {
+            let keys: Vec<HistoryKey> = journal
+                .get(&h)
+                .and_then(|buf| rmp_deserialize(&buf).ok())
+                .unwrap_or_default();
+            keys.iter()
+                .for_each(|key| map.remove(history_entry_key(key, h).as_slice()));
+            journal.remove(&h);
+        }
+    }
+}
+
+impl Drop for RocksDb {
+    fn drop(&mut self) {
+        self.wait_checkpoint();
     }
 }
 
@@ -159,6 +816,31 @@ impl Db for RocksDb {
         map.keys().collect()
     }
 
+    /// Load a page of the account data keys starting with `prefix`.
+    /// The account data keys are sorted if the database is not migrated yet.
+    fn load_account_keys_range(
+        &self,
+        id: &str,
+        prefix: &str,
+        after: Option<&str>,
+        limit: u64,
+    ) -> Vec<String> {
+        if self.schema_version() < ACCOUNT_KEYS_VERSION {
+            let keys: BTreeSet<String> = self.load_account_keys(id).into_iter().collect();
+            return account_keys_page(
+                |start| {
+                    keys.range::<str, _>((Bound::Included(start), Bound::Unbounded))
+                        .cloned()
+                },
+                prefix,
+                after,
+                limit,
+            );
+        }
+        let index: KeySetIndex<_, str> = self.snap.get_key_set((ACCOUNT_KEYS, id));
+        account_keys_page(|start| index.iter_from(start), prefix, after, limit)
+    }
+
     /// Load data associated to the given account `id`.
     fn load_account_data(&self, id: &str, key: &str) -> Option<Vec<u8>> {
         let map: ProofMapIndex<_, str, Vec<u8>> = self.snap.get_proof_map((ACCOUNTS, id));
@@ -199,6 +881,43 @@ impl Db for RocksDb {
         }
     }
 
+    /// Get block by its header hash.
+    /// Blocks are scanned if the database is not migrated yet.
+    fn load_block_by_hash(&self, hash: &Hash) -> Option<Block> {
+        if self.schema_version() < BLOCKS_BY_HASH_VERSION {
+            let blocks: ListIndex<_, Block> = self.snap.get_list(BLOCKS);
+            return blocks
+                .iter()
+                .find(|block| block.data.primary_hash() == *hash);
+        }
+        let index: MapIndex<_, Hash, u64> = self.snap.get_map(BLOCKS_BY_HASH);
+        index.get(hash).and_then(|height| self.load_block(height))
+    }
+
+    /// Get a page of the transactions index of the account `id`.
+    fn load_account_transactions(
+        &self,
+        id: &str,
+        from: u64,
+        limit: u64,
+    ) -> Vec<AccountTransaction> {
+        let list: ListIndex<_, AccountTransaction> = self.snap.get_list((ACCOUNT_TXS, id));
+        list.iter_from(from).take(limit as usize).collect()
+    }
+
+    /// Get a page of the smart contract events selected by `filter`.
+    fn load_events(&self, filter: &EventFilter, from: u64, limit: u64) -> Vec<EventRecord> {
+        let records: ListIndex<_, EventRecord> = self.snap.get_list(EVENTS);
+        match filter.index_key() {
+            Some(key) => {
+                let index: ListIndex<_, u64> = self.snap.get_list((EVENTS_INDEX, key.as_str()));
+                let record = |i| index.get(i).and_then(|pos| records.get(pos));
+                events_page(index.len(), record, filter, from, limit)
+            }
+            None => events_page(records.len(), |i| records.get(i), filter, from, limit),
+        }
+    }
+
     /// Get transactions hashes associated to a given block identified by `height`.
     /// The `height` refers to the block position within the blockchain.
     fn load_transactions_hashes(&self, height: u64) -> Option<Vec<Hash>> {
@@ -210,10 +929,25 @@ impl Db for RocksDb {
         }
     }
 
+    /// Get receipts hashes associated to a given block identified by `height`.
+    /// The `height` refers to the block position within the blockchain.
+    fn load_receipts_hashes(&self, height: u64) -> Option<Vec<Hash>> {
+        let map: ProofListIndex<_, Hash> = self.snap.get_proof_list((RECEIPTS_HASH, &height));
+        if map.is_empty() {
+            None
+        } else {
+            Some(map.into_iter().collect())
+        }
+    }
+
     /// Create a fork.
     /// A fork is a set of uncommitted modifications to the database.
     fn fork_create(&mut self) -> RocksDbFork {
-        RocksDbFork(self.backend.fork())
+        RocksDbFork {
+            fork: self.backend.fork(),
+            history: self.history,
+            undo: BTreeMap::new(),
+        }
     }
 
     /// Commit a fork.
@@ -223,11 +957,12 @@ impl Db for RocksDb {
     /// to the same data, the next fork should be created after the previous fork has
     /// been merged.
     fn fork_merge(&mut self, fork: RocksDbFork) -> crate::Result<()> {
-        let patch = fork.0.into_patch();
+        let patch = fork.fork.into_patch();
         self.backend
             .merge(patch)
             .map_err(|err| Error::new_ext(ErrorKind::DatabaseFault, err))?;
         self.snap = self.backend.snapshot();
+        self.publish_checkpoint();
         Ok(())
     }
 
@@ -235,6 +970,71 @@ impl Db for RocksDb {
         let map: ProofMapIndex<_, str, Vec<u8>> = self.snap.get_proof_map(CONFIG);
         map.get(id)
     }
+
+    /// Load account as it was after the block at the given `height`.
+    fn load_account_at(&self, id: &str, height: u64) -> Option<Account> {
+        let current = || self.load_account(id).map(|account| account.to_bytes());
+        let buf = self.load_history(&HistoryKey::Account(id.to_owned()), height, current)??;
+        rmp_deserialize(&buf).ok()
+    }
+
+    /// Load account data as it was after the block at the given `height`.
+    fn load_account_data_at(&self, id: &str, key: &str, height: u64) -> Option<Vec<u8>> {
+        let current = || self.load_account_data(id, key);
+        let entry = HistoryKey::AccountData(id.to_owned(), key.to_owned());
+        self.load_history(&entry, height, current)?
+    }
+
+    /// Revert the blocks following `height` using the historical state.
+    /// Returns the reverted transactions, in execution order.
+    fn revert_to(&mut self, height: u64) -> crate::Result<Vec<Transaction>> {
+        let last = match self.load_block(u64::MAX) {
+            Some(block) if height <= block.data.height => block.data.height,
+            _ => return Err(Error::new(ErrorKind::ResourceNotFound)),
+        };
+        if height == last {
+            return Ok(vec![]);
+        }
+        if !self.history_covers(height) {
+            return Err(Error::new_ext(
+                ErrorKind::ResourceNotFound,
+                "state history not available for the target height",
+            ));
+        }
+        let fork = self.backend.fork();
+        let txs = revert_fork(&fork, height, last);
+        self.backend
+            .merge(fork.into_patch())
+            .map_err(|err| Error::new_ext(ErrorKind::DatabaseFault, err))?;
+        self.snap = self.backend.snapshot();
+        Ok(txs)
+    }
+
+    /// Load the account proof against the accounts state hash.
+    fn load_account_proof(&self, id: &str) -> Option<MapProof> {
+        let map: ProofMapIndex<_, str, Vec<u8>> = self.snap.get_proof_map(ACCOUNTS);
+        map_proof(&map, id)
+    }
+
+    /// Load the account data proof against the account data hash.
+    fn load_account_data_proof(&self, id: &str, key: &str) -> Option<MapProof> {
+        let map: ProofMapIndex<_, str, Vec<u8>> = self.snap.get_proof_map((ACCOUNTS, id));
+        map_proof(&map, key)
+    }
+}
+
+/// Proof of an existing map entry, converted from the merkledb one.
+fn map_proof<T: RawAccess>(map: &ProofMapIndex<T, str, Vec<u8>>, key: &str) -> Option<MapProof> {
+    let value = map.get(key)?;
+    let siblings = map
+        .get_proof(key.to_owned())
+        .proof_unchecked()
+        .into_iter()
+        .map(|(path, hash)| {
+            MapProofNode::from_path(path.raw_key(), path.end() as usize, hash.into())
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some(MapProof::from_siblings(key, value, siblings))
 }
 
 impl DbFork for RocksDbFork {
@@ -242,11 +1042,11 @@ impl DbFork for RocksDbFork {
     fn state_hash(&self, id: &str) -> Hash {
         match id.is_empty() {
             false => {
-                let map: ProofMapIndex<_, str, Vec<u8>> = self.0.get_proof_map((ACCOUNTS, id));
+                let map: ProofMapIndex<_, str, Vec<u8>> = self.fork.get_proof_map((ACCOUNTS, id));
                 map.object_hash()
             }
             true => {
-                let map: ProofMapIndex<_, str, Vec<u8>> = self.0.get_proof_map(ACCOUNTS);
+                let map: ProofMapIndex<_, str, Vec<u8>> = self.fork.get_proof_map(ACCOUNTS);
                 map.object_hash()
             }
         }
@@ -255,62 +1055,102 @@ impl DbFork for RocksDbFork {
 
     /// Fetch account.
     fn load_account(&self, id: &str) -> Option<Account> {
-        let map: ProofMapIndex<_, str, Account> = self.0.get_proof_map(ACCOUNTS);
+        let map: ProofMapIndex<_, str, Account> = self.fork.get_proof_map(ACCOUNTS);
         map.get(id)
     }
 
     /// Insert/Update account.
     fn store_account(&mut self, account: Account) {
-        let mut map: ProofMapIndex<_, str, Account> = self.0.get_proof_map(ACCOUNTS);
+        self.record_undo(HistoryKey::Account(account.id.clone()), |fork| {
+            fork.load_account(&account.id)
+                .map(|account| account.to_bytes())
+        });
+        let mut map: ProofMapIndex<_, str, Account> = self.fork.get_proof_map(ACCOUNTS);
         let id = account.id.clone();
         map.put(&id, account);
     }
 
     /// Load data associated to the given account `id`.
     fn load_account_data(&self, id: &str, key: &str) -> Option<Vec<u8>> {
-        let map: ProofMapIndex<_, str, Vec<u8>> = self.0.get_proof_map((ACCOUNTS, id));
+        let map: ProofMapIndex<_, str, Vec<u8>> = self.fork.get_proof_map((ACCOUNTS, id));
         map.get(key)
     }
 
     /// Store data associated to the given account `id`.
     fn store_account_data(&mut self, id: &str, key: &str, data: Vec<u8>) {
-        let mut map: ProofMapIndex<_, str, Vec<u8>> = self.0.get_proof_map((ACCOUNTS, id));
+        self.record_undo(
+            HistoryKey::AccountData(id.to_owned(), key.to_owned()),
+            |fork| fork.load_account_data(id, key),
+        );
+        let mut map: ProofMapIndex<_, str, Vec<u8>> = self.fork.get_proof_map((ACCOUNTS, id));
         map.put(key, data);
+        let mut keys: KeySetIndex<_, str> = self.fork.get_key_set((ACCOUNT_KEYS, id));
+        keys.insert(key);
     }
 
     /// Fetch DB generic data (this shuld be used by core only, this map sould be used only internally).
     fn load_data(&self, key: &str) -> Option<Vec<u8>> {
-        let map: ProofMapIndex<_, str, Vec<u8>> = self.0.get_proof_map(INTERNAL_DB);
+        let map: ProofMapIndex<_, str, Vec<u8>> = self.fork.get_proof_map(INTERNAL_DB);
         map.get(key)
     }
 
     /// Insert/Update generic data.
     fn store_data(&mut self, key: &str, data: Vec<u8>) {
-        let mut map: ProofMapIndex<_, str, Vec<u8>> = self.0.get_proof_map(INTERNAL_DB);
+        self.record_undo(HistoryKey::Internal(key.to_owned()), |fork| {
+            fork.load_data(key)
+        });
+        let mut map: ProofMapIndex<_, str, Vec<u8>> = self.fork.get_proof_map(INTERNAL_DB);
         map.put(key, data);
     }
 
     /// Remove data associated to the given account `id`.
     fn remove_account_data(&mut self, id: &str, key: &str) {
-        let mut map: ProofMapIndex<_, str, Vec<u8>> = self.0.get_proof_map((ACCOUNTS, id));
-        map.remove(key)
+        self.record_undo(
+            HistoryKey::AccountData(id.to_owned(), key.to_owned()),
+            |fork| fork.load_account_data(id, key),
+        );
+        let mut map: ProofMapIndex<_, str, Vec<u8>> = self.fork.get_proof_map((ACCOUNTS, id));
+        map.remove(key);
+        let mut keys: KeySetIndex<_, str> = self.fork.get_key_set((ACCOUNT_KEYS, id));
+        keys.remove(key);
     }
 
     /// Insert transaction.
     fn store_transaction(&mut self, hash: &Hash, transaction: Transaction) {
-        let mut map: MapIndex<_, Hash, Transaction> = self.0.get_map(TRANSACTIONS);
+        let mut map: MapIndex<_, Hash, Transaction> = self.fork.get_map(TRANSACTIONS);
         map.put(hash, transaction);
     }
 
     /// Insert transaction result.
     fn store_receipt(&mut self, hash: &Hash, receipt: Receipt) {
-        let mut map: MapIndex<_, Hash, Receipt> = self.0.get_map(RECEIPTS);
+        let mut map: MapIndex<_, Hash, Receipt> = self.fork.get_map(RECEIPTS);
         map.put(hash, receipt);
     }
 
+    /// Append an entry to the account transactions index.
+    fn store_account_transaction(&mut self, id: &str, entry: AccountTransaction) {
+        let mut list: ListIndex<_, AccountTransaction> = self.fork.get_list((ACCOUNT_TXS, id));
+        list.push(entry);
+    }
+
+    /// Append a smart contract event to the events index.
+    fn store_event(&mut self, record: EventRecord) {
+        let mut records: ListIndex<_, EventRecord> = self.fork.get_list(EVENTS);
+        let pos = records.len();
+        for key in event_index_keys(&record.event) {
+            let mut index: ListIndex<_, u64> = self.fork.get_list((EVENTS_INDEX, key.as_str()));
+            index.push(pos);
+        }
+        records.push(record);
+    }
+
     /// Insert new block.
+    /// If the historical state is enabled, the state changes are recorded at the block height.
     fn store_block(&mut self, block: Block) {
-        let mut list: ListIndex<_, Block> = self.0.get_list(BLOCKS);
+        self.store_history(block.data.height);
+        let mut index: MapIndex<_, Hash, u64> = self.fork.get_map(BLOCKS_BY_HASH);
+        index.put(&block.data.primary_hash(), block.data.height);
+        let mut list: ListIndex<_, Block> = self.fork.get_list(BLOCKS);
         list.push(block)
     }
 
@@ -318,7 +1158,8 @@ impl DbFork for RocksDbFork {
     /// The `height` refers to the block position within the blockchain.
     /// Returns the transactions trie root.
     fn store_transactions_hashes(&mut self, height: u64, hashes: Vec<Hash>) -> Hash {
-        let mut map: ProofListIndex<_, Hash> = self.0.get_proof_list((TRANSACTIONS_HASH, &height));
+        let mut map: ProofListIndex<_, Hash> =
+            self.fork.get_proof_list((TRANSACTIONS_HASH, &height));
         hashes.into_iter().for_each(|hash| map.push(hash));
         map.object_hash().into()
     }
@@ -327,27 +1168,42 @@ impl DbFork for RocksDbFork {
     /// The `height` refers to the associated block height within the blockchain.
     /// Returns the receipts trie root.
     fn store_receipts_hashes(&mut self, height: u64, hashes: Vec<Hash>) -> Hash {
-        let mut map: ProofListIndex<_, Hash> = self.0.get_proof_list((RECEIPTS_HASH, &height));
+        let mut map: ProofListIndex<_, Hash> = self.fork.get_proof_list((RECEIPTS_HASH, &height));
         hashes.into_iter().for_each(|hash| map.push(hash));
         map.object_hash().into()
     }
 
     /// Creates a fork checkpoint.
     fn flush(&mut self) {
-        self.0.flush();
+        self.fork.flush();
     }
 
     /// Rollback to the last checkpoint (`flush` point).
     fn rollback(&mut self) {
-        self.0.rollback();
+        self.fork.rollback();
     }
 
     fn load_account_keys(&self, id: &str) -> Vec<String> {
-        let map: ProofMapIndex<_, str, Vec<u8>> = self.0.get_proof_map((ACCOUNTS, id));
+        let map: ProofMapIndex<_, str, Vec<u8>> = self.fork.get_proof_map((ACCOUNTS, id));
         map.keys().collect()
     }
+
+    fn load_account_keys_range(
+        &self,
+        id: &str,
+        prefix: &str,
+        after: Option<&str>,
+        limit: u64,
+    ) -> Vec<String> {
+        let index: KeySetIndex<_, str> = self.fork.get_key_set((ACCOUNT_KEYS, id));
+        account_keys_page(|start| index.iter_from(start), prefix, after, limit)
+    }
     fn store_configuration(&mut self, id: &str, config: Vec<u8>) {
-        let mut map: ProofMapIndex<_, str, Vec<u8>> = self.0.get_proof_map(CONFIG);
+        self.record_undo(HistoryKey::Config(id.to_owned()), |fork| {
+            let map: ProofMapIndex<_, str, Vec<u8>> = fork.fork.get_proof_map(CONFIG);
+            map.get(id)
+        });
+        let mut map: ProofMapIndex<_, str, Vec<u8>> = self.fork.get_proof_map(CONFIG);
         map.put(id, config);
     }
 }
@@ -358,10 +1214,12 @@ mod tests {
     use crate::{
         base::schema::Account,
         base::schema::{
-            tests::{create_test_account, create_test_block, create_test_unit_tx},
-            FUEL_LIMIT,
+            tests::{
+                create_test_account, create_test_block, create_test_receipt, create_test_unit_tx,
+            },
+            SmartContractEvent, FUEL_LIMIT,
         },
-        crypto::Hashable,
+        crypto::merkle::{self, ListProof},
     };
     use std::{
         fs,
@@ -597,6 +1455,84 @@ mod tests {
         // debug!("{:?}", indexes);
     }
 
+    #[test]
+    fn receipts_hashes_proof() {
+        let mut db = TempDb::new();
+        let mut fork = db.fork_create();
+        let hashes: Vec<Hash> = (0..5u8)
+            .map(|i| Hash::from_data(HashAlgorithm::Sha256, &[i]))
+            .collect();
+
+        let root_hash = fork.store_receipts_hashes(3, hashes.clone());
+        db.fork_merge(fork).unwrap();
+
+        let stored = db.load_receipts_hashes(3).unwrap();
+        assert_eq!(stored, hashes);
+        assert_eq!(db.load_receipts_hashes(4), None);
+        for (index, hash) in stored.iter().enumerate() {
+            let proof = ListProof::new(&stored, index as u64).unwrap();
+            assert!(proof.verify(hash.as_bytes(), &root_hash));
+        }
+    }
+
+    #[test]
+    fn state_hash_matches_merkle_map_hash() {
+        let mut db = TempDb::new();
+        let mut fork = db.fork_create();
+        let accounts: Vec<Account> = (0..9)
+            .map(|i| Account::new(&format!("account{}", i), None))
+            .collect();
+        accounts
+            .iter()
+            .for_each(|account| fork.store_account(account.clone()));
+        let data: Vec<(String, Vec<u8>)> = (0..9u8)
+            .map(|i| (format!("key{}", i), vec![i; i as usize + 1]))
+            .collect();
+        data.iter()
+            .for_each(|(key, value)| fork.store_account_data(ACCOUNT_ID1, key, value.clone()));
+
+        let accounts_hash = merkle::map_hash(
+            accounts
+                .iter()
+                .map(|account| (account.id.clone(), rmp_serialize(account).unwrap())),
+        );
+        let data_hash = merkle::map_hash(data.iter().cloned());
+
+        assert_eq!(fork.state_hash(""), accounts_hash);
+        assert_eq!(fork.state_hash(ACCOUNT_ID1), data_hash);
+    }
+
+    #[test]
+    fn account_proof_against_state_hash() {
+        let mut db = TempDb::new();
+        let mut fork = db.fork_create();
+        for i in 0..9 {
+            fork.store_account(Account::new(&format!("account{}", i), None));
+            fork.store_account_data(ACCOUNT_ID1, &format!("key{}", i), vec![i]);
+        }
+        let state_hash = fork.state_hash("");
+        let data_hash = fork.state_hash(ACCOUNT_ID1);
+        db.fork_merge(fork).unwrap();
+
+        for i in 0..9 {
+            let id = format!("account{}", i);
+            let proof = db.load_account_proof(&id).unwrap();
+            assert!(proof.verify(&state_hash));
+            assert_eq!(
+                proof.value,
+                rmp_serialize(&Account::new(&id, None)).unwrap()
+            );
+
+            let proof = db
+                .load_account_data_proof(ACCOUNT_ID1, &format!("key{}", i))
+                .unwrap();
+            assert!(proof.verify(&data_hash));
+            assert_eq!(proof.value, vec![i]);
+        }
+        assert!(db.load_account_proof("unknown").is_none());
+        assert!(db.load_account_data_proof(ACCOUNT_ID2, "key1").is_none());
+    }
+
     #[test]
     fn merge_conflict() {
         let mut db = TempDb::new();
@@ -682,8 +1618,632 @@ mod tests {
         assert_eq!(db.load_transaction(&t3.primary_hash()), Some(t3));
     }
 
+    #[test]
+    fn account_proof_against_state_hash() {
+        let mut db = TempDb::new();
+        let mut fork = db.fork_create();
+        fork.store_account(create_test_account());
+        fork.store_account(Account::new(ACCOUNT_ID1, None));
+        fork.store_account(Account::new(ACCOUNT_ID2, None));
+        fork.store_account_data(ACCOUNT_ID1, "data1", vec![1, 2, 3]);
+        fork.store_account_data(ACCOUNT_ID1, "data2", vec![4, 5, 6]);
+        let state_hash = fork.state_hash("");
+        let data_hash = fork.state_hash(ACCOUNT_ID1);
+        db.fork_merge(fork).unwrap();
+
+        let proof = db.load_account_proof(ACCOUNT_ID1).unwrap();
+        let data_proof = db.load_account_data_proof(ACCOUNT_ID1, "data2").unwrap();
+
+        assert!(proof.verify(&state_hash));
+        assert!(data_proof.verify(&data_hash));
+        assert_eq!(data_proof.value, vec![4, 5, 6]);
+        assert!(db.load_account_proof("unknown").is_none());
+    }
+
     // TODO Tests on
     //  - get_keys
     // store_config
     // load_config
+
+    fn commit_history_block(db: &mut TempDb, height: u64, op: impl FnOnce(&mut RocksDbFork)) {
+        let mut fork = db.fork_create();
+        op(&mut fork);
+        let mut block = create_test_block();
+        block.data.height = height;
+        fork.store_block(block);
+        db.fork_merge(fork).unwrap();
+    }
+
+    #[test]
+    fn load_account_at_height() {
+        let mut db = TempDb::new();
+        db.set_history_retention(Some(3));
+        let account = create_test_account();
+        let id = account.id.clone();
+
+        commit_history_block(&mut db, 0, |fork| {
+            fork.store_account_data(&id, "k", vec![0])
+        });
+        commit_history_block(&mut db, 1, |fork| {
+            fork.store_account_data(&id, "k", vec![1])
+        });
+        commit_history_block(&mut db, 2, |fork| {
+            fork.store_account(account.clone());
+            fork.remove_account_data(&id, "k");
+        });
+        commit_history_block(&mut db, 3, |fork| {
+            fork.store_account_data(&id, "k", vec![2]);
+            fork.rollback();
+            fork.store_account_data(&id, "k", vec![3]);
+        });
+        commit_history_block(&mut db, 4, |_| ());
+
+        assert_eq!(db.load_account_at(&id, 1), None);
+        assert_eq!(db.load_account_at(&id, 2), Some(account.clone()));
+        assert_eq!(db.load_account_at(&id, 4), Some(account));
+        assert_eq!(db.load_account_data_at(&id, "k", 1), Some(vec![1]));
+        assert_eq!(db.load_account_data_at(&id, "k", 2), None);
+        assert_eq!(db.load_account_data_at(&id, "k", 3), Some(vec![3]));
+        assert_eq!(db.load_account_data_at(&id, "k", 4), Some(vec![3]));
+    }
+
+    #[test]
+    fn load_account_at_height_out_of_window() {
+        let mut db = TempDb::new();
+        db.set_history_retention(Some(1));
+        let id = ACCOUNT_ID1;
+
+        commit_history_block(&mut db, 0, |fork| fork.store_account_data(id, "k", vec![0]));
+        commit_history_block(&mut db, 1, |fork| fork.store_account_data(id, "k", vec![1]));
+        commit_history_block(&mut db, 2, |fork| fork.store_account_data(id, "k", vec![2]));
+
+        assert_eq!(db.load_account_data_at(id, "k", 0), None);
+        assert_eq!(db.load_account_data_at(id, "k", 1), Some(vec![1]));
+        assert_eq!(db.load_account_data_at(id, "k", 3), None);
+    }
+
+    #[test]
+    fn load_account_data_at_height_similar_keys() {
+        let mut db = TempDb::new();
+        db.set_history_retention(Some(10));
+        let id = ACCOUNT_ID1;
+        let other = "k0000000000000001";
+
+        commit_history_block(&mut db, 0, |fork| {
+            fork.store_account_data(id, "k", vec![0]);
+            fork.store_account_data(id, other, vec![10]);
+        });
+        commit_history_block(&mut db, 1, |fork| {
+            fork.store_account_data(id, other, vec![11])
+        });
+        commit_history_block(&mut db, 2, |fork| fork.store_account_data(id, "k", vec![2]));
+
+        assert_eq!(db.load_account_data_at(id, "k", 0), Some(vec![0]));
+        assert_eq!(db.load_account_data_at(id, "k", 1), Some(vec![0]));
+        assert_eq!(db.load_account_data_at(id, "k", 2), Some(vec![2]));
+        assert_eq!(db.load_account_data_at(id, other, 0), Some(vec![10]));
+        assert_eq!(db.load_account_data_at(id, other, 2), Some(vec![11]));
+    }
+
+    #[test]
+    fn load_account_at_height_history_disabled() {
+        let mut db = TempDb::new();
+        let account = create_test_account();
+        let id = account.id.clone();
+
+        commit_history_block(&mut db, 0, |fork| fork.store_account(account));
+
+        assert_eq!(db.load_account_at(&id, 0), None);
+    }
+
+    #[test]
+    fn snapshot_export_import() {
+        let mut db = TempDb::new();
+        let mut fork = db.fork_create();
+        fork.store_account_data(ACCOUNT_ID1, "data", vec![1, 2, 3]);
+        let mut account = Account::new(ACCOUNT_ID1, None);
+        account.data_hash = Some(fork.state_hash(ACCOUNT_ID1));
+        fork.store_account(account);
+        fork.store_configuration("blockchain:settings", vec![1]);
+        let mut block = create_test_block();
+        block.data.height = 0;
+        block.data.prev_hash = Hash::default();
+        block.data.txs_hash = fork.store_transactions_hashes(0, vec![Hash::default()]);
+        block.data.rxs_hash = fork.store_receipts_hashes(0, vec![Hash::default()]);
+        block.data.state_hash = fork.state_hash("");
+        fork.store_block(block.clone());
+        db.fork_merge(fork).unwrap();
+        let mut buf = vec![];
+        db.export_snapshot(0, &mut buf).unwrap();
+
+        let path = TempDir::new().unwrap().into_path();
+        let imported =
+            RocksDb::import_snapshot(&path, buf.as_slice(), &block.data.primary_hash()).unwrap();
+
+        assert_eq!(imported.load_block(u64::MAX), Some(block));
+        assert_eq!(
+            imported.load_transactions_hashes(0),
+            Some(vec![Hash::default()])
+        );
+        assert_eq!(
+            imported.load_receipts_hashes(0),
+            Some(vec![Hash::default()])
+        );
+        assert_eq!(
+            imported.load_account(ACCOUNT_ID1),
+            db.load_account(ACCOUNT_ID1)
+        );
+        assert_eq!(
+            imported.load_account_data(ACCOUNT_ID1, "data"),
+            Some(vec![1, 2, 3])
+        );
+        assert_eq!(
+            imported.load_configuration("blockchain:settings"),
+            Some(vec![1])
+        );
+        drop(imported);
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    fn commit_snapshot_test_block(db: &mut TempDb, value: u8) -> Block {
+        let (height, prev_hash) = match db.load_block(u64::MAX) {
+            Some(block) => (block.data.height + 1, block.data.primary_hash()),
+            None => (0, Hash::default()),
+        };
+        let mut fork = db.fork_create();
+        fork.store_account_data(ACCOUNT_ID1, "k", vec![value]);
+        let mut account = Account::new(ACCOUNT_ID1, None);
+        account.data_hash = Some(fork.state_hash(ACCOUNT_ID1));
+        fork.store_account(account);
+        fork.store_configuration("blockchain:settings", vec![value]);
+        fork.store_data("seed", vec![value]);
+        if value > 0 {
+            fork.store_account(Account::new(ACCOUNT_ID2, None));
+        }
+        let mut block = create_test_block();
+        block.data.height = height;
+        block.data.prev_hash = prev_hash;
+        block.data.txs_hash = fork.store_transactions_hashes(height, vec![Hash::default()]);
+        block.data.rxs_hash = fork.store_receipts_hashes(height, vec![Hash::default()]);
+        block.data.state_hash = fork.state_hash("");
+        fork.store_block(block.clone());
+        db.fork_merge(fork).unwrap();
+        block
+    }
+
+    #[test]
+    fn snapshot_export_at_height() {
+        let mut db = TempDb::new();
+        db.set_history_retention(Some(10));
+        let target = commit_snapshot_test_block(&mut db, 0);
+        commit_snapshot_test_block(&mut db, 1);
+        commit_snapshot_test_block(&mut db, 2);
+        let mut buf = vec![];
+        db.export_snapshot(0, &mut buf).unwrap();
+
+        let path = TempDir::new().unwrap().into_path();
+        let trusted_hash = target.data.primary_hash();
+        let imported = RocksDb::import_snapshot(&path, buf.as_slice(), &trusted_hash).unwrap();
+
+        assert_eq!(imported.load_block(u64::MAX), Some(target));
+        assert_eq!(imported.load_account_data(ACCOUNT_ID1, "k"), Some(vec![0]));
+        assert_eq!(imported.load_account(ACCOUNT_ID2), None);
+        assert_eq!(
+            imported.load_configuration("blockchain:settings"),
+            Some(vec![0])
+        );
+        assert_eq!(imported.load_data("seed"), Some(vec![0]));
+        drop(imported);
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn snapshot_export_out_of_history() {
+        let mut db = TempDb::new();
+        commit_snapshot_test_block(&mut db, 0);
+        commit_snapshot_test_block(&mut db, 1);
+        let mut buf = vec![];
+
+        assert!(db.export_snapshot(0, &mut buf).is_err());
+        assert!(db.export_snapshot(2, &mut buf).is_err());
+        assert!(db.export_snapshot(1, &mut buf).is_ok());
+    }
+
+    #[test]
+    fn snapshot_import_untrusted() {
+        let mut db = TempDb::new();
+        commit_snapshot_test_block(&mut db, 0);
+        let mut buf = vec![];
+        db.export_snapshot(0, &mut buf).unwrap();
+
+        let path = TempDir::new().unwrap().into_path();
+        let err = RocksDb::import_snapshot(&path, buf.as_slice(), &Hash::default()).unwrap_err();
+
+        assert_eq!(err.kind, ErrorKind::BrokenIntegrity);
+        assert!(!path.exists());
+    }
+
+    fn create_test_event_record(height: u64, emitter: &str, name: &str) -> EventRecord {
+        EventRecord {
+            height,
+            index: 0,
+            event: SmartContractEvent {
+                event_tx: Hash::default(),
+                emitter_account: emitter.to_owned(),
+                emitter_smart_contract: Hash::default(),
+                event_name: name.to_owned(),
+                event_data: vec![height as u8],
+            },
+        }
+    }
+
+    fn commit_revert_test_block(db: &mut TempDb, value: u8) -> Block {
+        let (height, prev_hash) = match db.load_block(u64::MAX) {
+            Some(block) => (block.data.height + 1, block.data.primary_hash()),
+            None => (0, Hash::default()),
+        };
+        let mut fork = db.fork_create();
+        let mut tx = create_test_unit_tx(FUEL_LIMIT);
+        if let Transaction::UnitTransaction(ref mut tx) = tx {
+            tx.data.set_nonce(vec![value; 8]);
+        }
+        let hash = tx.primary_hash();
+        let rx = create_test_receipt();
+        let rx_hash = rx.primary_hash();
+        for id in transaction_accounts(&tx) {
+            let entry = AccountTransaction {
+                height,
+                index: 0,
+                hash,
+            };
+            fork.store_account_transaction(&id, entry);
+        }
+        fork.store_transaction(&hash, tx);
+        fork.store_receipt(&hash, rx);
+        fork.store_event(create_test_event_record(height, ACCOUNT_ID1, "k"));
+        fork.store_account_data(ACCOUNT_ID1, "k", vec![value]);
+        let mut account = Account::new(ACCOUNT_ID1, None);
+        account.data_hash = Some(fork.state_hash(ACCOUNT_ID1));
+        fork.store_account(account);
+        if height > 0 {
+            fork.store_account(Account::new(ACCOUNT_ID2, None));
+        }
+        let mut block = create_test_block();
+        block.data.height = height;
+        block.data.prev_hash = prev_hash;
+        block.data.txs_hash = fork.store_transactions_hashes(height, vec![hash]);
+        block.data.rxs_hash = fork.store_receipts_hashes(height, vec![rx_hash]);
+        block.data.state_hash = fork.state_hash("");
+        fork.store_block(block.clone());
+        db.fork_merge(fork).unwrap();
+        block
+    }
+
+    #[test]
+    fn revert_to_height() {
+        let mut db = TempDb::new();
+        db.set_history_retention(Some(10));
+        let target = commit_revert_test_block(&mut db, 0);
+        commit_revert_test_block(&mut db, 1);
+        commit_revert_test_block(&mut db, 2);
+        let reverted = db.load_transactions_hashes(1).unwrap()[0];
+
+        let reverted_block = db.load_block(1).unwrap().data.primary_hash();
+
+        let txs = db.revert_to(0).unwrap();
+
+        assert_eq!(txs.len(), 2);
+        assert_eq!(txs[0].primary_hash(), reverted);
+        assert_eq!(db.load_block(u64::MAX), Some(target.clone()));
+        assert_eq!(db.fork_create().state_hash(""), target.data.state_hash);
+        assert_eq!(db.load_account_data(ACCOUNT_ID1, "k"), Some(vec![0]));
+        assert_eq!(db.load_account(ACCOUNT_ID2), None);
+        assert!(!db.contains_transaction(&reverted));
+        assert_eq!(db.load_receipt(&reverted), None);
+        assert_eq!(db.load_transactions_hashes(1), None);
+        assert_eq!(db.load_receipts_hashes(1), None);
+        assert_eq!(db.load_block_by_hash(&reverted_block), None);
+        assert_eq!(
+            db.load_events(&EventFilter::default(), 0, 10),
+            vec![create_test_event_record(0, ACCOUNT_ID1, "k")]
+        );
+
+        let block = commit_revert_test_block(&mut db, 3);
+        assert_eq!(block.data.height, 1);
+        assert_eq!(db.load_block(1), Some(block));
+    }
+
+    #[test]
+    fn revert_to_height_restores_config_and_data() {
+        let mut db = TempDb::new();
+        db.set_history_retention(Some(10));
+        commit_history_block(&mut db, 0, |fork| {
+            fork.store_configuration("blockchain:settings", vec![0]);
+            fork.store_data("k", vec![0]);
+        });
+        commit_history_block(&mut db, 1, |fork| {
+            fork.store_configuration("blockchain:settings", vec![1]);
+            fork.store_configuration("other", vec![1]);
+            fork.store_data("k", vec![1]);
+            fork.store_data("other", vec![1]);
+        });
+
+        db.revert_to(0).unwrap();
+
+        assert_eq!(db.load_configuration("blockchain:settings"), Some(vec![0]));
+        assert_eq!(db.load_configuration("other"), None);
+        assert_eq!(db.load_data("k"), Some(vec![0]));
+        assert_eq!(db.load_data("other"), None);
+    }
+
+    #[test]
+    fn revert_to_height_without_history() {
+        let mut db = TempDb::new();
+        commit_revert_test_block(&mut db, 0);
+        commit_revert_test_block(&mut db, 1);
+
+        assert!(db.revert_to(0).is_err());
+        assert!(db.revert_to(2).is_err());
+        assert!(db.revert_to(1).unwrap().is_empty());
+        assert_eq!(db.load_block(u64::MAX).unwrap().data.height, 1);
+    }
+
+    #[test]
+    fn load_block_by_hash() {
+        let mut db = TempDb::new();
+        let block0 = commit_revert_test_block(&mut db, 0);
+        let block1 = commit_revert_test_block(&mut db, 1);
+
+        assert_eq!(
+            db.load_block_by_hash(&block0.data.primary_hash()),
+            Some(block0)
+        );
+        assert_eq!(
+            db.load_block_by_hash(&block1.data.primary_hash()),
+            Some(block1.clone())
+        );
+        assert_eq!(db.load_block_by_hash(&block1.primary_hash()), None);
+    }
+
+    #[test]
+    fn load_account_transactions() {
+        let mut db = TempDb::new();
+        db.set_history_retention(Some(10));
+        commit_revert_test_block(&mut db, 0);
+        commit_revert_test_block(&mut db, 1);
+        commit_revert_test_block(&mut db, 2);
+        let tx = db.load_transaction(&db.load_transactions_hashes(1).unwrap()[0]);
+        let account = tx.unwrap().get_account().to_owned();
+
+        let page = db.load_account_transactions(&account, 1, 10);
+        assert_eq!(page.len(), 2);
+        assert_eq!(page[0].height, 1);
+        assert_eq!(page[0].hash, db.load_transactions_hashes(1).unwrap()[0]);
+        assert_eq!(page[1].height, 2);
+        assert_eq!(db.load_account_transactions(&account, 0, 1).len(), 1);
+        assert!(db.load_account_transactions(&account, 3, 10).is_empty());
+        assert!(db.load_account_transactions(ACCOUNT_ID2, 0, 10).is_empty());
+
+        db.revert_to(0).unwrap();
+        let page = db.load_account_transactions(&account, 0, 10);
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].height, 0);
+    }
+
+    #[test]
+    fn load_events() {
+        let mut db = TempDb::new();
+        let records = vec![
+            create_test_event_record(0, ACCOUNT_ID1, "a"),
+            create_test_event_record(1, ACCOUNT_ID2, "a"),
+            create_test_event_record(1, ACCOUNT_ID1, "b"),
+            create_test_event_record(2, ACCOUNT_ID1, "a"),
+        ];
+        for record in records.iter() {
+            let mut fork = db.fork_create();
+            fork.store_event(record.clone());
+            db.fork_merge(fork).unwrap();
+        }
+        let filter = |emitter: Option<&str>, name: Option<&str>| EventFilter {
+            emitter: emitter.map(str::to_owned),
+            name: name.map(str::to_owned),
+            ..Default::default()
+        };
+
+        assert_eq!(db.load_events(&filter(None, None), 0, 10), records);
+        assert_eq!(
+            db.load_events(&filter(Some(ACCOUNT_ID1), None), 0, 10),
+            vec![records[0].clone(), records[2].clone(), records[3].clone()]
+        );
+        assert_eq!(
+            db.load_events(&filter(Some(ACCOUNT_ID1), None), 1, 1),
+            vec![records[2].clone()]
+        );
+        assert_eq!(
+            db.load_events(&filter(None, Some("a")), 0, 10),
+            vec![records[0].clone(), records[1].clone(), records[3].clone()]
+        );
+        let by_height = EventFilter {
+            min_height: Some(1),
+            ..filter(Some(ACCOUNT_ID1), Some("a"))
+        };
+        assert_eq!(db.load_events(&by_height, 0, 10), vec![records[3].clone()]);
+        let by_height = EventFilter {
+            min_height: Some(1),
+            max_height: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            db.load_events(&by_height, 0, 10),
+            vec![records[1].clone(), records[2].clone()]
+        );
+        assert!(db
+            .load_events(&filter(Some(ACCOUNT_ID2), Some("b")), 0, 10)
+            .is_empty());
+    }
+
+    #[test]
+    fn load_account_keys_range() {
+        let mut db = TempDb::new();
+        let mut fork = db.fork_create();
+        for key in ["a", "b:1", "b:2", "b:3", "c"] {
+            fork.store_account_data(ACCOUNT_ID1, key, vec![0]);
+        }
+        db.fork_merge(fork).unwrap();
+
+        assert_eq!(
+            db.load_account_keys_range(ACCOUNT_ID1, "", None, 10),
+            vec!["a", "b:1", "b:2", "b:3", "c"]
+        );
+        assert_eq!(
+            db.load_account_keys_range(ACCOUNT_ID1, "b:", None, 2),
+            vec!["b:1", "b:2"]
+        );
+        assert_eq!(
+            db.load_account_keys_range(ACCOUNT_ID1, "b:", Some("b:2"), 2),
+            vec!["b:3"]
+        );
+        assert_eq!(
+            db.load_account_keys_range(ACCOUNT_ID1, "b:", Some("a"), 1),
+            vec!["b:1"]
+        );
+        assert!(db
+            .load_account_keys_range(ACCOUNT_ID1, "d", None, 10)
+            .is_empty());
+        assert!(db
+            .load_account_keys_range(ACCOUNT_ID2, "", None, 10)
+            .is_empty());
+
+        let mut fork = db.fork_create();
+        fork.remove_account_data(ACCOUNT_ID1, "b:2");
+        fork.store_account_data(ACCOUNT_ID1, "b:4", vec![0]);
+        assert_eq!(
+            fork.load_account_keys_range(ACCOUNT_ID1, "b:", Some("b:1"), 10),
+            vec!["b:3", "b:4"]
+        );
+    }
+
+    #[test]
+    fn load_account_keys_range_after_revert() {
+        let mut db = TempDb::new();
+        db.set_history_retention(Some(10));
+        commit_history_block(&mut db, 0, |fork| {
+            fork.store_account_data(ACCOUNT_ID1, "a", vec![0]);
+            fork.store_account_data(ACCOUNT_ID1, "b", vec![0]);
+        });
+        commit_history_block(&mut db, 1, |fork| {
+            fork.remove_account_data(ACCOUNT_ID1, "a");
+            fork.store_account_data(ACCOUNT_ID1, "c", vec![1]);
+        });
+        assert_eq!(
+            db.load_account_keys_range(ACCOUNT_ID1, "", None, 10),
+            vec!["b", "c"]
+        );
+
+        db.revert_to(0).unwrap();
+
+        assert_eq!(
+            db.load_account_keys_range(ACCOUNT_ID1, "", None, 10),
+            vec!["a", "b"]
+        );
+    }
+
+    #[test]
+    fn schema_version_new_db() {
+        let db = TempDb::new();
+
+        let marker: Entry<_, u32> = db.snap.get_entry(SCHEMA_VERSION_KEY);
+        assert_eq!(marker.get(), Some(SCHEMA_VERSION));
+        assert_eq!(db.schema_version(), SCHEMA_VERSION);
+    }
+
+    // Create a database without the version marker and the indexes added by
+    // the migrations.
+    fn create_legacy_db(path: &Path, block: Block) -> RocksDb {
+        let mut db = RocksDb::new(path);
+        let mut fork = db.fork_create();
+        fork.store_account(Account::new(ACCOUNT_ID1, None));
+        fork.store_account_data(ACCOUNT_ID1, "k", vec![0]);
+        fork.store_block(block);
+        db.fork_merge(fork).unwrap();
+        let fork = db.backend.fork();
+        {
+            let mut marker: Entry<_, u32> = fork.get_entry(SCHEMA_VERSION_KEY);
+            marker.remove();
+            let mut blocks_by_hash: MapIndex<_, Hash, u64> = fork.get_map(BLOCKS_BY_HASH);
+            blocks_by_hash.clear();
+            let mut keys: KeySetIndex<_, str> = fork.get_key_set((ACCOUNT_KEYS, ACCOUNT_ID1));
+            keys.clear();
+        }
+        db.backend.merge(fork.into_patch()).unwrap();
+        db.snap = db.backend.snapshot();
+        db
+    }
+
+    #[test]
+    fn schema_migration_legacy_db() {
+        let dir = TempDir::new().unwrap();
+        let block = create_test_block();
+        create_legacy_db(dir.path(), block.clone());
+
+        let db = RocksDb::open(dir.path()).unwrap();
+
+        assert_eq!(db.schema_version(), SCHEMA_VERSION);
+        assert_eq!(
+            db.load_block_by_hash(&block.data.primary_hash()),
+            Some(block)
+        );
+        assert_eq!(
+            db.load_account_keys_range(ACCOUNT_ID1, "", None, 10),
+            vec!["k"]
+        );
+    }
+
+    #[test]
+    fn load_block_by_hash_legacy_db() {
+        let dir = TempDir::new().unwrap();
+        let block = create_test_block();
+
+        let db = create_legacy_db(dir.path(), block.clone());
+
+        assert_eq!(db.schema_version(), 0);
+        assert_eq!(
+            db.load_block_by_hash(&block.data.primary_hash()),
+            Some(block)
+        );
+        assert_eq!(db.load_block_by_hash(&Hash::default()), None);
+    }
+
+    #[test]
+    fn load_account_keys_range_legacy_db() {
+        let dir = TempDir::new().unwrap();
+
+        let db = create_legacy_db(dir.path(), create_test_block());
+
+        assert_eq!(
+            db.load_account_keys_range(ACCOUNT_ID1, "", None, 10),
+            vec!["k"]
+        );
+        assert!(db
+            .load_account_keys_range(ACCOUNT_ID1, "", Some("k"), 10)
+            .is_empty());
+    }
+
+    #[test]
+    fn schema_version_unknown() {
+        let dir = TempDir::new().unwrap();
+        {
+            let db = RocksDb::new(dir.path());
+            let fork = db.backend.fork();
+            {
+                let mut marker: Entry<_, u32> = fork.get_entry(SCHEMA_VERSION_KEY);
+                marker.set(SCHEMA_VERSION + 1);
+            }
+            db.backend.merge(fork.into_patch()).unwrap();
+        }
+
+        let err = RocksDb::open(dir.path()).err().unwrap();
+
+        assert_eq!(err.kind, ErrorKind::DatabaseFault);
+    }
 }
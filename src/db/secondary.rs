@@ -0,0 +1,426 @@
+// This file is part of TRINCI.
+//
+// Copyright (C) 2021 Affidaty Spa.
+//
+// TRINCI is free software: you can redistribute it and/or modify it under
+// the terms of the GNU Affero General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or (at your
+// option) any later version.
+//
+// TRINCI is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License
+// for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with TRINCI. If not, see <https://www.gnu.org/licenses/>.
+
+//! Secondary read-only database handle.
+//!
+//! The rocks-db backend is locked by the node process, thus other processes
+//! (e.g. analytics and export jobs) follow the node progress through the
+//! checkpoints published by the node (see `RocksDb::set_checkpoints`).
+//!
+//! The checkpoints directory contains one sub-directory per checkpoint, named
+//! after the height of the block triggering it. Checkpoints are created in
+//! background by the node, thus a checkpoint may contain some of the following
+//! blocks as well. A checkpoint is a consistent copy of the database sharing
+//! (hard-linking) the immutable table files with the node database.
+//!
+//! The merkledb backend doesn't expose the RocksDB secondary instances, so a
+//! `SecondaryDb` opens a private replica of the last checkpoint instead. This
+//! way several readers can follow the same node and the replica outlives the
+//! checkpoint pruning. The replica is never migrated nor written, and is moved
+//! forward with `catch_up`.
+//!
+//! Both the checkpoints and the replicas hard-link the table files, thus the
+//! node database and the checkpoints directory shall be on the same
+//! filesystem. The replicas are created within the checkpoints directory.
+
+use crate::{
+    base::schema::Block,
+    crypto::{merkle::MapProof, Hash},
+    db::{AccountTransaction, Db, EventFilter, EventRecord, RocksDb, RocksDbFork},
+    Account, Error, ErrorKind, Receipt, Result, Transaction,
+};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process,
+};
+
+/// Number of checkpoints kept by the node.
+/// A reader shall catch up at least once per checkpoints interval.
+pub const CHECKPOINTS_KEPT: usize = 2;
+
+/// Max number of checkpoints replicated by a single open or catch up, when
+/// the checkpoints are pruned by the node while being replicated.
+const REPLICATE_ATTEMPTS: usize = 3;
+
+/// Path of the checkpoint triggered by the block at the given `height`.
+pub(crate) fn checkpoint_path(dir: &Path, height: u64) -> PathBuf {
+    dir.join(height.to_string())
+}
+
+/// Heights of the checkpoints published into `dir`, in ascending order.
+/// Entries not named after a height (e.g. the temporary ones) are skipped.
+pub(crate) fn checkpoints(dir: &Path) -> Vec<u64> {
+    let mut heights: Vec<u64> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+            .collect(),
+        Err(_) => vec![],
+    };
+    heights.sort_unstable();
+    heights
+}
+
+/// Remove the oldest checkpoints, keeping the last `CHECKPOINTS_KEPT` ones.
+pub(crate) fn prune_checkpoints(dir: &Path) {
+    let heights = checkpoints(dir);
+    let count = heights.len().saturating_sub(CHECKPOINTS_KEPT);
+    for height in heights.into_iter().take(count) {
+        if let Err(err) = fs::remove_dir_all(checkpoint_path(dir, height)) {
+            warn!("[db] error removing checkpoint {}: {}", height, err);
+        }
+    }
+}
+
+/// Copy a checkpoint into `dst`.
+/// Table files are immutable and are hard-linked, the others are copied.
+fn replicate(src: &Path, dst: &Path) -> io::Result<()> {
+    fs::create_dir(dst)?;
+    for entry in fs::read_dir(src)? {
+        let path = entry?.path();
+        let target = dst.join(path.file_name().unwrap_or_default());
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("sst") => fs::hard_link(&path, &target)?,
+            _ => fs::copy(&path, &target).map(|_| ())?,
+        }
+    }
+    Ok(())
+}
+
+/// Replica directory, removed on drop.
+struct ReplicaDir(PathBuf);
+
+impl Drop for ReplicaDir {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.0).unwrap_or_else(|err| {
+            warn!("[db] error removing replica '{:?}': {}", self.0, err);
+        });
+    }
+}
+
+/// Read-only database following the checkpoints published by a node.
+pub struct SecondaryDb {
+    /// Database opened on the replica.
+    /// Declared before the replica directory to be closed before its removal.
+    db: RocksDb,
+    /// Replica of the last opened checkpoint.
+    _replica: ReplicaDir,
+    /// Checkpoints directory.
+    dir: PathBuf,
+    /// Height of the last opened checkpoint.
+    height: u64,
+}
+
+impl SecondaryDb {
+    /// Open the last checkpoint published into `dir`.
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let dir = dir.as_ref().to_owned();
+        let (db, replica, height) = match Self::open_last_replica(&dir, None)? {
+            Some(replica) => replica,
+            None => {
+                return Err(Error::new_ext(
+                    ErrorKind::ResourceNotFound,
+                    "no checkpoint available",
+                ))
+            }
+        };
+        Ok(SecondaryDb {
+            db,
+            _replica: replica,
+            dir,
+            height,
+        })
+    }
+
+    // Open a replica of the last checkpoint, if newer than `after`.
+    // A checkpoint pruned while being replicated is given up for the next
+    // newest one.
+    fn open_last_replica(
+        dir: &Path,
+        after: Option<u64>,
+    ) -> Result<Option<(RocksDb, ReplicaDir, u64)>> {
+        let mut attempts = REPLICATE_ATTEMPTS;
+        loop {
+            let height = match checkpoints(dir).last() {
+                Some(height) if after.map_or(true, |after| *height > after) => *height,
+                _ => return Ok(None),
+            };
+            match Self::open_replica(dir, height) {
+                Ok((db, replica)) => return Ok(Some((db, replica, height))),
+                Err(_) if attempts > 1 && !checkpoint_path(dir, height).exists() => {
+                    warn!("[db] checkpoint {} pruned while replicating", height);
+                    attempts -= 1;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    // The replica directory is removed on failure.
+    fn open_replica(dir: &Path, height: u64) -> Result<(RocksDb, ReplicaDir)> {
+        let path = dir.join(format!(".replica-{}-{}", process::id(), height));
+        let _ = fs::remove_dir_all(&path);
+        let replica = ReplicaDir(path);
+        replicate(&checkpoint_path(dir, height), &replica.0)
+            .map_err(|err| Error::new_ext(ErrorKind::DatabaseFault, err))?;
+        let db = RocksDb::open_checkpoint(&replica.0)?;
+        Ok((db, replica))
+    }
+
+    /// Move to the last checkpoint published by the node, if newer than the
+    /// current one.
+    /// Returns `true` if the database content has been updated.
+    pub fn catch_up(&mut self) -> Result<bool> {
+        let (db, replica, height) = match Self::open_last_replica(&self.dir, Some(self.height))? {
+            Some(replica) => replica,
+            None => return Ok(false),
+        };
+        // The previous database is closed before its replica removal.
+        self.db = db;
+        self._replica = replica;
+        self.height = height;
+        Ok(true)
+    }
+
+    /// Height of the block that triggered the current checkpoint.
+    pub fn checkpoint_height(&self) -> u64 {
+        self.height
+    }
+}
+
+fn read_only_error() -> Error {
+    Error::new_ext(ErrorKind::DatabaseFault, "read-only database")
+}
+
+impl Db for SecondaryDb {
+    /// Forks can be created, but cannot be merged.
+    type DbForkType = RocksDbFork;
+
+    fn load_account(&self, id: &str) -> Option<Account> {
+        self.db.load_account(id)
+    }
+
+    fn load_account_keys(&self, id: &str) -> Vec<String> {
+        self.db.load_account_keys(id)
+    }
+
+    fn load_account_keys_range(
+        &self,
+        id: &str,
+        prefix: &str,
+        after: Option<&str>,
+        limit: u64,
+    ) -> Vec<String> {
+        self.db.load_account_keys_range(id, prefix, after, limit)
+    }
+
+    fn load_account_data(&self, id: &str, key: &str) -> Option<Vec<u8>> {
+        self.db.load_account_data(id, key)
+    }
+
+    fn load_account_at(&self, id: &str, height: u64) -> Option<Account> {
+        self.db.load_account_at(id, height)
+    }
+
+    fn load_account_data_at(&self, id: &str, key: &str, height: u64) -> Option<Vec<u8>> {
+        self.db.load_account_data_at(id, key, height)
+    }
+
+    fn load_data(&self, key: &str) -> Option<Vec<u8>> {
+        self.db.load_data(key)
+    }
+
+    fn contains_transaction(&self, key: &Hash) -> bool {
+        self.db.contains_transaction(key)
+    }
+
+    fn load_transaction(&self, hash: &Hash) -> Option<Transaction> {
+        self.db.load_transaction(hash)
+    }
+
+    fn load_receipt(&self, hash: &Hash) -> Option<Receipt> {
+        self.db.load_receipt(hash)
+    }
+
+    fn load_block(&self, height: u64) -> Option<Block> {
+        self.db.load_block(height)
+    }
+
+    fn load_block_by_hash(&self, hash: &Hash) -> Option<Block> {
+        self.db.load_block_by_hash(hash)
+    }
+
+    fn load_account_transactions(
+        &self,
+        id: &str,
+        from: u64,
+        limit: u64,
+    ) -> Vec<AccountTransaction> {
+        self.db.load_account_transactions(id, from, limit)
+    }
+
+    fn load_events(&self, filter: &EventFilter, from: u64, limit: u64) -> Vec<EventRecord> {
+        self.db.load_events(filter, from, limit)
+    }
+
+    fn load_transactions_hashes(&self, height: u64) -> Option<Vec<Hash>> {
+        self.db.load_transactions_hashes(height)
+    }
+
+    fn load_receipts_hashes(&self, height: u64) -> Option<Vec<Hash>> {
+        self.db.load_receipts_hashes(height)
+    }
+
+    fn fork_create(&mut self) -> RocksDbFork {
+        self.db.fork_create()
+    }
+
+    fn fork_merge(&mut self, _fork: RocksDbFork) -> Result<()> {
+        Err(read_only_error())
+    }
+
+    fn load_configuration(&self, id: &str) -> Option<Vec<u8>> {
+        self.db.load_configuration(id)
+    }
+
+    fn revert_to(&mut self, _height: u64) -> Result<Vec<Transaction>> {
+        Err(read_only_error())
+    }
+
+    fn load_account_proof(&self, id: &str) -> Option<MapProof> {
+        self.db.load_account_proof(id)
+    }
+
+    fn load_account_data_proof(&self, id: &str, key: &str) -> Option<MapProof> {
+        self.db.load_account_data_proof(id, key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{base::schema::tests::create_test_block, db::DbFork};
+    use tempfile::TempDir;
+
+    const ACCOUNT_ID: &str = "QmNLei78zWmzUdbeRB3CiUfAizWUrbeeZh5K1rhAQKCh51";
+
+    fn commit_block(db: &mut RocksDb, height: u64) -> Block {
+        let mut fork = db.fork_create();
+        fork.store_account(Account::new(ACCOUNT_ID, None));
+        fork.store_account_data(ACCOUNT_ID, &height.to_string(), vec![height as u8]);
+        let mut block = create_test_block();
+        block.data.height = height;
+        fork.store_block(block.clone());
+        db.fork_merge(fork).unwrap();
+        db.wait_checkpoint();
+        block
+    }
+
+    #[test]
+    fn open_without_checkpoints() {
+        let dir = TempDir::new().unwrap();
+
+        let err = SecondaryDb::open(dir.path()).err().unwrap();
+
+        assert_eq!(err.kind, ErrorKind::ResourceNotFound);
+    }
+
+    #[test]
+    fn follow_primary() {
+        let primary_dir = TempDir::new().unwrap();
+        let checkpoints_dir = TempDir::new().unwrap();
+        let mut primary = RocksDb::new(primary_dir.path());
+        primary.set_checkpoints(Some((checkpoints_dir.path().to_owned(), 2)));
+        commit_block(&mut primary, 0);
+        commit_block(&mut primary, 1);
+
+        let mut secondary = SecondaryDb::open(checkpoints_dir.path()).unwrap();
+        assert_eq!(secondary.checkpoint_height(), 0);
+        assert_eq!(secondary.load_block(u64::MAX).unwrap().data.height, 0);
+        assert_eq!(secondary.load_account_data(ACCOUNT_ID, "0"), Some(vec![0]));
+        assert!(!secondary.catch_up().unwrap());
+
+        let block = commit_block(&mut primary, 2);
+        assert!(secondary.catch_up().unwrap());
+        assert_eq!(secondary.checkpoint_height(), 2);
+        assert_eq!(secondary.load_block(u64::MAX), Some(block));
+        assert_eq!(secondary.load_account_data(ACCOUNT_ID, "1"), Some(vec![1]));
+    }
+
+    #[test]
+    fn checkpoints_pruning() {
+        let primary_dir = TempDir::new().unwrap();
+        let checkpoints_dir = TempDir::new().unwrap();
+        let mut primary = RocksDb::new(primary_dir.path());
+        primary.set_checkpoints(Some((checkpoints_dir.path().to_owned(), 1)));
+        commit_block(&mut primary, 0);
+        let mut secondary = SecondaryDb::open(checkpoints_dir.path()).unwrap();
+
+        for height in 1..4 {
+            commit_block(&mut primary, height);
+        }
+
+        assert_eq!(checkpoints(checkpoints_dir.path()), vec![2, 3]);
+        // The replica outlives the pruned checkpoint.
+        assert_eq!(secondary.load_block(u64::MAX).unwrap().data.height, 0);
+        assert!(secondary.catch_up().unwrap());
+        assert_eq!(secondary.load_block(u64::MAX).unwrap().data.height, 3);
+    }
+
+    #[test]
+    fn broken_checkpoint_replica_removed() {
+        let primary_dir = TempDir::new().unwrap();
+        let checkpoints_dir = TempDir::new().unwrap();
+        let mut primary = RocksDb::new(primary_dir.path());
+        primary.set_checkpoints(Some((checkpoints_dir.path().to_owned(), 1)));
+        commit_block(&mut primary, 0);
+        let mut secondary = SecondaryDb::open(checkpoints_dir.path()).unwrap();
+        let broken = checkpoint_path(checkpoints_dir.path(), 1);
+        fs::create_dir(&broken).unwrap();
+        fs::write(broken.join("CURRENT"), "MANIFEST-000000\n").unwrap();
+
+        assert!(secondary.catch_up().is_err());
+
+        assert_eq!(secondary.checkpoint_height(), 0);
+        assert_eq!(secondary.load_block(u64::MAX).unwrap().data.height, 0);
+        let replicas = fs::read_dir(checkpoints_dir.path())
+            .unwrap()
+            .filter(|entry| {
+                let name = entry.as_ref().unwrap().file_name();
+                name.to_string_lossy().starts_with(".replica-")
+            })
+            .count();
+        assert_eq!(replicas, 1);
+    }
+
+    #[test]
+    fn write_refused() {
+        let primary_dir = TempDir::new().unwrap();
+        let checkpoints_dir = TempDir::new().unwrap();
+        let mut primary = RocksDb::new(primary_dir.path());
+        primary.set_checkpoints(Some((checkpoints_dir.path().to_owned(), 1)));
+        commit_block(&mut primary, 0);
+        let mut secondary = SecondaryDb::open(checkpoints_dir.path()).unwrap();
+
+        let mut fork = secondary.fork_create();
+        fork.store_account_data(ACCOUNT_ID, "k", vec![1]);
+
+        assert!(secondary.fork_merge(fork).is_err());
+        assert!(secondary.revert_to(0).is_err());
+        assert_eq!(secondary.load_account_data(ACCOUNT_ID, "k"), None);
+    }
+}
@@ -0,0 +1,631 @@
+// This file is part of TRINCI.
+//
+// Copyright (C) 2021 Affidaty Spa.
+//
+// TRINCI is free software: you can redistribute it and/or modify it under
+// the terms of the GNU Affero General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or (at your
+// option) any later version.
+//
+// TRINCI is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License
+// for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with TRINCI. If not, see <https://www.gnu.org/licenses/>.
+
+//! State snapshots, used to bootstrap a node without replaying the whole chain.
+//!
+//! A snapshot contains the blocks list, along with the transactions and
+//! receipts hashes lists, together with the accounts, the configuration and
+//! the internal data maps at the last block height.
+//! Transactions and receipts are not included.
+//!
+//! The snapshot is a sequence of chunks, each one framed as:
+//! `chunk length (u32 BE) | chunk (message pack) | chunk hash (multihash)`.
+//! The first chunk is the header and the last one is the trailer, holding the
+//! number of chunks and the digest chaining all the previous chunks hashes.
+//!
+//! The import verifies every chunk, the blocks chaining and hashes lists, the
+//! last block against a trusted hash and the resulting state hash against the
+//! last block `state_hash`. The content is committed in batches while it is
+//! read, thus a database whose import failed shall be discarded.
+
+use crate::{
+    base::{
+        schema::{Account, Block},
+        serialize::{rmp_deserialize, rmp_serialize},
+    },
+    crypto::{merkle, Hash, HashAlgorithm, Hashable},
+    db::{Db, DbFork},
+    Error, ErrorKind, Result,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{Read, Write},
+};
+
+/// Snapshot format version.
+pub const SNAPSHOT_VERSION: u8 = 1;
+
+/// Max number of entries within a chunk.
+const CHUNK_MAX_ENTRIES: usize = 1000;
+
+/// Chunk size threshold, entries are flushed as soon as it is reached.
+const CHUNK_MAX_BYTES: usize = 1024 * 1024;
+
+/// Max accepted frame length. A single entry may exceed `CHUNK_MAX_BYTES`.
+const FRAME_MAX_BYTES: usize = 256 * 1024 * 1024;
+
+/// Number of chunks committed at once by the import.
+const IMPORT_BATCH_CHUNKS: u64 = 64;
+
+/// Generic key-value map entry.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SnapshotEntry {
+    /// Entry key.
+    pub key: String,
+    /// Entry value.
+    #[serde(with = "serde_bytes")]
+    pub value: Vec<u8>,
+}
+
+/// Block along with its transactions and receipts hashes lists.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SnapshotBlock {
+    /// Block.
+    pub block: Block,
+    /// Block transactions hashes.
+    pub txs_hashes: Vec<Hash>,
+    /// Block receipts hashes.
+    pub rxs_hashes: Vec<Hash>,
+}
+
+/// Snapshot chunk.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum SnapshotChunk {
+    /// First chunk.
+    Header {
+        /// Format version.
+        version: u8,
+        /// Last block height.
+        height: u64,
+        /// Last block state hash.
+        state_hash: Hash,
+    },
+    /// Blocks, in height order.
+    Blocks(Vec<SnapshotBlock>),
+    /// Accounts.
+    Accounts(Vec<Account>),
+    /// Data entries of an account.
+    AccountData {
+        /// Account identifier.
+        id: String,
+        /// Account data entries.
+        entries: Vec<SnapshotEntry>,
+    },
+    /// Configuration entries.
+    Config(Vec<SnapshotEntry>),
+    /// Internal data entries.
+    InternalDb(Vec<SnapshotEntry>),
+    /// Last chunk.
+    Trailer {
+        /// Number of chunks preceding the trailer.
+        chunks: u64,
+        /// Chained hash of the chunks preceding the trailer.
+        digest: Hash,
+    },
+}
+
+/// Chain the running digest with the next chunk hash.
+fn chain_digest(digest: &Hash, hash: &Hash) -> Hash {
+    let mut buf = digest.to_bytes();
+    buf.extend_from_slice(hash.as_bytes());
+    Hash::from_data(HashAlgorithm::Sha256, &buf)
+}
+
+fn io_error(err: std::io::Error) -> Error {
+    Error::new_ext(ErrorKind::Other, err)
+}
+
+fn integrity_error(msg: &str) -> Error {
+    Error::new_ext(ErrorKind::BrokenIntegrity, msg.to_owned())
+}
+
+/// Values, at a past height, of the state entries modified afterwards.
+/// Entries that were not existing are marked with `None`.
+#[derive(Default)]
+pub(crate) struct StateRewind {
+    pub accounts: BTreeMap<String, Option<Account>>,
+    pub accounts_data: BTreeMap<String, BTreeMap<String, Option<Vec<u8>>>>,
+    pub config: BTreeMap<String, Option<Vec<u8>>>,
+    pub internal_db: BTreeMap<String, Option<Vec<u8>>>,
+}
+
+/// Rewind the `current` entries using the `previous` values.
+pub(crate) fn rewind_entries<'a, V: Clone + 'a>(
+    current: impl Iterator<Item = (String, V)> + 'a,
+    previous: &'a BTreeMap<String, Option<V>>,
+) -> impl Iterator<Item = (String, V)> + 'a {
+    current
+        .filter(move |(key, _)| !previous.contains_key(key))
+        .chain(
+            previous
+                .iter()
+                .filter_map(|(key, value)| Some((key.clone(), value.clone()?))),
+        )
+}
+
+/// Snapshot serializer.
+pub struct SnapshotWriter<W: Write> {
+    /// Output stream.
+    writer: W,
+    /// Number of written chunks.
+    chunks: u64,
+    /// Chained hash of the written chunks.
+    digest: Hash,
+}
+
+impl<W: Write> SnapshotWriter<W> {
+    /// Start a new snapshot for the state at the given block.
+    pub fn new(writer: W, height: u64, state_hash: Hash) -> Result<Self> {
+        let mut snapshot = SnapshotWriter {
+            writer,
+            chunks: 0,
+            digest: Hash::default(),
+        };
+        snapshot.write_chunk(&SnapshotChunk::Header {
+            version: SNAPSHOT_VERSION,
+            height,
+            state_hash,
+        })?;
+        Ok(snapshot)
+    }
+
+    fn write_chunk(&mut self, chunk: &SnapshotChunk) -> Result<()> {
+        let buf = rmp_serialize(chunk)?;
+        let hash = Hash::from_data(HashAlgorithm::Sha256, &buf);
+        self.writer
+            .write_all(&(buf.len() as u32).to_be_bytes())
+            .and_then(|_| self.writer.write_all(&buf))
+            .and_then(|_| self.writer.write_all(hash.as_bytes()))
+            .map_err(io_error)?;
+        self.digest = chain_digest(&self.digest, &hash);
+        self.chunks += 1;
+        Ok(())
+    }
+
+    /// Split the items in chunks limited by number of entries and size.
+    fn write_items<T>(
+        &mut self,
+        items: impl IntoIterator<Item = T>,
+        size: impl Fn(&T) -> usize,
+        chunk: impl Fn(Vec<T>) -> SnapshotChunk,
+    ) -> Result<()> {
+        let mut pending = vec![];
+        let mut pending_size = 0;
+        for item in items {
+            pending_size += size(&item);
+            pending.push(item);
+            if pending.len() >= CHUNK_MAX_ENTRIES || pending_size >= CHUNK_MAX_BYTES {
+                self.write_chunk(&chunk(std::mem::take(&mut pending)))?;
+                pending_size = 0;
+            }
+        }
+        if !pending.is_empty() {
+            self.write_chunk(&chunk(pending))?;
+        }
+        Ok(())
+    }
+
+    /// Write the blocks, in height order.
+    pub fn write_blocks(&mut self, blocks: impl IntoIterator<Item = SnapshotBlock>) -> Result<()> {
+        self.write_items(
+            blocks,
+            |block| {
+                block
+                    .txs_hashes
+                    .iter()
+                    .chain(block.rxs_hashes.iter())
+                    .map(Hash::size)
+                    .sum()
+            },
+            SnapshotChunk::Blocks,
+        )
+    }
+
+    /// Write the accounts.
+    pub fn write_accounts(&mut self, accounts: impl IntoIterator<Item = Account>) -> Result<()> {
+        self.write_items(accounts, |_| 0, SnapshotChunk::Accounts)
+    }
+
+    /// Write the data entries of the account `id`.
+    pub fn write_account_data(
+        &mut self,
+        id: &str,
+        entries: impl IntoIterator<Item = (String, Vec<u8>)>,
+    ) -> Result<()> {
+        let entries = entries
+            .into_iter()
+            .map(|(key, value)| SnapshotEntry { key, value });
+        self.write_items(
+            entries,
+            |entry| entry.value.len(),
+            |entries| SnapshotChunk::AccountData {
+                id: id.to_owned(),
+                entries,
+            },
+        )
+    }
+
+    /// Write the configuration entries.
+    pub fn write_config(
+        &mut self,
+        entries: impl IntoIterator<Item = (String, Vec<u8>)>,
+    ) -> Result<()> {
+        let entries = entries
+            .into_iter()
+            .map(|(key, value)| SnapshotEntry { key, value });
+        self.write_items(entries, |entry| entry.value.len(), SnapshotChunk::Config)
+    }
+
+    /// Write the internal data entries.
+    pub fn write_internal_db(
+        &mut self,
+        entries: impl IntoIterator<Item = (String, Vec<u8>)>,
+    ) -> Result<()> {
+        let entries = entries
+            .into_iter()
+            .map(|(key, value)| SnapshotEntry { key, value });
+        self.write_items(
+            entries,
+            |entry| entry.value.len(),
+            SnapshotChunk::InternalDb,
+        )
+    }
+
+    /// Write the trailer and flush the output stream.
+    /// Returns the snapshot digest.
+    pub fn finish(mut self) -> Result<Hash> {
+        let digest = self.digest;
+        self.write_chunk(&SnapshotChunk::Trailer {
+            chunks: self.chunks,
+            digest,
+        })?;
+        self.writer.flush().map_err(io_error)?;
+        Ok(digest)
+    }
+}
+
+/// Snapshot deserializer.
+/// Every chunk is verified against its hash while it is read.
+pub struct SnapshotReader<R: Read> {
+    /// Input stream.
+    reader: R,
+    /// Snapshot block height.
+    height: u64,
+    /// Snapshot state hash.
+    state_hash: Hash,
+    /// Number of read chunks.
+    chunks: u64,
+    /// Chained hash of the read chunks.
+    digest: Hash,
+    /// Trailer reached.
+    done: bool,
+}
+
+impl<R: Read> SnapshotReader<R> {
+    /// Open a snapshot reading its header.
+    pub fn new(reader: R) -> Result<Self> {
+        let mut snapshot = SnapshotReader {
+            reader,
+            height: 0,
+            state_hash: Hash::default(),
+            chunks: 0,
+            digest: Hash::default(),
+            done: false,
+        };
+        match snapshot.read_chunk()? {
+            SnapshotChunk::Header {
+                version: SNAPSHOT_VERSION,
+                height,
+                state_hash,
+            } => {
+                snapshot.height = height;
+                snapshot.state_hash = state_hash;
+                Ok(snapshot)
+            }
+            SnapshotChunk::Header { .. } => Err(Error::new_ext(
+                ErrorKind::NotImplemented,
+                "unsupported snapshot version",
+            )),
+            _ => Err(Error::new_ext(
+                ErrorKind::MalformedData,
+                "missing snapshot header",
+            )),
+        }
+    }
+
+    /// Snapshot block height.
+    pub fn height(&self) -> u64 {
+        self.height
+    }
+
+    /// Snapshot state hash.
+    pub fn state_hash(&self) -> Hash {
+        self.state_hash
+    }
+
+    fn read_chunk(&mut self) -> Result<SnapshotChunk> {
+        let mut len = [0; 4];
+        self.reader.read_exact(&mut len).map_err(io_error)?;
+        let len = u32::from_be_bytes(len) as usize;
+        if len > FRAME_MAX_BYTES {
+            return Err(Error::new_ext(
+                ErrorKind::MalformedData,
+                "snapshot chunk too large",
+            ));
+        }
+        let mut buf = vec![0; len];
+        self.reader.read_exact(&mut buf).map_err(io_error)?;
+        let mut hash_buf = [0; 2];
+        self.reader.read_exact(&mut hash_buf).map_err(io_error)?;
+        let mut hash_buf = hash_buf.to_vec();
+        hash_buf.resize(2 + hash_buf[1] as usize, 0);
+        self.reader
+            .read_exact(&mut hash_buf[2..])
+            .map_err(io_error)?;
+        let hash = Hash::from_bytes(&hash_buf)?;
+        if hash != Hash::from_data(HashAlgorithm::Sha256, &buf) {
+            return Err(integrity_error("snapshot chunk hash mismatch"));
+        }
+        let chunk = rmp_deserialize(&buf)?;
+        if let SnapshotChunk::Trailer { chunks, digest } = &chunk {
+            if *chunks != self.chunks || *digest != self.digest {
+                return Err(integrity_error("snapshot digest mismatch"));
+            }
+        }
+        self.digest = chain_digest(&self.digest, &hash);
+        self.chunks += 1;
+        Ok(chunk)
+    }
+
+    /// Read the next data chunk.
+    /// Returns `None` once the trailer has been successfully verified.
+    pub fn next_chunk(&mut self) -> Result<Option<SnapshotChunk>> {
+        if self.done {
+            return Ok(None);
+        }
+        match self.read_chunk()? {
+            SnapshotChunk::Trailer { .. } => {
+                self.done = true;
+                Ok(None)
+            }
+            SnapshotChunk::Header { .. } => Err(Error::new_ext(
+                ErrorKind::MalformedData,
+                "unexpected snapshot header",
+            )),
+            chunk => Ok(Some(chunk)),
+        }
+    }
+}
+
+/// Block with the hashes lists stored within the database.
+pub(crate) fn snapshot_block<D: Db>(db: &D, block: Block) -> SnapshotBlock {
+    let height = block.data.height;
+    SnapshotBlock {
+        block,
+        txs_hashes: db.load_transactions_hashes(height).unwrap_or_default(),
+        rxs_hashes: db.load_receipts_hashes(height).unwrap_or_default(),
+    }
+}
+
+/// Initialize an empty database from a snapshot.
+/// The last snapshot block hash shall match the `trusted_hash`, obtained
+/// from a trusted source, e.g. the network validators.
+/// The content is committed in batches while the snapshot is read, if the
+/// snapshot is not consistent the database is left partially written and
+/// shall be discarded.
+/// After the import, the blocks execution resumes from the snapshot height + 1.
+/// Returns the last snapshot block.
+pub fn import_snapshot<D: Db, R: Read>(
+    db: &mut D,
+    reader: R,
+    trusted_hash: &Hash,
+) -> Result<Block> {
+    if db.load_block(u64::MAX).is_some() {
+        return Err(Error::new_ext(
+            ErrorKind::DatabaseFault,
+            "snapshot import requires an empty database",
+        ));
+    }
+    let mut reader = SnapshotReader::new(reader)?;
+    let mut fork = db.fork_create();
+    let mut pending = 0;
+    let mut last: Option<Block> = None;
+    let mut accounts = BTreeMap::new();
+    let mut data_ids = BTreeSet::new();
+
+    while let Some(chunk) = reader.next_chunk()? {
+        match chunk {
+            SnapshotChunk::Blocks(blocks) => {
+                for SnapshotBlock {
+                    block,
+                    txs_hashes,
+                    rxs_hashes,
+                } in blocks
+                {
+                    let (height, prev_hash) = match &last {
+                        Some(prev) => (prev.data.height + 1, prev.data.primary_hash()),
+                        None => (0, Hash::default()),
+                    };
+                    if block.data.height != height || block.data.prev_hash != prev_hash {
+                        return Err(integrity_error("broken snapshot blocks chain"));
+                    }
+                    if merkle::list_hash(&txs_hashes) != block.data.txs_hash
+                        || merkle::list_hash(&rxs_hashes) != block.data.rxs_hash
+                    {
+                        return Err(integrity_error("snapshot block hashes lists mismatch"));
+                    }
+                    // Empty lists are not stored.
+                    if !txs_hashes.is_empty() {
+                        fork.store_transactions_hashes(height, txs_hashes);
+                    }
+                    if !rxs_hashes.is_empty() {
+                        fork.store_receipts_hashes(height, rxs_hashes);
+                    }
+                    fork.store_block(block.clone());
+                    last = Some(block);
+                }
+            }
+            SnapshotChunk::Accounts(list) => {
+                for account in list {
+                    accounts.insert(account.id.clone(), account.data_hash);
+                    fork.store_account(account);
+                }
+            }
+            SnapshotChunk::AccountData { id, entries } => {
+                for entry in entries {
+                    fork.store_account_data(&id, &entry.key, entry.value);
+                }
+                data_ids.insert(id);
+            }
+            SnapshotChunk::Config(entries) => entries
+                .into_iter()
+                .for_each(|entry| fork.store_configuration(&entry.key, entry.value)),
+            SnapshotChunk::InternalDb(entries) => entries
+                .into_iter()
+                .for_each(|entry| fork.store_data(&entry.key, entry.value)),
+            SnapshotChunk::Header { .. } | SnapshotChunk::Trailer { .. } => {
+                return Err(Error::new_ext(
+                    ErrorKind::MalformedData,
+                    "unexpected snapshot chunk",
+                ))
+            }
+        }
+        pending += 1;
+        if pending >= IMPORT_BATCH_CHUNKS {
+            db.fork_merge(fork)?;
+            fork = db.fork_create();
+            pending = 0;
+        }
+    }
+    db.fork_merge(fork)?;
+    let fork = db.fork_create();
+
+    let last = match last {
+        Some(block)
+            if block.data.height == reader.height()
+                && block.data.state_hash == reader.state_hash() =>
+        {
+            block
+        }
+        _ => return Err(integrity_error("snapshot header mismatch")),
+    };
+    if last.data.primary_hash() != *trusted_hash {
+        return Err(integrity_error("snapshot last block not trusted"));
+    }
+    if data_ids.iter().any(|id| !accounts.contains_key(id)) {
+        return Err(integrity_error("snapshot data of unknown account"));
+    }
+    for (id, data_hash) in accounts.iter() {
+        let consistent = match data_hash {
+            Some(hash) => *hash == fork.state_hash(id),
+            None => !data_ids.contains(id),
+        };
+        if !consistent {
+            return Err(integrity_error("snapshot account data hash mismatch"));
+        }
+    }
+    if fork.state_hash("") != last.data.state_hash {
+        return Err(integrity_error("snapshot state hash mismatch"));
+    }
+    Ok(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base::schema::tests::{create_test_account, create_test_block};
+
+    fn create_test_snapshot(chunks: Vec<SnapshotChunk>) -> Vec<u8> {
+        let mut buf = vec![];
+        let block = create_test_block();
+        let mut writer =
+            SnapshotWriter::new(&mut buf, block.data.height, block.data.state_hash).unwrap();
+        for chunk in chunks {
+            writer.write_chunk(&chunk).unwrap();
+        }
+        writer.finish().unwrap();
+        buf
+    }
+
+    #[test]
+    fn write_and_read_chunks() {
+        let chunks = vec![
+            SnapshotChunk::Blocks(vec![SnapshotBlock {
+                block: create_test_block(),
+                txs_hashes: vec![Hash::default()],
+                rxs_hashes: vec![],
+            }]),
+            SnapshotChunk::Accounts(vec![create_test_account()]),
+        ];
+        let buf = create_test_snapshot(chunks.clone());
+
+        let mut reader = SnapshotReader::new(buf.as_slice()).unwrap();
+
+        assert_eq!(reader.height(), create_test_block().data.height);
+        assert_eq!(reader.next_chunk().unwrap(), Some(chunks[0].clone()));
+        assert_eq!(reader.next_chunk().unwrap(), Some(chunks[1].clone()));
+        assert_eq!(reader.next_chunk().unwrap(), None);
+    }
+
+    #[test]
+    fn entries_are_chunked() {
+        let mut buf = vec![];
+        let mut writer = SnapshotWriter::new(&mut buf, 0, Hash::default()).unwrap();
+        let entries = (0..CHUNK_MAX_ENTRIES + 1).map(|i| (i.to_string(), vec![0]));
+        writer.write_config(entries).unwrap();
+        writer.finish().unwrap();
+
+        let mut reader = SnapshotReader::new(buf.as_slice()).unwrap();
+        let mut count = 0;
+        while let Some(chunk) = reader.next_chunk().unwrap() {
+            assert!(matches!(chunk, SnapshotChunk::Config(_)));
+            count += 1;
+        }
+
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn tampered_chunk() {
+        let chunks = vec![SnapshotChunk::Accounts(vec![create_test_account()])];
+        let mut buf = create_test_snapshot(chunks);
+        let header_len = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+        buf[4 + header_len + 34 + 4 + 2] ^= 1;
+
+        let mut reader = SnapshotReader::new(buf.as_slice()).unwrap();
+        let err = reader.next_chunk().unwrap_err();
+
+        assert_eq!(err.kind, ErrorKind::BrokenIntegrity);
+    }
+
+    #[test]
+    fn truncated_snapshot() {
+        let chunks = vec![SnapshotChunk::Accounts(vec![create_test_account()])];
+        let buf = create_test_snapshot(chunks);
+        let header_len = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+
+        // Drop the trailer.
+        let mut reader = SnapshotReader::new(&buf[..buf.len() - 10]).unwrap();
+        assert!(reader.next_chunk().unwrap().is_some());
+        assert!(reader.next_chunk().is_err());
+
+        // Drop everything but the header.
+        let mut reader = SnapshotReader::new(&buf[..4 + header_len + 34]).unwrap();
+        assert!(reader.next_chunk().is_err());
+    }
+}
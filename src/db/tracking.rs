@@ -0,0 +1,416 @@
+// This file is part of TRINCI.
+//
+// Copyright (C) 2021 Affidaty Spa.
+//
+// TRINCI is free software: you can redistribute it and/or modify it under
+// the terms of the GNU Affero General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or (at your
+// option) any later version.
+//
+// TRINCI is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License
+// for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with TRINCI. If not, see <https://www.gnu.org/licenses/>.
+
+//! Database fork tracking the state accessed by an execution.
+//!
+//! A `TrackingFork` wraps a fork and records the state read from the initial
+//! fork state, together with the observed values, and the state effectively
+//! written, that is not discarded by a rollback.
+//!
+//! The recorded `StateAccess` can be validated against another fork: when the
+//! other fork holds the same values for the read state, the execution on it
+//! would behave exactly the same and the recorded writes can be applied in
+//! place of re-executing.
+
+use super::{AccountTransaction, DbFork, EventRecord};
+use crate::{base::schema::Block, crypto::Hash, Account, Receipt, Transaction};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+};
+
+/// State entry written through a tracking fork.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum StateKey {
+    Account(String),
+    AccountData(String, String),
+    Data(String),
+}
+
+/// State read and written by an execution.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StateAccess {
+    /// Accounts read from the initial state.
+    pub read_accounts: BTreeMap<String, Option<Account>>,
+    /// Accounts data read from the initial state.
+    pub read_data: BTreeMap<(String, String), Option<Vec<u8>>>,
+    /// Accounts data sets read from the initial state, by state hash.
+    /// The empty identifier stands for the global accounts state.
+    pub read_hashes: BTreeMap<String, Hash>,
+    /// Internal data read from the initial state.
+    pub read_internal: BTreeMap<String, Option<Vec<u8>>>,
+    /// Written accounts.
+    pub accounts: Vec<Account>,
+    /// Written accounts data, `None` if removed.
+    pub data: Vec<(String, String, Option<Vec<u8>>)>,
+    /// Written internal data.
+    pub internal: Vec<(String, Vec<u8>)>,
+    /// The execution cannot be applied to another fork.
+    /// Set when some writes have been discarded by a rollback, since these
+    /// still leave a trace in the fork history, or when entries other than
+    /// the state have been written.
+    pub opaque: bool,
+}
+
+impl StateAccess {
+    /// Check that a fork holds the same values read by the execution.
+    pub fn is_valid(&self, fork: &dyn DbFork) -> bool {
+        !self.opaque
+            && self
+                .read_accounts
+                .iter()
+                .all(|(id, account)| fork.load_account(id) == *account)
+            && self
+                .read_data
+                .iter()
+                .all(|((id, key), data)| fork.load_account_data(id, key) == *data)
+            && self
+                .read_hashes
+                .iter()
+                .all(|(id, hash)| fork.state_hash(id) == *hash)
+            && self
+                .read_internal
+                .iter()
+                .all(|(key, data)| fork.load_data(key) == *data)
+    }
+
+    /// Apply the execution writes to a fork.
+    pub fn apply(&self, fork: &mut dyn DbFork) {
+        for account in self.accounts.iter() {
+            fork.store_account(account.clone());
+        }
+        for (id, key, data) in self.data.iter() {
+            match data {
+                Some(data) => fork.store_account_data(id, key, data.clone()),
+                None => fork.remove_account_data(id, key),
+            }
+        }
+        for (key, data) in self.internal.iter() {
+            fork.store_data(key, data.clone());
+        }
+    }
+}
+
+/// Fork wrapper tracking the state accesses.
+pub struct TrackingFork<F: DbFork> {
+    /// Wrapped fork.
+    fork: F,
+    /// State read from the initial state.
+    access: RefCell<StateAccess>,
+    /// Entries written before the last flush.
+    written: BTreeSet<StateKey>,
+    /// Entries written after the last flush.
+    pending: BTreeSet<StateKey>,
+    /// Initial state hashes, recorded before the first write.
+    hashes: BTreeMap<String, Hash>,
+}
+
+impl<F: DbFork> TrackingFork<F> {
+    /// Wraps a fork.
+    pub fn new(fork: F) -> Self {
+        TrackingFork {
+            fork,
+            access: RefCell::new(StateAccess::default()),
+            written: BTreeSet::new(),
+            pending: BTreeSet::new(),
+            hashes: BTreeMap::new(),
+        }
+    }
+
+    /// Get the state accessed through the fork, with the written values.
+    pub fn into_access(self) -> StateAccess {
+        let mut access = self.access.into_inner();
+        for key in self.written.union(&self.pending) {
+            match key {
+                StateKey::Account(id) => {
+                    if let Some(account) = self.fork.load_account(id) {
+                        access.accounts.push(account);
+                    }
+                }
+                StateKey::AccountData(id, key) => {
+                    let data = self.fork.load_account_data(id, key);
+                    access.data.push((id.clone(), key.clone(), data));
+                }
+                StateKey::Data(key) => {
+                    if let Some(data) = self.fork.load_data(key) {
+                        access.internal.push((key.clone(), data));
+                    }
+                }
+            }
+        }
+        access
+    }
+
+    fn is_written(&self, key: &StateKey) -> bool {
+        self.written.contains(key) || self.pending.contains(key)
+    }
+
+    /// Records a state entry write, together with the initial hash of the
+    /// state it belongs to.
+    fn record_write(&mut self, key: StateKey) {
+        let id = match &key {
+            StateKey::Account(_) => Some(""),
+            StateKey::AccountData(id, _) => Some(id.as_str()),
+            StateKey::Data(_) => None,
+        };
+        if let Some(id) = id {
+            if !self.hashes.contains_key(id) {
+                let hash = self.fork.state_hash(id);
+                self.hashes.insert(id.to_owned(), hash);
+            }
+        }
+        self.pending.insert(key);
+    }
+
+    /// Records the read of a state hash, as it was before the first write.
+    fn record_hash(&self, id: &str) {
+        let hash = match self.hashes.get(id) {
+            Some(hash) => *hash,
+            None => self.fork.state_hash(id),
+        };
+        self.access
+            .borrow_mut()
+            .read_hashes
+            .entry(id.to_owned())
+            .or_insert(hash);
+    }
+
+    /// Records an execution writing entries that cannot be applied.
+    fn record_opaque(&mut self) {
+        self.access.get_mut().opaque = true;
+    }
+}
+
+impl<F: DbFork> DbFork for TrackingFork<F> {
+    fn state_hash(&self, id: &str) -> Hash {
+        self.record_hash(id);
+        self.fork.state_hash(id)
+    }
+
+    fn load_account(&self, id: &str) -> Option<Account> {
+        let account = self.fork.load_account(id);
+        if !self.is_written(&StateKey::Account(id.to_owned())) {
+            self.access
+                .borrow_mut()
+                .read_accounts
+                .entry(id.to_owned())
+                .or_insert_with(|| account.clone());
+        }
+        account
+    }
+
+    fn store_account(&mut self, account: Account) {
+        self.record_write(StateKey::Account(account.id.clone()));
+        self.fork.store_account(account)
+    }
+
+    fn load_account_data(&self, id: &str, key: &str) -> Option<Vec<u8>> {
+        let data = self.fork.load_account_data(id, key);
+        if !self.is_written(&StateKey::AccountData(id.to_owned(), key.to_owned())) {
+            self.access
+                .borrow_mut()
+                .read_data
+                .entry((id.to_owned(), key.to_owned()))
+                .or_insert_with(|| data.clone());
+        }
+        data
+    }
+
+    fn store_account_data(&mut self, id: &str, key: &str, data: Vec<u8>) {
+        self.record_write(StateKey::AccountData(id.to_owned(), key.to_owned()));
+        self.fork.store_account_data(id, key, data)
+    }
+
+    fn load_data(&self, key: &str) -> Option<Vec<u8>> {
+        let data = self.fork.load_data(key);
+        if !self.is_written(&StateKey::Data(key.to_owned())) {
+            self.access
+                .borrow_mut()
+                .read_internal
+                .entry(key.to_owned())
+                .or_insert_with(|| data.clone());
+        }
+        data
+    }
+
+    fn store_data(&mut self, key: &str, data: Vec<u8>) {
+        self.record_write(StateKey::Data(key.to_owned()));
+        self.fork.store_data(key, data)
+    }
+
+    fn remove_account_data(&mut self, id: &str, key: &str) {
+        self.record_write(StateKey::AccountData(id.to_owned(), key.to_owned()));
+        self.fork.remove_account_data(id, key)
+    }
+
+    fn load_account_keys(&self, id: &str) -> Vec<String> {
+        self.record_hash(id);
+        self.fork.load_account_keys(id)
+    }
+
+    fn load_account_keys_range(
+        &self,
+        id: &str,
+        prefix: &str,
+        after: Option<&str>,
+        limit: u64,
+    ) -> Vec<String> {
+        self.record_hash(id);
+        self.fork.load_account_keys_range(id, prefix, after, limit)
+    }
+
+    fn store_transaction(&mut self, hash: &Hash, tx: Transaction) {
+        self.record_opaque();
+        self.fork.store_transaction(hash, tx)
+    }
+
+    fn store_receipt(&mut self, hash: &Hash, receipt: Receipt) {
+        self.record_opaque();
+        self.fork.store_receipt(hash, receipt)
+    }
+
+    fn store_account_transaction(&mut self, id: &str, entry: AccountTransaction) {
+        self.record_opaque();
+        self.fork.store_account_transaction(id, entry)
+    }
+
+    fn store_event(&mut self, record: EventRecord) {
+        self.record_opaque();
+        self.fork.store_event(record)
+    }
+
+    fn store_block(&mut self, block: Block) {
+        self.record_opaque();
+        self.fork.store_block(block)
+    }
+
+    fn store_transactions_hashes(&mut self, height: u64, hashes: Vec<Hash>) -> Hash {
+        self.record_opaque();
+        self.fork.store_transactions_hashes(height, hashes)
+    }
+
+    fn store_receipts_hashes(&mut self, height: u64, hashes: Vec<Hash>) -> Hash {
+        self.record_opaque();
+        self.fork.store_receipts_hashes(height, hashes)
+    }
+
+    fn flush(&mut self) {
+        self.written.append(&mut self.pending);
+        self.fork.flush()
+    }
+
+    fn rollback(&mut self) {
+        if !self.pending.is_empty() {
+            self.record_opaque();
+            self.pending.clear();
+        }
+        self.fork.rollback()
+    }
+
+    fn store_configuration(&mut self, id: &str, config: Vec<u8>) {
+        self.record_opaque();
+        self.fork.store_configuration(id, config)
+    }
+}
+
+#[cfg(all(test, feature = "with-rocksdb"))]
+mod tests {
+    use super::*;
+    use crate::{
+        base::schema::tests::create_test_account,
+        db::{Db, RocksDb},
+    };
+    use tempfile::TempDir;
+
+    const ACCOUNT_ID: &str = "QmNLei78zWmzUdbeRB3CiUfAizWUrbeeZh5K1rhAQKCh51";
+
+    fn create_db(dir: &TempDir) -> RocksDb {
+        let mut db = RocksDb::new(dir.path());
+        let mut fork = db.fork_create();
+        fork.store_account(create_test_account());
+        fork.store_account_data(ACCOUNT_ID, "count", vec![1]);
+        db.fork_merge(fork).unwrap();
+        db
+    }
+
+    // Increments the account counter, then reads back the account data hash.
+    fn increment<F: DbFork>(fork: &mut F) -> Hash {
+        let count = fork.load_account_data(ACCOUNT_ID, "count").unwrap()[0];
+        fork.store_account_data(ACCOUNT_ID, "count", vec![count + 1]);
+        fork.load_account_data(ACCOUNT_ID, "count");
+        fork.state_hash(ACCOUNT_ID)
+    }
+
+    #[test]
+    fn access_records_initial_reads() {
+        let dir = TempDir::new().unwrap();
+        let mut db = create_db(&dir);
+        let initial_hash = db.fork_create().state_hash(ACCOUNT_ID);
+        let mut fork = TrackingFork::new(db.fork_create());
+
+        let hash = increment(&mut fork);
+        let access = fork.into_access();
+
+        assert_ne!(hash, initial_hash);
+        assert_eq!(
+            access.read_data.into_iter().collect::<Vec<_>>(),
+            vec![((ACCOUNT_ID.to_owned(), "count".to_owned()), Some(vec![1]))]
+        );
+        assert_eq!(access.read_hashes.get(ACCOUNT_ID), Some(&initial_hash));
+        assert_eq!(
+            access.data,
+            vec![(ACCOUNT_ID.to_owned(), "count".to_owned(), Some(vec![2]))]
+        );
+        assert!(!access.opaque);
+    }
+
+    #[test]
+    fn access_validate_and_apply() {
+        let dir = TempDir::new().unwrap();
+        let mut db = create_db(&dir);
+        let mut fork = TrackingFork::new(db.fork_create());
+        increment(&mut fork);
+        let access = fork.into_access();
+
+        // Unrelated writes do not invalidate the execution.
+        let mut other = db.fork_create();
+        other.store_account(Account::new("other", None));
+        assert!(access.is_valid(&other));
+        access.apply(&mut other);
+        assert_eq!(other.load_account_data(ACCOUNT_ID, "count"), Some(vec![2]));
+
+        // A write to the read state does.
+        let mut other = db.fork_create();
+        other.store_account_data(ACCOUNT_ID, "other", vec![0]);
+        assert!(!access.is_valid(&other));
+    }
+
+    #[test]
+    fn access_opaque_after_rollback() {
+        let dir = TempDir::new().unwrap();
+        let mut db = create_db(&dir);
+        let mut fork = TrackingFork::new(db.fork_create());
+
+        fork.flush();
+        increment(&mut fork);
+        fork.rollback();
+        let access = fork.into_access();
+
+        assert!(access.data.is_empty());
+        assert!(access.opaque);
+    }
+}
@@ -0,0 +1,260 @@
+// This file is part of TRINCI.
+//
+// Copyright (C) 2021 Affidaty Spa.
+//
+// TRINCI is free software: you can redistribute it and/or modify it under
+// the terms of the GNU Affero General Public License as published by the
+// Free Software Foundation, either version 3 of the License, or (at your
+// option) any later version.
+//
+// TRINCI is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License
+// for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with TRINCI. If not, see <https://www.gnu.org/licenses/>.
+
+//! Database integrity verifier.
+//!
+//! Walks the blockchain from the genesis block and, for each block, checks:
+//! - the block height and the `prev_hash` chaining;
+//! - the transactions and receipts hashes lists against the block
+//!   `txs_hash`/`rxs_hash` roots;
+//! - the block signature against the block `validator` (the genesis block is
+//!   not signed).
+//!
+//! The walk stops at the first inconsistency.
+
+use crate::{
+    base::{schema::Block, serialize::rmp_serialize},
+    crypto::{merkle, Hash, Hashable},
+    db::Db,
+};
+use std::fmt::{self, Display};
+
+/// Inconsistency type.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FaultKind {
+    /// The block is missing, while blocks with greater height are present.
+    MissingBlock,
+    /// The block height doesn't match its position within the blockchain.
+    HeightMismatch,
+    /// The block `prev_hash` doesn't match the previous block hash.
+    PrevHashMismatch,
+    /// The stored transactions hashes don't match the block `txs_hash`.
+    TxsHashMismatch,
+    /// The stored receipts hashes don't match the block `rxs_hash`.
+    RxsHashMismatch,
+    /// The block has no validator public key.
+    MissingValidator,
+    /// The block signature is not valid.
+    BadSignature,
+}
+
+impl Display for FaultKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            FaultKind::MissingBlock => "missing block",
+            FaultKind::HeightMismatch => "height mismatch",
+            FaultKind::PrevHashMismatch => "prev_hash mismatch",
+            FaultKind::TxsHashMismatch => "txs_hash mismatch",
+            FaultKind::RxsHashMismatch => "rxs_hash mismatch",
+            FaultKind::MissingValidator => "missing validator",
+            FaultKind::BadSignature => "bad signature",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// First inconsistency found by the verifier.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fault {
+    /// Height of the inconsistent block.
+    pub height: u64,
+    /// Inconsistency type.
+    pub kind: FaultKind,
+}
+
+impl Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "block {}: {}", self.height, self.kind)
+    }
+}
+
+impl std::error::Error for Fault {}
+
+/// Verify the blockchain stored within the database.
+/// Returns the number of verified blocks or the first inconsistency found.
+pub fn verify_db<D: Db>(db: &D) -> std::result::Result<u64, Fault> {
+    let last = match db.load_block(u64::MAX) {
+        Some(block) => block.data.height,
+        None => return Ok(0),
+    };
+    let mut prev_hash = None;
+    for height in 0..=last {
+        let fault = |kind| Fault { height, kind };
+        let block = db
+            .load_block(height)
+            .ok_or_else(|| fault(FaultKind::MissingBlock))?;
+        verify_block(db, &block, height, prev_hash).map_err(fault)?;
+        prev_hash = Some(block.data.primary_hash());
+    }
+    Ok(last + 1)
+}
+
+fn verify_block<D: Db>(
+    db: &D,
+    block: &Block,
+    height: u64,
+    prev_hash: Option<Hash>,
+) -> std::result::Result<(), FaultKind> {
+    let data = &block.data;
+    if data.height != height {
+        return Err(FaultKind::HeightMismatch);
+    }
+    if matches!(prev_hash, Some(hash) if hash != data.prev_hash) {
+        return Err(FaultKind::PrevHashMismatch);
+    }
+
+    // Empty lists are not stored.
+    let txs_hashes = db.load_transactions_hashes(height).unwrap_or_default();
+    if merkle::list_hash(&txs_hashes) != data.txs_hash {
+        return Err(FaultKind::TxsHashMismatch);
+    }
+    let rxs_hashes = db.load_receipts_hashes(height).unwrap_or_default();
+    if merkle::list_hash(&rxs_hashes) != data.rxs_hash {
+        return Err(FaultKind::RxsHashMismatch);
+    }
+
+    if height == 0 {
+        return Ok(());
+    }
+    let validator = data.validator.as_ref().ok_or(FaultKind::MissingValidator)?;
+    let buf = rmp_serialize(data).map_err(|_| FaultKind::BadSignature)?;
+    match validator.verify(&buf, &block.signature) {
+        true => Ok(()),
+        false => Err(FaultKind::BadSignature),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        base::schema::tests::create_test_block,
+        crypto::{sign::tests::create_test_keypair, HashAlgorithm},
+        db::MockDb,
+    };
+
+    struct TestBlock {
+        block: Block,
+        txs: Vec<Hash>,
+        rxs: Vec<Hash>,
+    }
+
+    fn create_test_chain(len: u64) -> Vec<TestBlock> {
+        let keypair = create_test_keypair();
+        let mut prev_hash = Hash::default();
+        (0..len)
+            .map(|height| {
+                let txs = vec![Hash::from_data(
+                    HashAlgorithm::Sha256,
+                    &height.to_be_bytes(),
+                )];
+                let rxs = vec![Hash::from_data(HashAlgorithm::Sha256, &[height as u8; 2])];
+                let mut block = create_test_block();
+                block.data.height = height;
+                block.data.prev_hash = prev_hash;
+                block.data.txs_hash = merkle::list_hash(&txs);
+                block.data.rxs_hash = merkle::list_hash(&rxs);
+                block.signature = keypair.sign(&rmp_serialize(&block.data).unwrap()).unwrap();
+                prev_hash = block.data.primary_hash();
+                TestBlock { block, txs, rxs }
+            })
+            .collect()
+    }
+
+    fn create_test_db(chain: Vec<TestBlock>) -> MockDb {
+        let mut db = MockDb::new();
+        let blocks: Vec<Block> = chain.iter().map(|b| b.block.clone()).collect();
+        db.expect_load_block()
+            .returning(move |height| match height {
+                u64::MAX => blocks.last().cloned(),
+                _ => blocks.get(height as usize).cloned(),
+            });
+        let txs: Vec<Vec<Hash>> = chain.iter().map(|b| b.txs.clone()).collect();
+        db.expect_load_transactions_hashes()
+            .returning(move |height| txs.get(height as usize).cloned());
+        let rxs: Vec<Vec<Hash>> = chain.iter().map(|b| b.rxs.clone()).collect();
+        db.expect_load_receipts_hashes()
+            .returning(move |height| rxs.get(height as usize).cloned());
+        db
+    }
+
+    #[test]
+    fn verify_consistent_db() {
+        let db = create_test_db(create_test_chain(3));
+
+        assert_eq!(verify_db(&db), Ok(3));
+    }
+
+    #[test]
+    fn verify_empty_db() {
+        let mut db = MockDb::new();
+        db.expect_load_block().returning(|_| None);
+
+        assert_eq!(verify_db(&db), Ok(0));
+    }
+
+    #[test]
+    fn verify_broken_chaining() {
+        let mut chain = create_test_chain(3);
+        let keypair = create_test_keypair();
+        let block = &mut chain[2].block;
+        block.data.prev_hash = Hash::default();
+        block.signature = keypair.sign(&rmp_serialize(&block.data).unwrap()).unwrap();
+        let db = create_test_db(chain);
+
+        let fault = verify_db(&db).unwrap_err();
+
+        assert_eq!(fault.height, 2);
+        assert_eq!(fault.kind, FaultKind::PrevHashMismatch);
+    }
+
+    #[test]
+    fn verify_bad_transactions_list() {
+        let mut chain = create_test_chain(3);
+        chain[1].txs.pop();
+        let db = create_test_db(chain);
+
+        let fault = verify_db(&db).unwrap_err();
+
+        assert_eq!(fault.height, 1);
+        assert_eq!(fault.kind, FaultKind::TxsHashMismatch);
+    }
+
+    #[test]
+    fn verify_bad_receipts_list() {
+        let mut chain = create_test_chain(3);
+        chain[2].rxs.push(Hash::default());
+        let db = create_test_db(chain);
+
+        let fault = verify_db(&db).unwrap_err();
+
+        assert_eq!(fault.height, 2);
+        assert_eq!(fault.kind, FaultKind::RxsHashMismatch);
+    }
+
+    #[test]
+    fn verify_bad_signature() {
+        let mut chain = create_test_chain(3);
+        chain[1].block.signature[0] ^= 1;
+        let db = create_test_db(chain);
+
+        let fault = verify_db(&db).unwrap_err();
+
+        assert_eq!(fault.height, 1);
+        assert_eq!(fault.kind, FaultKind::BadSignature);
+    }
+}
@@ -35,6 +35,9 @@ pub enum ErrorKind {
     DuplicatedUnconfirmedTx,
     DuplicatedConfirmedTx,
     TooLargeTx,
+    PoolLimitExceeded,
+    ExpiredTx,
+    InvalidSequence,
     DatabaseFault,
     WasmMachineFault,
     SmartContractFault,
@@ -56,6 +59,9 @@ pub(super) mod error_kind_str {
     pub const DUPLICATED_UNCONFIRMED_TX: &str = "duplicated unconfirmed transaction";
     pub const DUPLICATED_CONFIRMED_TX: &str = "duplicated confirmed transaction";
     pub const TOO_LARGE_TX: &str = "transaction too large";
+    pub const POOL_LIMIT_EXCEEDED: &str = "transactions pool limit exceeded";
+    pub const EXPIRED_TX: &str = "expired transaction";
+    pub const INVALID_SEQUENCE: &str = "invalid transaction sequence number";
     pub const RESOURCE_NOT_FOUND: &str = "resource not found";
     pub const DATABASE_FAULT: &str = "database fault";
     pub const WASM_MACHINE_FAULT: &str = "wasm machine fault";
@@ -90,6 +96,9 @@ impl Display for ErrorKind {
             Other => error_kind_str::OTHER,
             InvalidContract => error_kind_str::INVALID_CONTRACT,
             TooLargeTx => error_kind_str::TOO_LARGE_TX,
+            PoolLimitExceeded => error_kind_str::POOL_LIMIT_EXCEEDED,
+            ExpiredTx => error_kind_str::EXPIRED_TX,
+            InvalidSequence => error_kind_str::INVALID_SEQUENCE,
         };
         write!(f, "{}", kind_str)
     }
@@ -130,6 +139,9 @@ impl<'de> Deserialize<'de> for ErrorKind {
             error_kind_str::INVALID_SIGNATURE => ErrorKind::InvalidSignature,
             error_kind_str::DUPLICATED_UNCONFIRMED_TX => ErrorKind::DuplicatedUnconfirmedTx,
             error_kind_str::DUPLICATED_CONFIRMED_TX => ErrorKind::DuplicatedConfirmedTx,
+            error_kind_str::POOL_LIMIT_EXCEEDED => ErrorKind::PoolLimitExceeded,
+            error_kind_str::EXPIRED_TX => ErrorKind::ExpiredTx,
+            error_kind_str::INVALID_SEQUENCE => ErrorKind::InvalidSequence,
             error_kind_str::RESOURCE_NOT_FOUND => ErrorKind::ResourceNotFound,
             error_kind_str::DATABASE_FAULT => ErrorKind::DatabaseFault,
             error_kind_str::WASM_MACHINE_FAULT => ErrorKind::WasmMachineFault,
@@ -48,7 +48,7 @@ pub mod kafka;
 #[cfg(feature = "rt-monitor")]
 pub mod network_monitor;
 
-pub use base::{Account, Block, Receipt, Transaction, TransactionDataV1};
+pub use base::{Account, Block, Receipt, Transaction, TransactionDataV1, TransactionDataV2};
 pub use blockchain::{BlockConfig, BlockService, Message};
 pub use crypto::{KeyPair, PublicKey};
 pub use error::{Error, ErrorKind, Result};
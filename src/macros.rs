@@ -60,4 +60,10 @@ macro_rules! unwrap_or_return {
             Err(e) => return (0, Err(e)), // FIXME return a fuel value
         }
     };
+    ( $e:expr, $fuel:expr ) => {
+        match $e {
+            Ok(x) => x,
+            Err(e) => return ($fuel, Err(e)),
+        }
+    };
 }
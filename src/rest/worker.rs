@@ -19,6 +19,7 @@ use crate::{
     base::serialize::{rmp_deserialize, rmp_serialize},
     blockchain::{BlockRequestSender, Message},
     crypto::Hash,
+    db::EventFilter,
     Error, ErrorKind, Result, VERSION,
 };
 
@@ -26,6 +27,9 @@ use tide::{http::mime, Request, Response, StatusCode};
 
 use super::service::NodeInfo;
 
+/// Default number of entries returned by paginated requests.
+const DEFAULT_PAGE_LIMIT: u64 = 20;
+
 /// Conversion from "core" errors to HTTP errors.
 impl From<ErrorKind> for StatusCode {
     fn from(err: ErrorKind) -> StatusCode {
@@ -35,6 +39,9 @@ impl From<ErrorKind> for StatusCode {
             BadNetwork => StatusCode::NotFound,
             InvalidSignature => StatusCode::Unauthorized,
             DuplicatedUnconfirmedTx | DuplicatedConfirmedTx => StatusCode::Conflict,
+            PoolLimitExceeded => StatusCode::TooManyRequests,
+            ExpiredTx => StatusCode::Gone,
+            InvalidSequence => StatusCode::Conflict,
             ResourceNotFound => StatusCode::NotFound,
             InvalidContract | WasmMachineFault | DatabaseFault | FuelError | TooLargeTx => {
                 StatusCode::InternalServerError
@@ -190,6 +197,53 @@ async fn read_only_sync_exec(mut req: Request<BlockRequestSender>) -> tide::Resu
     tide_result(bc_res)
 }
 
+async fn estimate_fuel(mut req: Request<BlockRequestSender>) -> tide::Result {
+    let body = req.body_bytes().await?;
+    let tx = rmp_deserialize(&body)?;
+    let bc_req = Message::EstimateFuelRequest { tx };
+    let res = match send_recv(req.state(), bc_req).await? {
+        Message::EstimateFuelResponse { estimate } => rmp_serialize(&estimate),
+        Message::Exception(err) => Err(err),
+        _ => Err(Error::new_ext(
+            ErrorKind::Other,
+            "unexpected response from block service",
+        )),
+    };
+    tide_result(res)
+}
+
+async fn read_only_simulate(mut req: Request<BlockRequestSender>) -> tide::Result {
+    let ReadOnlyArgs {
+        target,
+        method,
+        origin,
+        max_fuel,
+        args,
+        network,
+        contract,
+    } = req.body_json().await?;
+
+    let message = Message::SimulateReadOnlyTransaction {
+        target,
+        method,
+        origin,
+        max_fuel,
+        args,
+        contract,
+        network,
+    };
+
+    let res = match send_recv(req.state(), message).await? {
+        Message::SimulateTransactionResponse { rx, diff } => rmp_serialize(&(rx, diff)),
+        Message::Exception(err) => Err(err),
+        _ => Err(Error::new_ext(
+            ErrorKind::Other,
+            "unexpected response from block service",
+        )),
+    };
+    tide_result(res)
+}
+
 async fn get_receipt(req: Request<BlockRequestSender>) -> tide::Result {
     let ticket = req.param("0").unwrap_or_default();
     let hash = Hash::from_hex(ticket).unwrap_or_default();
@@ -224,9 +278,34 @@ async fn get_block(req: Request<BlockRequestSender>) -> tide::Result {
     tide_result(res)
 }
 
+async fn get_block_by_hash(req: Request<BlockRequestSender>) -> tide::Result {
+    let hash = req.param("0").unwrap_or_default();
+    let hash = Hash::from_hex(hash).unwrap_or_default();
+    let bc_req = Message::GetBlockByHashRequest { hash, txs: false };
+    let res = match send_recv(req.state(), bc_req).await? {
+        Message::GetBlockResponse { block, .. } => rmp_serialize(&block),
+        Message::Exception(err) => Err(err),
+        _ => Err(Error::new_ext(
+            ErrorKind::Other,
+            "unexpected response from block service",
+        )),
+    };
+    tide_result(res)
+}
+
+#[derive(Deserialize)]
+struct AccountQuery {
+    height: Option<u64>,
+}
+
 async fn get_account(req: Request<BlockRequestSender>) -> tide::Result {
     let id = req.param("0").unwrap_or_default().to_owned();
-    let bc_req = Message::GetAccountRequest { id, data: vec![] };
+    let AccountQuery { height } = req.query()?;
+    let bc_req = Message::GetAccountRequest {
+        id,
+        data: vec![],
+        height,
+    };
     let res = match send_recv(req.state(), bc_req).await? {
         Message::GetAccountResponse { acc, .. } => rmp_serialize(&acc),
         Message::Exception(err) => Err(err),
@@ -238,6 +317,118 @@ async fn get_account(req: Request<BlockRequestSender>) -> tide::Result {
     tide_result(res)
 }
 
+#[derive(Deserialize)]
+struct PageQuery {
+    from: Option<u64>,
+    limit: Option<u64>,
+}
+
+async fn get_account_transactions(req: Request<BlockRequestSender>) -> tide::Result {
+    let id = req.param("0").unwrap_or_default().to_owned();
+    let PageQuery { from, limit } = req.query()?;
+    let bc_req = Message::GetAccountTransactionsRequest {
+        id,
+        from: from.unwrap_or_default(),
+        limit: limit.unwrap_or(DEFAULT_PAGE_LIMIT),
+    };
+    let res = match send_recv(req.state(), bc_req).await? {
+        Message::GetAccountTransactionsResponse { txs } => rmp_serialize(&txs),
+        Message::Exception(err) => Err(err),
+        _ => Err(Error::new_ext(
+            ErrorKind::Other,
+            "unexpected response from block service",
+        )),
+    };
+    tide_result(res)
+}
+
+#[derive(Deserialize)]
+struct KeysQuery {
+    prefix: Option<String>,
+    after: Option<String>,
+    limit: Option<u64>,
+}
+
+async fn get_account_keys(req: Request<BlockRequestSender>) -> tide::Result {
+    let id = req.param("0").unwrap_or_default().to_owned();
+    let KeysQuery {
+        prefix,
+        after,
+        limit,
+    } = req.query()?;
+    let bc_req = Message::GetAccountKeysRequest {
+        id,
+        prefix: prefix.unwrap_or_default(),
+        after,
+        limit: limit.unwrap_or(DEFAULT_PAGE_LIMIT),
+    };
+    let res = match send_recv(req.state(), bc_req).await? {
+        Message::GetAccountKeysResponse { keys } => rmp_serialize(&keys),
+        Message::Exception(err) => Err(err),
+        _ => Err(Error::new_ext(
+            ErrorKind::Other,
+            "unexpected response from block service",
+        )),
+    };
+    tide_result(res)
+}
+
+#[derive(Deserialize)]
+struct EventsQuery {
+    emitter: Option<String>,
+    name: Option<String>,
+    min_height: Option<u64>,
+    max_height: Option<u64>,
+    from: Option<u64>,
+    limit: Option<u64>,
+}
+
+async fn get_events(req: Request<BlockRequestSender>) -> tide::Result {
+    let query: EventsQuery = req.query()?;
+    let bc_req = Message::GetEventsRequest {
+        filter: EventFilter {
+            emitter: query.emitter,
+            name: query.name,
+            min_height: query.min_height,
+            max_height: query.max_height,
+        },
+        from: query.from.unwrap_or_default(),
+        limit: query.limit.unwrap_or(DEFAULT_PAGE_LIMIT),
+    };
+    let res = match send_recv(req.state(), bc_req).await? {
+        Message::GetEventsResponse { events } => rmp_serialize(&events),
+        Message::Exception(err) => Err(err),
+        _ => Err(Error::new_ext(
+            ErrorKind::Other,
+            "unexpected response from block service",
+        )),
+    };
+    tide_result(res)
+}
+
+async fn account_proof(req: Request<BlockRequestSender>, data: Option<String>) -> tide::Result {
+    let id = req.param("0").unwrap_or_default().to_owned();
+    let bc_req = Message::GetAccountProofRequest { id, data };
+    let res = match send_recv(req.state(), bc_req).await? {
+        Message::GetAccountProofResponse { proof } => rmp_serialize(&proof),
+        Message::Exception(err) => Err(err),
+        _ => Err(Error::new_ext(
+            ErrorKind::Other,
+            "unexpected response from block service",
+        )),
+    };
+    tide_result(res)
+}
+
+async fn get_account_proof(req: Request<BlockRequestSender>) -> tide::Result {
+    account_proof(req, None).await
+}
+
+async fn get_account_data_proof(req: Request<BlockRequestSender>) -> tide::Result {
+    let key = req.param("1").unwrap_or_default().to_owned();
+    account_proof(req, Some(key)).await
+}
+
 async fn get_p2p_id(req: Request<BlockRequestSender>) -> tide::Result {
     let bc_req = Message::GetP2pIdRequest;
     let res = match send_recv(req.state(), bc_req).await? {
@@ -260,10 +451,20 @@ pub fn run(addr: String, port: u16, node_info: NodeInfo, block_chan: BlockReques
     app.at("/api/v1/message").post(message_handler);
     app.at("/api/v1/submit").post(put_transaction);
     app.at("/api/v1/ro/exec").post(read_only_sync_exec);
+    app.at("/api/v1/ro/simulate").post(read_only_simulate);
+    app.at("/api/v1/estimate").post(estimate_fuel);
     app.at("/api/v1/account/:0").get(get_account);
+    app.at("/api/v1/account/:0/transactions")
+        .get(get_account_transactions);
+    app.at("/api/v1/account/:0/keys").get(get_account_keys);
     app.at("/api/v1/transaction/:0").get(get_transaction);
     app.at("/api/v1/receipt/:0").get(get_receipt);
     app.at("/api/v1/block/:0").get(get_block);
+    app.at("/api/v1/block/hash/:0").get(get_block_by_hash);
+    app.at("/api/v1/events").get(get_events);
+    app.at("/api/v1/proof/account/:0").get(get_account_proof);
+    app.at("/api/v1/proof/account/:0/data/:1")
+        .get(get_account_data_proof);
     app.at("/api/v1/p2p/id").get(get_p2p_id);
     let _ = app
         .at("/api/v1/bootstrap")
@@ -290,12 +491,14 @@ mod tests {
                     create_test_account, create_test_block, create_test_receipt,
                     create_test_unit_tx,
                 },
-                FUEL_LIMIT,
+                FuelEstimate, SmartContractEvent, FUEL_LIMIT,
             },
             serialize::{rmp_deserialize, rmp_serialize},
         },
         blockchain::BlockRequestReceiver,
         channel,
+        crypto::merkle::{AccountProof, MapProof},
+        db::{AccountTransaction, EventRecord},
         rest::service::tests::create_node_info,
     };
     use std::{
@@ -308,6 +511,44 @@ mod tests {
     const HASH_HEX: &str = "1220ceb09a4dda3d8c0f900c75a6d826ae3296e31918e7b155b5dbe41d3d4f766aac";
     const ACCOUNT_ID: &str = "QmYHnEQLdf5h7KYbjFPuHSRk2SPgdXrJWFh5W696HPfq7i";
 
+    fn create_test_account_txs() -> Vec<AccountTransaction> {
+        (0..3)
+            .map(|height| AccountTransaction {
+                height,
+                index: 0,
+                hash: Hash::from_hex(HASH_HEX).unwrap(),
+            })
+            .collect()
+    }
+
+    fn create_test_event_records() -> Vec<EventRecord> {
+        ["a", "b", "a"]
+            .iter()
+            .enumerate()
+            .map(|(height, name)| EventRecord {
+                height: height as u64,
+                index: 0,
+                event: SmartContractEvent {
+                    event_tx: Hash::from_hex(HASH_HEX).unwrap(),
+                    emitter_account: ACCOUNT_ID.to_owned(),
+                    emitter_smart_contract: Hash::default(),
+                    event_name: name.to_string(),
+                    event_data: vec![],
+                },
+            })
+            .collect()
+    }
+
+    fn create_test_fuel_estimate() -> FuelEstimate {
+        FuelEstimate {
+            success: true,
+            returns: vec![],
+            wm_fuel: 2500,
+            burned_fuel: 13,
+            fuel_limit: 14,
+        }
+    }
+
     fn msg_handler(req: Message) -> Message {
         match req {
             Message::PutTransactionRequest { confirm, tx } if confirm => {
@@ -328,6 +569,9 @@ mod tests {
                     false => Message::Exception(ErrorKind::ResourceNotFound.into()),
                 }
             }
+            Message::EstimateFuelRequest { .. } => Message::EstimateFuelResponse {
+                estimate: create_test_fuel_estimate(),
+            },
             Message::GetReceiptRequest { hash } => {
                 match hash == Hash::from_hex(HASH_HEX).unwrap() {
                     true => Message::GetReceiptResponse {
@@ -336,11 +580,77 @@ mod tests {
                     false => Message::Exception(ErrorKind::ResourceNotFound.into()),
                 }
             }
-            Message::GetAccountRequest { id, data: _ } => match id == ACCOUNT_ID {
-                true => Message::GetAccountResponse {
+            Message::GetAccountRequest { id, height, .. } => match id == ACCOUNT_ID {
+                true if height.unwrap_or_default() == 0 => Message::GetAccountResponse {
                     acc: create_test_account(),
                     data: vec![],
                 },
+                _ => Message::Exception(ErrorKind::ResourceNotFound.into()),
+            },
+            Message::GetAccountTransactionsRequest { id, from, limit } => {
+                let txs = match id == ACCOUNT_ID {
+                    true => create_test_account_txs()
+                        .into_iter()
+                        .skip(from as usize)
+                        .take(limit as usize)
+                        .collect(),
+                    false => vec![],
+                };
+                Message::GetAccountTransactionsResponse { txs }
+            }
+            Message::GetAccountKeysRequest {
+                id,
+                prefix,
+                after,
+                limit,
+            } => {
+                let keys = match id == ACCOUNT_ID {
+                    true => ["a", "b:1", "b:2", "b:3"]
+                        .iter()
+                        .map(|key| key.to_string())
+                        .filter(|key| key.starts_with(&prefix))
+                        .filter(|key| after.as_ref().map_or(true, |after| key > after))
+                        .take(limit as usize)
+                        .collect(),
+                    false => vec![],
+                };
+                Message::GetAccountKeysResponse { keys }
+            }
+            Message::GetEventsRequest {
+                filter,
+                from,
+                limit,
+            } => {
+                let events = create_test_event_records()
+                    .into_iter()
+                    .filter(|record| match &filter.emitter {
+                        Some(emitter) => *emitter == record.event.emitter_account,
+                        None => true,
+                    })
+                    .filter(|record| match &filter.name {
+                        Some(name) => *name == record.event.event_name,
+                        None => true,
+                    })
+                    .filter(|record| record.height >= filter.min_height.unwrap_or_default())
+                    .skip(from as usize)
+                    .take(limit as usize)
+                    .collect();
+                Message::GetEventsResponse { events }
+            }
+            Message::GetBlockByHashRequest { hash, txs: _ } => {
+                match hash == Hash::from_hex(HASH_HEX).unwrap() {
+                    true => Message::GetBlockResponse {
+                        block: create_test_block(),
+                        txs: None,
+                        origin: None,
+                    },
+                    false => Message::Exception(ErrorKind::ResourceNotFound.into()),
+                }
+            }
+            Message::GetAccountProofRequest { id, data } => match id == ACCOUNT_ID {
+                true => Message::GetAccountProofResponse {
+                    proof: create_test_account_proof(data),
+                },
                 false => Message::Exception(ErrorKind::ResourceNotFound.into()),
             },
             Message::GetBlockRequest {
@@ -369,6 +679,15 @@ mod tests {
         }
     }
 
+    fn create_test_account_proof(data: Option<String>) -> AccountProof {
+        let account = rmp_serialize(&create_test_account()).unwrap();
+        AccountProof {
+            height: 0,
+            account: MapProof::new(vec![(ACCOUNT_ID, account)], ACCOUNT_ID).unwrap(),
+            data: data.and_then(|key| MapProof::new(vec![(key.as_str(), vec![1, 2, 3])], &key)),
+        }
+    }
+
     fn block_svc_mock_start(req_chan: BlockRequestReceiver) {
         let fut = async move {
             while let Ok((req, res_chan)) = req_chan.recv().await {
@@ -531,6 +850,22 @@ mod tests {
         assert_eq!(String::from_utf8_lossy(&body), "invalid signature");
     }
 
+    #[test]
+    fn estimate_fuel() {
+        let mut addr = start_listener();
+        addr.push_str("/api/v1/estimate");
+        let tx = create_test_unit_tx(FUEL_LIMIT);
+        let body = rmp_serialize(&tx).unwrap();
+
+        let response = ureq::post(&addr).send_bytes(&body).unwrap();
+
+        assert_eq!(response.status_text(), "OK");
+        assert_eq!(response.content_type(), "application/octet-stream");
+        let body = fetch_response_body(response);
+        let estimate: FuelEstimate = rmp_deserialize(&body).unwrap();
+        assert_eq!(estimate, create_test_fuel_estimate());
+    }
+
     #[test]
     fn get_transaction() {
         let mut addr = start_listener();
@@ -571,6 +906,19 @@ mod tests {
         assert_eq!(fetch_response_body(response), exp);
     }
 
+    #[test]
+    fn get_block_by_hash() {
+        let mut addr = start_listener();
+        addr.push_str("/api/v1/block/hash/");
+        addr.push_str(HASH_HEX);
+
+        let response: ureq::Response = ureq::get(&addr).call().unwrap();
+
+        assert_eq!(response.status_text(), "OK");
+        let exp = rmp_serialize(&create_test_block()).unwrap();
+        assert_eq!(fetch_response_body(response), exp);
+    }
+
     #[test]
     fn get_account() {
         let mut addr = start_listener();
@@ -584,4 +932,101 @@ mod tests {
         let exp = rmp_serialize(&create_test_account()).unwrap();
         assert_eq!(fetch_response_body(response), exp);
     }
+
+    #[test]
+    fn get_account_at_height() {
+        let mut addr = start_listener();
+        addr.push_str("/api/v1/account/");
+        addr.push_str(ACCOUNT_ID);
+        addr.push_str("?height=0");
+
+        let response: ureq::Response = ureq::get(&addr).call().unwrap();
+
+        assert_eq!(response.status_text(), "OK");
+        let exp = rmp_serialize(&create_test_account()).unwrap();
+        assert_eq!(fetch_response_body(response), exp);
+    }
+
+    #[test]
+    fn get_account_at_height_not_found() {
+        let mut addr = start_listener();
+        addr.push_str("/api/v1/account/");
+        addr.push_str(ACCOUNT_ID);
+        addr.push_str("?height=3");
+
+        let error = ureq::get(&addr).call().unwrap_err();
+        let response = fetch_error_response(error);
+
+        assert_eq!(response.status_text(), "Not Found");
+    }
+
+    #[test]
+    fn get_account_transactions() {
+        let mut addr = start_listener();
+        addr.push_str("/api/v1/account/");
+        addr.push_str(ACCOUNT_ID);
+        addr.push_str("/transactions?from=1&limit=1");
+
+        let response: ureq::Response = ureq::get(&addr).call().unwrap();
+
+        assert_eq!(response.status_text(), "OK");
+        let exp = rmp_serialize(&create_test_account_txs()[1..2]).unwrap();
+        assert_eq!(fetch_response_body(response), exp);
+    }
+
+    #[test]
+    fn get_account_keys() {
+        let mut addr = start_listener();
+        addr.push_str("/api/v1/account/");
+        addr.push_str(ACCOUNT_ID);
+        addr.push_str("/keys?prefix=b:&after=b:1&limit=1");
+
+        let response: ureq::Response = ureq::get(&addr).call().unwrap();
+
+        assert_eq!(response.status_text(), "OK");
+        let exp = rmp_serialize(&vec!["b:2"]).unwrap();
+        assert_eq!(fetch_response_body(response), exp);
+    }
+
+    #[test]
+    fn get_events() {
+        let mut addr = start_listener();
+        addr.push_str("/api/v1/events?emitter=");
+        addr.push_str(ACCOUNT_ID);
+        addr.push_str("&name=a&min_height=1");
+
+        let response: ureq::Response = ureq::get(&addr).call().unwrap();
+
+        assert_eq!(response.status_text(), "OK");
+        let exp = rmp_serialize(&create_test_event_records()[2..]).unwrap();
+        assert_eq!(fetch_response_body(response), exp);
+    }
+
+    #[test]
+    fn get_account_proof() {
+        let mut addr = start_listener();
+        addr.push_str("/api/v1/proof/account/");
+        addr.push_str(ACCOUNT_ID);
+
+        let response: ureq::Response = ureq::get(&addr).call().unwrap();
+
+        assert_eq!(response.status_text(), "OK");
+        assert_eq!(response.content_type(), "application/octet-stream");
+        let exp = rmp_serialize(&create_test_account_proof(None)).unwrap();
+        assert_eq!(fetch_response_body(response), exp);
+    }
+
+    #[test]
+    fn get_account_data_proof() {
+        let mut addr = start_listener();
+        addr.push_str("/api/v1/proof/account/");
+        addr.push_str(ACCOUNT_ID);
+        addr.push_str("/data/balance");
+
+        let response: ureq::Response = ureq::get(&addr).call().unwrap();
+
+        assert_eq!(response.status_text(), "OK");
+        let exp = rmp_serialize(&create_test_account_proof(Some("balance".to_owned()))).unwrap();
+        assert_eq!(fetch_response_body(response), exp);
+    }
 }
@@ -201,6 +201,21 @@ pub fn get_keys(ctx: &mut CallContext, pattern: &str) -> Vec<String> {
         .collect()
 }
 
+/// Maximum number of keys returned by a single `get_keys_page` call.
+pub const MAX_KEYS_PAGE_LIMIT: u64 = 100;
+
+/// Get a page of the account keys starting with `prefix`, in lexicographic order.
+/// The page starts after the `after` key and contains up to `limit` keys.
+pub fn get_keys_page(
+    ctx: &mut CallContext,
+    prefix: &str,
+    after: Option<&str>,
+    limit: u64,
+) -> Vec<String> {
+    ctx.db
+        .load_account_keys_range(ctx.owner, prefix, after, limit.min(MAX_KEYS_PAGE_LIMIT))
+}
+
 /// Returns an account asset field for a given `account_id`
 /// The `asset_id` key is the ctx.caller
 pub fn load_asset(ctx: &CallContext, account_id: &str) -> Vec<u8> {
@@ -618,6 +633,22 @@ mod tests {
         assert_eq!(result, Ok(0));
     }
 
+    #[test]
+    fn get_keys_page_test() {
+        let mut ctx = prepare_env();
+        ctx.db
+            .expect_load_account_keys_range()
+            .withf(|_, prefix, after, limit| {
+                prefix == "b:" && *after == Some("b:1") && *limit == MAX_KEYS_PAGE_LIMIT
+            })
+            .returning(|_, _, _, _| vec!["b:2".to_owned()]);
+        let mut ctx = ctx.as_wm_context();
+
+        let keys = get_keys_page(&mut ctx, "b:", Some("b:1"), u64::MAX);
+
+        assert_eq!(keys, vec!["b:2"]);
+    }
+
     #[test]
     fn sha256_success() {
         let mut ctx = prepare_env();
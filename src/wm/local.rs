@@ -231,6 +231,42 @@ mod local_host_func {
         return_buf(caller, mem, buf)
     }
 
+    /// Get a page of the data keys from the account that start with the prefix.
+    /// An empty `after` key requests the first page.
+    fn get_keys_page(
+        mut caller: Caller<'_, CallContext>,
+        prefix_offset: i32,
+        prefix_size: i32,
+        after_offset: i32,
+        after_size: i32,
+        limit: i32,
+    ) -> std::result::Result<WasmSlice, Trap> {
+        // Recover parameters from wasm memory.
+        let mem: Memory = mem_from(&mut caller)?;
+        let buf = slice_from(&mut caller, &mem, prefix_offset, prefix_size)?;
+        let prefix = std::str::from_utf8(buf)
+            .map_err(|_| Trap::new("invalid utf-8"))?
+            .to_owned();
+        let buf = slice_from(&mut caller, &mem, after_offset, after_size)?;
+        let after = std::str::from_utf8(buf)
+            .map_err(|_| Trap::new("invalid utf-8"))?
+            .to_owned();
+        let after = (!after.is_empty()).then(|| after.as_str());
+        let limit = u64::try_from(limit).map_err(|_| Trap::new("invalid limit"))?;
+        // Recover execution context.
+        let ctx = caller.data_mut();
+        // Invoke portable host function.
+        let data = host_func::get_keys_page(ctx, &prefix, after, limit);
+        let data_buf = rmp_serialize(&data).unwrap_or_default();
+        let output = AppOutput {
+            success: true,
+            data: &data_buf,
+        };
+        let buf = rmp_serialize(&output).unwrap_or_default();
+
+        return_buf(caller, mem, buf)
+    }
+
     /// Check if an account has a method
     fn is_callable(
         mut caller: Caller<'_, CallContext>,
@@ -534,6 +570,7 @@ mod local_host_func {
                 "hf_remove_asset" => Func::wrap(&mut store, remove_asset),
                 "hf_get_account_contract" => Func::wrap(&mut store, get_account_contract),
                 "hf_get_keys" => Func::wrap(&mut store, get_keys),
+                "hf_get_keys_page" => Func::wrap(&mut store, get_keys_page),
                 "hf_call" => Func::wrap(&mut store, call),
                 "hf_s_call" => Func::wrap(&mut store, s_call),
                 "hf_verify" => Func::wrap(&mut store, verify),
@@ -823,35 +860,41 @@ impl Wm for WmLocal {
         );
 
         // Call smart contract entry point.
-        let wslice =
-            unwrap_or_return!(run_func
-                .call(store.as_context_mut(), params)
-                .map_err(|err| {
-                    // Here the error shall be serious and a probable crash of the wasm sandbox.
-                    Error::new_ext(ErrorKind::WasmMachineFault, err.to_string())
-                }));
-
+        // The fuel consumed until a failure, e.g. fuel exhaustion, is charged.
+        let result = run_func.call(store.as_context_mut(), params);
         let consumed_fuel = store.fuel_consumed().unwrap_or_default();
+        let wslice = unwrap_or_return!(
+            result.map_err(|err| {
+                // Here the error shall be serious and a probable crash of the wasm sandbox.
+                Error::new_ext(ErrorKind::WasmMachineFault, err.to_string())
+            }),
+            consumed_fuel
+        );
 
         let ctx = store.data_mut();
 
         if ctx.data_updated {
             // Account data has been altered, update the `data_hash`.
-            let mut account = unwrap_or_return!(ctx
-                .db
-                .load_account(ctx.owner)
-                .ok_or_else(|| Error::new_ext(ErrorKind::WasmMachineFault, "inconsistent state")));
+            let mut account = unwrap_or_return!(
+                ctx.db.load_account(ctx.owner).ok_or_else(|| Error::new_ext(
+                    ErrorKind::WasmMachineFault,
+                    "inconsistent state"
+                )),
+                consumed_fuel
+            );
             account.data_hash = Some(ctx.db.state_hash(&account.id));
             ctx.db.store_account(account);
         }
         // Extract smart contract result from memory.
         let (offset, length) = wslice_split(wslice);
-        let buf = unwrap_or_return!(mem
-            .data(store.as_context())
-            .get(offset as usize..offset as usize + length as usize)
-            .ok_or_else(|| {
-                Error::new_ext(ErrorKind::WasmMachineFault, "out of bounds memory access")
-            }));
+        let buf = unwrap_or_return!(
+            mem.data(store.as_context())
+                .get(offset as usize..offset as usize + length as usize)
+                .ok_or_else(|| {
+                    Error::new_ext(ErrorKind::WasmMachineFault, "out of bounds memory access")
+                }),
+            consumed_fuel
+        );
 
         match rmp_deserialize::<AppOutput>(buf) {
             Ok(res) if res.success => (consumed_fuel, { Ok(res.data.to_owned()) }),